@@ -10,6 +10,21 @@ fn get_dtrace_libpath() -> PathBuf {
 }
 
 fn main() {
+    let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
+    let bindings = match target_os.as_str() {
+        "windows" => build_windows(),
+        "linux" => build_linux(),
+        os => panic!("Unsupported target OS: {}", os),
+    };
+
+    // Write the bindings to the $OUT_DIR/bindings.rs file.
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    bindings
+        .write_to_file(out_path.join("bindings.rs"))
+        .expect("Couldn't write bindings!");
+}
+
+fn build_windows() -> bindgen::Bindings {
     // Tell cargo to invalidate the built crate whenever the wrapper changes
     println!("cargo:rerun-if-changed=wrapper.h");
 
@@ -28,8 +43,95 @@ fn main() {
     )
     .expect("Failed to copy dll");
 
-    let bindings = bindgen::Builder::default()
-        .header("wrapper.h") // The input header
+    generate_bindings(
+        &[PathBuf::from("wrapper.h")],
+        &[
+            PathBuf::from("target\\dtrace\\lib\\libctf\\common"),
+            PathBuf::from("target\\dtrace\\lib\\libdtrace\\common"),
+            PathBuf::from("target\\dtrace\\lib\\libdtrace\\compat\\win32"),
+            PathBuf::from("target\\dtrace\\lib\\libdtrace\\compat\\win32\\inc"),
+        ],
+    )
+}
+
+fn build_linux() -> bindgen::Bindings {
+    // Tell cargo to invalidate the built crate whenever the wrapper changes
+    println!("cargo:rerun-if-changed=wrapper-linux.h");
+
+    let include_paths = find_libdtrace();
+    let edt = write_edt_header(&include_paths);
+    generate_bindings(&[PathBuf::from("wrapper-linux.h"), edt], &include_paths)
+}
+
+/// Copies the `EDT_*` error numbers out of libdtrace's private `dt_impl.h` into `$OUT_DIR/edt.h`.
+///
+/// libdtrace does not install `dt_impl.h`, and it includes the rest of the private headers, so only `EDT_BASE` and
+/// the enum declaring the error numbers are copied. `dt_impl.h` is looked up in the `libdtrace` directory of
+/// `LIBDTRACE_SOURCE_DIR`, the sources libdtrace was built from, and then in the include paths.
+///
+/// Returns the path of the header to hand to bindgen.
+fn write_edt_header(include_paths: &[PathBuf]) -> PathBuf {
+    println!("cargo:rerun-if-env-changed=LIBDTRACE_SOURCE_DIR");
+
+    let source_dir = env::var_os("LIBDTRACE_SOURCE_DIR").map(|dir| PathBuf::from(dir).join("libdtrace"));
+    let path = source_dir
+        .iter()
+        .chain(include_paths)
+        .map(|dir| dir.join("dt_impl.h"))
+        .find(|path| path.is_file())
+        .expect("dt_impl.h not found, set LIBDTRACE_SOURCE_DIR to the sources libdtrace was built from");
+    println!("cargo:rerun-if-changed={}", path.display());
+    let header = std::fs::read_to_string(&path).expect("Couldn't read dt_impl.h");
+
+    let base = header
+        .lines()
+        .find(|line| line.split_whitespace().take(2).eq(["#define", "EDT_BASE"]))
+        .expect("EDT_BASE not found in dt_impl.h");
+    let first = header.find("EDT_VERSION").expect("EDT_VERSION not found in dt_impl.h");
+    let start = header[..first].rfind("enum").expect("The EDT_* enum not found in dt_impl.h");
+    let end = first + header[first..].find("};").expect("The EDT_* enum is not terminated in dt_impl.h") + 2;
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("edt.h");
+    std::fs::write(&out_path, format!("{}\n{}\n", base, &header[start..end])).expect("Couldn't write edt.h");
+    out_path
+}
+
+/// Locates an installed libdtrace and emits the linker flags for it.
+///
+/// `LIBDTRACE_LIB_DIR` and `LIBDTRACE_INCLUDE_DIR` take precedence over pkg-config. If neither
+/// is set and pkg-config does not know about libdtrace, the system search paths are used.
+///
+/// Returns the include paths to hand to bindgen.
+fn find_libdtrace() -> Vec<PathBuf> {
+    println!("cargo:rerun-if-env-changed=LIBDTRACE_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=LIBDTRACE_INCLUDE_DIR");
+
+    let lib_dir = env::var_os("LIBDTRACE_LIB_DIR").map(PathBuf::from);
+    let include_dir = env::var_os("LIBDTRACE_INCLUDE_DIR").map(PathBuf::from);
+
+    if let Some(lib_dir) = lib_dir {
+        println!("cargo:rustc-link-search=native={}", lib_dir.display());
+        println!("cargo:rustc-link-lib=dtrace");
+        return include_dir.into_iter().collect();
+    }
+
+    match pkg_config::Config::new().probe("libdtrace") {
+        Ok(library) => match include_dir {
+            Some(include_dir) => vec![include_dir],
+            None => library.include_paths,
+        },
+        Err(_) => {
+            println!(
+                "cargo:warning=pkg-config could not find libdtrace, falling back to the system search paths"
+            );
+            println!("cargo:rustc-link-lib=dtrace");
+            include_dir.into_iter().collect()
+        }
+    }
+}
+
+fn generate_bindings(headers: &[PathBuf], include_paths: &[PathBuf]) -> bindgen::Bindings {
+    let mut builder = bindgen::Builder::default()
         .use_core() // Use core:: instead of std::
         .derive_debug(false) // Don't derive Debug for generated types
         .prepend_enum_name(false)
@@ -43,24 +145,28 @@ fn main() {
         .allowlist_var(".*(dt_.*|(?i)dtrace).*")
         .allowlist_type(".*(dt_.*|(?i)dtrace).*")
         .allowlist_function(".*(dt_.*|(?i)dtrace).*")
-        // Include paths for dtrace
-        .clang_arg("-Itarget\\dtrace\\lib\\libctf\\common")
-        .clang_arg("-Itarget\\dtrace\\lib\\libdtrace\\common")
-        .clang_arg("-Itarget\\dtrace\\lib\\libdtrace\\compat\\win32")
-        .clang_arg("-Itarget\\dtrace\\lib\\libdtrace\\compat\\win32\\inc")
-        .generate() // Generate the bindings.
-        .expect("Unable to generate bindings");
+        // The `EDT_*` error numbers are an anonymous enum, generate them as constants
+        .allowlist_var("EDT_.*")
+        .constified_enum("EDT_.*");
 
-    // Write the bindings to the $OUT_DIR/bindings.rs file.
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-    bindings
-        .write_to_file(out_path.join("bindings.rs"))
-        .expect("Couldn't write bindings!");
+    // The input headers
+    for header in headers {
+        builder = builder.header(header.display().to_string());
+    }
+
+    // Include paths for dtrace
+    for path in include_paths {
+        builder = builder.clang_arg(format!("-I{}", path.display()));
+    }
+
+    builder
+        .generate() // Generate the bindings.
+        .expect("Unable to generate bindings")
 }
 
 fn build_dtrace() {
     Command::new("git")
-        .args(&[
+        .args([
             "clone",
             "https://github.com/microsoft/DTrace-on-Windows.git",
             "target\\dtrace",
@@ -69,7 +175,7 @@ fn build_dtrace() {
         .expect("Failed to clone dtrace");
 
     Command::new("powershell")
-        .args(&[".\\build-dtrace.ps1"])
+        .args([".\\build-dtrace.ps1"])
         .output()
         .expect("failed to get external tools");
 }
@@ -2,21 +2,16 @@ use libdtrace_rs::*;
 
 fn main() -> Result<(), utils::Error> {
     let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
-        .dtrace_setopt("bufsize", "4m")?
-        .dtrace_setopt("aggsize", "4m")?
-        .dtrace_register_handler(
-            crate::types::dtrace_handler::Buffered(Some(callbacks::buffered)),
-            None,
-        )?;
-    let prog = handle
-        .dtrace_program_strcompile(
-            "syscall:::entry { @num[execname] = count(); }",
-            dtrace_probespec::DTRACE_PROBESPEC_NAME,
-            DTRACE_C_ZDEFS,
-            None,
-        )
+        .dtrace_set_option(&options::DtraceOption::BufSize(options::Size::mib(4)))?
+        .dtrace_set_option(&options::DtraceOption::AggSize(options::Size::mib(4)))?
+        .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Box::new(|output| {
+            print!("{}", output);
+            crate::types::HandleAction::Ok
+        })))?;
+    let mut prog = d! { syscall:::entry { @num[execname] = count(); } }
+        .compile(&handle, DTRACE_C_ZDEFS)
         .unwrap();
-    handle.dtrace_program_exec(prog, None).unwrap();
+    handle.dtrace_program_exec(&mut prog, None).unwrap();
     handle.dtrace_go().unwrap();
 
     for _ in 0..10 {
@@ -26,7 +21,16 @@ fn main() -> Result<(), utils::Error> {
             .unwrap();
     }
 
-    handle.dtrace_aggregate_print(None, None).unwrap();
+    handle.dtrace_aggregate_snap().unwrap();
+    for aggregation in handle.aggregations(types::dtrace_aggwalk_order::ValSorted)? {
+        if let (
+            [record::Record::String(execname)],
+            aggregation::AggregationValue::Count(count),
+        ) = (&aggregation.keys[..], &aggregation.value)
+        {
+            println!("{:<32}{}", execname, count);
+        }
+    }
     handle.dtrace_stop().unwrap();
 
     Ok(())
@@ -4,11 +4,11 @@ fn main() -> Result<(), utils::Error> {
     let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
         .dtrace_setopt("bufsize", "4m")?
         .dtrace_setopt("aggsize", "4m")?
-        .dtrace_register_handler(
-            crate::types::dtrace_handler::Buffered(Some(callbacks::buffered)),
-            None,
-        )?;
-    let prog = handle
+        .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Box::new(|output| {
+            print!("{}", output);
+            crate::types::HandleAction::Ok
+        })))?;
+    let mut prog = handle
         .dtrace_program_strcompile(
             "BEGIN {trace(\"Hello World\");}",
             dtrace_probespec::DTRACE_PROBESPEC_NAME,
@@ -16,16 +16,13 @@ fn main() -> Result<(), utils::Error> {
             None,
         )
         .unwrap();
-    handle.dtrace_program_exec(prog, None).unwrap();
+    handle.dtrace_program_exec(&mut prog, None).unwrap();
     handle.dtrace_go().unwrap();
 
-    match handle.dtrace_status().unwrap() {
-        types::dtrace_status::Ok => {
-            handle
-                .dtrace_consume(None, Some(callbacks::chew), Some(callbacks::chew_rec), None)
-                .unwrap();
-        }
-        _ => {}
+    if let types::dtrace_status::Ok = handle.dtrace_status().unwrap() {
+        handle
+            .dtrace_consume(None, Some(callbacks::chew), Some(callbacks::chew_rec), None)
+            .unwrap();
     }
 
     handle.dtrace_aggregate_print(None, None).unwrap();
@@ -1,26 +1,16 @@
+use libdtrace_rs::dlang::builder::{execname, pid, printf, probe, probefunc, self_pid, timestamp, Program};
+use libdtrace_rs::record::Record;
+use libdtrace_rs::types::{ConsumeAction, HandleAction};
 use libdtrace_rs::*;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
-static PROGRAM: &str = r#"
-    syscall:::entry
-    /pid != $pid/
-    {
-        printf("timestamp=%llu syscall_name=%s pid=%d process_name=%s \n", timestamp, probefunc, pid, execname);
-    }
-"#;
-
-pub unsafe extern "C" fn buffered(
-    bufdata: *const crate::dtrace_bufdata_t,
-    arg: *mut ::core::ffi::c_void,
-) -> ::core::ffi::c_int {
-    let tx = &*(arg as *mut ::std::sync::mpsc::Sender<::std::string::String>);
-    let msg = ::core::ffi::CStr::from_ptr((*bufdata).dtbda_buffered)
-        .to_str()
-        .expect("Failed to convert buffer to string");
-    tx.send(msg.to_string()).unwrap();
 
-    crate::DTRACE_HANDLE_OK as ::core::ffi::c_int
+fn program() -> Program {
+    Program::new().clause(probe("syscall:::entry").predicate(pid().ne(self_pid())).action(printf(
+        "timestamp=%llu syscall_name=%s pid=%d process_name=%s \n",
+        [timestamp(), probefunc(), pid(), execname()],
+    )))
 }
 
 fn main() {
@@ -30,26 +20,31 @@ fn main() {
         let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
             .dtrace_setopt("bufsize", "4m")?
             .dtrace_setopt("aggsize", "4m")?
-            .dtrace_register_handler(
-                crate::types::dtrace_handler::Buffered(Some(buffered)),
-                Some(&tx as *const _ as *mut _),
-            )?;
-        let prog = handle
-            .dtrace_program_strcompile(
-                PROGRAM,
-                dtrace_probespec::DTRACE_PROBESPEC_NAME,
-                DTRACE_C_ZDEFS,
-                None,
-            )
-            .unwrap();
-        handle.dtrace_program_exec(prog, None).unwrap();
+            .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Box::new(move |output| {
+                match tx.send(output.to_string()) {
+                    Ok(()) => HandleAction::Ok,
+                    Err(_) => HandleAction::Abort,
+                }
+            })))?;
+        let mut prog = program().compile(&handle, DTRACE_C_ZDEFS, None).unwrap();
+        handle.dtrace_program_exec(&mut prog, None).unwrap();
         handle.dtrace_go().unwrap();
         println!("Waiting for data...");
         loop {
             handle.dtrace_sleep(); // Wait until new data is available
-            handle
-                .dtrace_work(None, Some(callbacks::chew), Some(callbacks::chew_rec), None)
+            let status = handle
+                .dtrace_work_with(
+                    None,
+                    |_| ConsumeAction::This,
+                    |_, record| match record {
+                        Some(Record::Exit(_)) | None => ConsumeAction::Next,
+                        Some(_) => ConsumeAction::This,
+                    },
+                )
                 .unwrap_or(dtrace_workstatus_t::DTRACE_WORKSTATUS_OKAY);
+            if status == dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE {
+                break;
+            }
         }
         handle.dtrace_stop().unwrap();
 
@@ -18,36 +18,25 @@ syscall::NtCreateUserProcess:entry
 "#;
 
 fn main() -> Result<(), utils::Error> {
-    let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?;
-    handle.dtrace_setopt("bufsize", "4m")?;
-    handle.dtrace_setopt("aggsize", "4m")?;
-    handle.dtrace_setopt("sympath", "C:/symbols")?;
-    handle.dtrace_register_handler(
-        crate::types::dtrace_handler::Buffered(Some(callbacks::buffered)),
-        None,
-    )?;
-    let prog = handle.dtrace_program_strcompile(
+    let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
+        .dtrace_setopt("bufsize", "4m")?
+        .dtrace_setopt("aggsize", "4m")?
+        .dtrace_setopt("sympath", "C:/symbols")?;
+    let mut prog = handle.dtrace_program_strcompile(
         PROGRAM,
         dtrace_probespec::DTRACE_PROBESPEC_NAME,
         DTRACE_C_ZDEFS,
         None,
     )?;
-    handle.dtrace_program_exec(prog, None)?;
-    handle.dtrace_go()?;
+    handle.dtrace_program_exec(&mut prog, None)?;
 
-    loop {
-        handle.dtrace_sleep();
-        match handle.dtrace_work(
-            None,
-            Some(libdtrace_rs::callbacks::chew),
-            Some(libdtrace_rs::callbacks::chew_rec),
-            None,
-        ) {
-            Ok(libdtrace_rs::dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE) => break,
-            Ok(_) | Err(_) => (),
+    let mut consumer = consumer::Consumer::new(handle)?;
+    let reason = consumer.run(|event| {
+        if let event::Event::Buffered(output) = event {
+            print!("{}", output);
         }
-    }
+    })?;
+    println!("Tracing stopped: {:?}", reason);
 
-    handle.dtrace_stop()?;
     Ok(())
 }
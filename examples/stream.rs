@@ -0,0 +1,38 @@
+use futures::StreamExt;
+use libdtrace_rs::event::Event;
+use libdtrace_rs::*;
+
+static PROGRAM: &str = r#"
+    syscall:::entry
+    /pid != $pid/
+    {
+        printf("timestamp=%llu syscall_name=%s pid=%d process_name=%s \n", timestamp, probefunc, pid, execname);
+    }
+"#;
+
+fn main() -> Result<(), utils::Error> {
+    let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
+        .dtrace_setopt("bufsize", "4m")?
+        .dtrace_setopt("aggsize", "4m")?;
+    let mut prog = handle.dtrace_program_strcompile(
+        PROGRAM,
+        dtrace_probespec::DTRACE_PROBESPEC_NAME,
+        DTRACE_C_ZDEFS,
+        None,
+    )?;
+    handle.dtrace_program_exec(&mut prog, None)?;
+
+    let mut events = handle.into_stream()?;
+    println!("Waiting for data...");
+    futures::executor::block_on(async {
+        while let Some(event) = events.next().await {
+            match event? {
+                Event::Buffered(output) => print!("Recieved: {}", output),
+                Event::Drop(drop) => eprintln!("{}", drop.message),
+                Event::Fault(fault) => eprintln!("{}", fault.message),
+                Event::Probe(_) | Event::Status(_) => {}
+            }
+        }
+        Ok(())
+    })
+}
@@ -1,5 +1,10 @@
 use libdtrace_rs::*;
 
+/// Prints the CPU, ID and function of each probe that fired.
+///
+/// # Safety
+///
+/// `data` must point to a valid `dtrace_probedata_t`, as passed by `dtrace_consume`.
 pub unsafe extern "C" fn custom_callback(
     data: *const dtrace_probedata_t,
     _arg: *mut ::core::ffi::c_void,
@@ -28,11 +33,11 @@ pub unsafe extern "C" fn custom_callback(
 fn main() -> Result<(), utils::Error> {
     let handle = wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?
         .dtrace_setopt("bufsize", "4m")?
-        .dtrace_register_handler(
-            crate::types::dtrace_handler::Buffered(Some(callbacks::buffered)),
-            None,
-        )?;
-    let prog = handle
+        .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Box::new(|output| {
+            print!("{}", output);
+            crate::types::HandleAction::Ok
+        })))?;
+    let mut prog = handle
         .dtrace_program_strcompile(
             "syscall",
             dtrace_probespec::DTRACE_PROBESPEC_PROVIDER,
@@ -40,18 +45,13 @@ fn main() -> Result<(), utils::Error> {
             None,
         )
         .unwrap();
-    handle.dtrace_program_exec(prog, None).unwrap();
+    handle.dtrace_program_exec(&mut prog, None).unwrap();
     handle.dtrace_go().unwrap();
 
-    loop {
-        match handle.dtrace_status().unwrap() {
-            types::dtrace_status::Ok => {
-                handle
-                    .dtrace_consume(None, Some(custom_callback), Some(callbacks::chew_rec), None)
-                    .unwrap();
-            }
-            _ => break,
-        }
+    while let types::dtrace_status::Ok = handle.dtrace_status().unwrap() {
+        handle
+            .dtrace_consume(None, Some(custom_callback), Some(callbacks::chew_rec), None)
+            .unwrap();
     }
 
     handle.dtrace_stop().unwrap();
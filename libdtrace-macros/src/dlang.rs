@@ -0,0 +1,22 @@
+#[path = "../../src/dlang/ast.rs"]
+pub mod ast;
+#[path = "../../src/dlang/lexer.rs"]
+pub mod lexer;
+#[path = "../../src/dlang/parser.rs"]
+pub mod parser;
+
+/// A syntax error, as `libdtrace_rs::dlang::ParseError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: ast::Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: ast::Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Procedural macros of `libdtrace-rs`, use them through the re-exports of `libdtrace_rs`.
+
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// The parser of `libdtrace_rs::dlang`, shared with the library so both accept the same D.
+#[allow(dead_code)]
+mod dlang;
+
+/// Checks the syntax of an embedded D program at compile time and expands to a `libdtrace_rs::dlang::Embedded`.
+///
+/// The program is written either as D tokens or as a single string literal, which also allows comments and
+/// `` ` `` symbols. `$(expr)` and `$$(expr)` interpolate Rust values, they are passed as the macro arguments
+/// `$1`, `$2`, ... so `$$(expr)` always is a single string constant, whatever the value contains. A program that
+/// interpolates values cannot refer to macro arguments by number as well, `$1` would be the first interpolated value.
+///
+/// A program with C preprocessor directives such as `#define` is checked as `dtrace -C` reads it, and compiled with
+/// `DTRACE_C_CPP`. Its macros are not expanded, so a macro that does not expand to a whole expression or statement
+/// is reported as a syntax error.
+///
+/// ```no_run
+/// use libdtrace_rs::{d, DTRACE_C_ZDEFS};
+/// # let handle = libdtrace_rs::wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?;
+/// let name = "sshd";
+/// let program = d! {
+///     syscall::read:entry
+///     /execname == $$(name)/
+///     {
+///         @bytes[probefunc] = sum(arg2);
+///     }
+/// };
+/// assert!(program.source().contains("/execname == $$1/"));
+/// let mut prog = program.compile(&handle, DTRACE_C_ZDEFS)?;
+/// handle.dtrace_program_exec(&mut prog, None)?;
+/// # Ok::<(), libdtrace_rs::utils::Error>(())
+/// ```
+#[proc_macro]
+pub fn d(input: TokenStream) -> TokenStream {
+    let tokens: Vec<_> = input.clone().into_iter().collect();
+    if let [TokenTree::Literal(literal)] = &tokens[..] {
+        return match unquote(&literal.to_string()) {
+            Some(source) => match parse(&source) {
+                Ok(cpp) => expand(&source, cpp, Vec::new()),
+                Err(error) => {
+                    let (line, column) = error.span.line_col(&source);
+                    compile_error(&format!("{} at line {}, column {}", error.message, line, column), literal.span())
+                }
+            },
+            None => compile_error("expected a string literal or D tokens", literal.span()),
+        };
+    }
+    if tokens.is_empty() {
+        return compile_error("expected a D program", Span::call_site());
+    }
+
+    let mut source = Source::default();
+    source.push_stream(input);
+    if let Err((message, span)) = source.error {
+        return compile_error(&message, span);
+    }
+    if let (Some(span), false) = (source.numbered, source.args.is_empty()) {
+        let message = "`$n` macro arguments cannot be mixed with `$(expr)`, which is numbered `$1`, `$2`, ...";
+        return compile_error(message, span);
+    }
+    match parse(&source.text) {
+        Ok(cpp) => expand(&source.text, cpp, source.args),
+        Err(error) => {
+            let span = source
+                .tokens
+                .iter()
+                .find(|(range, _)| error.span.start < range.end)
+                .or(source.tokens.last())
+                .map(|(_, span)| *span);
+            compile_error(&error.message, span.unwrap_or_else(Span::call_site))
+        }
+    }
+}
+
+/// Parses a program, returning whether it needs the C preprocessor, i.e. only parses with its directives.
+fn parse(source: &str) -> Result<bool, dlang::ParseError> {
+    match dlang::parser::Parser::new(source).parse() {
+        Ok(_) => Ok(false),
+        Err(_) => dlang::parser::Parser::new(source).cpp(true).parse().map(|_| true),
+    }
+}
+
+/// The D source rebuilt from the tokens of the macro input.
+struct Source {
+    text: String,
+    /// The source range of each token and its span in the macro input, to report errors on the right token.
+    tokens: Vec<(std::ops::Range<usize>, Span)>,
+    /// The interpolated Rust expressions, `$1` first.
+    args: Vec<TokenStream>,
+    /// The first macro argument referred to by number, e.g. `$1` or `$$2`.
+    numbered: Option<Span>,
+    /// The line and column of the end of the last token.
+    end: Option<(usize, usize)>,
+    error: Result<(), (String, Span)>,
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            tokens: Vec::new(),
+            args: Vec::new(),
+            numbered: None,
+            end: None,
+            error: Ok(()),
+        }
+    }
+}
+
+impl Source {
+    /// Appends `text` at the position of `span`, keeping the line breaks and the spacing of the input.
+    fn push(&mut self, text: &str, span: Span) {
+        self.push_between(text, span, span)
+    }
+
+    /// Appends `text` in place of the tokens from `first` to `last`, errors in `text` are reported at `last`.
+    fn push_between(&mut self, text: &str, first: Span, last: Span) {
+        let (line, column) = (first.line(), first.column());
+        match self.end {
+            Some((end_line, _)) if line > end_line => {
+                self.text.push_str(&"\n".repeat(line - end_line));
+                self.text.push_str(&" ".repeat(column.saturating_sub(1)));
+            }
+            Some((end_line, end_column)) if line == end_line && column >= end_column => {
+                self.text.push_str(&" ".repeat(column - end_column));
+            }
+            Some(_) => self.text.push(' '),
+            None => {}
+        }
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.tokens.push((start..self.text.len(), last));
+        let end = last.end();
+        self.end = Some((end.line(), end.column()));
+    }
+
+    fn push_stream(&mut self, stream: TokenStream) {
+        let mut tokens = stream.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match token {
+                TokenTree::Punct(punct) if punct.as_char() == '$' => {
+                    // `$(expr)` or `$$(expr)`, other `$` tokens are D macro variables such as `$pid` or `$1`
+                    let quoted = matches!(tokens.peek(), Some(TokenTree::Punct(next)) if next.as_char() == '$');
+                    let mut lookahead = tokens.clone();
+                    if quoted {
+                        lookahead.next();
+                    }
+                    match lookahead.next() {
+                        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+                            tokens = lookahead;
+                            if group.stream().is_empty() {
+                                self.fail("expected a Rust expression to interpolate", group.span());
+                            }
+                            self.args.push(group.stream());
+                            let prefix = if quoted { "$$" } else { "$" };
+                            self.push_between(&format!("{}{}", prefix, self.args.len()), punct.span(), group.span());
+                        }
+                        _ => {
+                            let number = matches!(tokens.peek(), Some(TokenTree::Literal(literal))
+                                if literal.to_string().bytes().all(|b| b.is_ascii_digit()));
+                            if number && self.numbered.is_none() {
+                                self.numbered = Some(punct.span());
+                            }
+                            self.push("$", punct.span())
+                        }
+                    }
+                }
+                TokenTree::Punct(punct) if punct.as_char() == '#' && is_doc(tokens.clone()) => {
+                    // A doc comment, e.g. `/// ...`, is a comment in D as well
+                    if matches!(tokens.peek(), Some(TokenTree::Punct(next)) if next.as_char() == '!') {
+                        tokens.next();
+                    }
+                    tokens.next();
+                }
+                TokenTree::Punct(punct) => self.push(&punct.as_char().to_string(), punct.span()),
+                TokenTree::Ident(ident) => self.push(&ident.to_string(), ident.span()),
+                TokenTree::Literal(literal) => {
+                    let text = literal.to_string();
+                    if text.starts_with('r') || text.starts_with('b') || text.starts_with('c') {
+                        self.fail("raw, byte and C string literals are not D", literal.span());
+                    }
+                    self.push(&text, literal.span())
+                }
+                TokenTree::Group(group) => {
+                    let (open, close) = match group.delimiter() {
+                        Delimiter::Parenthesis => ("(", ")"),
+                        Delimiter::Brace => ("{", "}"),
+                        Delimiter::Bracket => ("[", "]"),
+                        Delimiter::None => ("", ""),
+                    };
+                    if !open.is_empty() {
+                        self.push(open, group.span_open());
+                    }
+                    self.push_stream(group.stream());
+                    if !close.is_empty() {
+                        self.push(close, group.span_close());
+                    }
+                }
+            }
+        }
+    }
+
+    fn fail(&mut self, message: &str, span: Span) {
+        if self.error.is_ok() {
+            self.error = Err((message.to_string(), span));
+        }
+    }
+}
+
+/// Whether the tokens after a `#` are the `[doc = "..."]` or `![doc = "..."]` of a doc comment.
+fn is_doc(mut tokens: impl Iterator<Item = TokenTree>) -> bool {
+    let group = match tokens.next() {
+        Some(TokenTree::Punct(punct)) if punct.as_char() == '!' => tokens.next(),
+        token => token,
+    };
+    match group {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => {
+            matches!(group.stream().into_iter().next(), Some(TokenTree::Ident(ident)) if ident.to_string() == "doc")
+        }
+        _ => false,
+    }
+}
+
+/// `::libdtrace_rs::dlang::Embedded::new(source, cpp, MacroArgs::new().arg(MacroArg::from(expr))...)`
+fn expand(source: &str, cpp: bool, args: Vec<TokenStream>) -> TokenStream {
+    let mut macro_args: TokenStream = "::libdtrace_rs::args::MacroArgs::new()".parse().unwrap();
+    for arg in args {
+        let span = arg.clone().into_iter().next().map_or_else(Span::call_site, |token| token.span());
+        let mut from: TokenStream = "::libdtrace_rs::args::MacroArg::from".parse().unwrap();
+        from.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, arg))]);
+        macro_args.extend([
+            TokenTree::Punct(Punct::new('.', Spacing::Alone)),
+            TokenTree::Ident(Ident::new("arg", span)),
+            TokenTree::Group(Group::new(Delimiter::Parenthesis, from)),
+        ]);
+    }
+
+    let mut params = TokenStream::from(TokenTree::Literal(Literal::string(source)));
+    params.extend([
+        TokenTree::Punct(Punct::new(',', Spacing::Alone)),
+        TokenTree::Ident(Ident::new(if cpp { "true" } else { "false" }, Span::call_site())),
+        TokenTree::Punct(Punct::new(',', Spacing::Alone)),
+    ]);
+    params.extend(macro_args);
+    let mut expanded: TokenStream = "::libdtrace_rs::dlang::Embedded::new".parse().unwrap();
+    expanded.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, params))]);
+    expanded
+}
+
+/// `compile_error!(message)`, reported at `span`.
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut group = Group::new(Delimiter::Parenthesis, TokenStream::from(TokenTree::Literal(message)));
+    group.set_span(span);
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(group),
+    ])
+}
+
+/// The value of a Rust string literal, `None` if `literal` is not one.
+fn unquote(literal: &str) -> Option<String> {
+    if let Some(raw) = literal.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let end = raw.len().checked_sub(hashes + 1)?;
+        return raw.get(hashes + 1..end).map(str::to_string);
+    }
+
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::new();
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            't' => value.push('\t'),
+            'r' => value.push('\r'),
+            '0' => value.push('\0'),
+            c @ ('\\' | '"' | '\'') => value.push(c),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                value.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            }
+            'u' => {
+                let hex: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            '\n' => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            }
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquote_literals() {
+        assert_eq!(unquote(r#""BEGIN { trace(\"a\\n\"); }""#).as_deref(), Some("BEGIN { trace(\"a\\n\"); }"));
+        assert_eq!(unquote(r#""tab\there\x41\u{e9}""#).as_deref(), Some("tab\there\u{41}\u{e9}"));
+        assert_eq!(unquote("\"one \\\n    two\"").as_deref(), Some("one two"));
+        assert_eq!(unquote("r#\"a \"quoted\" `max_ncpus\"#").as_deref(), Some("a \"quoted\" `max_ncpus"));
+        assert_eq!(unquote("42"), None);
+        assert_eq!(unquote("'a'"), None);
+    }
+
+    #[test]
+    fn shared_parser() {
+        assert!(dlang::parser::Parser::new("BEGIN { trace(`max_ncpus); }").parse().is_ok());
+        let error = dlang::parser::Parser::new("BEGIN { trace(1 +); }").parse().unwrap_err();
+        assert_eq!(error.message, "expected an expression, found `)`");
+    }
+
+    #[test]
+    fn parse_with_cpp_when_needed() {
+        assert_eq!(parse("BEGIN { exit(0); }"), Ok(false));
+        assert_eq!(parse("#pragma D option quiet\n#line 1\nBEGIN { exit(0); }"), Ok(false));
+        assert_eq!(parse("#define LIMIT 10\nBEGIN { trace(LIMIT); }"), Ok(true));
+        let error = parse("#define LIMIT 10\nBEGIN { trace(LIMIT +); }").unwrap_err();
+        assert_eq!(error.message, "expected an expression, found `)`");
+    }
+}
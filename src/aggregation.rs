@@ -0,0 +1,354 @@
+use crate::data::{AggregateData, RecordDesc};
+use crate::record::{checked_bytes, record_bytes, words, Record};
+
+/// A bucket of a `quantize()`, `lquantize()` or `llquantize()` distribution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    /// The smallest value counted in this bucket, [`i64::MIN`] for the underflow bucket.
+    pub lower: i64,
+    /// The number of values counted in this bucket.
+    pub count: i64,
+}
+
+/// The value of an aggregation, decoded according to the aggregating function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregationValue {
+    /// `count()`
+    Count(u64),
+    /// `sum()`
+    Sum(i64),
+    /// `min()`
+    Min(i64),
+    /// `max()`
+    Max(i64),
+    /// `avg()`, the number and the sum of the values.
+    Avg { count: u64, total: i64 },
+    /// `stddev()`, the number, the sum and the sum of the squares of the values.
+    Stddev {
+        count: u64,
+        total: i64,
+        total_squares: u128,
+    },
+    /// `quantize()`, the power-of-two buckets from the most negative to the most positive.
+    Quantize(Vec<Bucket>),
+    /// `lquantize()`, the underflow bucket, `levels` buckets of width `step` starting at `base`, and the overflow
+    /// bucket.
+    Lquantize {
+        base: i32,
+        step: u16,
+        levels: u16,
+        buckets: Vec<Bucket>,
+    },
+    /// `llquantize()`, the underflow bucket, `steps` buckets per power of `factor` from `factor^low` to
+    /// `factor^(high + 1)`, and the overflow bucket.
+    Llquantize {
+        factor: u16,
+        low: u16,
+        high: u16,
+        steps: u16,
+        buckets: Vec<Bucket>,
+    },
+    /// Any other aggregating function, with its raw data.
+    Other { action: u16, data: Vec<u8> },
+}
+
+/// The number of buckets of a `quantize()` aggregation, `DTRACE_QUANTIZE_NBUCKETS`.
+const QUANTIZE_NBUCKETS: usize = 127;
+/// The bucket of a `quantize()` aggregation counting zero, `DTRACE_QUANTIZE_ZEROBUCKET`.
+const QUANTIZE_ZEROBUCKET: usize = 63;
+
+impl AggregationValue {
+    /// Decodes the value record of an aggregation.
+    pub fn decode(desc: &RecordDesc, bytes: &[u8]) -> Self {
+        let mut values = words(bytes);
+        let mut next = || values.next().unwrap_or(0);
+
+        match desc.action as u32 {
+            crate::DTRACEAGG_COUNT => AggregationValue::Count(next()),
+            crate::DTRACEAGG_SUM => AggregationValue::Sum(next() as i64),
+            crate::DTRACEAGG_MIN => AggregationValue::Min(next() as i64),
+            crate::DTRACEAGG_MAX => AggregationValue::Max(next() as i64),
+            crate::DTRACEAGG_AVG => AggregationValue::Avg {
+                count: next(),
+                total: next() as i64,
+            },
+            crate::DTRACEAGG_STDDEV => AggregationValue::Stddev {
+                count: next(),
+                total: next() as i64,
+                total_squares: next() as u128 | (next() as u128) << 64,
+            },
+            crate::DTRACEAGG_QUANTIZE => {
+                let lower = (0..QUANTIZE_NBUCKETS).map(|bucket| match bucket {
+                    bucket if bucket < QUANTIZE_ZEROBUCKET => -(1i64 << (QUANTIZE_ZEROBUCKET - 1 - bucket)),
+                    QUANTIZE_ZEROBUCKET => 0,
+                    bucket => 1i64 << (bucket - QUANTIZE_ZEROBUCKET - 1),
+                });
+                AggregationValue::Quantize(buckets(lower, words(bytes)))
+            }
+            crate::DTRACEAGG_LQUANTIZE => {
+                let arg = next();
+                let step = (arg >> 48) as u16;
+                let levels = (arg >> 32) as u16;
+                let base = arg as i32;
+                let lower = std::iter::once(i64::MIN)
+                    .chain((0..=levels as i64).map(|level| base as i64 + level * step as i64));
+                AggregationValue::Lquantize {
+                    base,
+                    step,
+                    levels,
+                    buckets: buckets(lower, words(bytes).skip(1)),
+                }
+            }
+            crate::DTRACEAGG_LLQUANTIZE => {
+                let arg = next();
+                let factor = (arg >> 48) as u16;
+                let low = (arg >> 32) as u16;
+                let high = (arg >> 16) as u16;
+                let steps = arg as u16;
+                AggregationValue::Llquantize {
+                    factor,
+                    low,
+                    high,
+                    steps,
+                    buckets: buckets(llquantize_lower(factor, low, high, steps), words(bytes).skip(1)),
+                }
+            }
+            _ => AggregationValue::Other {
+                action: desc.action,
+                data: bytes.to_vec(),
+            },
+        }
+    }
+
+    /// The average of an `avg()` or `stddev()` aggregation.
+    pub fn average(&self) -> Option<f64> {
+        match *self {
+            AggregationValue::Avg { count, total } | AggregationValue::Stddev { count, total, .. } if count > 0 => {
+                Some(total as f64 / count as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// The standard deviation of a `stddev()` aggregation.
+    pub fn stddev(&self) -> Option<f64> {
+        match *self {
+            AggregationValue::Stddev {
+                count,
+                total,
+                total_squares,
+            } if count > 0 => {
+                let average = total as f64 / count as f64;
+                let variance = total_squares as f64 / count as f64 - average * average;
+                Some(variance.max(0.0).sqrt())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Pairs the lower bounds of the buckets with their counts.
+fn buckets(lower: impl Iterator<Item = i64>, counts: impl Iterator<Item = u64>) -> Vec<Bucket> {
+    lower
+        .zip(counts)
+        .map(|(lower, count)| Bucket {
+            lower,
+            count: count as i64,
+        })
+        .collect()
+}
+
+/// The lower bounds of the buckets of an `llquantize()` aggregation, following
+/// `dtrace_aggregate_llquantize_bucket()`.
+fn llquantize_lower(factor: u16, low: u16, high: u16, steps: u16) -> impl Iterator<Item = i64> {
+    let (factor, steps) = (factor.max(2) as i64, steps.max(1) as i64);
+    let mut lower = vec![i64::MIN];
+
+    let mut last = (0..low).try_fold(1i64, |value, _| value.checked_mul(factor));
+    let mut this = last.and_then(|last| last.checked_mul(factor));
+    for _ in low..=high {
+        let (Some(from), Some(to)) = (last, this) else {
+            break;
+        };
+        let nbuckets = to.min(steps);
+        let width = to / nbuckets;
+        lower.extend((0..nbuckets - nbuckets / factor).map(|bucket| from + bucket * width));
+        last = this;
+        this = to.checked_mul(factor);
+    }
+    lower.extend(last);
+
+    lower.into_iter()
+}
+
+/// A single entry of an aggregation, e.g. `@num[execname] = count()` for one `execname`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregation {
+    /// The aggregation ID.
+    pub id: u32,
+    /// The ID of the aggregation variable, shared by all aggregations assigned to the same `@name`.
+    pub variable_id: i64,
+    /// The name of the aggregation variable without the leading `@`, empty for the anonymous `@` aggregation.
+    pub name: String,
+    /// The keys of the aggregation, empty for an aggregation without keys.
+    pub keys: Vec<Record>,
+    /// The aggregated value.
+    pub value: AggregationValue,
+}
+
+impl Aggregation {
+    /// Decodes the data handed to an aggregation walk handler.
+    ///
+    /// The first record holds the aggregation variable ID and the last record the value, the records in between are
+    /// the keys.
+    pub fn from_data(data: &AggregateData) -> Self {
+        let records: Vec<RecordDesc> = data.records().collect();
+        let keys = match records.len() {
+            0..=2 => &[][..],
+            len => &records[1..len - 1],
+        };
+        let value = match records.last() {
+            Some(desc) if records.len() > 1 => AggregationValue::decode(desc, record_bytes(data.data(), desc)),
+            _ => AggregationValue::Other {
+                action: crate::DTRACEACT_NONE as u16,
+                data: Vec::new(),
+            },
+        };
+
+        Self {
+            id: data.id(),
+            variable_id: data.variable_id(),
+            name: data.name().to_string(),
+            keys: keys
+                .iter()
+                .map(|desc| match checked_bytes(data.data(), desc) {
+                    Some(bytes) => Record::from_desc(desc, bytes),
+                    None => Record::Invalid(*desc),
+                })
+                .collect(),
+            value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockAggregate;
+    use crate::record::Value;
+
+    fn words(values: &[i64]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn decode_count_with_keys() {
+        let mock = MockAggregate::new(3, 1, "num")
+            .record(crate::DTRACEACT_DIFEXPR, 8, &1i64.to_ne_bytes())
+            .record(crate::DTRACEACT_DIFEXPR, 1, b"bash\0\0\0\0\0\0\0\0\0\0\0\0")
+            .record(crate::DTRACEACT_DIFEXPR, 4, &7u32.to_ne_bytes())
+            .record(crate::DTRACEAGG_COUNT, 8, &42u64.to_ne_bytes());
+        let aggregation = Aggregation::from_data(&mock.as_aggregate_data());
+
+        assert_eq!(aggregation.name, "num");
+        assert_eq!(aggregation.id, 3);
+        assert_eq!(
+            aggregation.keys,
+            vec![
+                Record::String("bash".to_string()),
+                Record::Trace(Value::Integer { value: 7, size: 4 })
+            ]
+        );
+        assert_eq!(aggregation.value, AggregationValue::Count(42));
+    }
+
+    #[test]
+    fn decode_scalar_values() {
+        let desc = |action: u32| RecordDesc {
+            action: action as u16,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            AggregationValue::decode(&desc(crate::DTRACEAGG_SUM), &words(&[-5])),
+            AggregationValue::Sum(-5)
+        );
+        let avg = AggregationValue::decode(&desc(crate::DTRACEAGG_AVG), &words(&[4, 10]));
+        assert_eq!(avg, AggregationValue::Avg { count: 4, total: 10 });
+        assert_eq!(avg.average(), Some(2.5));
+
+        // Values 2, 4, 4, 4, 5, 5, 7, 9 have a standard deviation of 2
+        let stddev = AggregationValue::decode(&desc(crate::DTRACEAGG_STDDEV), &words(&[8, 40, 232, 0]));
+        assert_eq!(stddev.average(), Some(5.0));
+        assert_eq!(stddev.stddev(), Some(2.0));
+    }
+
+    #[test]
+    fn decode_quantize() {
+        let mut counts = vec![0; QUANTIZE_NBUCKETS];
+        counts[QUANTIZE_ZEROBUCKET - 1] = 1; // -1
+        counts[QUANTIZE_ZEROBUCKET] = 2; // 0
+        counts[QUANTIZE_ZEROBUCKET + 4] = 3; // 8..15
+        let desc = RecordDesc {
+            action: crate::DTRACEAGG_QUANTIZE as u16,
+            ..Default::default()
+        };
+
+        let AggregationValue::Quantize(buckets) = AggregationValue::decode(&desc, &words(&counts)) else {
+            panic!("not a quantize() value");
+        };
+        assert_eq!(buckets.len(), QUANTIZE_NBUCKETS);
+        assert_eq!(buckets[0].lower, i64::MIN / 2);
+        let used: Vec<_> = buckets.iter().filter(|bucket| bucket.count != 0).copied().collect();
+        assert_eq!(
+            used,
+            vec![
+                Bucket { lower: -1, count: 1 },
+                Bucket { lower: 0, count: 2 },
+                Bucket { lower: 8, count: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_lquantize() {
+        // lquantize(x, 0, 30, 10)
+        let arg = (10i64 << 48) | (3 << 32);
+        let desc = RecordDesc {
+            action: crate::DTRACEAGG_LQUANTIZE as u16,
+            ..Default::default()
+        };
+
+        let value = AggregationValue::decode(&desc, &words(&[arg, 1, 2, 3, 4, 5]));
+        let AggregationValue::Lquantize { base, step, levels, buckets } = value else {
+            panic!("not an lquantize() value");
+        };
+        assert_eq!((base, step, levels), (0, 10, 3));
+        let lower: Vec<_> = buckets.iter().map(|bucket| bucket.lower).collect();
+        assert_eq!(lower, vec![i64::MIN, 0, 10, 20, 30]);
+        assert_eq!(buckets[4].count, 5);
+    }
+
+    #[test]
+    fn decode_llquantize() {
+        // llquantize(x, 10, 0, 2, 10)
+        let arg = (10i64 << 48) | (2 << 16) | 10;
+        let desc = RecordDesc {
+            action: crate::DTRACEAGG_LLQUANTIZE as u16,
+            ..Default::default()
+        };
+        let mut data = vec![arg];
+        data.extend(1..=29);
+
+        let value = AggregationValue::decode(&desc, &words(&data));
+        let AggregationValue::Llquantize { buckets, .. } = value else {
+            panic!("not an llquantize() value");
+        };
+        let lower: Vec<_> = buckets.iter().map(|bucket| bucket.lower).collect();
+        let mut expected = vec![i64::MIN];
+        expected.extend((1..10).chain((10..100).step_by(10)).chain((100..1000).step_by(100)));
+        expected.push(1000);
+        assert_eq!(lower, expected);
+        assert_eq!(buckets.last().unwrap().count, 29);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::utils::Error;
+use ::core::ffi::{c_char, c_int};
+use std::ffi::CString;
+
+/// A single macro argument of a D program, referenced as `$1`, `$2`, ... or `$$1`, `$$2`, ... in the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MacroArg {
+    /// A string, usually referenced as `$$n` to be used as a string literal.
+    String(String),
+    /// A signed integer.
+    Int(i64),
+    /// An unsigned integer.
+    UInt(u64),
+    /// A process ID, e.g. for use in a `/pid == $1/` predicate or a `pid$1:::` probe description.
+    Pid(u32),
+}
+
+impl std::fmt::Display for MacroArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MacroArg::String(value) => write!(f, "{}", value),
+            MacroArg::Int(value) => write!(f, "{}", value),
+            MacroArg::UInt(value) => write!(f, "{}", value),
+            MacroArg::Pid(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<&str> for MacroArg {
+    fn from(value: &str) -> Self {
+        MacroArg::String(value.to_string())
+    }
+}
+
+impl From<String> for MacroArg {
+    fn from(value: String) -> Self {
+        MacroArg::String(value)
+    }
+}
+
+impl From<&String> for MacroArg {
+    fn from(value: &String) -> Self {
+        MacroArg::String(value.clone())
+    }
+}
+
+impl From<i64> for MacroArg {
+    fn from(value: i64) -> Self {
+        MacroArg::Int(value)
+    }
+}
+
+impl From<i32> for MacroArg {
+    fn from(value: i32) -> Self {
+        MacroArg::Int(value as i64)
+    }
+}
+
+impl From<u64> for MacroArg {
+    fn from(value: u64) -> Self {
+        MacroArg::UInt(value)
+    }
+}
+
+impl From<u32> for MacroArg {
+    fn from(value: u32) -> Self {
+        MacroArg::UInt(value as u64)
+    }
+}
+
+/// The macro arguments of a D program, passed as `argv` to `dtrace_program_strcompile`.
+///
+/// `$0` is the name set with [`MacroArgs::name`], `dtrace` by default. The arguments are numbered from `$1` in the
+/// order they are added.
+///
+/// ```
+/// use libdtrace_rs::args::MacroArgs;
+///
+/// let args = MacroArgs::new().pid(1234).string("open");
+/// assert_eq!(args.to_strings(), ["dtrace", "1234", "open"]);
+/// ```
+///
+/// ```no_run
+/// # use libdtrace_rs::args::MacroArgs;
+/// # use libdtrace_rs::dtrace_probespec;
+/// # let handle = libdtrace_rs::wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?;
+/// let args = MacroArgs::new().pid(1234).string("open");
+/// handle.dtrace_program_strcompile(
+///     "syscall::$$2:entry /pid == $1/ { trace(arg0); }",
+///     dtrace_probespec::DTRACE_PROBESPEC_NAME,
+///     0,
+///     Some(&args),
+/// )?;
+/// # Ok::<(), libdtrace_rs::utils::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroArgs {
+    name: String,
+    args: Vec<MacroArg>,
+}
+
+impl Default for MacroArgs {
+    fn default() -> Self {
+        Self {
+            name: "dtrace".to_string(),
+            args: Vec::new(),
+        }
+    }
+}
+
+impl MacroArgs {
+    /// Creates an empty argument list.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets `$0`.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Appends an argument.
+    pub fn arg(mut self, arg: MacroArg) -> Self {
+        self.args.push(arg);
+        self
+    }
+
+    /// Appends a string argument.
+    pub fn string(self, value: &str) -> Self {
+        self.arg(MacroArg::String(value.to_string()))
+    }
+
+    /// Appends a signed integer argument.
+    pub fn int(self, value: i64) -> Self {
+        self.arg(MacroArg::Int(value))
+    }
+
+    /// Appends an unsigned integer argument.
+    pub fn uint(self, value: u64) -> Self {
+        self.arg(MacroArg::UInt(value))
+    }
+
+    /// Appends a process ID argument.
+    pub fn pid(self, pid: u32) -> Self {
+        self.arg(MacroArg::Pid(pid))
+    }
+
+    /// The value of `$0`.
+    pub fn program_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The arguments, starting with `$1`.
+    pub fn args(&self) -> &[MacroArg] {
+        &self.args
+    }
+
+    /// The argument vector as libdtrace sees it, starting with `$0`.
+    pub fn to_strings(&self) -> Vec<String> {
+        std::iter::once(self.name.clone())
+            .chain(self.args.iter().map(|arg| arg.to_string()))
+            .collect()
+    }
+
+    /// Builds the C argument vector. It has to be kept alive for as long as libdtrace may read it.
+    pub(crate) fn to_argv(&self) -> Result<Argv, Error> {
+        let storage = self
+            .to_strings()
+            .into_iter()
+            .map(CString::new)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::from(libc::EINVAL))?;
+        let pointers = storage.iter().map(|arg| arg.as_ptr() as *mut c_char).collect();
+
+        Ok(Argv {
+            _storage: storage,
+            pointers,
+        })
+    }
+}
+
+impl<S: AsRef<str>> From<&[S]> for MacroArgs {
+    /// Creates macro arguments `$1`, `$2`, ... from strings.
+    fn from(args: &[S]) -> Self {
+        args.iter()
+            .fold(MacroArgs::new(), |args, arg| args.string(arg.as_ref()))
+    }
+}
+
+/// A C argument vector whose strings live as long as the vector.
+pub(crate) struct Argv {
+    _storage: Vec<CString>,
+    pointers: Vec<*mut c_char>,
+}
+
+impl Argv {
+    pub fn argc(&self) -> c_int {
+        self.pointers.len() as c_int
+    }
+
+    pub fn as_ptr(&self) -> *const *mut c_char {
+        self.pointers.as_ptr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argv_outlives_the_builder() {
+        let argv = MacroArgs::new()
+            .name("script.d")
+            .pid(1234)
+            .string("open")
+            .int(-1)
+            .to_argv()
+            .unwrap();
+
+        assert_eq!(argv.argc(), 4);
+        let args: Vec<_> = (0..argv.argc() as usize)
+            .map(|index| unsafe { ::core::ffi::CStr::from_ptr(*argv.as_ptr().add(index)) })
+            .map(|arg| arg.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(args, vec!["script.d", "1234", "open", "-1"]);
+    }
+
+    #[test]
+    fn argv_layout() {
+        let argv = MacroArgs::new().uint(42).string("").to_argv().unwrap();
+
+        assert_eq!(argv.argc() as usize, argv.pointers.len());
+        assert_eq!(argv.pointers.len(), argv._storage.len());
+        assert_eq!(argv.as_ptr(), argv.pointers.as_ptr() as *const _);
+        for (index, expected) in ["dtrace", "42", ""].iter().enumerate() {
+            let pointer = unsafe { *argv.as_ptr().add(index) };
+            assert_eq!(pointer as *const c_char, argv._storage[index].as_ptr());
+            let bytes = unsafe { std::slice::from_raw_parts(pointer as *const u8, expected.len() + 1) };
+            assert_eq!(&bytes[..expected.len()], expected.as_bytes());
+            assert_eq!(bytes[expected.len()], 0);
+        }
+    }
+
+    #[test]
+    fn argv_rejects_nul() {
+        assert!(MacroArgs::new().string("a\0b").to_argv().is_err());
+    }
+}
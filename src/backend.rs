@@ -0,0 +1,222 @@
+use crate::aggregation::Aggregation;
+use crate::args::MacroArgs;
+use crate::program::Program;
+use crate::consumer::Events;
+use crate::data::{AggregateData, ProbeData};
+use crate::event::{DropEvent, Event, FaultEvent};
+use crate::options::DtraceOption;
+use crate::probe::{ProbeDescription, Probes};
+use crate::record::Record;
+use crate::types::{dtrace_aggwalk_order, dtrace_status, AggWalkAction, ConsumeAction};
+use crate::utils::Error;
+use crate::wrapper::dtrace_hdl;
+use ::core::ffi::c_int;
+
+/// The operations a DTrace consumer performs against a DTrace instance.
+///
+/// [`dtrace_hdl`] implements this trait on top of libdtrace. [`crate::mock::MockBackend`] implements it in memory,
+/// so that consumer logic written against `DtraceBackend` can be tested without a DTrace kernel driver.
+pub trait DtraceBackend {
+    /// A compiled D program, borrowed from the backend that compiled it.
+    type Program<'a>
+    where
+        Self: 'a;
+
+    /// Opens a DTrace instance, see [`dtrace_hdl::dtrace_open`].
+    fn open(version: c_int, flags: c_int) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Sets a DTrace option, see [`dtrace_hdl::dtrace_setopt`].
+    fn setopt(&self, option: &str, value: &str) -> Result<(), Error>;
+
+    /// Retrieves the value of a DTrace option, see [`dtrace_hdl::dtrace_getopt`].
+    fn getopt(&self, option: &str) -> Result<crate::dtrace_optval_t, Error>;
+
+    /// Validates a typed DTrace option and sets it, see [`dtrace_hdl::dtrace_set_option`].
+    fn set_option(&self, option: &DtraceOption) -> Result<(), Error> {
+        option.validate()?;
+        self.setopt(option.name(), option.value().as_deref().unwrap_or_default())
+    }
+
+    /// Retrieves a typed DTrace option, `None` if it is not set, see [`dtrace_hdl::dtrace_get_option`].
+    fn get_option(&self, option: &str) -> Result<Option<DtraceOption>, Error> {
+        DtraceOption::from_optval(option, self.getopt(option)?)
+    }
+
+    /// Compiles a D program, see [`dtrace_hdl::dtrace_program_strcompile`].
+    fn compile<'a>(
+        &'a self,
+        program: &str,
+        spec: crate::dtrace_probespec,
+        flags: u32,
+        args: Option<&MacroArgs>,
+    ) -> Result<Self::Program<'a>, Error>;
+
+    /// Downloads a compiled program to the kernel, see [`dtrace_hdl::dtrace_program_exec`].
+    fn exec(&self, program: &mut Self::Program<'_>) -> Result<(), Error>;
+
+    /// Enables the probes of the executed programs, see [`dtrace_hdl::dtrace_go`].
+    fn go(&self) -> Result<(), Error>;
+
+    /// Stops tracing, see [`dtrace_hdl::dtrace_stop`].
+    fn stop(&self) -> Result<(), Error>;
+
+    /// Determines the status of tracing, see [`dtrace_hdl::dtrace_status`].
+    fn status(&self) -> Result<dtrace_status, Error>;
+
+    /// Pauses until the consumer has to interact with DTrace again, see [`dtrace_hdl::dtrace_sleep`].
+    fn sleep(&self);
+
+    /// Performs the periodic consumer work, see [`dtrace_hdl::dtrace_work`].
+    ///
+    /// `probe` is called for each enabled probe that fired, `record` for each of its decoded records and once more
+    /// with `None` after the last record.
+    fn work(
+        &self,
+        probe: &mut dyn FnMut(&ProbeData) -> ConsumeAction,
+        record: &mut dyn FnMut(&ProbeData, Option<&Record>) -> ConsumeAction,
+    ) -> Result<crate::dtrace_workstatus_t, Error>;
+
+    /// Registers a handler for buffered output, drops and faults, see [`dtrace_hdl::dtrace_handle_events`].
+    fn handle_events(&mut self, handler: Box<dyn FnMut(Event) + Send>) -> Result<(), Error>;
+
+    /// Registers a handler for the data the kernel dropped, see [`dtrace_hdl::dtrace_handle_drop`].
+    fn handle_drop(&mut self, handler: Box<dyn FnMut(&DropEvent) + Send>) -> Result<(), Error>;
+
+    /// Registers a handler for the faults raised by enabled probes, see [`dtrace_hdl::dtrace_handle_err`].
+    fn handle_fault(&mut self, handler: Box<dyn FnMut(&FaultEvent) + Send>) -> Result<(), Error>;
+
+    /// Lists the probes known to DTrace, see [`dtrace_hdl::probes`].
+    fn probes(&self, filter: Option<&ProbeDescription>) -> Result<Probes, Error>;
+
+    /// Retrieves the aggregation data from the kernel, see [`dtrace_hdl::dtrace_aggregate_snap`].
+    fn aggregate_snap(&self) -> Result<(), Error>;
+
+    /// Prints the aggregation data of the last snapshot the way `printa()` does, to the buffered output handler if one
+    /// is registered, see [`dtrace_hdl::dtrace_aggregate_print`].
+    fn aggregate_print(&self) -> Result<(), Error>;
+
+    /// Walks the aggregation data of the last snapshot in the given order, see [`dtrace_hdl::dtrace_aggregate_walk`].
+    fn aggregate_walk(
+        &self,
+        order: dtrace_aggwalk_order,
+        handler: &mut dyn FnMut(&AggregateData) -> AggWalkAction,
+    ) -> Result<(), Error>;
+
+    /// Starts tracing and iterates over the events of the consumer, see [`crate::consumer::Events`].
+    fn events(&mut self) -> Result<Events<'_, Self>, Error>
+    where
+        Self: Sized,
+    {
+        Events::new(self)
+    }
+
+    /// Decodes the aggregation data of the last snapshot in the given order, see [`dtrace_hdl::aggregations`].
+    fn aggregations(&self, order: dtrace_aggwalk_order) -> Result<Vec<Aggregation>, Error> {
+        let mut aggregations = Vec::new();
+        self.aggregate_walk(order, &mut |data| {
+            aggregations.push(Aggregation::from_data(data));
+            AggWalkAction::Next
+        })?;
+        Ok(aggregations)
+    }
+}
+
+impl DtraceBackend for dtrace_hdl {
+    type Program<'a> = Program<'a>;
+
+    fn open(version: c_int, flags: c_int) -> Result<Self, Error> {
+        dtrace_hdl::dtrace_open(version, flags)
+    }
+
+    fn setopt(&self, option: &str, value: &str) -> Result<(), Error> {
+        self.setopt_raw(option, Some(value))
+    }
+
+    fn getopt(&self, option: &str) -> Result<crate::dtrace_optval_t, Error> {
+        self.dtrace_getopt(option)
+    }
+
+    fn set_option(&self, option: &DtraceOption) -> Result<(), Error> {
+        option.validate()?;
+        self.setopt_raw(option.name(), option.value().as_deref())
+    }
+
+    fn compile<'a>(
+        &'a self,
+        program: &str,
+        spec: crate::dtrace_probespec,
+        flags: u32,
+        args: Option<&MacroArgs>,
+    ) -> Result<Self::Program<'a>, Error> {
+        self.dtrace_program_strcompile(program, spec, flags, args)
+    }
+
+    fn exec(&self, program: &mut Self::Program<'_>) -> Result<(), Error> {
+        program.exec().map(|_| ())
+    }
+
+    fn go(&self) -> Result<(), Error> {
+        self.dtrace_go()
+    }
+
+    fn stop(&self) -> Result<(), Error> {
+        self.dtrace_stop()
+    }
+
+    fn status(&self) -> Result<dtrace_status, Error> {
+        self.dtrace_status()
+    }
+
+    fn sleep(&self) {
+        self.dtrace_sleep()
+    }
+
+    fn work(
+        &self,
+        probe: &mut dyn FnMut(&ProbeData) -> ConsumeAction,
+        record: &mut dyn FnMut(&ProbeData, Option<&Record>) -> ConsumeAction,
+    ) -> Result<crate::dtrace_workstatus_t, Error> {
+        self.dtrace_work_with(None, probe, record)
+    }
+
+    fn handle_events(&mut self, handler: Box<dyn FnMut(Event) + Send>) -> Result<(), Error> {
+        self.dtrace_handle_events(handler)
+    }
+
+    fn handle_drop(&mut self, handler: Box<dyn FnMut(&DropEvent) + Send>) -> Result<(), Error> {
+        self.dtrace_handle_drop(handler)
+    }
+
+    fn handle_fault(&mut self, handler: Box<dyn FnMut(&FaultEvent) + Send>) -> Result<(), Error> {
+        self.dtrace_handle_err(handler)
+    }
+
+    fn probes(&self, filter: Option<&ProbeDescription>) -> Result<Probes, Error> {
+        dtrace_hdl::probes(self, filter)
+    }
+
+    fn aggregate_snap(&self) -> Result<(), Error> {
+        self.dtrace_aggregate_snap()
+    }
+
+    fn aggregate_print(&self) -> Result<(), Error> {
+        self.dtrace_aggregate_print(None, None)
+    }
+
+    fn aggregate_walk(
+        &self,
+        order: dtrace_aggwalk_order,
+        handler: &mut dyn FnMut(&AggregateData) -> AggWalkAction,
+    ) -> Result<(), Error> {
+        let mut handler = crate::callbacks::AggregateHandler::new(handler);
+        let result = self.dtrace_aggregate_walk(
+            Some(crate::callbacks::aggregate),
+            Some(&mut handler as *mut _ as *mut ::core::ffi::c_void),
+            order,
+        );
+        handler.resume_panic();
+        result
+    }
+}
@@ -1,3 +1,9 @@
+/// Buffered output handler that prints the buffered output to stdout.
+///
+/// # Safety
+///
+/// `bufdata` must be a valid buffered data pointer handed out by libdtrace.
+#[deprecated(note = "register a closure with `dtrace_hdl::dtrace_register_handler`, which the handle owns")]
 pub unsafe extern "C" fn buffered(
     bufdata: *const crate::dtrace_bufdata_t,
     _arg: *mut ::core::ffi::c_void,
@@ -10,6 +16,11 @@ pub unsafe extern "C" fn buffered(
     crate::DTRACE_HANDLE_OK as ::core::ffi::c_int
 }
 
+/// Probe handler that consumes every enabled probe.
+///
+/// # Safety
+///
+/// Only meant to be called by libdtrace.
 pub unsafe extern "C" fn chew(
     _data: *const crate::dtrace_probedata_t,
     _arg: *mut ::core::ffi::c_void,
@@ -17,6 +28,11 @@ pub unsafe extern "C" fn chew(
     crate::DTRACE_CONSUME_THIS as ::core::ffi::c_int
 }
 
+/// Record handler that consumes every record and stops at `exit()`.
+///
+/// # Safety
+///
+/// `record` must be null or a valid record pointer handed out by libdtrace.
 pub unsafe extern "C" fn chew_rec(
     _data: *const crate::dtrace_probedata_t,
     record: *const crate::dtrace_recdesc_t,
@@ -34,6 +50,12 @@ pub unsafe extern "C" fn chew_rec(
     crate::DTRACE_CONSUME_THIS as ::core::ffi::c_int
 }
 
+/// Aggregation handler that prints aggregations keyed by a single string with an integer value.
+///
+/// # Safety
+///
+/// `aggdata` must be a valid aggregation data pointer handed out by libdtrace.
+#[deprecated(note = "use `dtrace_hdl::aggregations`, which decodes any aggregation")]
 pub unsafe extern "C" fn walk(
     aggdata: *const crate::dtrace_aggdata_t,
     _arg: *mut ::core::ffi::c_void,
@@ -55,5 +77,580 @@ pub unsafe extern "C" fn walk(
     
     println!("{}\t\t\t\t\t\t{}", name_str, instance);
 
-    return crate::DTRACE_AGGWALK_NEXT as ::core::ffi::c_int;
+    crate::DTRACE_AGGWALK_NEXT as ::core::ffi::c_int
+}
+
+/// The Rust handlers passed as `arg` to [`consume_probe`] and [`consume_rec`].
+///
+/// A panic raised by either handler is caught before it reaches libdtrace and stored in `panic`, so that the caller
+/// can resume it once the libdtrace call has returned.
+pub(crate) struct ConsumeHandlers<'a> {
+    pub probe: &'a mut dyn FnMut(&crate::data::ProbeData) -> crate::types::ConsumeAction,
+    pub record: &'a mut dyn FnMut(
+        &crate::data::ProbeData,
+        Option<&crate::record::Record>,
+    ) -> crate::types::ConsumeAction,
+    pub panic: Option<Box<dyn ::std::any::Any + Send + 'static>>,
+}
+
+impl<'a> ConsumeHandlers<'a> {
+    pub fn new(
+        probe: &'a mut dyn FnMut(&crate::data::ProbeData) -> crate::types::ConsumeAction,
+        record: &'a mut dyn FnMut(
+            &crate::data::ProbeData,
+            Option<&crate::record::Record>,
+        ) -> crate::types::ConsumeAction,
+    ) -> Self {
+        Self {
+            probe,
+            record,
+            panic: None,
+        }
+    }
+
+    /// Resumes the panic raised by one of the handlers, if any.
+    pub fn resume_panic(&mut self) {
+        if let Some(panic) = self.panic.take() {
+            ::std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// The Rust handler passed as `arg` to [`aggregate`], see [`ConsumeHandlers`].
+pub(crate) struct AggregateHandler<'a> {
+    pub handler: &'a mut dyn FnMut(&crate::data::AggregateData) -> crate::types::AggWalkAction,
+    pub panic: Option<Box<dyn ::std::any::Any + Send + 'static>>,
+}
+
+impl<'a> AggregateHandler<'a> {
+    pub fn new(
+        handler: &'a mut dyn FnMut(&crate::data::AggregateData) -> crate::types::AggWalkAction,
+    ) -> Self {
+        Self {
+            handler,
+            panic: None,
+        }
+    }
+
+    /// Resumes the panic raised by the handler, if any.
+    pub fn resume_panic(&mut self) {
+        if let Some(panic) = self.panic.take() {
+            ::std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// The Rust handler passed as `arg` to [`statement`] and [`probe`], see [`ConsumeHandlers`].
+pub(crate) struct IterHandler<'a, T> {
+    pub handler: &'a mut dyn FnMut(&T),
+    pub panic: Option<Box<dyn ::std::any::Any + Send + 'static>>,
+}
+
+impl<'a, T> IterHandler<'a, T> {
+    pub fn new(handler: &'a mut dyn FnMut(&T)) -> Self {
+        Self { handler, panic: None }
+    }
+
+    /// Resumes the panic raised by the handler, if any.
+    pub fn resume_panic(&mut self) {
+        if let Some(panic) = self.panic.take() {
+            ::std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// Probe handler trampoline for [`ConsumeHandlers`].
+///
+/// # Safety
+///
+/// `data` must be a valid probe data pointer handed out by libdtrace and `arg` must point to a [`ConsumeHandlers`].
+pub(crate) unsafe extern "C" fn consume_probe(
+    data: *const crate::dtrace_probedata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let handlers = &mut *(arg as *mut ConsumeHandlers);
+    let data = crate::data::ProbeData::from_raw(&*data);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| (handlers.probe)(&data))) {
+        Ok(action) => action.into(),
+        Err(panic) => {
+            handlers.panic = Some(panic);
+            crate::types::ConsumeAction::Abort.into()
+        }
+    }
+}
+
+/// Record handler trampoline for [`ConsumeHandlers`].
+///
+/// # Safety
+///
+/// `data` must be a valid probe data pointer handed out by libdtrace, `record` must be null or point to one of its
+/// records and `arg` must point to a [`ConsumeHandlers`].
+pub(crate) unsafe extern "C" fn consume_rec(
+    data: *const crate::dtrace_probedata_t,
+    record: *const crate::dtrace_recdesc_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let handlers = &mut *(arg as *mut ConsumeHandlers);
+    let first = (*(*data).dtpda_edesc).dtepd_rec.as_ptr();
+    let data = crate::data::ProbeData::from_raw(&*data);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        let record = match record.is_null() {
+            true => None,
+            false => Some(
+                usize::try_from(record.offset_from(first))
+                    .ok()
+                    .and_then(|index| crate::record::Record::decode(&data, index))
+                    .unwrap_or_else(|| crate::record::Record::Invalid(crate::data::RecordDesc::from(&*record))),
+            ),
+        };
+        (handlers.record)(&data, record.as_ref())
+    })) {
+        Ok(action) => action.into(),
+        Err(panic) => {
+            handlers.panic = Some(panic);
+            crate::types::ConsumeAction::Abort.into()
+        }
+    }
+}
+
+/// Aggregation walk trampoline for [`AggregateHandler`].
+///
+/// # Safety
+///
+/// `aggdata` must be a valid aggregation data pointer handed out by libdtrace and `arg` must point to an
+/// [`AggregateHandler`].
+pub(crate) unsafe extern "C" fn aggregate(
+    aggdata: *const crate::dtrace_aggdata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let handler = &mut *(arg as *mut AggregateHandler);
+    let aggdata = crate::data::AggregateData::from_raw(&*aggdata);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| (handler.handler)(&aggdata))) {
+        Ok(action) => action.into(),
+        Err(panic) => {
+            handler.panic = Some(panic);
+            crate::types::AggWalkAction::Abort.into()
+        }
+    }
+}
+
+/// Statement iteration trampoline for an [`IterHandler`] of `dtrace_stmtdesc_t`.
+///
+/// # Safety
+///
+/// `stmt` must be a valid statement pointer handed out by libdtrace and `arg` must point to an [`IterHandler`] of
+/// `dtrace_stmtdesc_t`.
+pub(crate) unsafe extern "C" fn statement(
+    _handle: *mut crate::dtrace_hdl_t,
+    _program: *mut crate::dtrace_prog_t,
+    stmt: *mut crate::dtrace_stmtdesc_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let handler = &mut *(arg as *mut IterHandler<crate::dtrace_stmtdesc_t>);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| (handler.handler)(&*stmt))) {
+        Ok(()) => 0,
+        Err(panic) => {
+            handler.panic = Some(panic);
+            -1
+        }
+    }
+}
+
+/// Probe iteration trampoline for an [`IterHandler`] of `dtrace_probedesc_t`.
+///
+/// # Safety
+///
+/// `desc` must be a valid probe description pointer handed out by libdtrace and `arg` must point to an
+/// [`IterHandler`] of `dtrace_probedesc_t`.
+pub(crate) unsafe extern "C" fn probe(
+    _handle: *mut crate::dtrace_hdl_t,
+    desc: *const crate::dtrace_probedesc_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let handler = &mut *(arg as *mut IterHandler<crate::dtrace_probedesc_t>);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| (handler.handler)(&*desc))) {
+        Ok(()) => 0,
+        Err(panic) => {
+            handler.panic = Some(panic);
+            -1
+        }
+    }
+}
+
+/// The Rust handlers owned by a [`crate::wrapper::dtrace_hdl`], passed as `arg` to [`event_buffered`],
+/// [`event_drop`], [`event_fault`], [`event_setopt`] and [`event_proc`].
+///
+/// Buffered output, drops and faults are delivered to the handler registered for them first and then, as an
+/// [`crate::event::Event`], to the events handler.
+#[derive(Default)]
+pub(crate) struct Handlers {
+    pub events: Option<EventHandler>,
+    pub buffered: Option<BufferedHandler>,
+    pub drop: Option<DropHandler>,
+    pub fault: Option<FaultHandler>,
+    pub setopt: Option<SetOptHandler>,
+    pub proc: Option<ProcHandler>,
+}
+
+pub(crate) type EventHandler = Box<dyn FnMut(crate::event::Event) + Send>;
+pub(crate) type BufferedHandler = Box<dyn FnMut(&str) -> crate::types::HandleAction + Send>;
+pub(crate) type DropHandler = Box<dyn FnMut(&crate::event::DropEvent) -> crate::types::HandleAction + Send>;
+pub(crate) type FaultHandler = Box<dyn FnMut(&crate::event::FaultEvent) -> crate::types::HandleAction + Send>;
+pub(crate) type SetOptHandler = Box<dyn FnMut(&crate::event::OptionChange) -> crate::types::HandleAction + Send>;
+pub(crate) type ProcHandler = Box<dyn FnMut(&str) + Send>;
+
+impl Handlers {
+    /// Sets a drop handler that never aborts.
+    pub fn set_drop(&mut self, mut handler: Box<dyn FnMut(&crate::event::DropEvent) + Send>) {
+        self.drop = Some(Box::new(move |drop| {
+            handler(drop);
+            crate::types::HandleAction::Ok
+        }));
+    }
+
+    /// Sets a fault handler that never aborts.
+    pub fn set_fault(&mut self, mut handler: Box<dyn FnMut(&crate::event::FaultEvent) + Send>) {
+        self.fault = Some(Box::new(move |fault| {
+            handler(fault);
+            crate::types::HandleAction::Ok
+        }));
+    }
+
+    /// Delivers `event` to the handlers interested in it.
+    ///
+    /// Like libdtrace without a drop or error handler, drops and faults nobody handles abort the consume call.
+    pub fn dispatch(&mut self, event: crate::event::Event) -> crate::types::HandleAction {
+        use crate::types::HandleAction;
+
+        let action = match &event {
+            crate::event::Event::Buffered(output) => self.buffered.as_mut().map(|handler| handler(output)),
+            crate::event::Event::Drop(drop) => self.drop.as_mut().map(|handler| handler(drop)),
+            crate::event::Event::Fault(fault) => self.fault.as_mut().map(|handler| handler(fault)),
+            _ => None,
+        };
+        let handled = action.is_some() || self.events.is_some();
+        if let Some(handler) = self.events.as_mut() {
+            handler(event);
+        }
+
+        match action {
+            Some(action) => action,
+            None if handled => HandleAction::Ok,
+            None => HandleAction::Abort,
+        }
+    }
+}
+
+/// Runs `handler` on the [`Handlers`] behind `arg`. A panic aborts the libdtrace call in progress.
+unsafe fn with_handlers(
+    arg: *mut ::core::ffi::c_void,
+    handler: impl FnOnce(&mut Handlers) -> crate::types::HandleAction,
+) -> ::core::ffi::c_int {
+    let handlers = &mut *(arg as *mut Handlers);
+
+    match ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| handler(handlers))) {
+        Ok(action) => action.into(),
+        Err(_) => crate::types::HandleAction::Abort.into(),
+    }
+}
+
+/// Buffered output trampoline for [`Handlers`].
+///
+/// # Safety
+///
+/// `bufdata` must be a valid buffered data pointer handed out by libdtrace and `arg` must point to [`Handlers`].
+pub(crate) unsafe extern "C" fn event_buffered(
+    bufdata: *const crate::dtrace_bufdata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let output = ::core::ffi::CStr::from_ptr((*bufdata).dtbda_buffered).to_string_lossy();
+    let event = crate::event::Event::Buffered(output.into_owned());
+    with_handlers(arg, |handlers| handlers.dispatch(event))
+}
+
+/// Drop trampoline for [`Handlers`].
+///
+/// # Safety
+///
+/// `data` must be a valid drop data pointer handed out by libdtrace and `arg` must point to [`Handlers`].
+pub(crate) unsafe extern "C" fn event_drop(
+    data: *const crate::dtrace_dropdata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let event = crate::event::Event::Drop(crate::event::DropEvent::from_raw(&*data));
+    with_handlers(arg, |handlers| handlers.dispatch(event))
+}
+
+/// Error trampoline for [`Handlers`].
+///
+/// # Safety
+///
+/// `data` must be a valid error data pointer handed out by libdtrace and `arg` must point to [`Handlers`].
+pub(crate) unsafe extern "C" fn event_fault(
+    data: *const crate::dtrace_errdata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let event = crate::event::Event::Fault(crate::event::FaultEvent::from_raw(&*data));
+    with_handlers(arg, |handlers| handlers.dispatch(event))
+}
+
+/// Option trampoline for [`Handlers`].
+///
+/// # Safety
+///
+/// `data` must be a valid option data pointer handed out by libdtrace and `arg` must point to [`Handlers`].
+pub(crate) unsafe extern "C" fn event_setopt(
+    data: *const crate::dtrace_setoptdata_t,
+    arg: *mut ::core::ffi::c_void,
+) -> ::core::ffi::c_int {
+    let change = crate::event::OptionChange::from_raw(&*data);
+    with_handlers(arg, |handlers| match handlers.setopt.as_mut() {
+        Some(handler) => handler(&change),
+        None => crate::types::HandleAction::Ok,
+    })
+}
+
+/// Process message trampoline for [`Handlers`]. libdtrace ignores the outcome, so a panic is only swallowed.
+///
+/// # Safety
+///
+/// `message` must be null or a valid string handed out by libdtrace and `arg` must point to [`Handlers`].
+pub(crate) unsafe extern "C" fn event_proc(
+    _process: *mut crate::ps_prochandle,
+    message: *const ::core::ffi::c_char,
+    arg: *mut ::core::ffi::c_void,
+) {
+    let message = match message.is_null() {
+        true => Default::default(),
+        false => ::core::ffi::CStr::from_ptr(message).to_string_lossy(),
+    };
+    with_handlers(arg, |handlers| {
+        if let Some(handler) = handlers.proc.as_mut() {
+            handler(&message);
+        }
+        crate::types::HandleAction::Ok
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ConsumeAction;
+
+    #[test]
+    fn consume_trampolines_catch_panics() {
+        let mut pdesc: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        let mut edesc: crate::dtrace_eprobedesc_t = unsafe { ::core::mem::zeroed() };
+        let mut data: crate::dtrace_probedata_t = unsafe { ::core::mem::zeroed() };
+        data.dtpda_pdesc = &mut pdesc;
+        data.dtpda_edesc = &mut edesc;
+
+        let mut probe = |_: &crate::data::ProbeData| -> ConsumeAction { panic!("probe handler") };
+        let mut record = |_: &crate::data::ProbeData, _: Option<&crate::record::Record>| ConsumeAction::Next;
+        let mut handlers = ConsumeHandlers::new(&mut probe, &mut record);
+        let arg = &mut handlers as *mut _ as *mut ::core::ffi::c_void;
+
+        assert_eq!(
+            unsafe { consume_rec(&data, std::ptr::null(), arg) },
+            crate::DTRACE_CONSUME_NEXT as ::core::ffi::c_int
+        );
+        assert_eq!(
+            unsafe { consume_probe(&data, arg) },
+            crate::DTRACE_CONSUME_ABORT as ::core::ffi::c_int
+        );
+
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handlers.resume_panic()));
+        assert_eq!(*panic.unwrap_err().downcast::<&str>().unwrap(), "probe handler");
+    }
+
+    #[test]
+    fn iteration_trampolines_resume_panics() {
+        let desc: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        let mut calls = 0;
+        let mut count = |_: &crate::dtrace_probedesc_t| calls += 1;
+        let mut handler = IterHandler::new(&mut count);
+        let arg = &mut handler as *mut _ as *mut ::core::ffi::c_void;
+        assert_eq!(unsafe { probe(std::ptr::null_mut(), &desc, arg) }, 0);
+        handler.resume_panic();
+        assert_eq!(calls, 1);
+
+        let mut panicking = |_: &crate::dtrace_probedesc_t| panic!("probe iteration");
+        let mut handler = IterHandler::new(&mut panicking);
+        let arg = &mut handler as *mut _ as *mut ::core::ffi::c_void;
+        assert_eq!(unsafe { probe(std::ptr::null_mut(), &desc, arg) }, -1);
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| handler.resume_panic()));
+        assert_eq!(*panic.unwrap_err().downcast::<&str>().unwrap(), "probe iteration");
+    }
+
+    #[test]
+    fn consume_rec_decodes_raw_probe_data() {
+        use crate::record::{Record, Value};
+
+        /// An enabled probe description followed by the records that do not fit into `dtepd_rec`.
+        #[repr(C)]
+        struct EProbeDesc {
+            edesc: crate::dtrace_eprobedesc_t,
+            more: [crate::dtrace_recdesc_t; 2],
+        }
+
+        let recdesc = |action: u32, size, offset, alignment, format| {
+            let mut desc: crate::dtrace_recdesc_t = unsafe { ::core::mem::zeroed() };
+            desc.dtrd_action = action as _;
+            desc.dtrd_size = size;
+            desc.dtrd_offset = offset;
+            desc.dtrd_alignment = alignment;
+            desc.dtrd_format = format;
+            desc
+        };
+        let mut bytes = [0u8; 16];
+        bytes[..4].copy_from_slice(&7i32.to_ne_bytes());
+        let mut pdesc: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        let mut edesc = EProbeDesc {
+            edesc: unsafe { ::core::mem::zeroed() },
+            more: [
+                recdesc(crate::DTRACEACT_PRINTF, 4, 0, 4, 0),
+                recdesc(crate::DTRACEACT_DIFEXPR, 8, 4, 8, 0),
+            ],
+        };
+        edesc.edesc.dtepd_nrecs = 3;
+        edesc.edesc.dtepd_size = bytes.len() as u32;
+        edesc.edesc.dtepd_rec[0] = recdesc(crate::DTRACEACT_PRINTF, 0, 0, 1, 1);
+        let mut data: crate::dtrace_probedata_t = unsafe { ::core::mem::zeroed() };
+        data.dtpda_pdesc = &mut pdesc;
+        data.dtpda_edesc = &mut edesc.edesc;
+        data.dtpda_data = bytes.as_mut_ptr() as _;
+
+        let mut records = Vec::new();
+        let mut probe = |_: &crate::data::ProbeData| ConsumeAction::Next;
+        let mut record = |_: &crate::data::ProbeData, record: Option<&Record>| {
+            records.push(record.cloned());
+            ConsumeAction::Next
+        };
+        let mut handlers = ConsumeHandlers::new(&mut probe, &mut record);
+        let arg = &mut handlers as *mut _ as *mut ::core::ffi::c_void;
+        unsafe {
+            consume_rec(&data, edesc.edesc.dtepd_rec.as_ptr(), arg);
+            consume_rec(&data, &edesc.more[1], arg);
+        }
+        drop(handlers);
+
+        // Off Windows libdtrace has no public lookup of a format string by its index, see `ProbeData::format`
+        assert_eq!(
+            records[0],
+            Some(Record::Printf {
+                action: crate::DTRACEACT_PRINTF as u16,
+                format: None,
+                args: vec![Value::Integer { value: 7, size: 4 }],
+            })
+        );
+        // The offset 4 does not honor the alignment of 8
+        assert!(matches!(records[1], Some(Record::Invalid(desc)) if desc.offset == 4 && desc.alignment == 8));
+    }
+
+    #[test]
+    fn event_trampolines_decode_drops_and_faults() {
+        use crate::event::{DropKind, Event, FaultKind};
+        use crate::types::HandleAction;
+        use std::sync::{Arc, Mutex};
+
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let faults = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut handlers = Handlers::default();
+        let seen = drops.clone();
+        handlers.drop = Some(Box::new(move |drop| {
+            seen.lock().unwrap().push(drop.clone());
+            HandleAction::Ok
+        }));
+        let seen = faults.clone();
+        handlers.fault = Some(Box::new(move |fault| {
+            seen.lock().unwrap().push(fault.clone());
+            HandleAction::Ok
+        }));
+        let seen = events.clone();
+        handlers.events = Some(Box::new(move |event| seen.lock().unwrap().push(event)));
+        let arg = &mut handlers as *mut _ as *mut ::core::ffi::c_void;
+
+        let mut drop: crate::dtrace_dropdata_t = unsafe { ::core::mem::zeroed() };
+        drop.dtdda_cpu = 2;
+        drop.dtdda_kind = crate::dtrace_dropkind_t::DTRACEDROP_AGGREGATION;
+        drop.dtdda_drops = 3;
+        drop.dtdda_total = 5;
+        drop.dtdda_msg = c"3 aggregation drops on CPU 2\n".as_ptr();
+        assert_eq!(
+            unsafe { event_drop(&drop, arg) },
+            crate::DTRACE_HANDLE_OK as ::core::ffi::c_int
+        );
+
+        let mut pdesc: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        pdesc.dtpd_id = 42;
+        for (field, value) in [(&mut pdesc.dtpd_provider[..], "syscall"), (&mut pdesc.dtpd_name[..], "entry")] {
+            value.bytes().zip(field.iter_mut()).for_each(|(byte, c)| *c = byte as ::core::ffi::c_char);
+        }
+        let mut fault: crate::dtrace_errdata_t = unsafe { ::core::mem::zeroed() };
+        fault.dteda_pdesc = &mut pdesc;
+        fault.dteda_fault = crate::DTRACEFLT_BADADDR as ::core::ffi::c_int;
+        fault.dteda_action = 1;
+        fault.dteda_offset = 16;
+        fault.dteda_addr = 0xdead;
+        assert_eq!(
+            unsafe { event_fault(&fault, arg) },
+            crate::DTRACE_HANDLE_OK as ::core::ffi::c_int
+        );
+
+        let drops = drops.lock().unwrap();
+        assert_eq!(drops.len(), 1);
+        assert_eq!(drops[0].kind, DropKind::Aggregation);
+        assert_eq!((drops[0].cpu, drops[0].count, drops[0].total), (2, 3, 5));
+        assert_eq!(drops[0].message, "3 aggregation drops on CPU 2");
+
+        let faults = faults.lock().unwrap();
+        assert_eq!(faults.len(), 1);
+        assert_eq!(faults[0].kind, FaultKind::BadAddress);
+        assert_eq!(faults[0].probe.to_string(), "syscall:::entry");
+        assert_eq!(faults[0].probe.id, 42);
+        assert_eq!((faults[0].action, faults[0].offset, faults[0].address), (1, 16, 0xdead));
+        assert_eq!(faults[0].message, "");
+
+        let events = events.lock().unwrap();
+        assert_eq!(*events, vec![Event::Drop(drops[0].clone()), Event::Fault(faults[0].clone())]);
+    }
+
+    #[test]
+    fn handler_trampolines_catch_panics() {
+        use crate::types::HandleAction;
+
+        let mut handlers = Handlers {
+            buffered: Some(Box::new(|output| match output {
+                "ok" => HandleAction::Ok,
+                _ => panic!("buffered handler"),
+            })),
+            ..Default::default()
+        };
+        let arg = &mut handlers as *mut _ as *mut ::core::ffi::c_void;
+
+        let mut bufdata: crate::dtrace_bufdata_t = unsafe { ::core::mem::zeroed() };
+        bufdata.dtbda_buffered = c"ok".as_ptr();
+        assert_eq!(
+            unsafe { event_buffered(&bufdata, arg) },
+            crate::DTRACE_HANDLE_OK as ::core::ffi::c_int
+        );
+        bufdata.dtbda_buffered = c"panic".as_ptr();
+        assert_eq!(
+            unsafe { event_buffered(&bufdata, arg) },
+            crate::DTRACE_HANDLE_ABORT as ::core::ffi::c_int
+        );
+
+        // Without a drop handler libdtrace fails the consume call, and so do the trampolines
+        let drop: crate::dtrace_dropdata_t = unsafe { ::core::mem::zeroed() };
+        assert_eq!(
+            unsafe { event_drop(&drop, arg) },
+            crate::DTRACE_HANDLE_ABORT as ::core::ffi::c_int
+        );
+    }
 }
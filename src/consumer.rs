@@ -0,0 +1,503 @@
+use crate::backend::DtraceBackend;
+use crate::event::{Event, ProbeEvent};
+use crate::options::DtraceOption;
+use crate::record::Record;
+use crate::types::{dtrace_status, ConsumeAction};
+use crate::utils::Error;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+/// The interval used for a rate that is not set, libdtrace defaults to `1hz` for all of them.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why [`Consumer::run`] stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The program called `exit()`, [`dtrace_status::Exited`]
+    Exited,
+    /// The principal buffer filled up under the `fill` buffer policy, [`dtrace_status::Filled`]
+    Filled,
+    /// Tracing was stopped outside of the consumer, [`dtrace_status::Stopped`]
+    Stopped,
+    /// The [`StopToken`] of the consumer was triggered
+    Requested,
+}
+
+impl StopReason {
+    /// The reason to stop for a status reported by `dtrace_status`, `None` if tracing goes on.
+    pub fn from_status(status: dtrace_status) -> Option<Self> {
+        match status {
+            dtrace_status::Exited => Some(StopReason::Exited),
+            dtrace_status::Filled => Some(StopReason::Filled),
+            dtrace_status::Stopped => Some(StopReason::Stopped),
+            dtrace_status::None | dtrace_status::Ok => None,
+        }
+    }
+
+    /// The status of tracing once the consumer stopped for this reason.
+    pub fn status(&self) -> dtrace_status {
+        match self {
+            StopReason::Exited => dtrace_status::Exited,
+            StopReason::Filled => dtrace_status::Filled,
+            StopReason::Stopped | StopReason::Requested => dtrace_status::Stopped,
+        }
+    }
+}
+
+/// Asks a running [`Consumer`] to stop, from any thread.
+///
+/// The consumer notices the request right away instead of at its next scheduled wake-up.
+#[derive(Debug, Clone, Default)]
+pub struct StopToken {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl StopToken {
+    /// Creates a token that has not been triggered.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the consumer to stop.
+    pub fn stop(&self) {
+        let (stopped, condvar) = &*self.state;
+        *stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        condvar.notify_all();
+    }
+
+    /// Whether the consumer was asked to stop.
+    pub fn is_stopped(&self) -> bool {
+        *self.state.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Waits until `timeout` elapsed or the token was triggered, returns whether it was triggered.
+    fn wait(&self, timeout: Duration) -> bool {
+        let (stopped, condvar) = &*self.state;
+        let stopped = stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        let (stopped, _) = condvar
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        *stopped
+    }
+}
+
+/// Drives a DTrace consumer through the canonical consumer loop.
+///
+/// [`Consumer::run`] starts tracing and then, until tracing stops, waits for the shortest of the `switchrate`,
+/// `aggrate` and `statusrate` intervals, performs the consumer work and checks the status. Once the program called
+/// `exit()`, the buffer filled up or the [`StopToken`] was triggered, it stops tracing, consumes what is left (e.g.
+/// the output of the `END` clause) and snapshots and prints the aggregations.
+///
+/// Every probe that fired, all buffered output (including the printed aggregations), drops, faults and status
+/// changes are passed to the handler as [`Event`]s.
+///
+/// ```ignore
+/// let mut prog = handle.dtrace_program_strcompile(PROGRAM, spec, 0, None)?;
+/// handle.dtrace_program_exec(&mut prog, None)?;
+/// let mut consumer = Consumer::new(handle)?;
+/// let reason = consumer.run(|event| {
+///     if let Event::Buffered(output) = event {
+///         print!("{}", output);
+///     }
+/// })?;
+/// ```
+pub struct Consumer<B: DtraceBackend> {
+    backend: B,
+    stop: StopToken,
+    events: Arc<Mutex<Vec<Event>>>,
+    started: bool,
+    print_aggregations: bool,
+}
+
+impl<B: DtraceBackend> Consumer<B> {
+    /// Wraps a backend whose programs have been executed, but not started yet.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Consumer)` - If the event handler of the consumer was registered.
+    /// * `Err(errno)` - If the event handler could not be registered, e.g. because tracing already started.
+    pub fn new(mut backend: B) -> Result<Self, Error> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let queue = events.clone();
+        backend.handle_events(Box::new(move |event| {
+            queue.lock().unwrap_or_else(PoisonError::into_inner).push(event)
+        }))?;
+
+        Ok(Self {
+            backend,
+            stop: StopToken::new(),
+            events,
+            started: false,
+            print_aggregations: true,
+        })
+    }
+
+    /// Whether the aggregations are printed once tracing stopped, `true` by default.
+    pub fn print_aggregations(mut self, print: bool) -> Self {
+        self.print_aggregations = print;
+        self
+    }
+
+    /// The token that stops [`Consumer::run`].
+    pub fn stop_token(&self) -> StopToken {
+        self.stop.clone()
+    }
+
+    /// The backend driven by the consumer.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns the backend driven by the consumer.
+    pub fn into_inner(self) -> B {
+        self.backend
+    }
+
+    /// Starts tracing, unless it has been started already. [`Consumer::run`] calls it as well.
+    pub fn start(&mut self) -> Result<(), Error> {
+        if !self.started {
+            self.backend.go()?;
+            self.started = true;
+        }
+        Ok(())
+    }
+
+    /// Runs the consumer loop until tracing stops, passing every event to `handler`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(StopReason)` - Why tracing stopped.
+    /// * `Err(errno)` - If tracing could not be started, or consuming failed. Tracing is stopped in either case.
+    pub fn run<F>(&mut self, mut handler: F) -> Result<StopReason, Error>
+    where
+        F: FnMut(Event),
+    {
+        self.start()?;
+        let consumed = self.consume(&mut handler);
+        let stopped = self.backend.stop();
+        let reason = consumed?;
+        stopped?;
+
+        // Deliver what the `END` clause traced
+        let drained = work(&self.backend, &self.events);
+        self.deliver(&mut handler);
+        drained?;
+
+        if self.print_aggregations {
+            let printed = self
+                .backend
+                .aggregate_snap()
+                .and_then(|_| self.backend.aggregate_print());
+            self.deliver(&mut handler);
+            printed?;
+        }
+        Ok(reason)
+    }
+
+    /// The consumer loop itself, returns once tracing stopped.
+    fn consume(&self, handler: &mut dyn FnMut(Event)) -> Result<StopReason, Error> {
+        let interval = self.interval();
+        let mut status = dtrace_status::Ok;
+
+        loop {
+            if self.stop.wait(interval) {
+                return Ok(StopReason::Requested);
+            }
+
+            let result = work(&self.backend, &self.events);
+            self.deliver(handler);
+            let done = result? == crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE;
+
+            let current = self.backend.status()?;
+            if current != status && current != dtrace_status::None {
+                status = current;
+                handler(Event::Status(current));
+            }
+            match StopReason::from_status(current) {
+                Some(reason) => return Ok(reason),
+                // `dtrace_work` reports done once the program called `exit()`, the status may lag behind
+                None if done => return Ok(StopReason::Exited),
+                None => {}
+            }
+        }
+    }
+
+    /// Passes the queued events to `handler`.
+    fn deliver(&self, handler: &mut dyn FnMut(Event)) {
+        let events = std::mem::take(&mut *self.events.lock().unwrap_or_else(PoisonError::into_inner));
+        events.into_iter().for_each(handler);
+    }
+
+    /// The time to wait between two passes, the shortest interval of the rates the consumer has to honor.
+    fn interval(&self) -> Duration {
+        ["switchrate", "aggrate", "statusrate"]
+            .into_iter()
+            .map(|rate| match self.backend.get_option(rate) {
+                Ok(Some(
+                    DtraceOption::SwitchRate(rate) | DtraceOption::AggRate(rate) | DtraceOption::StatusRate(rate),
+                )) => rate.interval(),
+                _ => DEFAULT_INTERVAL,
+            })
+            .min()
+            .unwrap_or(DEFAULT_INTERVAL)
+    }
+}
+
+/// A blocking iterator over the events of a running DTrace consumer, see [`DtraceBackend::events`].
+///
+/// Each pass sleeps until data is due (see [`DtraceBackend::sleep`]), performs the consumer work and checks the
+/// status. The events of a pass are buffered and yielded one by one. Once the status reports that tracing exited,
+/// stopped or filled up, tracing is stopped, the output of the `END` clause is yielded and the iterator ends. An error
+/// is yielded once and ends the iterator as well. Dropping the iterator early stops tracing.
+///
+/// ```ignore
+/// for event in handle.events()? {
+///     if let Event::Probe(probe) = event? {
+///         println!("{}:{}", probe.function, probe.name);
+///     }
+/// }
+/// ```
+pub struct Events<'a, B: DtraceBackend + ?Sized> {
+    backend: &'a B,
+    queue: Arc<Mutex<Vec<Event>>>,
+    buffer: VecDeque<Result<Event, Error>>,
+    status: dtrace_status,
+    stopping: bool,
+    finished: bool,
+}
+
+impl<'a, B: DtraceBackend + ?Sized> Events<'a, B> {
+    /// Registers the event handler and starts tracing on a backend whose programs have been executed.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Events)` - If tracing started.
+    /// * `Err(errno)` - If the handler could not be registered or tracing could not be started.
+    pub fn new(backend: &'a mut B) -> Result<Self, Error> {
+        let queue = Arc::new(Mutex::new(Vec::new()));
+        let events = queue.clone();
+        backend.handle_events(Box::new(move |event| {
+            events.lock().unwrap_or_else(PoisonError::into_inner).push(event)
+        }))?;
+        backend.go()?;
+
+        Ok(Self {
+            backend,
+            queue,
+            buffer: VecDeque::new(),
+            status: dtrace_status::Ok,
+            stopping: false,
+            finished: false,
+        })
+    }
+
+    /// Performs one pass of consumer work and buffers its events.
+    fn pass(&mut self) {
+        if self.stopping {
+            self.finish();
+            return;
+        }
+
+        self.backend.sleep();
+        let result = work(self.backend, &self.queue);
+        self.drain();
+        match result {
+            Ok(crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE) => self.stopping = true,
+            Ok(_) => {}
+            Err(err) => return self.fail(err),
+        }
+
+        match self.backend.status() {
+            Ok(dtrace_status::None) => {}
+            Ok(current) => {
+                if current != self.status {
+                    self.status = current;
+                    self.buffer.push_back(Ok(Event::Status(current)));
+                }
+                self.stopping |= StopReason::from_status(current).is_some();
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Stops tracing and buffers what the `END` clause traced.
+    fn finish(&mut self) {
+        self.finished = true;
+        if let Err(err) = self.backend.stop() {
+            self.buffer.push_back(Err(err));
+            return;
+        }
+        let result = work(self.backend, &self.queue);
+        self.drain();
+        if let Err(err) = result {
+            self.buffer.push_back(Err(err));
+        }
+    }
+
+    /// Buffers an error and ends the iteration after stopping tracing.
+    fn fail(&mut self, err: Error) {
+        self.buffer.push_back(Err(err));
+        self.finished = true;
+        let _ = self.backend.stop();
+    }
+
+    /// Moves the events queued by the handlers to the buffer.
+    fn drain(&mut self) {
+        let events = std::mem::take(&mut *self.queue.lock().unwrap_or_else(PoisonError::into_inner));
+        self.buffer.extend(events.into_iter().map(Ok));
+    }
+}
+
+impl<B: DtraceBackend + ?Sized> Iterator for Events<'_, B> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.buffer.pop_front() {
+                return Some(item);
+            }
+            if self.finished {
+                return None;
+            }
+            self.pass();
+        }
+    }
+}
+
+impl<B: DtraceBackend + ?Sized> Drop for Events<'_, B> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.backend.stop();
+        }
+    }
+}
+
+/// Performs one pass of consumer work, queueing a [`ProbeEvent`] per probe that fired.
+pub(crate) fn work<B: DtraceBackend + ?Sized>(
+    backend: &B,
+    queue: &Mutex<Vec<Event>>,
+) -> Result<crate::dtrace_workstatus_t, Error> {
+    let mut records = Vec::new();
+    backend.work(&mut |_| ConsumeAction::This, &mut |probe, record| match record {
+        Some(record) => {
+            records.push(record.clone());
+            // Let libdtrace format the records, so that `printf()` output reaches the buffered handler
+            match record {
+                Record::Exit(_) => ConsumeAction::Next,
+                _ => ConsumeAction::This,
+            }
+        }
+        None => {
+            let event = Event::Probe(ProbeEvent::new(probe, std::mem::take(&mut records)));
+            queue.lock().unwrap_or_else(PoisonError::into_inner).push(event);
+            ConsumeAction::Next
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockBackend, MockProbe};
+
+    fn backend() -> Result<MockBackend, Error> {
+        let backend = MockBackend::new();
+        backend.setopt("switchrate", "1ms")?;
+        Ok(backend)
+    }
+
+    #[test]
+    fn consumer_stops_on_exit() -> Result<(), Error> {
+        let backend = backend()?;
+        backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")]);
+        backend.push_work(vec![MockProbe::new("syscall", "", "write", "entry")]);
+
+        let mut consumer = Consumer::new(backend)?;
+        let mut events = Vec::new();
+        assert_eq!(consumer.run(|event| events.push(event))?, StopReason::Exited);
+
+        let functions: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Probe(probe) => Some(probe.function.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(functions, vec!["read", "write"]);
+        assert_eq!(events.last(), Some(&Event::Status(dtrace_status::Exited)));
+        assert!(!consumer.backend().is_running());
+        assert_eq!(consumer.backend().aggregations_printed(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn consumer_stops_on_request() -> Result<(), Error> {
+        let backend = backend()?;
+        for _ in 0..1000 {
+            backend.push_work(vec![MockProbe::new("profile", "", "", "tick-1ms")]);
+        }
+
+        let mut consumer = Consumer::new(backend)?.print_aggregations(false);
+        let stop = consumer.stop_token();
+        let mut probes = 0;
+        let reason = consumer.run(|event| {
+            if let Event::Probe(_) = event {
+                probes += 1;
+                if probes == 3 {
+                    stop.stop();
+                }
+            }
+        })?;
+
+        assert_eq!(reason, StopReason::Requested);
+        assert_eq!(probes, 3);
+        assert_eq!(consumer.backend().aggregations_printed(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn consumer_honors_the_shortest_rate() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        backend.setopt("statusrate", "10hz")?;
+        backend.setopt("aggrate", "50ms")?;
+        assert_eq!(Consumer::new(backend)?.interval(), Duration::from_millis(50));
+        assert_eq!(Consumer::new(MockBackend::new())?.interval(), DEFAULT_INTERVAL);
+        Ok(())
+    }
+
+    #[test]
+    fn events_iterate_until_exit() -> Result<(), Error> {
+        let mut backend = MockBackend::new();
+        backend.push_work(vec![
+            MockProbe::new("syscall", "", "read", "entry"),
+            MockProbe::new("syscall", "", "read", "return"),
+        ]);
+        backend.push_work(vec![MockProbe::new("syscall", "", "write", "entry")]);
+
+        let events = backend.events()?.collect::<Result<Vec<_>, _>>()?;
+        let names: Vec<_> = events
+            .iter()
+            .map(|event| match event {
+                Event::Probe(probe) => format!("{}:{}", probe.function, probe.name),
+                Event::Status(status) => format!("{:?}", status),
+                event => panic!("unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(names, vec!["read:entry", "read:return", "write:entry", "Exited"]);
+        assert!(!backend.is_running());
+        Ok(())
+    }
+
+    #[test]
+    fn events_stop_when_dropped() -> Result<(), Error> {
+        let mut backend = MockBackend::new();
+        for _ in 0..10 {
+            backend.push_work(vec![MockProbe::new("profile", "", "", "tick-1ms")]);
+        }
+
+        assert_eq!(backend.events()?.take(3).count(), 3);
+        assert!(!backend.is_running());
+        Ok(())
+    }
+}
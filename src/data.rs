@@ -0,0 +1,339 @@
+use std::borrow::Cow;
+
+/// Describes a single record of an enabled probe or aggregation, mirrors `dtrace_recdesc_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordDesc {
+    /// The action that generated the record, one of the `DTRACEACT_*` or `DTRACEAGG_*` values
+    pub action: u16,
+    /// Size of the record data in bytes
+    pub size: u32,
+    /// Offset of the record data from the start of the enabled probe data
+    pub offset: u32,
+    /// Required alignment of the record data
+    pub alignment: u16,
+    /// Index of the printf-like format string of the record, `0` if there is none
+    pub format: u32,
+    /// Action specific argument
+    pub arg: u64,
+    /// User argument
+    pub uarg: u64,
+}
+
+impl From<&crate::dtrace_recdesc_t> for RecordDesc {
+    fn from(value: &crate::dtrace_recdesc_t) -> Self {
+        Self {
+            action: value.dtrd_action,
+            size: value.dtrd_size,
+            offset: value.dtrd_offset,
+            alignment: value.dtrd_alignment,
+            format: value.dtrd_format as u32,
+            arg: value.dtrd_arg,
+            uarg: value.dtrd_uarg,
+        }
+    }
+}
+
+/// Record descriptions borrowed either from libdtrace or from a Rust backend.
+#[derive(Clone, Copy)]
+enum Records<'a> {
+    Raw(&'a [crate::dtrace_recdesc_t]),
+    Owned(&'a [RecordDesc]),
+}
+
+impl<'a> Records<'a> {
+    fn len(&self) -> usize {
+        match self {
+            Records::Raw(records) => records.len(),
+            Records::Owned(records) => records.len(),
+        }
+    }
+
+    fn get(&self, index: usize) -> Option<RecordDesc> {
+        match self {
+            Records::Raw(records) => records.get(index).map(RecordDesc::from),
+            Records::Owned(records) => records.get(index).copied(),
+        }
+    }
+}
+
+/// Iterator over the [`RecordDesc`]s of a [`ProbeData`] or [`AggregateData`].
+///
+/// Skipping records with [`Iterator::nth`] takes constant time.
+#[derive(Clone)]
+pub struct RecordIter<'a> {
+    records: Records<'a>,
+    index: usize,
+}
+
+impl<'a> Iterator for RecordIter<'a> {
+    type Item = RecordDesc;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.records.get(self.index)?;
+        self.index += 1;
+        Some(record)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.index = self.index.saturating_add(n).min(self.records.len());
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.records.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for RecordIter<'a> {}
+
+/// Where the format strings of `printf()`-like records are looked up.
+#[derive(Clone, Copy)]
+enum Formats<'a> {
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    Handle(*mut crate::dtrace_hdl_t),
+    Owned(&'a [String]),
+}
+
+/// Data of a single firing of an enabled probe, passed to the probe and record handlers.
+///
+/// This is a safe view of `dtrace_probedata_t` that is only valid for the duration of the handler call.
+pub struct ProbeData<'a> {
+    cpu: i32,
+    epid: u32,
+    id: u32,
+    provider: Cow<'a, str>,
+    module: Cow<'a, str>,
+    function: Cow<'a, str>,
+    name: Cow<'a, str>,
+    data: &'a [u8],
+    records: Records<'a>,
+    formats: Formats<'a>,
+}
+
+impl<'a> ProbeData<'a> {
+    /// Creates probe data from the pieces a Rust backend keeps around, see [`crate::mock::MockProbe`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        cpu: i32,
+        epid: u32,
+        id: u32,
+        provider: &'a str,
+        module: &'a str,
+        function: &'a str,
+        name: &'a str,
+        data: &'a [u8],
+        records: &'a [RecordDesc],
+    ) -> Self {
+        Self {
+            cpu,
+            epid,
+            id,
+            provider: Cow::Borrowed(provider),
+            module: Cow::Borrowed(module),
+            function: Cow::Borrowed(function),
+            name: Cow::Borrowed(name),
+            data,
+            records: Records::Owned(records),
+            formats: Formats::Owned(&[]),
+        }
+    }
+
+    /// Sets the format strings referenced by [`RecordDesc::format`], the first one has the index `1`.
+    pub fn with_formats(mut self, formats: &'a [String]) -> Self {
+        self.formats = Formats::Owned(formats);
+        self
+    }
+
+    /// Wraps the `dtrace_probedata_t` handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to a valid `dtrace_probedata_t` whose descriptions and data buffer outlive `'a`.
+    pub(crate) unsafe fn from_raw(data: &'a crate::dtrace_probedata_t) -> Self {
+        let pdesc = &*data.dtpda_pdesc;
+        let edesc = &*data.dtpda_edesc;
+        let records = std::slice::from_raw_parts(
+            edesc.dtepd_rec.as_ptr(),
+            edesc.dtepd_nrecs.max(0) as usize,
+        );
+        let bytes = match data.dtpda_data.is_null() {
+            true => &[][..],
+            false => std::slice::from_raw_parts(data.dtpda_data as *const u8, edesc.dtepd_size as usize),
+        };
+
+        Self {
+            cpu: data.dtpda_cpu,
+            epid: edesc.dtepd_epid,
+            id: pdesc.dtpd_id,
+            provider: ::core::ffi::CStr::from_ptr(pdesc.dtpd_provider.as_ptr()).to_string_lossy(),
+            module: ::core::ffi::CStr::from_ptr(pdesc.dtpd_mod.as_ptr()).to_string_lossy(),
+            function: ::core::ffi::CStr::from_ptr(pdesc.dtpd_func.as_ptr()).to_string_lossy(),
+            name: ::core::ffi::CStr::from_ptr(pdesc.dtpd_name.as_ptr()).to_string_lossy(),
+            data: bytes,
+            records: Records::Raw(records),
+            formats: Formats::Handle(data.dtpda_handle),
+        }
+    }
+
+    /// The CPU on which the probe fired.
+    pub fn cpu(&self) -> i32 {
+        self.cpu
+    }
+
+    /// The enabled probe ID (EPID).
+    pub fn epid(&self) -> u32 {
+        self.epid
+    }
+
+    /// The probe ID.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The provider of the probe.
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// The module of the probe.
+    pub fn module(&self) -> &str {
+        &self.module
+    }
+
+    /// The function of the probe.
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// The name of the probe.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The raw data recorded by the enabled probe. Record offsets are relative to the start of this buffer.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The descriptions of the records in [`ProbeData::data`].
+    pub fn records(&self) -> RecordIter<'a> {
+        RecordIter {
+            records: self.records,
+            index: 0,
+        }
+    }
+
+    /// The format string with the given index, see [`RecordDesc::format`].
+    ///
+    /// libdtrace only exposes its format table on Windows. Elsewhere there is no public lookup of a format string by
+    /// its index, so this always returns `None` for probe data handed out by libdtrace; probe data built with
+    /// [`ProbeData::with_formats`] is not affected.
+    pub fn format(&self, index: u32) -> Option<String> {
+        match self.formats {
+            Formats::Owned(formats) => formats.get((index as usize).checked_sub(1)?).cloned(),
+            #[cfg(target_os = "windows")]
+            Formats::Handle(handle) => unsafe {
+                if handle.is_null() {
+                    return None;
+                }
+                let format = crate::dt_format_lookup(handle, index as ::core::ffi::c_int);
+                if format.is_null() {
+                    return None;
+                }
+                let len = crate::dtrace_printf_format(handle, format, std::ptr::null_mut(), 0);
+                let mut buffer = vec![0u8; len];
+                crate::dtrace_printf_format(handle, format, buffer.as_mut_ptr() as *mut _, len);
+                ::core::ffi::CStr::from_bytes_until_nul(&buffer)
+                    .ok()
+                    .map(|format| format.to_string_lossy().into_owned())
+            },
+            #[cfg(not(target_os = "windows"))]
+            Formats::Handle(_) => None,
+        }
+    }
+}
+
+/// Data of a single aggregation record, passed to the `dtrace_aggregate_walk` handler.
+///
+/// This is a safe view of `dtrace_aggdata_t` that is only valid for the duration of the handler call.
+pub struct AggregateData<'a> {
+    id: u32,
+    variable_id: i64,
+    name: Cow<'a, str>,
+    data: &'a [u8],
+    records: Records<'a>,
+}
+
+impl<'a> AggregateData<'a> {
+    /// Creates aggregation data from the pieces a Rust backend keeps around, see [`crate::mock::MockAggregate`].
+    pub fn new(
+        id: u32,
+        variable_id: i64,
+        name: &'a str,
+        data: &'a [u8],
+        records: &'a [RecordDesc],
+    ) -> Self {
+        Self {
+            id,
+            variable_id,
+            name: Cow::Borrowed(name),
+            data,
+            records: Records::Owned(records),
+        }
+    }
+
+    /// Wraps the `dtrace_aggdata_t` handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `data` must point to a valid `dtrace_aggdata_t` whose description and data buffer outlive `'a`.
+    pub(crate) unsafe fn from_raw(data: &'a crate::dtrace_aggdata_t) -> Self {
+        let desc = &*data.dtada_desc;
+        let records = std::slice::from_raw_parts(
+            desc.dtagd_rec.as_ptr(),
+            desc.dtagd_nrecs.max(0) as usize,
+        );
+        let name = match desc.dtagd_name.is_null() {
+            true => Cow::Borrowed(""),
+            false => ::core::ffi::CStr::from_ptr(desc.dtagd_name).to_string_lossy(),
+        };
+
+        Self {
+            id: desc.dtagd_id,
+            variable_id: desc.dtagd_varid,
+            name,
+            data: std::slice::from_raw_parts(data.dtada_data as *const u8, data.dtada_size),
+            records: Records::Raw(records),
+        }
+    }
+
+    /// The aggregation ID.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// The ID of the aggregation variable, shared by all aggregations assigned to the same `@name`.
+    pub fn variable_id(&self) -> i64 {
+        self.variable_id
+    }
+
+    /// The name of the aggregation variable without the leading `@`, empty for the anonymous `@` aggregation.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The raw aggregation data. Record offsets are relative to the start of this buffer.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The descriptions of the records in [`AggregateData::data`]: the aggregation variable ID, the keys and finally
+    /// the value.
+    pub fn records(&self) -> RecordIter<'a> {
+        RecordIter {
+            records: self.records,
+            index: 0,
+        }
+    }
+}
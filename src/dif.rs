@@ -0,0 +1,749 @@
+//! A decoder and disassembler for DIF, the D Intermediate Format that D programs compile to.
+//!
+//! [`DifObject`] is a copy of a `dtrace_difo_t`, e.g. the predicate or an action of a
+//! [`Statement`](crate::program::Statement) on Windows, and displays as the listing `DTRACE_C_DIFV` writes to stderr:
+//!
+//! ```text
+//! DIFO returns D type (integer) (size 4)
+//! OFF OPCODE      INSTRUCTION
+//! 00: 29011601    ldgs DT_VAR(278), %r1         ! DT_VAR(278) = "pid"
+//! 01: 25000002    setx DT_INTEGER[0], %r2       ! 0x4d2
+//! ```
+
+/// The type of a DIF object's result or of a variable, a copy of `dtrace_diftype_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DifType {
+    /// `DIF_TYPE_CTF` (0) or `DIF_TYPE_STRING` (1)
+    pub kind: u8,
+    /// The CTF kind of a `DIF_TYPE_CTF` type, e.g. 1 for an integer
+    pub ckind: u8,
+    /// `DIF_TF_BYREF` (1) if the value is passed by reference
+    pub flags: u8,
+    pub size: u32,
+}
+
+impl From<&crate::dtrace_diftype_t> for DifType {
+    fn from(value: &crate::dtrace_diftype_t) -> Self {
+        Self {
+            kind: value.dtdt_kind,
+            ckind: value.dtdt_ckind,
+            flags: value.dtdt_flags,
+            size: value.dtdt_size,
+        }
+    }
+}
+
+impl std::fmt::Display for DifType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        const CKINDS: &[&str] = &[
+            "unknown", "integer", "float", "pointer", "array", "function", "struct", "union", "enum", "forward",
+            "typedef", "volatile", "const", "restrict",
+        ];
+        match self.kind {
+            DIF_TYPE_CTF => write!(f, "D type")?,
+            DIF_TYPE_STRING => write!(f, "string")?,
+            kind => write!(f, "0x{:x}", kind)?,
+        }
+        match CKINDS.get(self.ckind as usize) {
+            Some(ckind) => write!(f, " ({})", ckind)?,
+            None => write!(f, " (0x{:x})", self.ckind)?,
+        }
+        if self.flags & DIF_TF_BYREF != 0 {
+            write!(f, " by ref")?;
+        }
+        write!(f, " (size {})", self.size)
+    }
+}
+
+const DIF_TYPE_CTF: u8 = 0;
+const DIF_TYPE_STRING: u8 = 1;
+const DIF_TF_BYREF: u8 = 1;
+
+/// The scope of a DIF variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    /// A global variable, including the built-in variables
+    Global,
+    /// A thread-local variable, `self->name`
+    Thread,
+    /// A clause-local variable, `this->name`
+    Local,
+}
+
+impl Scope {
+    fn from_raw(scope: u8) -> Option<Self> {
+        match scope {
+            0 => Some(Scope::Global),
+            1 => Some(Scope::Thread),
+            2 => Some(Scope::Local),
+            _ => None,
+        }
+    }
+}
+
+/// An entry of the variable table of a DIF object, a copy of `dtrace_difv_t`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    /// The name, from the string table
+    pub name: String,
+    pub id: u32,
+    /// `DIFV_KIND_ARRAY` (0) or `DIFV_KIND_SCALAR` (1)
+    pub kind: u8,
+    /// `DIFV_SCOPE_GLOBAL` (0), `DIFV_SCOPE_THREAD` (1) or `DIFV_SCOPE_LOCAL` (2)
+    pub scope: u8,
+    /// `DIFV_F_REF` (1) if the object reads the variable, `DIFV_F_MOD` (2) if it writes it
+    pub flags: u16,
+    pub ty: DifType,
+}
+
+/// The operation of a DIF instruction, numbered as the `DIF_OP_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Or = 1,
+    Xor,
+    And,
+    Sll,
+    Srl,
+    Sub,
+    Add,
+    Mul,
+    Sdiv,
+    Udiv,
+    Srem,
+    Urem,
+    Not,
+    Mov,
+    Cmp,
+    Tst,
+    Ba,
+    Be,
+    Bne,
+    Bg,
+    Bgu,
+    Bge,
+    Bgeu,
+    Bl,
+    Blu,
+    Ble,
+    Bleu,
+    Ldsb,
+    Ldsh,
+    Ldsw,
+    Ldub,
+    Lduh,
+    Lduw,
+    Ldx,
+    Ret,
+    Nop,
+    Setx,
+    Sets,
+    Scmp,
+    Ldga,
+    Ldgs,
+    Stgs,
+    Ldta,
+    Ldts,
+    Stts,
+    Sra,
+    Call,
+    Pushtr,
+    Pushtv,
+    Popts,
+    Flushts,
+    Ldgaa,
+    Ldtaa,
+    Stgaa,
+    Sttaa,
+    Ldls,
+    Stls,
+    Allocs,
+    Copys,
+    Stb,
+    Sth,
+    Stw,
+    Stx,
+    Uldsb,
+    Uldsh,
+    Uldsw,
+    Uldub,
+    Ulduh,
+    Ulduw,
+    Uldx,
+    Rldsb,
+    Rldsh,
+    Rldsw,
+    Rldub,
+    Rlduh,
+    Rlduw,
+    Rldx,
+    Xlate,
+    Xlarg,
+}
+
+/// The opcodes in `DIF_OP_*` order, starting with `DIF_OP_OR` (1).
+const OPCODES: &[Opcode] = {
+    use Opcode::*;
+    &[
+        Or, Xor, And, Sll, Srl, Sub, Add, Mul, Sdiv, Udiv, Srem, Urem, Not, Mov, Cmp, Tst, Ba, Be, Bne, Bg, Bgu, Bge,
+        Bgeu, Bl, Blu, Ble, Bleu, Ldsb, Ldsh, Ldsw, Ldub, Lduh, Lduw, Ldx, Ret, Nop, Setx, Sets, Scmp, Ldga, Ldgs, Stgs,
+        Ldta, Ldts, Stts, Sra, Call, Pushtr, Pushtv, Popts, Flushts, Ldgaa, Ldtaa, Stgaa, Sttaa, Ldls, Stls, Allocs,
+        Copys, Stb, Sth, Stw, Stx, Uldsb, Uldsh, Uldsw, Uldub, Ulduh, Ulduw, Uldx, Rldsb, Rldsh, Rldsw, Rldub, Rlduh,
+        Rlduw, Rldx, Xlate, Xlarg,
+    ]
+};
+
+impl Opcode {
+    /// The opcode numbered `op`, if there is one.
+    pub fn from_u8(op: u8) -> Option<Self> {
+        OPCODES.get((op as usize).checked_sub(1)?).copied()
+    }
+
+    /// The mnemonic, e.g. `ldgs`.
+    pub fn name(&self) -> &'static str {
+        use Opcode::*;
+        match self {
+            Or => "or",
+            Xor => "xor",
+            And => "and",
+            Sll => "sll",
+            Srl => "srl",
+            Sub => "sub",
+            Add => "add",
+            Mul => "mul",
+            Sdiv => "sdiv",
+            Udiv => "udiv",
+            Srem => "srem",
+            Urem => "urem",
+            Not => "not",
+            Mov => "mov",
+            Cmp => "cmp",
+            Tst => "tst",
+            Ba => "ba",
+            Be => "be",
+            Bne => "bne",
+            Bg => "bg",
+            Bgu => "bgu",
+            Bge => "bge",
+            Bgeu => "bgeu",
+            Bl => "bl",
+            Blu => "blu",
+            Ble => "ble",
+            Bleu => "bleu",
+            Ldsb => "ldsb",
+            Ldsh => "ldsh",
+            Ldsw => "ldsw",
+            Ldub => "ldub",
+            Lduh => "lduh",
+            Lduw => "lduw",
+            Ldx => "ldx",
+            Ret => "ret",
+            Nop => "nop",
+            Setx => "setx",
+            Sets => "sets",
+            Scmp => "scmp",
+            Ldga => "ldga",
+            Ldgs => "ldgs",
+            Stgs => "stgs",
+            Ldta => "ldta",
+            Ldts => "ldts",
+            Stts => "stts",
+            Sra => "sra",
+            Call => "call",
+            Pushtr => "pushtr",
+            Pushtv => "pushtv",
+            Popts => "popts",
+            Flushts => "flushts",
+            Ldgaa => "ldgaa",
+            Ldtaa => "ldtaa",
+            Stgaa => "stgaa",
+            Sttaa => "sttaa",
+            Ldls => "ldls",
+            Stls => "stls",
+            Allocs => "allocs",
+            Copys => "copys",
+            Stb => "stb",
+            Sth => "sth",
+            Stw => "stw",
+            Stx => "stx",
+            Uldsb => "uldsb",
+            Uldsh => "uldsh",
+            Uldsw => "uldsw",
+            Uldub => "uldub",
+            Ulduh => "ulduh",
+            Ulduw => "ulduw",
+            Uldx => "uldx",
+            Rldsb => "rldsb",
+            Rldsh => "rldsh",
+            Rldsw => "rldsw",
+            Rldub => "rldub",
+            Rlduh => "rlduh",
+            Rlduw => "rlduw",
+            Rldx => "rldx",
+            Xlate => "xlate",
+            Xlarg => "xlarg",
+        }
+    }
+
+    /// The scope of the variable a variable access reads or writes.
+    pub fn scope(&self) -> Option<Scope> {
+        use Opcode::*;
+        match self {
+            Ldga | Ldgs | Stgs | Ldgaa | Stgaa => Some(Scope::Global),
+            Ldta | Ldts | Stts | Ldtaa | Sttaa => Some(Scope::Thread),
+            Ldls | Stls => Some(Scope::Local),
+            _ => None,
+        }
+    }
+}
+
+/// The operands of a DIF instruction, by the format of its opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    /// `op`, e.g. `nop` or `popts`
+    None,
+    /// `op %r1, %r2, %rd`, arithmetic and logical operations and `copys`
+    Binary { r1: u8, r2: u8, rd: u8 },
+    /// `op %r1, %rd`, e.g. `mov`, `not` or `allocs`
+    Unary { r1: u8, rd: u8 },
+    /// `op %r1, %r2`, `cmp` and `scmp`
+    Compare { r1: u8, r2: u8 },
+    /// `tst %r1`
+    Test { r1: u8 },
+    /// `ret %rd`
+    Return { rd: u8 },
+    /// `op label`, the index of the instruction to branch to
+    Branch { label: u32 },
+    /// `op [%r1], %rd`
+    Load { r1: u8, rd: u8 },
+    /// `op %r1, [%rd]`
+    Store { r1: u8, rd: u8 },
+    /// `setx DT_INTEGER[index], %rd`
+    Integer { index: u16, rd: u8 },
+    /// `sets DT_STRING[offset], %rd`
+    String { offset: u16, rd: u8 },
+    /// `op DT_VAR(var), %r2, %rd`, loads an element of a built-in array such as `args[]`
+    LoadArray { var: u8, r2: u8, rd: u8 },
+    /// `op DT_VAR(var), %rd`
+    LoadVar { var: u16, rd: u8 },
+    /// `op %rs, DT_VAR(var)`
+    StoreVar { rs: u8, var: u16 },
+    /// `call DIF_SUBR(subr), %rd`
+    Call { subr: u16, rd: u8 },
+    /// `op DT_TYPE(ty), %r2, %rs`, pushes onto the tuple stack
+    Push { ty: u8, r2: u8, rs: u8 },
+    /// `op DT_XLREF[xlref], %rd`
+    Translate { xlref: u16, rd: u8 },
+}
+
+/// A decoded DIF instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub operands: Operands,
+}
+
+impl Instruction {
+    /// Decodes an instruction word, `None` if its opcode is not a DIF opcode.
+    pub fn decode(word: u32) -> Option<Self> {
+        use Opcode::*;
+        let [op, r1, r2, rd] = word.to_be_bytes();
+        let wide = (word >> 8) as u16;
+        let opcode = Opcode::from_u8(op)?;
+        let operands = match opcode {
+            Or | Xor | And | Sll | Srl | Sub | Add | Mul | Sdiv | Udiv | Srem | Urem | Sra | Copys => {
+                Operands::Binary { r1, r2, rd }
+            }
+            Not | Mov | Allocs => Operands::Unary { r1, rd },
+            Cmp | Scmp => Operands::Compare { r1, r2 },
+            Tst => Operands::Test { r1 },
+            Ret => Operands::Return { rd },
+            Ba | Be | Bne | Bg | Bgu | Bge | Bgeu | Bl | Blu | Ble | Bleu => Operands::Branch {
+                label: word & 0xff_ffff,
+            },
+            Ldsb | Ldsh | Ldsw | Ldub | Lduh | Lduw | Ldx | Uldsb | Uldsh | Uldsw | Uldub | Ulduh | Ulduw | Uldx
+            | Rldsb | Rldsh | Rldsw | Rldub | Rlduh | Rlduw | Rldx => Operands::Load { r1, rd },
+            Stb | Sth | Stw | Stx => Operands::Store { r1, rd },
+            Setx => Operands::Integer { index: wide, rd },
+            Sets => Operands::String { offset: wide, rd },
+            Ldga | Ldta => Operands::LoadArray { var: r1, r2, rd },
+            Ldgs | Ldts | Ldls | Ldgaa | Ldtaa => Operands::LoadVar { var: wide, rd },
+            Stgs | Stts | Stls | Stgaa | Sttaa => Operands::StoreVar { rs: rd, var: wide },
+            Call => Operands::Call { subr: wide, rd },
+            Pushtr | Pushtv => Operands::Push { ty: r1, r2, rs: rd },
+            Popts | Flushts | Nop => Operands::None,
+            Xlate | Xlarg => Operands::Translate { xlref: wide, rd },
+        };
+        Some(Self { opcode, operands })
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = self.opcode.name();
+        match self.operands {
+            Operands::None => write!(f, "{}", name),
+            Operands::Binary { r1, r2, rd } => write!(f, "{:<4} %r{}, %r{}, %r{}", name, r1, r2, rd),
+            Operands::Unary { r1, rd } => write!(f, "{:<4} %r{}, %r{}", name, r1, rd),
+            Operands::Compare { r1, r2 } => write!(f, "{:<4} %r{}, %r{}", name, r1, r2),
+            Operands::Test { r1 } => write!(f, "{:<4} %r{}", name, r1),
+            Operands::Return { rd } => write!(f, "{:<4} %r{}", name, rd),
+            Operands::Branch { label } => write!(f, "{:<4} {}", name, label),
+            Operands::Load { r1, rd } => write!(f, "{:<4} [%r{}], %r{}", name, r1, rd),
+            Operands::Store { r1, rd } => write!(f, "{:<4} %r{}, [%r{}]", name, r1, rd),
+            Operands::Integer { index, rd } => write!(f, "{:<4} DT_INTEGER[{}], %r{}", name, index, rd),
+            Operands::String { offset, rd } => write!(f, "{:<4} DT_STRING[{}], %r{}", name, offset, rd),
+            Operands::LoadArray { var, r2, rd } => write!(f, "{:<4} DT_VAR({}), %r{}, %r{}", name, var, r2, rd),
+            Operands::LoadVar { var, rd } => write!(f, "{:<4} DT_VAR({}), %r{}", name, var, rd),
+            Operands::StoreVar { rs, var } => write!(f, "{:<4} %r{}, DT_VAR({})", name, rs, var),
+            Operands::Call { subr, rd } => write!(f, "{:<4} DIF_SUBR({}), %r{}", name, subr, rd),
+            Operands::Push { ty, r2, rs } => write!(f, "{:<4} DT_TYPE({}), %r{}, %r{}", name, ty, r2, rs),
+            Operands::Translate { xlref, rd } => write!(f, "{:<4} DT_XLREF[{}], %r{}", name, xlref, rd),
+        }
+    }
+}
+
+/// The built-in variables, from `DIF_VAR_ARGS` (0) to `DIF_VAR_ERRNO` (0x120).
+fn builtin(id: u32) -> Option<&'static str> {
+    const ARGS: &[&str] = &["args", "regs", "uregs"];
+    const OTHER: &[&str] = &[
+        "curthread", "timestamp", "vtimestamp", "ipl", "epid", "id", "arg0", "arg1", "arg2", "arg3", "arg4", "arg5",
+        "arg6", "arg7", "arg8", "arg9", "stackdepth", "caller", "probeprov", "probemod", "probefunc", "probename",
+        "pid", "tid", "execname", "zonename", "walltimestamp", "ustackdepth", "ucaller", "ppid", "uid", "gid", "errno",
+    ];
+    match id {
+        0..=0xff => ARGS.get(id as usize).copied(),
+        _ => OTHER.get(id as usize - 0x100).copied(),
+    }
+}
+
+/// The subroutines shared by the DTrace implementations, from `DIF_SUBR_RAND` (0) to `DIF_SUBR_TOLOWER` (45).
+fn subroutine(subr: u16) -> Option<&'static str> {
+    const SUBRS: &[&str] = &[
+        "rand", "mutex_owned", "mutex_owner", "mutex_type_adaptive", "mutex_type_spin", "rw_read_held",
+        "rw_write_held", "rw_iswriter", "copyin", "copyinstr", "speculation", "progenyof", "strlen", "copyout",
+        "copyoutstr", "alloca", "bcopy", "copyinto", "msgdsize", "msgsize", "getmajor", "getminor", "ddi_pathname",
+        "strjoin", "lltostr", "basename", "dirname", "cleanpath", "strchr", "strrchr", "strstr", "strtok", "substr",
+        "index", "rindex", "htons", "htonl", "htonll", "ntohs", "ntohl", "ntohll", "inet_ntop", "inet_ntoa",
+        "inet_ntoa6", "toupper", "tolower",
+    ];
+    SUBRS.get(subr as usize).copied()
+}
+
+/// A DIF object, the code of a predicate or of an action expression with its tables.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DifObject {
+    /// The instruction words
+    pub instructions: Vec<u32>,
+    /// The integer table, referenced by `setx`
+    pub integers: Vec<u64>,
+    /// The string table, NUL terminated strings referenced by their offset
+    pub strings: Vec<u8>,
+    /// The variable table
+    pub variables: Vec<Variable>,
+    /// The type of the result
+    pub return_type: DifType,
+}
+
+impl DifObject {
+    /// Copies a DIF object built by the compiler.
+    ///
+    /// The decoder follows the instruction format of DTrace for Windows, the other implementations may encode their
+    /// instructions differently.
+    ///
+    /// # Safety
+    ///
+    /// `difo` must point to a valid `dtrace_difo_t`, whose tables hold as many entries as their lengths say.
+    #[cfg(target_os = "windows")]
+    pub(crate) unsafe fn from_raw(difo: &crate::dtrace_difo_t) -> Self {
+        unsafe fn table<T: Clone>(data: *const T, len: u32) -> Vec<T> {
+            match data.is_null() {
+                true => Vec::new(),
+                false => std::slice::from_raw_parts(data, len as usize).to_vec(),
+            }
+        }
+
+        let strings = table(difo.dtdo_strtab as *const u8, difo.dtdo_strlen);
+        let mut object = Self {
+            instructions: table(difo.dtdo_buf, difo.dtdo_len),
+            integers: table(difo.dtdo_inttab, difo.dtdo_intlen),
+            strings,
+            variables: Vec::new(),
+            return_type: DifType::from(&difo.dtdo_rtype),
+        };
+        object.variables = table(difo.dtdo_vartab, difo.dtdo_varlen)
+            .iter()
+            .map(|var| Variable {
+                name: object.string(var.dtdv_name as usize).unwrap_or_default().to_string(),
+                id: var.dtdv_id,
+                kind: var.dtdv_kind,
+                scope: var.dtdv_scope,
+                flags: var.dtdv_flags,
+                ty: DifType::from(&var.dtdv_type),
+            })
+            .collect();
+        object
+    }
+
+    /// The string at `offset` in the string table.
+    pub fn string(&self, offset: usize) -> Option<&str> {
+        let tail = self.strings.get(offset..)?;
+        let end = tail.iter().position(|&byte| byte == 0).unwrap_or(tail.len());
+        std::str::from_utf8(&tail[..end]).ok()
+    }
+
+    /// The name of variable `id` in `scope`, from the variable table or of a built-in variable.
+    pub fn variable_name(&self, id: u32, scope: Scope) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|var| var.id == id && Scope::from_raw(var.scope) == Some(scope))
+            .map(|var| var.name.as_str())
+            .or(match scope {
+                Scope::Global => builtin(id),
+                _ => None,
+            })
+    }
+
+    /// The decoded instructions, `None` for a word with an unknown opcode.
+    pub fn decode(&self) -> Vec<Option<Instruction>> {
+        self.instructions.iter().map(|&word| Instruction::decode(word)).collect()
+    }
+
+    /// What the listing notes after an instruction, e.g. the value of an integer or the name of a variable.
+    fn annotation(&self, instruction: &Instruction) -> Option<String> {
+        let var = |id: u32| {
+            let scope = instruction.opcode.scope()?;
+            let name = self.variable_name(id, scope)?;
+            Some(format!("DT_VAR({}) = \"{}\"", id, name))
+        };
+        match instruction.operands {
+            Operands::Integer { index, .. } => Some(match self.integers.get(index as usize) {
+                Some(value) => format!("0x{:x}", value),
+                None => "invalid integer index".to_string(),
+            }),
+            Operands::String { offset, .. } => Some(match self.string(offset as usize) {
+                Some(value) => format!("{:?}", value),
+                None => "invalid string offset".to_string(),
+            }),
+            Operands::LoadArray { var: id, .. } => var(id as u32),
+            Operands::LoadVar { var: id, .. } | Operands::StoreVar { var: id, .. } => var(id as u32),
+            Operands::Call { subr, .. } => subroutine(subr).map(str::to_string),
+            Operands::Push { ty, .. } => Some(
+                match ty {
+                    DIF_TYPE_CTF => "D type",
+                    DIF_TYPE_STRING => "string",
+                    _ => "unknown type",
+                }
+                .to_string(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for DifObject {
+    /// Writes the listing of the instructions, followed by the integer, string and variable tables.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "DIFO returns {}", self.return_type)?;
+        writeln!(f, "{:<3} {:<8}    INSTRUCTION", "OFF", "OPCODE")?;
+        for (index, &word) in self.instructions.iter().enumerate() {
+            match Instruction::decode(word) {
+                Some(instruction) => match self.annotation(&instruction) {
+                    Some(note) => writeln!(f, "{:02}: {:08x}    {:<29} ! {}", index, word, instruction.to_string(), note)?,
+                    None => writeln!(f, "{:02}: {:08x}    {}", index, word, instruction)?,
+                },
+                None => writeln!(f, "{:02}: {:08x}    (illegal opcode)", index, word)?,
+            }
+        }
+
+        if !self.integers.is_empty() {
+            writeln!(f, "\n{:<6} INTEGER", "INDEX")?;
+            for (index, value) in self.integers.iter().enumerate() {
+                writeln!(f, "{:<6} 0x{:x}", index, value)?;
+            }
+        }
+        if !self.strings.is_empty() {
+            writeln!(f, "\n{:<6} STRING", "OFF")?;
+            let mut offset = 0;
+            while let Some(tail) = self.strings.get(offset..).filter(|tail| !tail.is_empty()) {
+                let string = tail.split(|&byte| byte == 0).next().unwrap_or_default();
+                writeln!(f, "{:<6} {:?}", offset, String::from_utf8_lossy(string))?;
+                offset += string.len() + 1;
+            }
+        }
+        if !self.variables.is_empty() {
+            writeln!(f, "\n{:<16} {:<4} {:<3} {:<3} {:<4} TYPE", "NAME", "ID", "KND", "SCP", "FLAG")?;
+            for var in &self.variables {
+                let kind = match var.kind {
+                    0 => "arr",
+                    1 => "scl",
+                    _ => "?",
+                };
+                let scope = match Scope::from_raw(var.scope) {
+                    Some(Scope::Global) => "glb",
+                    Some(Scope::Thread) => "tls",
+                    Some(Scope::Local) => "loc",
+                    None => "?",
+                };
+                let flags = match (var.flags & 1 != 0, var.flags & 2 != 0) {
+                    (true, true) => "r/w",
+                    (true, false) => "r",
+                    (false, true) => "w",
+                    (false, false) => "-",
+                };
+                writeln!(f, "{:<16} {:<4x} {:<3} {:<3} {:<4} {}", var.name, var.id, kind, scope, flags, var.ty)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles an instruction from its opcode and three operand bytes.
+    fn instr(op: Opcode, a: u8, b: u8, c: u8) -> u32 {
+        u32::from_be_bytes([op as u8, a, b, c])
+    }
+
+    /// Assembles an instruction with a 16-bit operand, e.g. a variable ID, and a register.
+    fn wide(op: Opcode, operand: u16, reg: u8) -> u32 {
+        (op as u32) << 24 | (operand as u32) << 8 | reg as u32
+    }
+
+    #[test]
+    fn decode_opcodes() {
+        assert_eq!(Opcode::from_u8(0), None);
+        assert_eq!(Opcode::from_u8(1), Some(Opcode::Or));
+        assert_eq!(Opcode::from_u8(41), Some(Opcode::Ldgs));
+        assert_eq!(Opcode::from_u8(79), Some(Opcode::Xlarg));
+        assert_eq!(Opcode::from_u8(80), None);
+        assert!(OPCODES.iter().enumerate().all(|(index, &op)| op as usize == index + 1));
+    }
+
+    #[test]
+    fn decode_operands() {
+        let decode = |word| Instruction::decode(word).unwrap();
+        assert_eq!(decode(0x07010203).operands, Operands::Binary { r1: 1, r2: 2, rd: 3 });
+        assert_eq!(decode(0x07010203).to_string(), "add  %r1, %r2, %r3");
+        assert_eq!(decode(0x12000006).operands, Operands::Branch { label: 6 });
+        assert_eq!(decode(0x22050006).to_string(), "ldx  [%r5], %r6");
+        assert_eq!(decode(0x3f050006).to_string(), "stx  %r5, [%r6]");
+        assert_eq!(decode(0x28000203).to_string(), "ldga DT_VAR(0), %r2, %r3");
+        assert_eq!(decode(0x2d050001).to_string(), "stts %r1, DT_VAR(1280)");
+        assert_eq!(decode(0x2f000901).to_string(), "call DIF_SUBR(9), %r1");
+        assert_eq!(decode(0x30010002).to_string(), "pushtr DT_TYPE(1), %r0, %r2");
+        assert_eq!(decode(0x24000000).to_string(), "nop");
+        assert_eq!(Instruction::decode(0xff000000), None);
+    }
+
+    #[test]
+    fn disassemble_predicate() {
+        // pid == 1234
+        let object = DifObject {
+            instructions: vec![
+                wide(Opcode::Ldgs, 0x116, 1),
+                wide(Opcode::Setx, 0, 2),
+                instr(Opcode::Cmp, 1, 2, 0),
+                instr(Opcode::Be, 0, 0, 6),
+                instr(Opcode::Mov, 0, 0, 1),
+                instr(Opcode::Ret, 0, 0, 1),
+                wide(Opcode::Setx, 1, 1),
+                instr(Opcode::Ret, 0, 0, 1),
+            ],
+            integers: vec![1234, 1],
+            return_type: DifType {
+                kind: 0,
+                ckind: 1,
+                flags: 0,
+                size: 4,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            object.to_string(),
+            "DIFO returns D type (integer) (size 4)
+OFF OPCODE      INSTRUCTION
+00: 29011601    ldgs DT_VAR(278), %r1         ! DT_VAR(278) = \"pid\"
+01: 25000002    setx DT_INTEGER[0], %r2       ! 0x4d2
+02: 0f010200    cmp  %r1, %r2
+03: 12000006    be   6
+04: 0e000001    mov  %r0, %r1
+05: 23000001    ret  %r1
+06: 25000101    setx DT_INTEGER[1], %r1       ! 0x1
+07: 23000001    ret  %r1
+
+INDEX  INTEGER
+0      0x4d2
+1      0x1
+"
+        );
+    }
+
+    #[test]
+    fn disassemble_tables() {
+        // self->ts = timestamp; copyinstr(arg0) == "sshd"
+        let object = DifObject {
+            instructions: vec![
+                wide(Opcode::Ldgs, 0x101, 1),
+                wide(Opcode::Stts, 0x500, 1),
+                wide(Opcode::Ldgs, 0x106, 2),
+                instr(Opcode::Pushtv, 0, 0, 2),
+                wide(Opcode::Call, 9, 2),
+                wide(Opcode::Sets, 3, 3),
+                instr(Opcode::Scmp, 2, 3, 0),
+                wide(Opcode::Ldts, 0x501, 4),
+                0x5a000000,
+            ],
+            strings: b"ts\0sshd\0gone\0".to_vec(),
+            variables: vec![Variable {
+                name: "ts".to_string(),
+                id: 0x500,
+                kind: 1,
+                scope: 1,
+                flags: 2,
+                ty: DifType {
+                    kind: 0,
+                    ckind: 1,
+                    flags: 0,
+                    size: 8,
+                },
+            }],
+            return_type: DifType {
+                kind: 1,
+                ckind: 0,
+                flags: 1,
+                size: 256,
+            },
+            ..Default::default()
+        };
+        assert_eq!(object.string(3), Some("sshd"));
+        assert_eq!(object.variable_name(0x500, Scope::Thread), Some("ts"));
+        assert_eq!(object.variable_name(0x500, Scope::Global), None);
+        assert_eq!(object.variable_name(0x118, Scope::Global), Some("execname"));
+        assert_eq!(object.decode().last(), Some(&None));
+        assert_eq!(
+            object.to_string(),
+            "DIFO returns string (unknown) by ref (size 256)
+OFF OPCODE      INSTRUCTION
+00: 29010101    ldgs DT_VAR(257), %r1         ! DT_VAR(257) = \"timestamp\"
+01: 2d050001    stts %r1, DT_VAR(1280)        ! DT_VAR(1280) = \"ts\"
+02: 29010602    ldgs DT_VAR(262), %r2         ! DT_VAR(262) = \"arg0\"
+03: 31000002    pushtv DT_TYPE(0), %r0, %r2   ! D type
+04: 2f000902    call DIF_SUBR(9), %r2         ! copyinstr
+05: 26000303    sets DT_STRING[3], %r3        ! \"sshd\"
+06: 27020300    scmp %r2, %r3
+07: 2c050104    ldts DT_VAR(1281), %r4
+08: 5a000000    (illegal opcode)
+
+OFF    STRING
+0      \"ts\"
+3      \"sshd\"
+8      \"gone\"
+
+NAME             ID   KND SCP FLAG TYPE
+ts               500  scl tls w    D type (integer) (size 8)
+"
+        );
+    }
+}
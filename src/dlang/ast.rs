@@ -0,0 +1,437 @@
+/// A range of bytes in the parsed source.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Span {
+    /// Offset of the first byte
+    pub start: usize,
+    /// Offset past the last byte
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+
+    /// The 1-based line and column of the start of the span in `source`.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+        (line, column)
+    }
+}
+
+/// A parsed D script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    pub items: Vec<Item>,
+    /// The comments of the script, in source order. The `#!` line of an interpreter file is kept as a comment.
+    pub comments: Vec<Comment>,
+}
+
+/// A comment, including its delimiters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A top level item of a D script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    /// `syscall::read:entry /pid == $target/ { ... }`
+    Clause(Clause),
+    /// `#pragma D ...`
+    Pragma(Pragma),
+    /// `#define NAME value`, only in CPP mode
+    Define(Define),
+    /// Any other control line, e.g. `#include <file>` in CPP mode or `#line 1`
+    Directive(Directive),
+    /// `int x;`, `self string name;`, `this int64_t delta;`
+    Declaration(Declaration),
+    /// `inline int NAME = expr;`
+    Inline(Inline),
+    /// `typedef type name;`
+    Typedef(Declarator),
+    /// `struct name { ... };` or `union name { ... };`
+    Struct(StructDef),
+    /// `enum name { ... };`
+    Enum(EnumDef),
+    /// `translator output < input param > { ... };`
+    Translator(Translator),
+    /// `provider name { probe ...; };`
+    Provider(ProviderDef),
+}
+
+impl Item {
+    pub fn span(&self) -> Span {
+        match self {
+            Item::Clause(clause) => clause.span,
+            Item::Pragma(pragma) => pragma.span,
+            Item::Define(define) => define.span,
+            Item::Directive(directive) => directive.span,
+            Item::Declaration(declaration) => declaration.span,
+            Item::Inline(inline) => inline.span,
+            Item::Typedef(declarator) => declarator.span,
+            Item::Struct(def) => def.span,
+            Item::Enum(def) => def.span,
+            Item::Translator(translator) => translator.span,
+            Item::Provider(provider) => provider.span,
+        }
+    }
+}
+
+/// A probe clause: the probe descriptions it enables, its predicate and its actions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clause {
+    pub probes: Vec<ProbeSpec>,
+    pub predicate: Option<Expr>,
+    /// The statements of the clause, `None` for a clause without braces which takes the default action
+    pub body: Option<Vec<Stmt>>,
+    /// The span of the body from its `{` to its `}`
+    pub body_span: Option<Span>,
+    pub span: Span,
+}
+
+/// A probe description as written in a clause, e.g. `syscall::read*:entry` or `tick-1sec`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeSpec {
+    pub text: String,
+    pub span: Span,
+}
+
+/// `#pragma D option name=value` and the other `#pragma D` forms.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pragma {
+    pub kind: PragmaKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PragmaKind {
+    /// `#pragma D option name` or `#pragma D option name=value`
+    Option { name: String, value: Option<String> },
+    /// `#pragma D attributes ...`, `#pragma D depends_on ...` and the like, split into words
+    Other(Vec<String>),
+}
+
+/// `#define NAME body` or `#define NAME(params) body`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Define {
+    pub name: String,
+    /// The parameters of a function-like macro
+    pub params: Option<Vec<String>>,
+    pub body: String,
+    pub span: Span,
+}
+
+/// A control line other than `#pragma` and `#define`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Directive {
+    /// The name of the directive, e.g. `include` or `ifdef`
+    pub name: String,
+    /// The rest of the line
+    pub text: String,
+    pub span: Span,
+}
+
+/// The scope of a variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VarScope {
+    Global,
+    /// Thread-local, `self->`
+    Thread,
+    /// Clause-local, `this->`
+    Clause,
+}
+
+/// A variable declaration, e.g. `self int start;`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub scope: VarScope,
+    /// Storage classes such as `extern` or `static`
+    pub storage: Vec<String>,
+    pub declarators: Vec<Declarator>,
+    pub span: Span,
+}
+
+/// A declared name with its type, e.g. `char *name` or `int counts[string]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declarator {
+    pub ty: TypeName,
+    pub name: String,
+    pub dims: Vec<ArrayDim>,
+    pub span: Span,
+}
+
+/// The dimension of an array or the keys of an associative array.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArrayDim {
+    /// `[10]`
+    Size(Expr),
+    /// `[int, string]`
+    Keys(Vec<TypeName>),
+}
+
+/// A type as written in the script, e.g. `unsigned long`, `struct proc *` or ``nt`_PEB``.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeName {
+    /// The qualifiers and the base type, separated by single spaces
+    pub name: String,
+    /// The number of `*` after the base type
+    pub pointers: usize,
+    pub span: Span,
+}
+
+/// `inline type name = value;`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inline {
+    pub ty: TypeName,
+    pub name: String,
+    pub value: Expr,
+    pub span: Span,
+}
+
+/// `struct name { fields };`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructDef {
+    /// Whether this is a `union` rather than a `struct`
+    pub union: bool,
+    pub name: String,
+    pub fields: Vec<Declarator>,
+    pub span: Span,
+}
+
+/// `enum name { A, B = 2 };`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<(String, Option<Expr>)>,
+    pub span: Span,
+}
+
+/// `translator output < input param > { member = expr; ... };`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translator {
+    pub output: TypeName,
+    pub input: TypeName,
+    pub param: String,
+    pub members: Vec<(String, Expr)>,
+    pub span: Span,
+}
+
+/// `provider name { probe probe-name(args); ... };` from a USDT provider definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderDef {
+    pub name: String,
+    pub probes: Vec<ProbeDecl>,
+    pub span: Span,
+}
+
+/// `probe name(type, ...)` in a provider definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeDecl {
+    pub name: String,
+    pub args: Vec<TypeName>,
+    pub span: Span,
+}
+
+/// A statement of a clause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stmt {
+    /// An action or an assignment, e.g. `trace(pid);` or `self->ts = timestamp;`
+    Expr(Expr),
+    /// `@name[keys] = function(args);`
+    Aggregate(Aggregate),
+    /// `if (cond) { ... } else { ... }`
+    ///
+    /// The spans of the branches run from their `{` to their `}`, or cover the statement of a branch without braces.
+    If {
+        cond: Expr,
+        then: Vec<Stmt>,
+        then_span: Span,
+        otherwise: Option<Vec<Stmt>>,
+        otherwise_span: Option<Span>,
+        span: Span,
+    },
+    /// A nested block, `{ ... }`
+    Block { stmts: Vec<Stmt>, span: Span },
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expr(expr) => expr.span,
+            Stmt::Aggregate(aggregate) => aggregate.span,
+            Stmt::If { span, .. } | Stmt::Block { span, .. } => *span,
+        }
+    }
+}
+
+/// An aggregating statement, e.g. `@bytes[execname] = sum(arg2);`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aggregate {
+    /// The name of the aggregation without the `@`, empty for the anonymous aggregation
+    pub name: String,
+    pub keys: Vec<Expr>,
+    /// The aggregating function, e.g. `count` or `quantize`
+    pub function: String,
+    pub args: Vec<Expr>,
+    pub span: Span,
+}
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExprKind {
+    /// An integer constant, with its text as written, e.g. `0x10` or `1ULL`
+    Int { value: u64, text: String },
+    /// A character constant, with its text between the quotes
+    Char(String),
+    /// A string constant, with its escapes resolved into bytes, which need not be valid UTF-8
+    Str(Vec<u8>),
+    /// A variable, constant or built-in such as `pid` or `arg0`
+    Ident(String),
+    /// A kernel symbol, ``` `max_ncpus ``` or ``` genunix`max_ncpus ```
+    Symbol { module: Option<String>, name: String },
+    /// A macro variable, `$1` or `$target`, or `$$1` for its string form
+    MacroVar { name: String, quoted: bool },
+    /// `self->name` or `this->name`
+    Var { scope: VarScope, name: String },
+    /// An aggregation reference, e.g. `@counts` in `printa(@counts)`
+    Aggregation { name: String, keys: Vec<Expr> },
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    /// `expr++` or `expr--`
+    Postfix { op: UnaryOp, expr: Box<Expr> },
+    Binary { op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr> },
+    /// `lhs = rhs` or a compound assignment such as `lhs += rhs`, `op` is `None` for a plain assignment
+    Assign { op: Option<BinaryOp>, lhs: Box<Expr>, rhs: Box<Expr> },
+    Ternary { cond: Box<Expr>, then: Box<Expr>, otherwise: Box<Expr> },
+    /// `lhs, rhs`, which evaluates `lhs` and then `rhs`
+    Comma { lhs: Box<Expr>, rhs: Box<Expr> },
+    /// A call of an action or a subroutine, e.g. `printf("%d", pid)`
+    Call { function: String, args: Vec<Expr> },
+    /// `base[keys]`
+    Index { base: Box<Expr>, keys: Vec<Expr> },
+    /// `base.member`, or `base->member` if `arrow` is set
+    Member { base: Box<Expr>, member: String, arrow: bool },
+    Cast { ty: TypeName, expr: Box<Expr> },
+    SizeofType(TypeName),
+    SizeofExpr(Box<Expr>),
+    Stringof(Box<Expr>),
+    Offsetof { ty: TypeName, member: String },
+    /// `xlate <type> (expr)`
+    Xlate { ty: TypeName, expr: Box<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// `-`
+    Neg,
+    /// `+`
+    Plus,
+    /// `!`
+    Not,
+    /// `~`
+    BitNot,
+    /// `*`
+    Deref,
+    /// `&`
+    AddrOf,
+    /// `++`
+    Inc,
+    /// `--`
+    Dec,
+}
+
+impl UnaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::Not => "!",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Deref => "*",
+            UnaryOp::AddrOf => "&",
+            UnaryOp::Inc => "++",
+            UnaryOp::Dec => "--",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    /// `^^`, the logical exclusive or of D
+    Xor,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitOr => "|",
+            BinaryOp::And => "&&",
+            BinaryOp::Xor => "^^",
+            BinaryOp::Or => "||",
+        }
+    }
+
+    /// The binding power of the operator, higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 10,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitOr => 3,
+            BinaryOp::And => 2,
+            BinaryOp::Xor => 1,
+            BinaryOp::Or => 0,
+        }
+    }
+}
@@ -0,0 +1,829 @@
+//! Builds D programs in Rust instead of formatting their source, see [`Program`].
+//!
+//! Strings are always emitted as escaped string constants, and probe descriptions, identifiers and options are
+//! validated when the program is rendered, so values from user input cannot change the structure of the program.
+
+use super::ast::{self, BinaryOp, ExprKind, Item, Span, Stmt, UnaryOp, VarScope};
+use super::format::Formatter;
+use crate::args::MacroArgs;
+use crate::utils::Error;
+use crate::wrapper::dtrace_hdl;
+
+/// Why a [`Program`] cannot be rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildError {
+    pub message: String,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// A D program built clause by clause, compiled with [`Program::compile`] or rendered with [`Program::to_source`].
+///
+/// ```
+/// use libdtrace_rs::dlang::builder::*;
+///
+/// let program = Program::new().option("quiet", None).clause(
+///     probe("syscall:::entry")
+///         .predicate(pid().ne(self_pid()))
+///         .action(printf("%s %s\n", [execname(), probefunc()])),
+/// );
+/// let source = program.to_source()?;
+/// assert!(source.starts_with("#pragma D option quiet\n\nsyscall:::entry\n/pid != $pid/\n"));
+/// assert!(source.contains("printf(\"%s %s\\n\", execname, probefunc);"));
+/// # Ok::<(), libdtrace_rs::dlang::builder::BuildError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Program {
+    items: Vec<Item>,
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets an option with `#pragma D option name` or `#pragma D option name=value`.
+    ///
+    /// The name and the value may only contain ASCII letters, digits and `_.+-`, rendering fails otherwise.
+    pub fn option(mut self, name: &str, value: Option<&str>) -> Self {
+        self.items.push(Item::Pragma(ast::Pragma {
+            kind: ast::PragmaKind::Option {
+                name: name.to_string(),
+                value: value.map(str::to_string),
+            },
+            span: Span::default(),
+        }));
+        self
+    }
+
+    /// Adds a probe clause.
+    pub fn clause(mut self, clause: Clause) -> Self {
+        self.items.push(Item::Clause(ast::Clause {
+            probes: clause.probes,
+            predicate: clause.predicate,
+            body: Some(clause.actions),
+            body_span: None,
+            span: Span::default(),
+        }));
+        self
+    }
+
+    /// Renders the D source of the program.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The source, formatted by [`Formatter`].
+    /// * `Err(BuildError)` - If a probe description, identifier, type or option is not valid D.
+    pub fn to_source(&self) -> Result<String, BuildError> {
+        for item in &self.items {
+            validate_item(item)?;
+        }
+        Ok(Formatter::new().format_script(&ast::Script {
+            items: self.items.clone(),
+            comments: Vec::new(),
+        }))
+    }
+
+    /// Renders the program and compiles it with [`dtrace_hdl::dtrace_program_strcompile`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Program)` - The compiled program.
+    /// * `Err(errno)` - `EINVAL` if the program cannot be rendered, or the compiler error.
+    pub fn compile<'h>(
+        &self,
+        handle: &'h dtrace_hdl,
+        flags: u32,
+        args: Option<&MacroArgs>,
+    ) -> Result<crate::program::Program<'h>, Error> {
+        let source = self.to_source()?;
+        handle.dtrace_program_strcompile(&source, crate::dtrace_probespec::DTRACE_PROBESPEC_NAME, flags, args)
+    }
+}
+
+/// A probe clause, created by [`probe`].
+#[derive(Debug, Clone)]
+pub struct Clause {
+    probes: Vec<ast::ProbeSpec>,
+    predicate: Option<ast::Expr>,
+    actions: Vec<Stmt>,
+}
+
+/// Starts a clause enabling the probes matching `spec`, e.g. `syscall::read:entry`.
+pub fn probe(spec: &str) -> Clause {
+    Clause {
+        probes: Vec::new(),
+        predicate: None,
+        actions: Vec::new(),
+    }
+    .probe(spec)
+}
+
+impl Clause {
+    /// Also enables the probes matching `spec`.
+    pub fn probe(mut self, spec: &str) -> Self {
+        self.probes.push(ast::ProbeSpec {
+            text: spec.to_string(),
+            span: Span::default(),
+        });
+        self
+    }
+
+    /// Sets the predicate, a second predicate is combined with the first by `&&`.
+    pub fn predicate(mut self, predicate: Expr) -> Self {
+        self.predicate = Some(match self.predicate.take() {
+            Some(first) => Expr(first).and(predicate).0,
+            None => predicate.0,
+        });
+        self
+    }
+
+    /// Appends an action.
+    pub fn action(mut self, action: impl Into<Action>) -> Self {
+        self.actions.push(action.into().0);
+        self
+    }
+}
+
+/// A D expression.
+#[derive(Debug, Clone)]
+pub struct Expr(ast::Expr);
+
+fn expr(kind: ExprKind) -> Expr {
+    Expr(ast::Expr {
+        kind,
+        span: Span::default(),
+    })
+}
+
+impl Expr {
+    fn binary(self, op: BinaryOp, rhs: impl Into<Expr>) -> Expr {
+        expr(ExprKind::Binary {
+            op,
+            lhs: Box::new(self.0),
+            rhs: Box::new(rhs.into().0),
+        })
+    }
+
+    /// `self == rhs`
+    pub fn eq(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Eq, rhs)
+    }
+
+    /// `self != rhs`
+    pub fn ne(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Ne, rhs)
+    }
+
+    /// `self < rhs`
+    pub fn lt(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Lt, rhs)
+    }
+
+    /// `self <= rhs`
+    pub fn le(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Le, rhs)
+    }
+
+    /// `self > rhs`
+    pub fn gt(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Gt, rhs)
+    }
+
+    /// `self >= rhs`
+    pub fn ge(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Ge, rhs)
+    }
+
+    /// `self && rhs`
+    pub fn and(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::And, rhs)
+    }
+
+    /// `self || rhs`
+    pub fn or(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Or, rhs)
+    }
+
+    /// `self ^^ rhs`
+    pub fn xor(self, rhs: impl Into<Expr>) -> Expr {
+        self.binary(BinaryOp::Xor, rhs)
+    }
+
+    /// `self[keys]`
+    pub fn index(self, keys: impl IntoIterator<Item = Expr>) -> Expr {
+        expr(ExprKind::Index {
+            base: Box::new(self.0),
+            keys: keys.into_iter().map(|key| key.0).collect(),
+        })
+    }
+
+    /// `self.member`
+    pub fn member(self, member: &str) -> Expr {
+        expr(ExprKind::Member {
+            base: Box::new(self.0),
+            member: member.to_string(),
+            arrow: false,
+        })
+    }
+
+    /// `self->member`
+    pub fn arrow(self, member: &str) -> Expr {
+        expr(ExprKind::Member {
+            base: Box::new(self.0),
+            member: member.to_string(),
+            arrow: true,
+        })
+    }
+
+    /// `(ty)self`, where `ty` is a type such as `uint64_t` or `struct proc *`.
+    pub fn cast(self, ty: &str) -> Expr {
+        let name = ty.trim_end_matches(|c: char| c == '*' || c.is_whitespace());
+        expr(ExprKind::Cast {
+            ty: ast::TypeName {
+                name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+                pointers: ty[name.len()..].matches('*').count(),
+                span: Span::default(),
+            },
+            expr: Box::new(self.0),
+        })
+    }
+
+    /// `self ? then : otherwise`
+    pub fn select(self, then: impl Into<Expr>, otherwise: impl Into<Expr>) -> Expr {
+        expr(ExprKind::Ternary {
+            cond: Box::new(self.0),
+            then: Box::new(then.into().0),
+            otherwise: Box::new(otherwise.into().0),
+        })
+    }
+
+    /// `self = value`, e.g. `self_var("ts").assign(timestamp())`.
+    pub fn assign(self, value: impl Into<Expr>) -> Action {
+        Action::from(expr(ExprKind::Assign {
+            op: None,
+            lhs: Box::new(self.0),
+            rhs: Box::new(value.into().0),
+        }))
+    }
+
+    /// The AST of the expression.
+    pub fn as_ast(&self) -> &ast::Expr {
+        &self.0
+    }
+}
+
+macro_rules! binary_ops {
+    ($($trait:ident :: $method:ident => $op:ident),* $(,)?) => {
+        $(
+            impl<T: Into<Expr>> std::ops::$trait<T> for Expr {
+                type Output = Expr;
+
+                fn $method(self, rhs: T) -> Expr {
+                    self.binary(BinaryOp::$op, rhs)
+                }
+            }
+        )*
+    };
+}
+
+binary_ops! {
+    Add::add => Add,
+    Sub::sub => Sub,
+    Mul::mul => Mul,
+    Div::div => Div,
+    Rem::rem => Mod,
+    BitAnd::bitand => BitAnd,
+    BitOr::bitor => BitOr,
+    BitXor::bitxor => BitXor,
+    Shl::shl => Shl,
+    Shr::shr => Shr,
+}
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        expr(ExprKind::Unary {
+            op: UnaryOp::Not,
+            expr: Box::new(self.0),
+        })
+    }
+}
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        expr(ExprKind::Unary {
+            op: UnaryOp::Neg,
+            expr: Box::new(self.0),
+        })
+    }
+}
+
+impl From<i64> for Expr {
+    fn from(value: i64) -> Self {
+        int(value)
+    }
+}
+
+impl From<i32> for Expr {
+    fn from(value: i32) -> Self {
+        int(value as i64)
+    }
+}
+
+impl From<u64> for Expr {
+    fn from(value: u64) -> Self {
+        uint(value)
+    }
+}
+
+impl From<u32> for Expr {
+    fn from(value: u32) -> Self {
+        uint(value as u64)
+    }
+}
+
+impl From<&str> for Expr {
+    fn from(value: &str) -> Self {
+        string(value)
+    }
+}
+
+impl From<String> for Expr {
+    fn from(value: String) -> Self {
+        string(&value)
+    }
+}
+
+/// An integer constant.
+pub fn int(value: i64) -> Expr {
+    match value < 0 {
+        true => -uint(value.unsigned_abs()),
+        false => uint(value as u64),
+    }
+}
+
+/// An unsigned integer constant.
+pub fn uint(value: u64) -> Expr {
+    expr(ExprKind::Int {
+        value,
+        text: value.to_string(),
+    })
+}
+
+/// A string constant, escaped as needed.
+pub fn string(value: &str) -> Expr {
+    expr(ExprKind::Str(value.as_bytes().to_vec()))
+}
+
+/// A global variable, a constant or a built-in variable such as `curthread`.
+pub fn var(name: &str) -> Expr {
+    expr(ExprKind::Ident(name.to_string()))
+}
+
+/// A thread-local variable, `self->name`.
+pub fn self_var(name: &str) -> Expr {
+    expr(ExprKind::Var {
+        scope: VarScope::Thread,
+        name: name.to_string(),
+    })
+}
+
+/// A clause-local variable, `this->name`.
+pub fn this_var(name: &str) -> Expr {
+    expr(ExprKind::Var {
+        scope: VarScope::Clause,
+        name: name.to_string(),
+    })
+}
+
+/// A macro variable, e.g. `$target` for `macro_var("target")` or `$1` for `macro_var("1")`.
+pub fn macro_var(name: &str) -> Expr {
+    expr(ExprKind::MacroVar {
+        name: name.to_string(),
+        quoted: false,
+    })
+}
+
+/// The process ID of the consumer, `$pid`.
+pub fn self_pid() -> Expr {
+    macro_var("pid")
+}
+
+/// A call of a subroutine, e.g. `call("copyinstr", [arg(0)])`.
+pub fn call(function: &str, args: impl IntoIterator<Item = Expr>) -> Expr {
+    expr(ExprKind::Call {
+        function: function.to_string(),
+        args: args.into_iter().map(|arg| arg.0).collect(),
+    })
+}
+
+/// An aggregation, for actions such as `printa()` or `clear()`.
+pub fn aggregation(name: &str) -> Expr {
+    expr(ExprKind::Aggregation {
+        name: name.to_string(),
+        keys: Vec::new(),
+    })
+}
+
+/// The probe argument `argN`.
+pub fn arg(n: u8) -> Expr {
+    var(&format!("arg{}", n))
+}
+
+/// The typed probe argument `args[N]`.
+pub fn args(n: u8) -> Expr {
+    var("args").index([uint(n as u64)])
+}
+
+macro_rules! builtins {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[doc = concat!("The built-in variable `", stringify!($name), "`.")]
+            pub fn $name() -> Expr {
+                var(stringify!($name))
+            }
+        )*
+    };
+}
+
+builtins!(
+    pid, ppid, tid, uid, gid, cpu, execname, timestamp, vtimestamp, walltimestamp, probeprov, probemod, probefunc,
+    probename, curthread, errno,
+);
+
+/// A statement of a clause, created by the action functions, [`Expr::assign`] or [`aggregate`].
+#[derive(Debug, Clone)]
+pub struct Action(Stmt);
+
+impl From<Expr> for Action {
+    fn from(expr: Expr) -> Self {
+        Action(Stmt::Expr(expr.0))
+    }
+}
+
+/// Any action, e.g. `action("stack", [])`.
+pub fn action(function: &str, args: impl IntoIterator<Item = Expr>) -> Action {
+    Action::from(call(function, args))
+}
+
+/// `trace(value)`
+pub fn trace(value: impl Into<Expr>) -> Action {
+    action("trace", [value.into()])
+}
+
+/// `printf(format, args)`
+pub fn printf(format: &str, args: impl IntoIterator<Item = Expr>) -> Action {
+    action("printf", std::iter::once(string(format)).chain(args))
+}
+
+/// `printa(@name)`
+pub fn printa(name: &str) -> Action {
+    action("printa", [aggregation(name)])
+}
+
+/// `exit(status)`
+pub fn exit(status: i32) -> Action {
+    action("exit", [int(status as i64)])
+}
+
+/// Starts an aggregating action on `@name`, the anonymous aggregation if `name` is empty.
+///
+/// ```
+/// use libdtrace_rs::dlang::builder::*;
+///
+/// let bytes = aggregate("bytes").key(execname()).sum(arg(2));
+/// let program = Program::new().clause(probe("syscall::write:entry").action(bytes));
+/// assert!(program.to_source()?.contains("@bytes[execname] = sum(arg2);"));
+/// # Ok::<(), libdtrace_rs::dlang::builder::BuildError>(())
+/// ```
+pub fn aggregate(name: &str) -> Aggregation {
+    Aggregation {
+        name: name.to_string(),
+        keys: Vec::new(),
+    }
+}
+
+/// An aggregation and its keys, completed into an [`Action`] by its aggregating function.
+#[derive(Debug, Clone)]
+pub struct Aggregation {
+    name: String,
+    keys: Vec<ast::Expr>,
+}
+
+impl Aggregation {
+    /// Adds a key.
+    pub fn key(mut self, key: impl Into<Expr>) -> Self {
+        self.keys.push(key.into().0);
+        self
+    }
+
+    fn function(self, function: &str, args: impl IntoIterator<Item = Expr>) -> Action {
+        Action(Stmt::Aggregate(ast::Aggregate {
+            name: self.name,
+            keys: self.keys,
+            function: function.to_string(),
+            args: args.into_iter().map(|arg| arg.0).collect(),
+            span: Span::default(),
+        }))
+    }
+
+    /// `= count()`
+    pub fn count(self) -> Action {
+        self.function("count", [])
+    }
+
+    /// `= sum(value)`
+    pub fn sum(self, value: impl Into<Expr>) -> Action {
+        self.function("sum", [value.into()])
+    }
+
+    /// `= avg(value)`
+    pub fn avg(self, value: impl Into<Expr>) -> Action {
+        self.function("avg", [value.into()])
+    }
+
+    /// `= min(value)`
+    pub fn min(self, value: impl Into<Expr>) -> Action {
+        self.function("min", [value.into()])
+    }
+
+    /// `= max(value)`
+    pub fn max(self, value: impl Into<Expr>) -> Action {
+        self.function("max", [value.into()])
+    }
+
+    /// `= stddev(value)`
+    pub fn stddev(self, value: impl Into<Expr>) -> Action {
+        self.function("stddev", [value.into()])
+    }
+
+    /// `= quantize(value)`
+    pub fn quantize(self, value: impl Into<Expr>) -> Action {
+        self.function("quantize", [value.into()])
+    }
+
+    /// `= lquantize(value, low, high, step)`
+    pub fn lquantize(self, value: impl Into<Expr>, low: i64, high: i64, step: i64) -> Action {
+        self.function("lquantize", [value.into(), int(low), int(high), int(step)])
+    }
+
+    /// `= llquantize(value, factor, low, high, steps)`
+    pub fn llquantize(self, value: impl Into<Expr>, factor: u16, low: u16, high: u16, steps: u16) -> Action {
+        let params = [factor, low, high, steps].map(|param| uint(param as u64));
+        self.function("llquantize", std::iter::once(value.into()).chain(params))
+    }
+}
+
+fn invalid(what: &str, text: &str) -> BuildError {
+    BuildError {
+        message: format!("invalid {} `{}`", what, text.escape_debug()),
+    }
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn ident(text: &str) -> Result<(), BuildError> {
+    match is_ident(text) {
+        true => Ok(()),
+        false => Err(invalid("identifier", text)),
+    }
+}
+
+/// Whether `text` is a word allowed in an option name or value, which cannot end the control line or open a comment.
+fn is_option_word(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "_.+-".contains(c))
+}
+
+fn validate_item(item: &Item) -> Result<(), BuildError> {
+    match item {
+        Item::Pragma(ast::Pragma {
+            kind: ast::PragmaKind::Option { name, value },
+            ..
+        }) => {
+            if !is_option_word(name) {
+                return Err(invalid("option", name));
+            }
+            match value {
+                Some(value) if !is_option_word(value) => Err(invalid("option value", value)),
+                _ => Ok(()),
+            }
+        }
+        Item::Clause(clause) => {
+            for probe in &clause.probes {
+                let mut chars = probe.text.chars();
+                let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || "-$:_.?*\\[]!".contains(c))
+                    && chars.all(|c| c.is_ascii_alphanumeric() || "-$:_.`?*\\[]!".contains(c));
+                if !valid {
+                    return Err(invalid("probe description", &probe.text));
+                }
+            }
+            clause.predicate.iter().try_for_each(validate_expr)?;
+            clause.body.iter().flatten().try_for_each(validate_stmt)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_stmt(stmt: &Stmt) -> Result<(), BuildError> {
+    match stmt {
+        Stmt::Expr(expr) => validate_expr(expr),
+        Stmt::Aggregate(aggregate) => {
+            if !aggregate.name.is_empty() {
+                ident(&aggregate.name)?;
+            }
+            ident(&aggregate.function)?;
+            aggregate.keys.iter().chain(&aggregate.args).try_for_each(validate_expr)
+        }
+        Stmt::If {
+            cond, then, otherwise, ..
+        } => {
+            validate_expr(cond)?;
+            then.iter().chain(otherwise.iter().flatten()).try_for_each(validate_stmt)
+        }
+        Stmt::Block { stmts, .. } => stmts.iter().try_for_each(validate_stmt),
+    }
+}
+
+fn validate_expr(expr: &ast::Expr) -> Result<(), BuildError> {
+    match &expr.kind {
+        ExprKind::Int { .. } | ExprKind::Char(_) | ExprKind::Str(_) => Ok(()),
+        ExprKind::Ident(name) | ExprKind::Var { name, .. } => ident(name),
+        ExprKind::Symbol { module, name } => {
+            module.iter().try_for_each(|module| ident(module))?;
+            ident(name)
+        }
+        ExprKind::MacroVar { name, .. } => match !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            true => Ok(()),
+            false => Err(invalid("macro variable", name)),
+        },
+        ExprKind::Aggregation { name, keys } => {
+            if !name.is_empty() {
+                ident(name)?;
+            }
+            keys.iter().try_for_each(validate_expr)
+        }
+        ExprKind::Unary { expr, .. }
+        | ExprKind::Postfix { expr, .. }
+        | ExprKind::SizeofExpr(expr)
+        | ExprKind::Stringof(expr) => validate_expr(expr),
+        ExprKind::Binary { lhs, rhs, .. } | ExprKind::Assign { lhs, rhs, .. } | ExprKind::Comma { lhs, rhs } => {
+            validate_expr(lhs)?;
+            validate_expr(rhs)
+        }
+        ExprKind::Ternary { cond, then, otherwise } => {
+            validate_expr(cond)?;
+            validate_expr(then)?;
+            validate_expr(otherwise)
+        }
+        ExprKind::Call { function, args } => {
+            ident(function)?;
+            args.iter().try_for_each(validate_expr)
+        }
+        ExprKind::Index { base, keys } => {
+            validate_expr(base)?;
+            keys.iter().try_for_each(validate_expr)
+        }
+        ExprKind::Member { base, member, .. } => {
+            validate_expr(base)?;
+            ident(member)
+        }
+        ExprKind::Cast { ty, expr } | ExprKind::Xlate { ty, expr } => {
+            validate_type(ty)?;
+            validate_expr(expr)
+        }
+        ExprKind::SizeofType(ty) => validate_type(ty),
+        ExprKind::Offsetof { ty, member } => {
+            validate_type(ty)?;
+            ident(member)
+        }
+    }
+}
+
+fn validate_type(ty: &ast::TypeName) -> Result<(), BuildError> {
+    let valid = !ty.name.is_empty()
+        && ty.name.split(' ').all(|word| {
+            let mut scoped = word.splitn(2, '`');
+            scoped.all(is_ident)
+        });
+    match valid {
+        true => Ok(()),
+        false => Err(invalid("type", &ty.name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlang::{parse, ParseError};
+
+    #[test]
+    fn render_program() -> Result<(), BuildError> {
+        let program = Program::new().option("quiet", None).option("bufsize", Some("4m")).clause(
+            probe("syscall:::entry")
+                .predicate(pid().ne(self_pid()))
+                .action(printf("%s %d\n", [execname(), (arg(0) + 1) * 2]))
+                .action(aggregate("calls").key(probefunc()).count())
+                .action(self_var("ts").assign(timestamp())),
+        );
+        let expected = "#pragma D option quiet
+#pragma D option bufsize=4m
+
+syscall:::entry
+/pid != $pid/
+{
+    printf(\"%s %d\\n\", execname, (arg0 + 1) * 2);
+    @calls[probefunc] = count();
+    self->ts = timestamp;
+}
+";
+        assert_eq!(program.to_source()?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn render_escapes_strings() -> Result<(), ParseError> {
+        let input = "\"); system(\"rm -rf /\"); trace(\"\\\n";
+        let source = Program::new()
+            .clause(probe("BEGIN").action(trace(input)).action(exit(0)))
+            .to_source()
+            .unwrap();
+        let script = parse(&source)?;
+        match &script.items[0] {
+            Item::Clause(ast::Clause { body: Some(body), .. }) => {
+                assert_eq!(body.len(), 2);
+                match &body[0] {
+                    Stmt::Expr(ast::Expr {
+                        kind: ExprKind::Call { args, .. },
+                        ..
+                    }) => assert_eq!(args[0].kind, ExprKind::Str(input.as_bytes().to_vec())),
+                    stmt => panic!("unexpected statement {:?}", stmt),
+                }
+            }
+            item => panic!("unexpected item {:?}", item),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn render_rejects_injection() {
+        let clause = |spec: &str| Program::new().clause(probe(spec).action(trace(1))).to_source();
+        assert!(clause("syscall::read:entry").is_ok());
+        assert_eq!(
+            clause("BEGIN { system(\"id\"); } END").unwrap_err().message,
+            "invalid probe description `BEGIN { system(\\\"id\\\"); } END`"
+        );
+        assert!(Program::new().option("quiet\n", None).to_source().is_err());
+        assert!(Program::new().option("bufsize", Some("4m; x")).to_source().is_err());
+        assert!(Program::new().option("bufsize", Some("4m")).to_source().is_ok());
+        assert!(Program::new().option("switchrate", Some("10hz")).to_source().is_ok());
+        for text in ["4m/*", "4m//", "4m\"", "/*"] {
+            assert_eq!(
+                Program::new().option("bufsize", Some(text)).to_source().unwrap_err().message,
+                format!("invalid option value `{}`", text.escape_debug())
+            );
+            assert!(Program::new().option(text, None).to_source().is_err());
+        }
+        assert!(Program::new().clause(probe("BEGIN").action(trace(var("x; y")))).to_source().is_err());
+        assert!(Program::new().clause(probe("BEGIN").action(aggregate("a b").count())).to_source().is_err());
+    }
+
+    #[test]
+    fn render_expressions() -> Result<(), ParseError> {
+        let program = Program::new().clause(
+            probe("fbt::*read*:entry")
+                .probe("fbt::*write*:entry")
+                .predicate(!self_var("skip"))
+                .predicate(args(0).arrow("b_flags").cast("uint64_t") & 1)
+                .action(this_var("len").assign(arg(2).gt(0).select(arg(2), -1)))
+                .action(aggregate("").key(call("stringof", [arg(1)])).lquantize(this_var("len"), 0, 1024, 64)),
+        );
+        let source = program.to_source().unwrap();
+        assert_eq!(
+            source,
+            "fbt::*read*:entry,
+fbt::*write*:entry
+/!self->skip && (uint64_t)args[0]->b_flags & 1/
+{
+    this->len = arg2 > 0 ? arg2 : -1;
+    @[stringof(arg1)] = lquantize(this->len, 0, 1024, 64);
+}
+"
+        );
+        parse(&source)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,456 @@
+use super::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Actions that need the `destructive` option, i.e. `dtrace -w`.
+const DESTRUCTIVE: &[&str] = &[
+    "stop", "raise", "copyout", "copyoutstr", "system", "freopen", "breakpoint", "panic", "chill",
+];
+
+/// Actions that take a `printf`-like format and its arguments.
+const PRINTF_LIKE: &[&str] = &["printf", "system", "freopen"];
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    /// The script is likely to work, but probably not as intended
+    Warning,
+    /// `dtrace_program_strcompile` or `dtrace_program_exec` will reject the script
+    Error,
+}
+
+/// A problem found by [`Checker::check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable name of the check that raised the diagnostic, e.g. `printf-arguments`
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}[{}]: {}", severity, self.code, self.message)
+    }
+}
+
+/// Checks D scripts for common mistakes, before they are handed to `dtrace_program_strcompile`.
+///
+/// | Code | Severity | Flags |
+/// |------|----------|-------|
+/// | `undefined-this` | error | a `this->` variable read before it is assigned by the clauses of the same probe |
+/// | `aggregation-keys` | error | an aggregation used with different numbers of keys |
+/// | `aggregation-function` | error | an aggregation used with different aggregating functions |
+/// | `printf-arguments` | error | a `printf()` format that does not match the number of arguments |
+/// | `self-not-cleared` | warning | a `self->` variable that is never assigned `0` and leaks dynamic variable space |
+/// | `destructive` | error | a destructive action without the `destructive` option |
+///
+/// ```
+/// use libdtrace_rs::dlang::{self, Checker};
+///
+/// let script = dlang::parse("BEGIN { printf(\"%d %d\\n\", pid); }")?;
+/// let diagnostics = Checker::new().check(&script);
+/// assert_eq!(diagnostics[0].code, "printf-arguments");
+/// for diagnostic in diagnostics {
+///     println!("{}", diagnostic);
+/// }
+/// # Ok::<(), dlang::ParseError>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Checker {
+    destructive: bool,
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows destructive actions, as the script will be run with the `destructive` option set outside of it.
+    pub fn destructive(mut self, destructive: bool) -> Self {
+        self.destructive = destructive;
+        self
+    }
+
+    /// Checks the script.
+    ///
+    /// # Returns
+    ///
+    /// The diagnostics, in the order of their spans.
+    pub fn check(&self, script: &Script) -> Vec<Diagnostic> {
+        let destructive = self.destructive
+            || script.items.iter().any(|item| {
+                matches!(item, Item::Pragma(Pragma { kind: PragmaKind::Option { name, .. }, .. }) if name == "destructive")
+            });
+        let mut context = Context {
+            destructive,
+            ..Default::default()
+        };
+
+        // `this->` variables declared at the top level, and the probes of the previous clause, whose `this->`
+        // variables are still set when the same probes fire the next clause
+        let mut this_declared = HashSet::new();
+        let mut previous_probes: Option<Vec<&str>> = None;
+        for item in &script.items {
+            match item {
+                Item::Declaration(declaration) if declaration.scope == VarScope::Clause => {
+                    let names = declaration.declarators.iter().map(|declarator| declarator.name.clone());
+                    this_declared.extend(names);
+                }
+                Item::Clause(clause) => {
+                    let probes: Vec<_> = clause.probes.iter().map(|probe| probe.text.as_str()).collect();
+                    if previous_probes.as_ref() != Some(&probes) {
+                        context.this_defined = this_declared.clone();
+                    }
+                    previous_probes = Some(probes);
+                    if let Some(predicate) = &clause.predicate {
+                        context.expr(predicate);
+                    }
+                    for stmt in clause.body.iter().flatten() {
+                        context.stmt(stmt);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut leaked: Vec<_> = context
+            .self_assigned
+            .iter()
+            .filter(|(name, _)| !context.self_cleared.contains(*name))
+            .collect();
+        leaked.sort_by_key(|(_, span)| **span);
+        for (name, span) in leaked {
+            context.diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                code: "self-not-cleared",
+                message: format!("`self->{}` is never assigned 0, its dynamic variable space is never freed", name),
+                span: *span,
+            });
+        }
+
+        context.diagnostics.sort_by_key(|diagnostic| diagnostic.span);
+        context.diagnostics
+    }
+}
+
+/// Checks a script with the default options, see [`Checker`].
+pub fn check(script: &Script) -> Vec<Diagnostic> {
+    Checker::new().check(script)
+}
+
+/// An aggregation as first used in the script.
+struct AggregationUse {
+    keys: usize,
+    function: String,
+}
+
+#[derive(Default)]
+struct Context {
+    destructive: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// `this->` variables declared or assigned so far in the clauses firing for the current probes
+    this_defined: HashSet<String>,
+    aggregations: HashMap<String, AggregationUse>,
+    /// The first assignment of each `self->` variable
+    self_assigned: HashMap<String, Span>,
+    self_cleared: HashSet<String>,
+}
+
+impl Context {
+    fn error(&mut self, code: &'static str, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            span,
+        });
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => self.expr(expr),
+            Stmt::Aggregate(aggregate) => {
+                aggregate.keys.iter().chain(&aggregate.args).for_each(|expr| self.expr(expr));
+                self.aggregate(aggregate);
+            }
+            Stmt::If {
+                cond, then, otherwise, ..
+            } => {
+                self.expr(cond);
+                then.iter().chain(otherwise.iter().flatten()).for_each(|stmt| self.stmt(stmt));
+            }
+            Stmt::Block { stmts, .. } => stmts.iter().for_each(|stmt| self.stmt(stmt)),
+        }
+    }
+
+    fn aggregate(&mut self, aggregate: &Aggregate) {
+        let display = format!("@{}", aggregate.name);
+        let first = match self.aggregations.get(&aggregate.name) {
+            Some(first) => first,
+            None => {
+                let first = AggregationUse {
+                    keys: aggregate.keys.len(),
+                    function: aggregate.function.clone(),
+                };
+                self.aggregations.insert(aggregate.name.clone(), first);
+                return;
+            }
+        };
+
+        let keys = |count: usize| match count {
+            1 => "1 key".to_string(),
+            count => format!("{} keys", count),
+        };
+        let mismatches = [
+            (first.keys != aggregate.keys.len()).then(|| {
+                let message = format!(
+                    "`{}` is indexed by {} here, but by {} before",
+                    display,
+                    keys(aggregate.keys.len()),
+                    keys(first.keys)
+                );
+                ("aggregation-keys", message)
+            }),
+            (first.function != aggregate.function).then(|| {
+                let message = format!(
+                    "`{}` is aggregated with `{}()` here, but with `{}()` before",
+                    display, aggregate.function, first.function
+                );
+                ("aggregation-function", message)
+            }),
+        ];
+        for (code, message) in mismatches.into_iter().flatten() {
+            self.error(code, message, aggregate.span);
+        }
+    }
+
+    /// Checks a read of `this->name`.
+    fn read_this(&mut self, name: &str, span: Span) {
+        if !self.this_defined.contains(name) {
+            self.error("undefined-this", format!("`this->{}` is read before it is assigned", name), span);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match &expr.kind {
+            ExprKind::Var {
+                scope: VarScope::Clause,
+                name,
+            } => self.read_this(name, expr.span),
+            ExprKind::Assign { op, lhs, rhs } => {
+                self.expr(rhs);
+                match &lhs.kind {
+                    ExprKind::Var {
+                        scope: VarScope::Clause,
+                        name,
+                    } => {
+                        if op.is_some() {
+                            self.read_this(name, lhs.span);
+                        }
+                        self.this_defined.insert(name.clone());
+                    }
+                    ExprKind::Var {
+                        scope: VarScope::Thread,
+                        name,
+                    } => match (op, &rhs.kind) {
+                        (None, ExprKind::Int { value: 0, .. }) => {
+                            self.self_cleared.insert(name.clone());
+                        }
+                        _ => {
+                            self.self_assigned.entry(name.clone()).or_insert(lhs.span);
+                        }
+                    },
+                    _ => self.expr(lhs),
+                }
+            }
+            ExprKind::Call { function, args } => {
+                args.iter().for_each(|arg| self.expr(arg));
+                if DESTRUCTIVE.contains(&function.as_str()) && !self.destructive {
+                    self.error(
+                        "destructive",
+                        format!("`{}()` is a destructive action, set the `destructive` option to allow it", function),
+                        expr.span,
+                    );
+                }
+                if PRINTF_LIKE.contains(&function.as_str()) {
+                    self.printf(function, args, expr.span);
+                }
+            }
+            ExprKind::Aggregation { keys, .. } => keys.iter().for_each(|key| self.expr(key)),
+            ExprKind::Unary { expr, .. }
+            | ExprKind::Postfix { expr, .. }
+            | ExprKind::Cast { expr, .. }
+            | ExprKind::SizeofExpr(expr)
+            | ExprKind::Stringof(expr)
+            | ExprKind::Xlate { expr, .. } => self.expr(expr),
+            ExprKind::Binary { lhs, rhs, .. } | ExprKind::Comma { lhs, rhs } => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprKind::Ternary { cond, then, otherwise } => {
+                self.expr(cond);
+                self.expr(then);
+                self.expr(otherwise);
+            }
+            ExprKind::Index { base, keys } => {
+                self.expr(base);
+                keys.iter().for_each(|key| self.expr(key));
+            }
+            ExprKind::Member { base, .. } => self.expr(base),
+            ExprKind::Var { .. }
+            | ExprKind::Int { .. }
+            | ExprKind::Char(_)
+            | ExprKind::Str(_)
+            | ExprKind::Ident(_)
+            | ExprKind::Symbol { .. }
+            | ExprKind::MacroVar { .. }
+            | ExprKind::SizeofType(_)
+            | ExprKind::Offsetof { .. } => {}
+        }
+    }
+
+    /// Checks that the format of a `printf`-like action matches its arguments.
+    fn printf(&mut self, function: &str, args: &[Expr], span: Span) {
+        let format = match args.first().map(|arg| &arg.kind) {
+            Some(ExprKind::Str(format)) => format,
+            _ => return,
+        };
+        match format_arguments(&String::from_utf8_lossy(format)) {
+            Ok(expected) if expected != args.len() - 1 => self.error(
+                "printf-arguments",
+                format!(
+                    "the format of `{}()` takes {} argument{}, but {} {} given",
+                    function,
+                    expected,
+                    if expected == 1 { "" } else { "s" },
+                    args.len() - 1,
+                    if args.len() == 2 { "was" } else { "were" }
+                ),
+                span,
+            ),
+            Ok(_) => {}
+            Err(message) => self.error("printf-arguments", message, args[0].span),
+        }
+    }
+}
+
+/// The number of arguments a `printf` format consumes, including `*` widths and precisions.
+fn format_arguments(format: &str) -> Result<usize, String> {
+    let mut count = 0;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            continue;
+        }
+        while chars.next_if(|c| "-+ #0'".contains(*c)).is_some() {}
+        if chars.next_if_eq(&'*').is_some() {
+            count += 1;
+        }
+        while chars.next_if(char::is_ascii_digit).is_some() {}
+        if chars.next_if_eq(&'.').is_some() {
+            if chars.next_if_eq(&'*').is_some() {
+                count += 1;
+            }
+            while chars.next_if(char::is_ascii_digit).is_some() {}
+        }
+        while chars.next_if(|c| "hlLjztq".contains(*c)).is_some() {}
+        match chars.next() {
+            Some('w') if chars.next_if(|c| *c == 'c' || *c == 's').is_some() => count += 1,
+            Some(conversion) if "aAcCdeEfFgGiIkmopPsSTuxXY".contains(conversion) => count += 1,
+            Some(conversion) => return Err(format!("invalid conversion `%{}` in format", conversion)),
+            None => return Err("incomplete conversion at the end of the format".to_string()),
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlang::{parse, ParseError};
+
+    fn codes(source: &str) -> Result<Vec<(Severity, &'static str, String)>, ParseError> {
+        let script = parse(source)?;
+        Ok(check(&script)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.code, source[diagnostic.span.start..diagnostic.span.end].to_string()))
+            .collect())
+    }
+
+    #[test]
+    fn check_clean_script() -> Result<(), ParseError> {
+        let source = "syscall::read:entry { self->ts = timestamp; this->fd = arg0; }
+            syscall::read:entry /this->fd > 2/ { @[execname] = count(); }
+            syscall::read:return /self->ts/ { @[probefunc] = count(); printf(\"%5d%% %*s\\n\", pid, 8, execname); self->ts = 0; }";
+        assert_eq!(codes(source)?, []);
+        Ok(())
+    }
+
+    #[test]
+    fn check_undefined_this() -> Result<(), ParseError> {
+        let source = "BEGIN { trace(this->x); this->y += 1; this->z = 1; trace(this->z); } this int w; END { trace(this->w); }";
+        assert_eq!(
+            codes(source)?,
+            [
+                (Severity::Error, "undefined-this", "this->x".to_string()),
+                (Severity::Error, "undefined-this", "this->y".to_string()),
+            ]
+        );
+
+        // Clause-local variables do not outlive the probe firing that assigned them
+        let source = "BEGIN { this->x = 1; } BEGIN { trace(this->x); } END { trace(this->x); }";
+        assert_eq!(codes(source)?, [(Severity::Error, "undefined-this", "this->x".to_string())]);
+        let source = "BEGIN { this->x = 1; } END { trace(this->x); }";
+        assert_eq!(codes(source)?, [(Severity::Error, "undefined-this", "this->x".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_aggregations() -> Result<(), ParseError> {
+        let source = "BEGIN { @a[pid] = count(); @b = sum(1); } END { @a[pid, tid] = count(); @b = max(1); }";
+        assert_eq!(
+            codes(source)?,
+            [
+                (Severity::Error, "aggregation-keys", "@a[pid, tid] = count()".to_string()),
+                (Severity::Error, "aggregation-function", "@b = max(1)".to_string()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn check_printf_arguments() -> Result<(), ParseError> {
+        let script = parse("BEGIN { printf(\"%s %d\\n\", execname); printf(\"%.*s\", 3, \"abc\"); printf(\"%y\", 1); }")?;
+        let diagnostics = check(&script);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "the format of `printf()` takes 2 arguments, but 1 was given");
+        assert_eq!(diagnostics[1].to_string(), "error[printf-arguments]: invalid conversion `%y` in format");
+        Ok(())
+    }
+
+    #[test]
+    fn check_self_not_cleared() -> Result<(), ParseError> {
+        let source = "BEGIN { self->a = 1; self->b = 2; } END { self->a = 0; }";
+        assert_eq!(codes(source)?, [(Severity::Warning, "self-not-cleared", "self->b".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn check_destructive() -> Result<(), ParseError> {
+        let source = "BEGIN { stop(); system(\"date\"); }";
+        let script = parse(source)?;
+        assert_eq!(check(&script).len(), 2);
+        assert!(Checker::new().destructive(true).check(&script).is_empty());
+        assert!(check(&parse(&format!("#pragma D option destructive\n{}", source))?).is_empty());
+        Ok(())
+    }
+}
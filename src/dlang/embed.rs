@@ -0,0 +1,159 @@
+use crate::args::{MacroArg, MacroArgs};
+use crate::program::Program;
+use crate::utils::Error;
+use crate::wrapper::dtrace_hdl;
+
+/// A D program embedded with [`d!`](crate::d), its syntax was checked when the crate was compiled.
+///
+/// The Rust values interpolated with `$(expr)` and `$$(expr)` are the macro arguments `$1`, `$2`, ... of the
+/// program, in the order they appear. A program that only parses with its C preprocessor directives, such as
+/// `#define`, is compiled with `DTRACE_C_CPP`.
+///
+/// ```compile_fail
+/// let program = libdtrace_rs::d! { BEGIN { trace(1 +); } };
+/// ```
+///
+/// A program that interpolates values cannot refer to macro arguments by number, as `$1` is the first value:
+///
+/// ```compile_fail
+/// let pid = 1234;
+/// let program = libdtrace_rs::d! { BEGIN /pid == $(pid)/ { trace($1); } };
+/// ```
+///
+/// ```no_run
+/// use libdtrace_rs::{d, DTRACE_C_ZDEFS};
+/// # let handle = libdtrace_rs::wrapper::dtrace_hdl::dtrace_open(libdtrace_rs::DTRACE_VERSION as i32, 0)?;
+/// let target = 1234;
+/// let mut prog = d! { syscall:::entry /pid == $(target)/ { @[probefunc] = count(); } }
+///     .compile(&handle, DTRACE_C_ZDEFS)?;
+/// handle.dtrace_program_exec(&mut prog, None)?;
+/// # Ok::<(), libdtrace_rs::utils::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Embedded {
+    source: &'static str,
+    cpp: bool,
+    args: MacroArgs,
+}
+
+impl Embedded {
+    /// Called by the expansion of [`d!`](crate::d).
+    #[doc(hidden)]
+    pub fn new(source: &'static str, cpp: bool, args: MacroArgs) -> Self {
+        Self { source, cpp, args }
+    }
+
+    /// The D source, with `$n` or `$$n` in place of each interpolated value.
+    pub fn source(&self) -> &'static str {
+        self.source
+    }
+
+    /// Whether the program has C preprocessor directives, so it is compiled with `DTRACE_C_CPP`.
+    pub fn cpp(&self) -> bool {
+        self.cpp
+    }
+
+    /// The macro arguments, starting with the interpolated values.
+    pub fn args(&self) -> &MacroArgs {
+        &self.args
+    }
+
+    /// Appends a macro argument, numbered after the interpolated values.
+    ///
+    /// Only a program without interpolated values can refer to it, as `$1`, `$2`, ...
+    pub fn arg(mut self, arg: impl Into<MacroArg>) -> Self {
+        self.args = self.args.arg(arg.into());
+        self
+    }
+
+    /// Compiles the program with its macro arguments, see [`dtrace_hdl::dtrace_program_strcompile`].
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - The handle to compile the program with.
+    /// * `flags` - The `DTRACE_C_*` compiler flags, `DTRACE_C_CPP` is added if [`Embedded::cpp`] is set.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Program)` - The compiled program.
+    /// * `Err(errno)` - If the program failed to compile, e.g. because a probe or a variable does not exist.
+    pub fn compile<'h>(&self, handle: &'h dtrace_hdl, flags: u32) -> Result<Program<'h>, Error> {
+        let flags = match self.cpp {
+            true => flags | crate::DTRACE_C_CPP,
+            false => flags,
+        };
+        handle.dtrace_program_strcompile(
+            self.source,
+            crate::dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            flags,
+            Some(&self.args),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::MacroArg;
+    use crate::d;
+    use crate::dlang::parse;
+
+    #[test]
+    fn embed_tokens() {
+        let program = d! {
+            syscall::read:entry, syscall::write:entry
+            /pid != $pid && self->ts == 0/
+            {
+                @bytes[execname, probefunc] = sum(arg2);
+                printf("%s\n", execname);
+            }
+        };
+        assert!(program.source().contains("syscall::read:entry, syscall::write:entry\n"));
+        assert!(program.source().contains("/pid != $pid && self->ts == 0/"));
+        assert!(program.source().contains("printf(\"%s\\n\", execname);"));
+        assert!(program.args().args().is_empty());
+        assert!(parse(program.source()).is_ok());
+    }
+
+    #[test]
+    fn embed_interpolation() {
+        let name = "sshd\"); system(\"id";
+        let pid = 1234u32;
+        let program = d! {
+            proc:::exec-success
+            /execname == $$(name) && ppid == $(pid)/
+            {
+                trace($(-1) + $pid);
+            }
+        };
+        assert!(program.source().contains("/execname == $$1 && ppid == $2/"));
+        assert!(program.source().contains("trace($3 + $pid);"));
+        assert_eq!(
+            program.clone().arg("extra").args().args(),
+            &[
+                MacroArg::String(name.to_string()),
+                MacroArg::UInt(1234),
+                MacroArg::Int(-1),
+                MacroArg::String("extra".to_string()),
+            ]
+        );
+
+        // Without interpolated values, the macro arguments are numbered by the caller
+        let program = d! { BEGIN /pid == $1/ { trace($$2); } }.arg(1234u32).arg("sshd");
+        assert!(program.source().contains("/pid == $1/ { trace($$2); }"));
+        assert_eq!(program.args().args().len(), 2);
+    }
+
+    #[test]
+    fn embed_string() {
+        let program = d!(r#"
+            /* a comment, and a symbol */
+            BEGIN { trace(`max_ncpus); exit(0); }
+        "#);
+        assert!(program.source().contains("trace(`max_ncpus);"));
+        assert_eq!(d!("BEGIN { exit(0); }").source(), "BEGIN { exit(0); }");
+        assert!(!program.cpp());
+
+        let program = d!("#define LIMIT 10\nsyscall::read:entry /arg2 > LIMIT/ { @ = count(); }");
+        assert!(program.cpp());
+    }
+}
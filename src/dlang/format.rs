@@ -0,0 +1,750 @@
+use super::ast::*;
+use super::lexer::escape;
+use super::{ParseError, Parser};
+
+/// The binding power of the comma operator, the loosest expressions.
+const COMMA: u8 = 0;
+/// The binding power of assignments.
+const ASSIGN: u8 = 1;
+/// The binding power of `?:`.
+const TERNARY: u8 = 2;
+/// The binding power of the loosest binary operator, see [`BinaryOp::precedence`].
+const BINARY: u8 = 3;
+/// The binding power of prefix operators, casts and `sizeof`.
+const UNARY: u8 = 20;
+/// The binding power of calls, subscripts, member accesses and postfix operators.
+const POSTFIX: u8 = 21;
+const PRIMARY: u8 = 22;
+
+/// Formats D scripts in a canonical layout.
+///
+/// Every probe description of a clause goes on its own line, followed by the predicate and the braces of the body on
+/// their own lines. Statements are indented one level per block and end with `;`, expressions are spaced around binary
+/// operators and only keep the parentheses they need. Comments are kept, on the line of the statement they follow or
+/// on their own line before the next statement, and so are the blank lines separating statements.
+///
+/// ```
+/// use libdtrace_rs::dlang::Formatter;
+///
+/// let formatter = Formatter::new().indent("\t");
+/// let source = "BEGIN{trace(1);}";
+/// assert!(!formatter.check(source)?);
+/// assert_eq!(formatter.format(source)?, "BEGIN\n{\n\ttrace(1);\n}\n");
+/// # Ok::<(), libdtrace_rs::dlang::ParseError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    indent: String,
+    cpp: bool,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            indent: "    ".to_string(),
+            cpp: false,
+        }
+    }
+}
+
+impl Formatter {
+    /// Creates a formatter indenting by four spaces.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the text of one level of indentation, e.g. `"\t"`.
+    pub fn indent(mut self, indent: &str) -> Self {
+        self.indent = indent.to_string();
+        self
+    }
+
+    /// Accepts the C preprocessor directives, see [`Parser::cpp`].
+    pub fn cpp(mut self, cpp: bool) -> Self {
+        self.cpp = cpp;
+        self
+    }
+
+    /// Formats a script.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(String)` - The formatted script.
+    /// * `Err(ParseError)` - If the script cannot be parsed.
+    pub fn format(&self, source: &str) -> Result<String, ParseError> {
+        let script = Parser::new(source).cpp(self.cpp).parse()?;
+        Ok(self.write(source, &script, &script.comments))
+    }
+
+    /// Formats a script built in Rust rather than parsed, e.g. by [`super::builder::Program`].
+    ///
+    /// The spans of the script are ignored, and so are its comments.
+    pub fn format_script(&self, script: &Script) -> String {
+        self.write("", script, &[])
+    }
+
+    fn write(&self, source: &str, script: &Script, comments: &[Comment]) -> String {
+        let mut writer = Writer {
+            source,
+            indent: &self.indent,
+            comments,
+            next_comment: 0,
+            out: String::new(),
+            level: 0,
+            last_end: None,
+            line_comment: false,
+        };
+        writer.script(script);
+        writer.out
+    }
+
+    /// Checks whether a script is already formatted.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(bool)` - Whether [`Formatter::format`] leaves the script unchanged.
+    /// * `Err(ParseError)` - If the script cannot be parsed.
+    pub fn check(&self, source: &str) -> Result<bool, ParseError> {
+        Ok(self.format(source)? == source)
+    }
+}
+
+/// Formats a script with the default options, see [`Formatter`].
+pub fn format(source: &str) -> Result<String, ParseError> {
+    Formatter::new().format(source)
+}
+
+struct Writer<'a> {
+    source: &'a str,
+    indent: &'a str,
+    comments: &'a [Comment],
+    /// The first comment not written yet
+    next_comment: usize,
+    out: String,
+    level: usize,
+    /// The end of the source of the last line written
+    last_end: Option<usize>,
+    /// Whether the last line written ends with a `//` comment, which would swallow another comment appended to it
+    line_comment: bool,
+}
+
+impl Writer<'_> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.level {
+            self.out.push_str(self.indent);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+        self.line_comment = false;
+    }
+
+    /// Separates the next line by a blank line, unless it is the first line or the first line of a block.
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether the source has a blank line between `start` and `end`.
+    fn blank_between(&self, start: usize, end: usize) -> bool {
+        if start > end {
+            return false;
+        }
+        let mut lines = self.source[start..end].split('\n');
+        lines.next();
+        lines.next_back();
+        lines.any(|line| line.trim().is_empty())
+    }
+
+    /// The next comment not written yet if it starts before `pos`.
+    fn next_comment(&self, pos: usize) -> Option<&Comment> {
+        self.comments.get(self.next_comment).filter(|comment| comment.span.start < pos)
+    }
+
+    /// Whether `comment` is inside the last node written or on its line.
+    fn is_trailing(&self, comment: &Comment) -> bool {
+        self.last_end
+            .is_some_and(|end| comment.span.start < end || !self.source[end..comment.span.start].contains('\n'))
+    }
+
+    /// Appends the comments inside the last node written or on its line to its line.
+    fn trailing_comments(&mut self, pos: usize) {
+        while let Some(comment) = self.next_comment(pos).filter(|comment| self.is_trailing(comment)) {
+            let text = self.reindent(&comment.text);
+            let end = comment.span.end.max(self.last_end.unwrap_or_default());
+            self.next_comment += 1;
+            match self.line_comment {
+                true => self.line(&text),
+                false => {
+                    self.out.pop();
+                    self.out.push(' ');
+                    self.out.push_str(&text);
+                    self.out.push('\n');
+                }
+            }
+            self.line_comment = text.starts_with("//");
+            self.last_end = Some(end);
+        }
+    }
+
+    /// Writes the comments before `pos`, each on its own line unless it trails the last node written.
+    fn comments_before(&mut self, pos: usize) {
+        self.trailing_comments(pos);
+        while let Some(comment) = self.next_comment(pos) {
+            let (text, span) = (self.reindent(&comment.text), comment.span);
+            self.next_comment += 1;
+            if self.last_end.is_some_and(|end| self.blank_between(end, span.start)) {
+                self.blank_line();
+            }
+            self.line(&text);
+            self.last_end = Some(span.end);
+            self.trailing_comments(pos);
+        }
+    }
+
+    /// Writes the comments before `pos` and keeps a blank line of the source before it.
+    fn comments_and_blank_before(&mut self, pos: usize) {
+        self.comments_before(pos);
+        if self.last_end.is_some_and(|end| self.blank_between(end, pos)) {
+            self.blank_line();
+        }
+    }
+
+    /// Indents the continuation lines of a block comment to the current level, aligning their leading `*`.
+    fn reindent(&self, text: &str) -> String {
+        let mut lines = text.split('\n');
+        let mut result = lines.next().unwrap_or_default().trim_end().to_string();
+        for line in lines {
+            result.push('\n');
+            let line = line.trim();
+            if !line.is_empty() {
+                result.push_str(&self.indent.repeat(self.level));
+                if line.starts_with('*') {
+                    result.push(' ');
+                }
+                result.push_str(line);
+            }
+        }
+        result
+    }
+
+    fn script(&mut self, script: &Script) {
+        let mut previous: Option<&Item> = None;
+        for item in &script.items {
+            let span = item.span();
+            let grouped = matches!(
+                (previous, item),
+                (
+                    Some(Item::Pragma(_) | Item::Define(_) | Item::Directive(_)),
+                    Item::Pragma(_) | Item::Define(_) | Item::Directive(_)
+                ) | (Some(Item::Declaration(_)), Item::Declaration(_))
+                    | (Some(Item::Inline(_)), Item::Inline(_))
+                    | (Some(Item::Typedef(_)), Item::Typedef(_))
+            );
+            self.trailing_comments(span.start);
+            if previous.is_some() && !grouped {
+                self.blank_line();
+            }
+            self.comments_and_blank_before(span.start);
+            self.item(item);
+            self.last_end = Some(span.end);
+            previous = Some(item);
+        }
+        self.comments_before(self.source.len());
+    }
+
+    fn item(&mut self, item: &Item) {
+        match item {
+            Item::Clause(clause) => self.clause(clause),
+            Item::Pragma(pragma) => match &pragma.kind {
+                PragmaKind::Option { name, value: Some(value) } => self.line(&format!("#pragma D option {}={}", name, value)),
+                PragmaKind::Option { name, value: None } => self.line(&format!("#pragma D option {}", name)),
+                PragmaKind::Other(words) => self.line(&format!("#pragma D {}", words.join(" "))),
+            },
+            Item::Define(define) => {
+                let mut text = format!("#define {}", define.name);
+                if let Some(params) = &define.params {
+                    text.push_str(&format!("({})", params.join(", ")));
+                }
+                if !define.body.is_empty() {
+                    text.push(' ');
+                    text.push_str(&define.body);
+                }
+                self.line(&text);
+            }
+            Item::Directive(directive) => match directive.text.is_empty() {
+                true => self.line(&format!("#{}", directive.name)),
+                false => self.line(&format!("#{} {}", directive.name, directive.text)),
+            },
+            Item::Declaration(declaration) => {
+                let mut words: Vec<String> = declaration.storage.clone();
+                match declaration.scope {
+                    VarScope::Global => {}
+                    VarScope::Thread => words.push("self".to_string()),
+                    VarScope::Clause => words.push("this".to_string()),
+                }
+                let declarators: Vec<_> = declaration.declarators.iter().map(|declarator| self.declarator(declarator)).collect();
+                words.push(declarators.join(", "));
+                self.line(&format!("{};", words.join(" ")));
+            }
+            Item::Inline(inline) => {
+                let ty = type_name(&inline.ty);
+                let value = self.expr(&inline.value, ASSIGN);
+                self.line(&format!("inline {}{} = {};", ty, spaced(&inline.ty, &inline.name), value));
+            }
+            Item::Typedef(declarator) => {
+                let declarator = self.declarator(declarator);
+                self.line(&format!("typedef {};", declarator));
+            }
+            Item::Struct(def) => {
+                self.line(&format!("{} {} {{", if def.union { "union" } else { "struct" }, def.name));
+                self.last_end = Some(def.span.start);
+                self.level += 1;
+                for field in &def.fields {
+                    self.comments_and_blank_before(field.span.start);
+                    let text = self.declarator(field);
+                    self.line(&format!("{};", text));
+                    self.last_end = Some(field.span.end);
+                }
+                self.comments_before(def.span.end);
+                self.level -= 1;
+                self.line("};");
+            }
+            Item::Enum(def) => {
+                self.line(&format!("enum {} {{", def.name));
+                self.level += 1;
+                for (index, (name, value)) in def.variants.iter().enumerate() {
+                    let separator = if index + 1 < def.variants.len() { "," } else { "" };
+                    match value {
+                        Some(value) => {
+                            let value = self.expr(value, TERNARY);
+                            self.line(&format!("{} = {}{}", name, value, separator));
+                        }
+                        None => self.line(&format!("{}{}", name, separator)),
+                    }
+                }
+                self.level -= 1;
+                self.line("};");
+            }
+            Item::Translator(translator) => {
+                let output = type_name(&translator.output);
+                let input = type_name(&translator.input);
+                let param = spaced(&translator.input, &translator.param);
+                self.line(&format!("translator {} < {}{} > {{", output, input, param));
+                self.level += 1;
+                for (member, value) in &translator.members {
+                    let value = self.expr(value, ASSIGN);
+                    self.line(&format!("{} = {};", member, value));
+                }
+                self.level -= 1;
+                self.line("};");
+            }
+            Item::Provider(provider) => {
+                self.line(&format!("provider {} {{", provider.name));
+                self.last_end = Some(provider.span.start);
+                self.level += 1;
+                for probe in &provider.probes {
+                    self.comments_and_blank_before(probe.span.start);
+                    let args: Vec<_> = probe.args.iter().map(type_name).collect();
+                    self.line(&format!("probe {}({});", probe.name, args.join(", ")));
+                    self.last_end = Some(probe.span.end);
+                }
+                self.comments_before(provider.span.end);
+                self.level -= 1;
+                self.line("};");
+            }
+        }
+    }
+
+    fn clause(&mut self, clause: &Clause) {
+        let body_start = clause.body_span.map_or(clause.span.end, |span| span.start);
+        for (index, probe) in clause.probes.iter().enumerate() {
+            let next = match clause.probes.get(index + 1) {
+                Some(next) => next.span.start,
+                None => clause.predicate.as_ref().map_or(body_start, |predicate| predicate.span.start),
+            };
+            let separator = if index + 1 < clause.probes.len() { "," } else { "" };
+            self.line(&format!("{}{}", probe.text, separator));
+            self.last_end = Some(probe.span.end);
+            self.comments_before(next);
+        }
+        if let Some(predicate) = &clause.predicate {
+            let text = self.expr(predicate, COMMA);
+            self.line(&format!("/{}/", text));
+            self.last_end = Some(predicate.span.end);
+            self.comments_before(body_start);
+        }
+        if let Some(body) = &clause.body {
+            self.line("{");
+            self.block(body, clause.body_span.unwrap_or_default());
+            self.line("}");
+        }
+    }
+
+    /// Writes the statements of a block, `span` runs from its opening to its closing brace.
+    ///
+    /// The comments before the opening brace not written yet go on the line written last, which opens the block.
+    fn block(&mut self, stmts: &[Stmt], span: Span) {
+        self.last_end = Some(span.start);
+        self.level += 1;
+        for stmt in stmts {
+            let span = stmt.span();
+            self.comments_and_blank_before(span.start);
+            self.stmt(stmt);
+            self.last_end = Some(span.end);
+        }
+        self.comments_before(span.end);
+        self.level -= 1;
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expr(expr) => {
+                let expr = self.expr(expr, COMMA);
+                self.line(&format!("{};", expr));
+            }
+            Stmt::Aggregate(aggregate) => {
+                let keys = self.keys(&aggregate.keys);
+                let args = self.args(&aggregate.args);
+                self.line(&format!("@{}{} = {}({});", aggregate.name, keys, aggregate.function, args));
+            }
+            Stmt::If { .. } => {
+                let mut stmt = stmt;
+                let mut keyword = "if";
+                loop {
+                    let (cond, then, then_span, otherwise, otherwise_span) = match stmt {
+                        Stmt::If {
+                            cond,
+                            then,
+                            then_span,
+                            otherwise,
+                            otherwise_span,
+                            ..
+                        } => (cond, then, then_span, otherwise, otherwise_span),
+                        _ => unreachable!(),
+                    };
+                    let cond = self.expr(cond, COMMA);
+                    self.line(&format!("{} ({}) {{", keyword, cond));
+                    self.block(then, *then_span);
+                    match (otherwise.as_deref(), otherwise_span) {
+                        (Some([next @ Stmt::If { .. }]), _) => {
+                            stmt = next;
+                            keyword = "} else if";
+                        }
+                        (Some(otherwise), Some(otherwise_span)) => {
+                            self.line("} else {");
+                            self.block(otherwise, *otherwise_span);
+                            self.line("}");
+                            break;
+                        }
+                        _ => {
+                            self.line("}");
+                            break;
+                        }
+                    }
+                }
+            }
+            Stmt::Block { stmts, span } => {
+                self.line("{");
+                self.block(stmts, *span);
+                self.line("}");
+            }
+        }
+    }
+
+    fn declarator(&self, declarator: &Declarator) -> String {
+        let mut text = format!("{}{}", type_name(&declarator.ty), spaced(&declarator.ty, &declarator.name));
+        for dim in &declarator.dims {
+            match dim {
+                ArrayDim::Size(size) => text.push_str(&format!("[{}]", self.expr(size, ASSIGN))),
+                ArrayDim::Keys(keys) => {
+                    let keys: Vec<_> = keys.iter().map(type_name).collect();
+                    text.push_str(&format!("[{}]", keys.join(", ")));
+                }
+            }
+        }
+        text
+    }
+
+    fn args(&self, args: &[Expr]) -> String {
+        let args: Vec<_> = args.iter().map(|arg| self.expr(arg, ASSIGN)).collect();
+        args.join(", ")
+    }
+
+    fn keys(&self, keys: &[Expr]) -> String {
+        match keys.is_empty() {
+            true => String::new(),
+            false => format!("[{}]", self.args(keys)),
+        }
+    }
+
+    /// Formats an expression, in parentheses if it binds looser than `min`.
+    fn expr(&self, expr: &Expr, min: u8) -> String {
+        let (text, power) = match &expr.kind {
+            ExprKind::Int { text, .. } => (text.clone(), PRIMARY),
+            ExprKind::Char(text) => (format!("'{}'", text), PRIMARY),
+            ExprKind::Str(value) => (format!("\"{}\"", escape(value)), PRIMARY),
+            ExprKind::Ident(ident) => (ident.clone(), PRIMARY),
+            ExprKind::Symbol { module, name } => (format!("{}`{}", module.as_deref().unwrap_or_default(), name), PRIMARY),
+            ExprKind::MacroVar { name, quoted } => (format!("{}{}", if *quoted { "$$" } else { "$" }, name), PRIMARY),
+            ExprKind::Var { scope, name } => {
+                let scope = match scope {
+                    VarScope::Thread => "self->",
+                    VarScope::Clause => "this->",
+                    VarScope::Global => "",
+                };
+                (format!("{}{}", scope, name), PRIMARY)
+            }
+            ExprKind::Aggregation { name, keys } => (format!("@{}{}", name, self.keys(keys)), PRIMARY),
+            ExprKind::Unary { op, expr } => {
+                let operand = self.expr(expr, UNARY);
+                // Keep `- -x` and `& &x` from running together into `--x` and `&&x`
+                let operand = match operand.starts_with(op.as_str().chars().last().unwrap()) {
+                    true => format!("({})", operand),
+                    false => operand,
+                };
+                (format!("{}{}", op.as_str(), operand), UNARY)
+            }
+            ExprKind::Postfix { op, expr } => (format!("{}{}", self.expr(expr, POSTFIX), op.as_str()), POSTFIX),
+            ExprKind::Binary { op, lhs, rhs } => {
+                let power = BINARY + op.precedence();
+                let lhs = self.expr(lhs, power);
+                let rhs = self.expr(rhs, power + 1);
+                (format!("{} {} {}", lhs, op.as_str(), rhs), power)
+            }
+            ExprKind::Assign { op, lhs, rhs } => {
+                let op = op.map_or(String::new(), |op| op.as_str().to_string());
+                (format!("{} {}= {}", self.expr(lhs, UNARY), op, self.expr(rhs, ASSIGN)), ASSIGN)
+            }
+            ExprKind::Ternary { cond, then, otherwise } => {
+                let text = format!(
+                    "{} ? {} : {}",
+                    self.expr(cond, BINARY),
+                    self.expr(then, ASSIGN),
+                    self.expr(otherwise, TERNARY)
+                );
+                (text, TERNARY)
+            }
+            ExprKind::Comma { lhs, rhs } => (format!("{}, {}", self.expr(lhs, COMMA), self.expr(rhs, ASSIGN)), COMMA),
+            ExprKind::Call { function, args } => (format!("{}({})", function, self.args(args)), POSTFIX),
+            ExprKind::Index { base, keys } => (format!("{}[{}]", self.expr(base, POSTFIX), self.args(keys)), POSTFIX),
+            ExprKind::Member { base, member, arrow } => {
+                let access = if *arrow { "->" } else { "." };
+                (format!("{}{}{}", self.expr(base, POSTFIX), access, member), POSTFIX)
+            }
+            ExprKind::Cast { ty, expr } => (format!("({}){}", type_name(ty), self.expr(expr, UNARY)), UNARY),
+            ExprKind::SizeofType(ty) => (format!("sizeof({})", type_name(ty)), UNARY),
+            ExprKind::SizeofExpr(expr) => (format!("sizeof({})", self.expr(expr, ASSIGN)), UNARY),
+            ExprKind::Stringof(expr) => (format!("stringof({})", self.expr(expr, ASSIGN)), UNARY),
+            ExprKind::Offsetof { ty, member } => (format!("offsetof({}, {})", type_name(ty), member), PRIMARY),
+            ExprKind::Xlate { ty, expr } => (format!("xlate<{}>({})", type_name(ty), self.expr(expr, COMMA)), PRIMARY),
+        };
+
+        match power < min {
+            true => format!("({})", text),
+            false => text,
+        }
+    }
+}
+
+/// Formats a type name, e.g. `char *`.
+fn type_name(ty: &TypeName) -> String {
+    match ty.pointers {
+        0 => ty.name.clone(),
+        pointers => format!("{} {}", ty.name, "*".repeat(pointers)),
+    }
+}
+
+/// The separator between the type and the declared name, none after a `*`.
+fn spaced(ty: &TypeName, name: &str) -> String {
+    match ty.pointers {
+        0 => format!(" {}", name),
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_clause_layout() -> Result<(), ParseError> {
+        let source = "syscall::read:entry,syscall::write:entry/pid!=$pid&&(arg0>0)/{self->ts=timestamp;@bytes[execname,probefunc]=sum(arg2)\n}\nEND{printa(@bytes);}";
+        let expected = "syscall::read:entry,
+syscall::write:entry
+/pid != $pid && arg0 > 0/
+{
+    self->ts = timestamp;
+    @bytes[execname, probefunc] = sum(arg2);
+}
+
+END
+{
+    printa(@bytes);
+}
+";
+        assert_eq!(format(source)?, expected);
+        assert!(Formatter::new().check(expected)?);
+        assert!(!Formatter::new().check(source)?);
+        Ok(())
+    }
+
+    #[test]
+    fn format_keeps_needed_parentheses() -> Result<(), ParseError> {
+        let source = "BEGIN { x = (a + b) * c - (d - e); y = -(-z); w = (x & 1) == 0 ? ((char *)arg0)->c : *(int *)arg1; }";
+        let expected = "BEGIN
+{
+    x = (a + b) * c - (d - e);
+    y = -(-z);
+    w = (x & 1) == 0 ? ((char *)arg0)->c : *(int *)arg1;
+}
+";
+        assert_eq!(format(source)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn format_preserves_comments() -> Result<(), ParseError> {
+        let source = "#!/usr/sbin/dtrace -s
+#pragma D option quiet
+#pragma D option bufsize=4m
+/* Count reads
+     * by process */
+syscall::read:entry { @[execname] = count(); /* per process */
+
+  // then trace
+  trace(arg0); }
+/* trailing */
+";
+        let expected = "#!/usr/sbin/dtrace -s
+#pragma D option quiet
+#pragma D option bufsize=4m
+
+/* Count reads
+ * by process */
+syscall::read:entry
+{
+    @[execname] = count(); /* per process */
+
+    // then trace
+    trace(arg0);
+}
+/* trailing */
+";
+        let formatter = Formatter::new();
+        assert_eq!(formatter.format(source)?, expected);
+        assert!(formatter.check(expected)?);
+        Ok(())
+    }
+
+    #[test]
+    fn format_if_and_declarations() -> Result<(), ParseError> {
+        let source = "self   int x;this struct proc * p;
+inline int LIMIT=10;
+tick-1s{if(x>LIMIT){trace(x);}else if(x==0)trace(0);else{x++;}}";
+        let expected = "self int x;
+this struct proc *p;
+
+inline int LIMIT = 10;
+
+tick-1s
+{
+\tif (x > LIMIT) {
+\t\ttrace(x);
+\t} else if (x == 0) {
+\t\ttrace(0);
+\t} else {
+\t\tx++;
+\t}
+}
+";
+        assert_eq!(Formatter::new().indent("\t").format(source)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn format_keeps_comments_in_place() -> Result<(), ParseError> {
+        let cases = [
+            (
+                "BEGIN { printf(\"%d\", /* inner */ a); trace(b // line\n); }",
+                "BEGIN\n{\n    printf(\"%d\", a); /* inner */\n    trace(b); // line\n}\n",
+            ),
+            (
+                "syscall::read:entry /* read */\n/pid == $target/ /* ours */\n{ trace(arg0); }",
+                "syscall::read:entry /* read */\n/pid == $target/ /* ours */\n{\n    trace(arg0);\n}\n",
+            ),
+            (
+                "tick-1s { if (x) { a(); /* then */ } else { b(); } }",
+                "tick-1s\n{\n    if (x) {\n        a(); /* then */\n    } else {\n        b();\n    }\n}\n",
+            ),
+            (
+                "tick-1s { if (x) { a(); } /* else */ else if (y) { b(); }\n// otherwise\nelse c(); }",
+                "tick-1s\n{\n    if (x) {\n        a();\n    } else if (y) { /* else */\n        b();\n    } else { // otherwise\n        c();\n    }\n}\n",
+            ),
+            (
+                "BEGIN { x = (a, b); a, b = c; f((a, b), c); { { trace(1); } } }",
+                "BEGIN\n{\n    x = (a, b);\n    a, b = c;\n    f((a, b), c);\n    {\n        {\n            trace(1);\n        }\n    }\n}\n",
+            ),
+            (
+                "#pragma D option /* quiet */ quiet\nBEGIN // start\n{ exit(0); }",
+                "#pragma D option quiet /* quiet */\n\nBEGIN // start\n{\n    exit(0);\n}\n",
+            ),
+        ];
+        for (source, expected) in cases {
+            let formatted = format(source)?;
+            assert_eq!(formatted, expected);
+            assert_eq!(format(&formatted)?, formatted);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn format_preserves_string_bytes() -> Result<(), ParseError> {
+        fn bytes(source: &str) -> Result<Vec<u8>, ParseError> {
+            let script = Parser::new(source).parse()?;
+            match &script.items[..] {
+                [Item::Clause(Clause { body: Some(body), .. })] => match &body[..] {
+                    [Stmt::Expr(Expr {
+                        kind: ExprKind::Call { args, .. },
+                        ..
+                    })] => match &args[0].kind {
+                        ExprKind::Str(value) => Ok(value.clone()),
+                        kind => panic!("unexpected argument {:?}", kind),
+                    },
+                    body => panic!("unexpected body {:?}", body),
+                },
+                items => panic!("unexpected items {:?}", items),
+            }
+        }
+
+        let source = "BEGIN { trace(\"\\377\\x80 é\\0\" \"\\303\\251\"); }";
+        let formatted = format(source)?;
+        assert!(formatted.contains("trace(\"\\377\\200 é\\000é\");"), "{}", formatted);
+        assert_eq!(bytes(source)?, b"\xff\x80 \xc3\xa9\0\xc3\xa9");
+        assert_eq!(bytes(&formatted)?, bytes(source)?);
+        Ok(())
+    }
+
+    #[test]
+    fn format_corpus_is_idempotent() -> Result<(), ParseError> {
+        let corpus = [
+            include_str!("corpus/hello.d"),
+            include_str!("corpus/syscalls.d"),
+            include_str!("corpus/latency.d"),
+            include_str!("corpus/distribution.d"),
+            include_str!("corpus/speculation.d"),
+            include_str!("corpus/types.d"),
+            include_str!("corpus/provider.d"),
+            include_str!("corpus/preprocessor.d"),
+            include_str!("corpus/processcreation.d"),
+        ];
+        let formatter = Formatter::new().cpp(true);
+        for source in corpus {
+            let formatted = formatter.format(source)?;
+            assert_eq!(formatter.format(&formatted)?, formatted);
+            let comments = |source: &str| Parser::new(source).cpp(true).parse().map(|script| script.comments.len());
+            assert_eq!(comments(&formatted)?, comments(source)?);
+        }
+        Ok(())
+    }
+}
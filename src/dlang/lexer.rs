@@ -0,0 +1,432 @@
+use super::ast::{Comment, Span};
+use super::ParseError;
+
+/// A token of D source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    /// An identifier or a keyword
+    Ident(String),
+    Int { value: u64, text: String },
+    Char(String),
+    /// A string constant, with its escapes resolved into bytes
+    Str(Vec<u8>),
+    MacroVar { name: String, quoted: bool },
+    /// `@name`, with an empty name for `@`
+    Aggregation(String),
+    /// An operator or a punctuator
+    Punct(&'static str),
+    /// A `/` closing a predicate, i.e. followed by `{`, `;` or the end of the source
+    PredicateEnd,
+    /// A control line without its `#`, e.g. `pragma D option quiet`
+    Directive(String),
+    /// A probe description, only produced by [`Lexer::probe_spec`]
+    ProbeSpec(String),
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// The operators and punctuators of D, longest first.
+const PUNCTS: &[&str] = &[
+    "<<=", ">>=", "...", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "^^", "+=", "-=", "*=", "/=",
+    "%=", "&=", "^=", "|=", "(", ")", "[", "]", "{", "}", ".", ",", ";", ":", "?", "=", "+", "-", "*", "/", "%", "&",
+    "|", "^", "!", "~", "<", ">", "`",
+];
+
+/// Splits D source into tokens.
+///
+/// The lexer is restartable at any offset, the parser uses this to lex the start of a clause again as a probe
+/// description once it knows it is not a declaration.
+pub struct Lexer<'s> {
+    source: &'s str,
+    pos: usize,
+    comments: Vec<Comment>,
+}
+
+impl<'s> Lexer<'s> {
+    pub fn new(source: &'s str) -> Self {
+        let mut lexer = Self {
+            source,
+            pos: 0,
+            comments: Vec::new(),
+        };
+        if source.starts_with("#!") {
+            let end = source.find('\n').unwrap_or(source.len());
+            lexer.comments.push(Comment {
+                text: source[..end].to_string(),
+                span: Span::new(0, end),
+            });
+            lexer.pos = end;
+        }
+        lexer
+    }
+
+    /// The offset of the next character to lex.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// Continues lexing at `pos`.
+    pub fn reset(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// The comments skipped so far, in source order.
+    pub fn into_comments(self) -> Vec<Comment> {
+        self.comments
+    }
+
+    fn rest(&self) -> &'s str {
+        &self.source[self.pos..]
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek_char()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>, start: usize) -> Result<T, ParseError> {
+        Err(ParseError::new(message, Span::new(start, self.pos.max(start + 1).min(self.source.len()))))
+    }
+
+    /// Whether only blanks precede `pos` on its line.
+    fn at_line_start(&self, pos: usize) -> bool {
+        self.source[..pos]
+            .chars()
+            .rev()
+            .take_while(|&c| c != '\n')
+            .all(|c| c == ' ' || c == '\t')
+    }
+
+    /// Skips blanks and comments, recording each comment the first time it is seen.
+    fn skip_trivia(&mut self) -> Result<(), ParseError> {
+        loop {
+            let rest = self.rest();
+            if let Some(c) = rest.chars().next().filter(|c| c.is_whitespace()) {
+                self.pos += c.len_utf8();
+            } else if let Some(body) = rest.strip_prefix("/*") {
+                let start = self.pos;
+                match body.find("*/") {
+                    Some(end) => self.pos += end + 4,
+                    None => {
+                        self.pos = self.source.len();
+                        return self.error("unterminated comment", start);
+                    }
+                }
+                self.comment(start);
+            } else if rest.starts_with("//") {
+                let start = self.pos;
+                self.pos += rest.find('\n').unwrap_or(rest.len());
+                self.comment(start);
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn comment(&mut self, start: usize) {
+        if self.comments.last().is_none_or(|last| last.span.start < start) {
+            self.comments.push(Comment {
+                text: self.source[start..self.pos].to_string(),
+                span: Span::new(start, self.pos),
+            });
+        }
+    }
+
+    /// Lexes the next token.
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let token = |kind, lexer: &Self| Ok(Token {
+            kind,
+            span: Span::new(start, lexer.pos),
+        });
+
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return token(TokenKind::Eof, self),
+        };
+
+        if c == '#' {
+            if !self.at_line_start(start) {
+                self.bump();
+                return self.error("`#` is only allowed at the start of a line", start);
+            }
+            let (text, end) = self.directive()?;
+            return Ok(Token {
+                kind: TokenKind::Directive(text),
+                span: Span::new(start, end),
+            });
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let ident = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+            return token(TokenKind::Ident(ident.to_string()), self);
+        }
+        if c.is_ascii_digit() {
+            let kind = self.number(start)?;
+            return token(kind, self);
+        }
+        match c {
+            '"' => {
+                let value = self.string(start)?;
+                return token(TokenKind::Str(value), self);
+            }
+            '\'' => {
+                self.bump();
+                let text = self.quoted('\'', start)?;
+                return token(TokenKind::Char(text.to_string()), self);
+            }
+            '$' => {
+                self.bump();
+                let quoted = self.peek_char() == Some('$');
+                if quoted {
+                    self.bump();
+                }
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                if name.is_empty() {
+                    return self.error("expected a macro variable name after `$`", start);
+                }
+                return token(
+                    TokenKind::MacroVar {
+                        name: name.to_string(),
+                        quoted,
+                    },
+                    self,
+                );
+            }
+            '@' => {
+                self.bump();
+                let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+                return token(TokenKind::Aggregation(name.to_string()), self);
+            }
+            _ => {}
+        }
+
+        let punct = PUNCTS.iter().find(|punct| self.rest().starts_with(*punct));
+        match punct {
+            Some(&"/") => {
+                self.pos += 1;
+                let after = self.pos;
+                self.skip_trivia()?;
+                let closes = matches!(self.peek_char(), None | Some('{') | Some(';'));
+                self.pos = after;
+                match closes {
+                    true => token(TokenKind::PredicateEnd, self),
+                    false => token(TokenKind::Punct("/"), self),
+                }
+            }
+            Some(punct) => {
+                self.pos += punct.len();
+                token(TokenKind::Punct(punct), self)
+            }
+            None => {
+                self.bump();
+                self.error(format!("invalid character `{}`", c.escape_default()), start)
+            }
+        }
+    }
+
+    /// Lexes a probe description such as `syscall::read*:entry` or `pid$target:a.out:main:entry`.
+    pub fn probe_spec(&mut self) -> Result<Token, ParseError> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let first = |c: char| c.is_ascii_alphabetic() || "-$:_.?*\\[]!".contains(c);
+        if !self.peek_char().is_some_and(first) {
+            let _ = self.next_token();
+            return self.error("expected a probe description", start);
+        }
+        let text = self.take_while(|c| c.is_ascii_alphanumeric() || "-$:_.`?*\\[]!".contains(c));
+        Ok(Token {
+            kind: TokenKind::ProbeSpec(text.to_string()),
+            span: Span::new(start, self.pos),
+        })
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'s str {
+        let rest = self.rest();
+        let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    /// Reads a control line, joining continued lines, and returns its text and the offset of its end.
+    ///
+    /// Like the preprocessor, comments are recorded and replaced by a blank, so the end is before a trailing comment.
+    fn directive(&mut self) -> Result<(String, usize), ParseError> {
+        self.bump();
+        let mut text = String::new();
+        let mut end = self.pos;
+        let mut quote = None;
+        while let Some(c) = self.bump() {
+            match c {
+                '\n' => break,
+                '\\' if self.peek_char() == Some('\n') => {
+                    self.bump();
+                    text.push(' ');
+                }
+                '/' if quote.is_none() && matches!(self.peek_char(), Some('*' | '/')) => {
+                    let start = self.pos - 1;
+                    let rest = &self.source[start..];
+                    if let Some(body) = rest.strip_prefix("/*") {
+                        match body.find("*/") {
+                            Some(len) => self.pos = start + len + 4,
+                            None => {
+                                self.pos = self.source.len();
+                                return self.error("unterminated comment", start);
+                            }
+                        }
+                    } else {
+                        self.pos = start + rest.find('\n').unwrap_or(rest.len());
+                    }
+                    self.comment(start);
+                    text.push(' ');
+                }
+                c => {
+                    text.push(c);
+                    match c {
+                        '\\' if quote.is_some() => text.extend(self.bump()),
+                        '"' | '\'' if quote.is_none() => quote = Some(c),
+                        c if quote == Some(c) => quote = None,
+                        _ => {}
+                    }
+                    if !c.is_whitespace() {
+                        end = self.pos;
+                    }
+                }
+            }
+        }
+        Ok((text.trim().to_string(), end))
+    }
+
+    fn number(&mut self, start: usize) -> Result<TokenKind, ParseError> {
+        let text = self.take_while(|c| c.is_ascii_alphanumeric() || c == '.');
+        let digits = text.trim_end_matches(['u', 'U', 'l', 'L']);
+        let suffix = &text[digits.len()..];
+        let value = match digits {
+            digits if digits.starts_with("0x") || digits.starts_with("0X") => u64::from_str_radix(&digits[2..], 16),
+            digits if digits.len() > 1 && digits.starts_with('0') => u64::from_str_radix(&digits[1..], 8),
+            digits => digits.parse(),
+        };
+        let valid_suffix = matches!(suffix.to_ascii_lowercase().as_str(), "" | "u" | "l" | "ul" | "lu" | "ll" | "ull" | "llu");
+
+        match value {
+            Ok(value) if valid_suffix => Ok(TokenKind::Int {
+                value,
+                text: text.to_string(),
+            }),
+            Err(error) if *error.kind() == std::num::IntErrorKind::PosOverflow => {
+                self.error(format!("integer constant `{}` is too large", text), start)
+            }
+            _ => self.error(format!("invalid integer constant `{}`", text), start),
+        }
+    }
+
+    /// Reads the text up to the unescaped `quote`, the opening quote has been consumed.
+    fn quoted(&mut self, quote: char, start: usize) -> Result<&'s str, ParseError> {
+        let from = self.pos;
+        loop {
+            match self.bump() {
+                Some('\\') => {
+                    self.bump();
+                }
+                Some(c) if c == quote => return Ok(&self.source[from..self.pos - 1]),
+                Some('\n') | None => return self.error("unterminated constant", start),
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn string(&mut self, start: usize) -> Result<Vec<u8>, ParseError> {
+        self.bump();
+        let text = self.quoted('"', start)?;
+        unescape(text).map_err(|message| ParseError::new(message, Span::new(start, self.pos)))
+    }
+}
+
+/// Resolves the escape sequences of the text of a string constant into its bytes.
+///
+/// Octal and hexadecimal escapes stand for a single byte each, which need not be part of valid UTF-8, while the other
+/// characters keep their UTF-8 encoding.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut value = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        let escape = chars.next().ok_or("unterminated escape sequence")?;
+        let resolved = match escape {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '\\' | '"' | '\'' | '?' => escape as u8,
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                code as u8
+            }
+            'x' => {
+                let mut code = 0u32;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    code = (code * 16 + digit) & 0xff;
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err("expected hexadecimal digits after `\\x`".to_string());
+                }
+                code as u8
+            }
+            other => return Err(format!("invalid escape sequence `\\{}`", other)),
+        };
+        value.push(resolved);
+    }
+    Ok(value)
+}
+
+/// Escapes `value` for use as the text of a D string constant.
+///
+/// Valid UTF-8 is written as is, every other byte as an octal escape, so that [`unescape`] gives back `value`.
+pub fn escape(value: &[u8]) -> String {
+    let mut text = String::with_capacity(value.len());
+    for chunk in value.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\n' => text.push_str("\\n"),
+                '\t' => text.push_str("\\t"),
+                '\r' => text.push_str("\\r"),
+                '\\' => text.push_str("\\\\"),
+                '"' => text.push_str("\\\""),
+                c if (c as u32) < 0x20 || c as u32 == 0x7f => text.push_str(&format!("\\{:03o}", c as u32)),
+                c => text.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            text.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    text
+}
@@ -0,0 +1,338 @@
+//! A pure-Rust front end for the D language, to validate and transform D scripts without libdtrace or a driver.
+//!
+//! [`parse`] turns a script into a [`Script`] of probe clauses, pragmas and declarations, every node carries the
+//! [`Span`] of its source text. [`check`] looks for common mistakes in a parsed script and
+//! [`Formatter`] lays scripts out canonically. [`builder`] builds programs from Rust values without formatting their
+//! source by hand, and [`d!`](crate::d) checks the syntax of programs embedded in Rust at compile time.
+//!
+//! ```
+//! use libdtrace_rs::dlang::{self, Item};
+//!
+//! let script = dlang::parse("syscall::read:entry /pid == $target/ { @bytes[execname] = sum(arg2); }")?;
+//! match &script.items[0] {
+//!     Item::Clause(clause) => assert_eq!(clause.probes[0].text, "syscall::read:entry"),
+//!     _ => unreachable!(),
+//! }
+//! # Ok::<(), dlang::ParseError>(())
+//! ```
+
+// `ast`, `lexer` and `parser` are also compiled into the `d!` macro, so they only depend on each other and on
+// `ParseError`
+pub mod ast;
+pub mod builder;
+pub mod check;
+pub mod embed;
+pub mod format;
+pub mod lexer;
+pub mod parser;
+
+pub use ast::*;
+pub use check::{check, Checker, Diagnostic, Severity};
+pub use embed::Embedded;
+pub use format::Formatter;
+pub use parser::Parser;
+
+/// A syntax error in a D script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a D script, without the C preprocessor directives, see [`Parser`] for the options.
+///
+/// # Returns
+///
+/// * `Ok(Script)` - The items and comments of the script.
+/// * `Err(ParseError)` - The first syntax error of the script.
+pub fn parse(source: &str) -> Result<Script, ParseError> {
+    Parser::new(source).parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample scripts, parsed with the C preprocessor directives enabled.
+    const CORPUS: &[(&str, &str)] = &[
+        ("program.d", include_str!("../../examples/program.d")),
+        ("hello.d", include_str!("corpus/hello.d")),
+        ("syscalls.d", include_str!("corpus/syscalls.d")),
+        ("latency.d", include_str!("corpus/latency.d")),
+        ("distribution.d", include_str!("corpus/distribution.d")),
+        ("speculation.d", include_str!("corpus/speculation.d")),
+        ("types.d", include_str!("corpus/types.d")),
+        ("provider.d", include_str!("corpus/provider.d")),
+        ("preprocessor.d", include_str!("corpus/preprocessor.d")),
+        ("processcreation.d", include_str!("corpus/processcreation.d")),
+    ];
+
+    fn clause(script: &Script, index: usize) -> &Clause {
+        match &script.items[index] {
+            Item::Clause(clause) => clause,
+            item => panic!("expected a clause, found {:?}", item),
+        }
+    }
+
+    #[test]
+    fn parse_corpus() {
+        for (name, source) in CORPUS {
+            if let Err(error) = Parser::new(source).cpp(true).parse() {
+                let (line, column) = error.span.line_col(source);
+                panic!("{}:{}:{}: {}", name, line, column, error);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_clause() -> Result<(), ParseError> {
+        let source = "syscall::read:entry, syscall::write:entry\n/pid != $pid && execname == \"sshd\"/\n{\n    self->ts = timestamp;\n    printf(\"%d\\n\", arg0)\n}";
+        let script = parse(source)?;
+        let clause = clause(&script, 0);
+
+        let probes: Vec<_> = clause.probes.iter().map(|probe| probe.text.as_str()).collect();
+        assert_eq!(probes, ["syscall::read:entry", "syscall::write:entry"]);
+        assert_eq!(clause.span, Span::new(0, source.len()));
+        let predicate = clause.predicate.as_ref().unwrap();
+        assert!(matches!(predicate.kind, ExprKind::Binary { op: BinaryOp::And, .. }));
+        assert_eq!(&source[predicate.span.start..predicate.span.end], "pid != $pid && execname == \"sshd\"");
+
+        let body = clause.body.as_ref().unwrap();
+        assert_eq!(body.len(), 2);
+        match &body[0] {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Assign { op: None, lhs, .. },
+                ..
+            }) => assert_eq!(
+                lhs.kind,
+                ExprKind::Var {
+                    scope: VarScope::Thread,
+                    name: "ts".to_string()
+                }
+            ),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        match &body[1] {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Call { function, args },
+                ..
+            }) => {
+                assert_eq!(function, "printf");
+                assert_eq!(args[0].kind, ExprKind::Str(b"%d\n".to_vec()));
+            }
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_aggregations() -> Result<(), ParseError> {
+        let script = parse("profile-997 { @[execname, pid] = count(); @lat = lquantize(arg0, 0, 100, 10); } END { printa(@lat); }")?;
+        let body = clause(&script, 0).body.as_ref().unwrap();
+        match &body[0] {
+            Stmt::Aggregate(aggregate) => {
+                assert_eq!((aggregate.name.as_str(), aggregate.function.as_str()), ("", "count"));
+                assert_eq!(aggregate.keys.len(), 2);
+            }
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        match &body[1] {
+            Stmt::Aggregate(aggregate) => {
+                assert_eq!((aggregate.name.as_str(), aggregate.function.as_str()), ("lat", "lquantize"));
+                assert_eq!(aggregate.args.len(), 4);
+            }
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        assert!(parse("BEGIN { @a = 1; }").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_precedence() -> Result<(), ParseError> {
+        let script = parse("BEGIN { x = 1 + 2 * 3 << 1 == 14 ^^ !y || z ? a : b; }")?;
+        let rhs = match &clause(&script, 0).body.as_ref().unwrap()[0] {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Assign { rhs, .. },
+                ..
+            }) => rhs,
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        let cond = match &rhs.kind {
+            ExprKind::Ternary { cond, .. } => cond,
+            kind => panic!("unexpected expression {:?}", kind),
+        };
+        match &cond.kind {
+            ExprKind::Binary {
+                op: BinaryOp::Or, lhs, ..
+            } => assert!(matches!(lhs.kind, ExprKind::Binary { op: BinaryOp::Xor, .. })),
+            kind => panic!("unexpected expression {:?}", kind),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_comma_and_blocks() -> Result<(), ParseError> {
+        let source = "BEGIN { x = (a, b); { { trace(1); } } }";
+        let script = parse(source)?;
+        let body = clause(&script, 0).body.as_ref().unwrap();
+        match &body[0] {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Assign { rhs, .. },
+                ..
+            }) => match &rhs.kind {
+                ExprKind::Comma { lhs, rhs } => {
+                    assert_eq!(lhs.kind, ExprKind::Ident("a".to_string()));
+                    assert_eq!(rhs.kind, ExprKind::Ident("b".to_string()));
+                }
+                kind => panic!("unexpected expression {:?}", kind),
+            },
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        match &body[1] {
+            Stmt::Block { stmts, span } => {
+                assert_eq!(&source[span.start..span.end], "{ { trace(1); } }");
+                assert!(matches!(&stmts[..], [Stmt::Block { stmts, .. }] if matches!(stmts[..], [Stmt::Expr(_)])));
+            }
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        // Arguments and subscripts are separated by commas, they do not take the comma operator
+        let script = parse("BEGIN { f(a, (b, c)); }")?;
+        match &clause(&script, 0).body.as_ref().unwrap()[0] {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Call { args, .. },
+                ..
+            }) => assert!(matches!(args[..], [_, Expr { kind: ExprKind::Comma { .. }, .. }])),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_predicate_division() -> Result<(), ParseError> {
+        let script = parse("tick-1s /x / 2 > 1/ { trace(x / 2); }")?;
+        assert!(matches!(
+            clause(&script, 0).predicate.as_ref().unwrap().kind,
+            ExprKind::Binary { op: BinaryOp::Gt, .. }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_casts_and_symbols() -> Result<(), ParseError> {
+        let script = parse("BEGIN { this->p = ((nt`_PEB*)arg0)->Ldr; x = (uint64_t)`max_ncpus + sizeof (int); }")?;
+        let body = clause(&script, 0).body.as_ref().unwrap();
+        let rhs = |stmt: &Stmt| match stmt {
+            Stmt::Expr(Expr {
+                kind: ExprKind::Assign { rhs, .. },
+                ..
+            }) => rhs.kind.clone(),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        match rhs(&body[0]) {
+            ExprKind::Member { base, member, arrow: true } => {
+                assert_eq!(member, "Ldr");
+                match base.kind {
+                    ExprKind::Cast { ty, .. } => assert_eq!((ty.name.as_str(), ty.pointers), ("nt`_PEB", 1)),
+                    kind => panic!("unexpected expression {:?}", kind),
+                }
+            }
+            kind => panic!("unexpected expression {:?}", kind),
+        }
+        match rhs(&body[1]) {
+            ExprKind::Binary { lhs, rhs, .. } => {
+                assert!(matches!(lhs.kind, ExprKind::Cast { .. }));
+                assert!(matches!(rhs.kind, ExprKind::SizeofType(_)));
+            }
+            kind => panic!("unexpected expression {:?}", kind),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pragmas_and_defines() -> Result<(), ParseError> {
+        let source = "#!/usr/sbin/dtrace -s\n#pragma D option quiet\n#pragma D option bufsize=4m\n#define LIMIT(x) ((x) > 10)\nBEGIN { exit(0); }\n";
+        assert!(parse(source).is_err());
+        let script = Parser::new(source).cpp(true).parse()?;
+
+        assert_eq!(script.comments[0].text, "#!/usr/sbin/dtrace -s");
+        assert_eq!(
+            script.items[1],
+            Item::Pragma(Pragma {
+                kind: PragmaKind::Option {
+                    name: "bufsize".to_string(),
+                    value: Some("4m".to_string())
+                },
+                span: Span::new(45, 72),
+            })
+        );
+        match &script.items[2] {
+            Item::Define(define) => {
+                assert_eq!(define.name, "LIMIT");
+                assert_eq!(define.params, Some(vec!["x".to_string()]));
+                assert_eq!(define.body, "((x) > 10)");
+            }
+            item => panic!("unexpected item {:?}", item),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_comments_in_control_lines() -> Result<(), ParseError> {
+        let source = "#pragma D option quiet /* be quiet */\n#pragma D option bufsize=4m // per CPU\n#define MSG \"a // b\" /* x */\n";
+        let script = Parser::new(source).cpp(true).parse()?;
+
+        let comments: Vec<_> = script.comments.iter().map(|comment| comment.text.as_str()).collect();
+        assert_eq!(comments, ["/* be quiet */", "// per CPU", "/* x */"]);
+        assert_eq!(
+            script.items[0],
+            Item::Pragma(Pragma {
+                kind: PragmaKind::Option {
+                    name: "quiet".to_string(),
+                    value: None
+                },
+                span: Span::new(0, 22),
+            })
+        );
+        assert!(matches!(&script.items[1], Item::Pragma(Pragma { kind: PragmaKind::Option { value: Some(value), .. }, .. }) if value == "4m"));
+        assert!(matches!(&script.items[2], Item::Define(define) if define.body == "\"a // b\""));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_pragma_rejects_trailing_words() {
+        let error = parse("#pragma D option quiet please\nBEGIN { exit(0); }").unwrap_err();
+        assert_eq!(error.message, "unexpected `please` after `#pragma D option quiet`");
+        assert_eq!(error.span.line_col("#pragma D option quiet please"), (1, 1));
+        assert!(parse("#pragma D option bufsize=4m extra=1\n").is_err());
+        assert!(parse("#pragma D option quiet\n").is_ok());
+    }
+
+    #[test]
+    fn parse_errors_have_spans() {
+        let source = "BEGIN\n{\n    trace(1 +);\n}";
+        let error = parse(source).unwrap_err();
+        assert_eq!(error.message, "expected an expression, found `)`");
+        assert_eq!(error.span.line_col(source), (3, 14));
+
+        let error = parse("BEGIN { trace(\"unterminated); }").unwrap_err();
+        assert_eq!(error.message, "unterminated constant");
+        assert!(parse("BEGIN { x = 99999999999999999999; }").is_err());
+    }
+}
@@ -0,0 +1,1090 @@
+use super::ast::*;
+use super::lexer::{Lexer, Token, TokenKind};
+use super::ParseError;
+use std::collections::{HashSet, VecDeque};
+
+/// Keywords that start a type name.
+const TYPE_KEYWORDS: &[&str] = &[
+    "char", "short", "int", "long", "signed", "unsigned", "float", "double", "void", "string", "struct", "union",
+    "enum", "const", "volatile", "restrict", "userland",
+];
+
+/// Keywords that start a declaration at the top level.
+const DECL_KEYWORDS: &[&str] = &[
+    "inline", "translator", "provider", "typedef", "extern", "static", "register", "auto", "self", "this",
+];
+
+/// Parses D scripts into a [`Script`].
+///
+/// ```
+/// use libdtrace_rs::dlang::{Item, Parser};
+///
+/// let script = Parser::new("#define LIMIT 10\nsyscall:::entry /arg0 > LIMIT/ { @[probefunc] = count(); }")
+///     .cpp(true)
+///     .parse()?;
+/// assert!(matches!(&script.items[0], Item::Define(define) if define.name == "LIMIT"));
+/// # Ok::<(), libdtrace_rs::dlang::ParseError>(())
+/// ```
+pub struct Parser<'s> {
+    source: &'s str,
+    cpp: bool,
+    typedefs: HashSet<String>,
+}
+
+impl<'s> Parser<'s> {
+    pub fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            cpp: false,
+            typedefs: HashSet::new(),
+        }
+    }
+
+    /// Accepts the C preprocessor directives such as `#define` and `#include`, like `dtrace -C` does.
+    ///
+    /// The directives are kept in the AST as they are, macros are not expanded.
+    pub fn cpp(mut self, cpp: bool) -> Self {
+        self.cpp = cpp;
+        self
+    }
+
+    /// Declares `name` as a type, for casts to types defined outside of the script.
+    ///
+    /// Types declared with `typedef` in the script, names ending in `_t` and module scoped names such as
+    /// ``nt`_PEB`` followed by `*` are recognized as types without declaring them.
+    pub fn typedef(mut self, name: &str) -> Self {
+        self.typedefs.insert(name.to_string());
+        self
+    }
+
+    /// Parses the script.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Script)` - The items and comments of the script.
+    /// * `Err(ParseError)` - The first syntax error of the script.
+    pub fn parse(self) -> Result<Script> {
+        let mut state = State {
+            lexer: Lexer::new(self.source),
+            tokens: VecDeque::new(),
+            cpp: self.cpp,
+            typedefs: self.typedefs,
+        };
+        let mut items = Vec::new();
+        while let Some(item) = state.item()? {
+            items.push(item);
+        }
+
+        Ok(Script {
+            items,
+            comments: state.lexer.into_comments(),
+        })
+    }
+}
+
+struct State<'s> {
+    lexer: Lexer<'s>,
+    /// Tokens lexed ahead of the parser
+    tokens: VecDeque<Token>,
+    cpp: bool,
+    typedefs: HashSet<String>,
+}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+impl State<'_> {
+    fn peek_nth(&mut self, n: usize) -> Result<&Token> {
+        while self.tokens.len() <= n {
+            let token = self.lexer.next_token()?;
+            self.tokens.push_back(token);
+        }
+        Ok(&self.tokens[n])
+    }
+
+    fn peek(&mut self) -> Result<&TokenKind> {
+        Ok(&self.peek_nth(0)?.kind)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        self.peek_nth(0)?;
+        Ok(self.tokens.pop_front().unwrap())
+    }
+
+    /// Drops the tokens lexed ahead and continues lexing at `pos`.
+    fn reset(&mut self, pos: usize) {
+        self.tokens.clear();
+        self.lexer.reset(pos);
+    }
+
+    fn is_punct(&mut self, punct: &str) -> Result<bool> {
+        Ok(matches!(self.peek()?, TokenKind::Punct(p) if *p == punct))
+    }
+
+    fn is_keyword(&mut self, keyword: &str) -> Result<bool> {
+        Ok(matches!(self.peek()?, TokenKind::Ident(ident) if ident == keyword))
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> Result<bool> {
+        let matched = self.is_punct(punct)?;
+        if matched {
+            self.next()?;
+        }
+        Ok(matched)
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<Span> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Punct(p) if p == punct => Ok(token.span),
+            _ => Err(unexpected(&token, &format!("`{}`", punct))),
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<(String, Span)> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Ident(ident) => Ok((ident, token.span)),
+            _ => Err(unexpected(&token, "an identifier")),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<Span> {
+        let token = self.next()?;
+        match &token.kind {
+            TokenKind::Ident(ident) if ident == keyword => Ok(token.span),
+            _ => Err(unexpected(&token, &format!("`{}`", keyword))),
+        }
+    }
+
+    /// Whether the tokens starting at `n` are a type name.
+    fn is_type_at(&mut self, n: usize) -> Result<bool> {
+        let ident = match &self.peek_nth(n)?.kind {
+            TokenKind::Ident(ident) => ident.clone(),
+            _ => return Ok(false),
+        };
+        if TYPE_KEYWORDS.contains(&ident.as_str()) {
+            return Ok(true);
+        }
+        let next = self.peek_nth(n + 1)?.kind.clone();
+        if matches!(next, TokenKind::Punct("`")) {
+            return Ok(matches!(self.peek_nth(n + 2)?.kind, TokenKind::Ident(_))
+                && matches!(self.peek_nth(n + 3)?.kind, TokenKind::Punct("*")));
+        }
+        Ok((self.typedefs.contains(&ident) || ident.ends_with("_t")) && !matches!(next, TokenKind::Punct("(")))
+    }
+
+    fn item(&mut self) -> Result<Option<Item>> {
+        let token = self.peek_nth(0)?.clone();
+        let item = match &token.kind {
+            TokenKind::Eof => return Ok(None),
+            TokenKind::Punct(";") => {
+                self.next()?;
+                return self.item();
+            }
+            TokenKind::Directive(text) => {
+                self.next()?;
+                self.directive(text, token.span)?
+            }
+            TokenKind::Ident(ident) if ident == "inline" => Item::Inline(self.inline()?),
+            TokenKind::Ident(ident) if ident == "translator" => Item::Translator(self.translator()?),
+            TokenKind::Ident(ident) if ident == "provider" => Item::Provider(self.provider()?),
+            TokenKind::Ident(ident) if ident == "typedef" => {
+                let start = self.expect_keyword("typedef")?;
+                let ty = self.type_name()?;
+                let declarator = self.declarator(ty, start)?;
+                self.expect_punct(";")?;
+                self.typedefs.insert(declarator.name.clone());
+                Item::Typedef(declarator)
+            }
+            TokenKind::Ident(ident)
+                if matches!(ident.as_str(), "struct" | "union" | "enum")
+                    && matches!(self.peek_nth(1)?.kind, TokenKind::Ident(_))
+                    && matches!(self.peek_nth(2)?.kind, TokenKind::Punct("{")) =>
+            {
+                match ident.as_str() {
+                    "enum" => Item::Enum(self.enum_def()?),
+                    _ => Item::Struct(self.struct_def()?),
+                }
+            }
+            TokenKind::Ident(ident) if DECL_KEYWORDS.contains(&ident.as_str()) || self.is_declaration()? => {
+                Item::Declaration(self.declaration()?)
+            }
+            _ => {
+                self.reset(token.span.start);
+                Item::Clause(self.clause()?)
+            }
+        };
+        Ok(Some(item))
+    }
+
+    /// Whether the item starting with the next token is a declaration rather than a clause.
+    fn is_declaration(&mut self) -> Result<bool> {
+        if !self.is_type_at(0)? {
+            return Ok(false);
+        }
+        let ident = match self.peek()? {
+            TokenKind::Ident(ident) => ident.clone(),
+            _ => return Ok(false),
+        };
+        // A type keyword can only start a declaration, a type name may also be a probe description.
+        Ok(TYPE_KEYWORDS.contains(&ident.as_str())
+            || matches!(self.peek_nth(1)?.kind, TokenKind::Ident(_) | TokenKind::Punct("*")))
+    }
+
+    fn directive(&mut self, text: &str, span: Span) -> Result<Item> {
+        let (name, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let rest = rest.trim();
+        match name {
+            "pragma" => {
+                let words: Vec<String> = rest.split_whitespace().map(str::to_string).collect();
+                let kind = match words.first().map(String::as_str) {
+                    Some("D") if words.get(1).map(String::as_str) == Some("option") => {
+                        let option = words
+                            .get(2)
+                            .ok_or_else(|| ParseError::new("expected an option name after `#pragma D option`", span))?;
+                        if let Some(extra) = words.get(3) {
+                            return Err(ParseError::new(
+                                format!("unexpected `{}` after `#pragma D option {}`", extra, option),
+                                span,
+                            ));
+                        }
+                        match option.split_once('=') {
+                            Some((name, value)) => PragmaKind::Option {
+                                name: name.to_string(),
+                                value: Some(value.to_string()),
+                            },
+                            None => PragmaKind::Option {
+                                name: option.clone(),
+                                value: None,
+                            },
+                        }
+                    }
+                    Some("D") => PragmaKind::Other(words[1..].to_vec()),
+                    _ => {
+                        return Ok(Item::Directive(Directive {
+                            name: name.to_string(),
+                            text: rest.to_string(),
+                            span,
+                        }))
+                    }
+                };
+                Ok(Item::Pragma(Pragma { kind, span }))
+            }
+            "line" | "ident" => Ok(Item::Directive(Directive {
+                name: name.to_string(),
+                text: rest.to_string(),
+                span,
+            })),
+            "define" if self.cpp => {
+                let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                let (macro_name, after) = rest.split_at(name_len);
+                if macro_name.is_empty() {
+                    return Err(ParseError::new("expected a macro name after `#define`", span));
+                }
+                let (params, body) = match after.strip_prefix('(') {
+                    Some(after) => {
+                        let (params, body) = after
+                            .split_once(')')
+                            .ok_or_else(|| ParseError::new("expected `)` after the macro parameters", span))?;
+                        let params = params.split(',').map(str::trim).filter(|param| !param.is_empty());
+                        (Some(params.map(str::to_string).collect()), body)
+                    }
+                    None => (None, after),
+                };
+                Ok(Item::Define(Define {
+                    name: macro_name.to_string(),
+                    params,
+                    body: body.trim().to_string(),
+                    span,
+                }))
+            }
+            "include" | "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" | "undef" | "error" | "warning"
+                if self.cpp =>
+            {
+                Ok(Item::Directive(Directive {
+                    name: name.to_string(),
+                    text: rest.to_string(),
+                    span,
+                }))
+            }
+            "define" | "include" | "if" | "ifdef" | "ifndef" | "elif" | "else" | "endif" | "undef" | "error"
+            | "warning" => Err(ParseError::new(
+                format!("`#{}` requires the C preprocessor, enable CPP mode", name),
+                span,
+            )),
+            _ => Err(ParseError::new(format!("unsupported control directive `#{}`", name), span)),
+        }
+    }
+
+    fn clause(&mut self) -> Result<Clause> {
+        let mut probes = vec![self.probe_spec()?];
+        while self.eat_punct(",")? {
+            probes.push(self.probe_spec()?);
+        }
+        let mut span = probes[0].span;
+
+        let predicate = match self.is_punct("/")? {
+            true => {
+                self.next()?;
+                let predicate = self.expr()?;
+                let token = self.next()?;
+                if token.kind != TokenKind::PredicateEnd {
+                    return Err(unexpected(&token, "`/` closing the predicate"));
+                }
+                span = span.to(token.span);
+                Some(predicate)
+            }
+            false => None,
+        };
+        let (body, body_span) = match self.is_punct("{")? {
+            true => {
+                let (body, body_span) = self.block()?;
+                span = span.to(body_span);
+                (Some(body), Some(body_span))
+            }
+            false => (None, None),
+        };
+
+        Ok(Clause {
+            probes,
+            predicate,
+            body,
+            body_span,
+            span,
+        })
+    }
+
+    fn probe_spec(&mut self) -> Result<ProbeSpec> {
+        let pos = match self.tokens.front() {
+            Some(token) => token.span.start,
+            None => self.lexer.pos(),
+        };
+        self.reset(pos);
+        let token = self.lexer.probe_spec()?;
+        match token.kind {
+            TokenKind::ProbeSpec(text) => Ok(ProbeSpec { text, span: token.span }),
+            _ => Err(unexpected(&token, "a probe description")),
+        }
+    }
+
+    /// Parses `{ statements }`, returning the statements and the span from the opening to the closing brace.
+    fn block(&mut self) -> Result<(Vec<Stmt>, Span)> {
+        let open = self.expect_punct("{")?;
+        let mut stmts = Vec::new();
+        loop {
+            if self.eat_punct(";")? {
+                continue;
+            }
+            if self.is_punct("}")? {
+                return Ok((stmts, open.to(self.next()?.span)));
+            }
+            let stmt = self.stmt()?;
+            let braced = matches!(stmt, Stmt::If { .. } | Stmt::Block { .. });
+            stmts.push(stmt);
+            if !braced && !self.eat_punct(";")? && !self.is_punct("}")? {
+                let token = self.next()?;
+                return Err(unexpected(&token, "`;` or `}`"));
+            }
+        }
+    }
+
+    fn stmt(&mut self) -> Result<Stmt> {
+        if self.is_punct("{")? {
+            let (stmts, span) = self.block()?;
+            return Ok(Stmt::Block { stmts, span });
+        }
+        if self.is_keyword("if")? {
+            let start = self.next()?.span;
+            self.expect_punct("(")?;
+            let cond = self.expr()?;
+            self.expect_punct(")")?;
+            let (then, then_span) = self.branch()?;
+            let (otherwise, otherwise_span) = match self.is_keyword("else")? {
+                true => {
+                    self.next()?;
+                    let (otherwise, otherwise_span) = self.branch()?;
+                    (Some(otherwise), Some(otherwise_span))
+                }
+                false => (None, None),
+            };
+            return Ok(Stmt::If {
+                cond,
+                then,
+                then_span,
+                otherwise,
+                otherwise_span,
+                span: start.to(otherwise_span.unwrap_or(then_span)),
+            });
+        }
+
+        let expr = self.expr()?;
+        match expr.kind {
+            ExprKind::Assign {
+                op: None,
+                lhs,
+                rhs,
+            } if matches!(lhs.kind, ExprKind::Aggregation { .. }) => {
+                let (name, keys) = match lhs.kind {
+                    ExprKind::Aggregation { name, keys } => (name, keys),
+                    _ => unreachable!(),
+                };
+                match rhs.kind {
+                    ExprKind::Call { function, args } => Ok(Stmt::Aggregate(Aggregate {
+                        name,
+                        keys,
+                        function,
+                        args,
+                        span: expr.span,
+                    })),
+                    _ => Err(ParseError::new("an aggregation must be assigned an aggregating function", rhs.span)),
+                }
+            }
+            ExprKind::Assign { lhs, .. } if matches!(lhs.kind, ExprKind::Aggregation { .. }) => Err(ParseError::new(
+                "an aggregation must be assigned an aggregating function with `=`",
+                expr.span,
+            )),
+            _ => Ok(Stmt::Expr(expr)),
+        }
+    }
+
+    /// Parses the branch of an `if`, a block or a single statement.
+    fn branch(&mut self) -> Result<(Vec<Stmt>, Span)> {
+        if self.is_punct("{")? {
+            return self.block();
+        }
+        let stmt = self.stmt()?;
+        let mut span = stmt.span();
+        if !matches!(stmt, Stmt::If { .. }) {
+            span = span.to(self.expect_punct(";")?);
+        }
+        Ok((vec![stmt], span))
+    }
+
+    fn inline(&mut self) -> Result<Inline> {
+        let start = self.expect_keyword("inline")?;
+        let ty = self.type_name()?;
+        let declarator = self.declarator(ty, start)?;
+        self.expect_punct("=")?;
+        let value = self.assignment()?;
+        let end = self.expect_punct(";")?;
+        Ok(Inline {
+            ty: declarator.ty,
+            name: declarator.name,
+            value,
+            span: start.to(end),
+        })
+    }
+
+    fn translator(&mut self) -> Result<Translator> {
+        let start = self.expect_keyword("translator")?;
+        let output = self.type_name()?;
+        self.expect_punct("<")?;
+        let input = self.type_name()?;
+        let Declarator { ty: input, name: param, .. } = self.declarator(input, start)?;
+        self.expect_punct(">")?;
+        self.expect_punct("{")?;
+        let mut members = Vec::new();
+        while !self.eat_punct("}")? {
+            let (member, _) = self.expect_ident()?;
+            self.expect_punct("=")?;
+            members.push((member, self.assignment()?));
+            self.expect_punct(";")?;
+        }
+        let end = self.expect_punct(";")?;
+        Ok(Translator {
+            output,
+            input,
+            param,
+            members,
+            span: start.to(end),
+        })
+    }
+
+    fn provider(&mut self) -> Result<ProviderDef> {
+        let start = self.expect_keyword("provider")?;
+        let (name, _) = self.expect_ident()?;
+        self.expect_punct("{")?;
+        let mut probes = Vec::new();
+        while !self.eat_punct("}")? {
+            let probe_start = self.expect_keyword("probe")?;
+            let (probe, _) = self.expect_ident()?;
+            self.expect_punct("(")?;
+            let mut args = Vec::new();
+            if !self.is_punct(")")? {
+                loop {
+                    args.push(self.abstract_type()?);
+                    if matches!(self.peek()?, TokenKind::Ident(_)) {
+                        self.next()?;
+                    }
+                    if !self.eat_punct(",")? {
+                        break;
+                    }
+                }
+            }
+            self.expect_punct(")")?;
+            let end = self.expect_punct(";")?;
+            probes.push(ProbeDecl {
+                name: probe,
+                args,
+                span: probe_start.to(end),
+            });
+        }
+        let end = self.expect_punct(";")?;
+        Ok(ProviderDef {
+            name,
+            probes,
+            span: start.to(end),
+        })
+    }
+
+    fn struct_def(&mut self) -> Result<StructDef> {
+        let token = self.next()?;
+        let union = token.kind == TokenKind::Ident("union".to_string());
+        let (name, _) = self.expect_ident()?;
+        self.expect_punct("{")?;
+        let mut fields = Vec::new();
+        while !self.eat_punct("}")? {
+            let ty = self.type_name()?;
+            let start = ty.span;
+            loop {
+                fields.push(self.declarator(ty.clone(), start)?);
+                if !self.eat_punct(",")? {
+                    break;
+                }
+            }
+            self.expect_punct(";")?;
+        }
+        let end = self.expect_punct(";")?;
+        Ok(StructDef {
+            union,
+            name,
+            fields,
+            span: token.span.to(end),
+        })
+    }
+
+    fn enum_def(&mut self) -> Result<EnumDef> {
+        let start = self.expect_keyword("enum")?;
+        let (name, _) = self.expect_ident()?;
+        self.expect_punct("{")?;
+        let mut variants = Vec::new();
+        while !self.eat_punct("}")? {
+            let (variant, _) = self.expect_ident()?;
+            let value = match self.eat_punct("=")? {
+                true => Some(self.ternary()?),
+                false => None,
+            };
+            variants.push((variant, value));
+            if !self.eat_punct(",")? {
+                self.expect_punct("}")?;
+                break;
+            }
+        }
+        let end = self.expect_punct(";")?;
+        Ok(EnumDef {
+            name,
+            variants,
+            span: start.to(end),
+        })
+    }
+
+    fn declaration(&mut self) -> Result<Declaration> {
+        let start = self.peek_nth(0)?.span;
+        let mut scope = VarScope::Global;
+        let mut storage = Vec::new();
+        loop {
+            match self.peek()? {
+                TokenKind::Ident(ident) if ident == "self" => scope = VarScope::Thread,
+                TokenKind::Ident(ident) if ident == "this" => scope = VarScope::Clause,
+                TokenKind::Ident(ident) if matches!(ident.as_str(), "extern" | "static" | "register" | "auto") => {
+                    storage.push(ident.clone())
+                }
+                _ => break,
+            }
+            self.next()?;
+        }
+
+        let ty = self.type_name()?;
+        let mut declarators = Vec::new();
+        loop {
+            declarators.push(self.declarator(ty.clone(), ty.span)?);
+            if !self.eat_punct(",")? {
+                break;
+            }
+        }
+        let end = self.expect_punct(";")?;
+        Ok(Declaration {
+            scope,
+            storage,
+            declarators,
+            span: start.to(end),
+        })
+    }
+
+    /// Parses the pointers, name and array dimensions declared with the type `ty`.
+    fn declarator(&mut self, mut ty: TypeName, start: Span) -> Result<Declarator> {
+        while self.is_punct("*")? {
+            ty.pointers += 1;
+            ty.span = ty.span.to(self.next()?.span);
+        }
+        let (name, mut span) = self.expect_ident()?;
+        let mut dims = Vec::new();
+        while self.eat_punct("[")? {
+            let dim = match self.is_type_at(0)? {
+                true => {
+                    let mut keys = vec![self.type_name()?];
+                    while self.eat_punct(",")? {
+                        keys.push(self.type_name()?);
+                    }
+                    ArrayDim::Keys(keys)
+                }
+                false => ArrayDim::Size(self.assignment()?),
+            };
+            dims.push(dim);
+            span = self.expect_punct("]")?;
+        }
+        Ok(Declarator {
+            ty,
+            name,
+            dims,
+            span: start.to(span),
+        })
+    }
+
+    fn type_name(&mut self) -> Result<TypeName> {
+        let start = self.peek_nth(0)?.span;
+        let mut end = start;
+        let mut words: Vec<String> = Vec::new();
+        let mut base = false;
+        while let TokenKind::Ident(ident) = self.peek()?.clone() {
+            match ident.as_str() {
+                "const" | "volatile" | "restrict" | "userland" => {}
+                "struct" | "union" | "enum" if !base => {
+                    self.next()?;
+                    let (name, span) = self.expect_ident()?;
+                    words.push(format!("{} {}", ident, name));
+                    end = span;
+                    base = true;
+                    continue;
+                }
+                "char" | "short" | "int" | "long" | "signed" | "unsigned" | "float" | "double" | "void" | "string" => {
+                    base = true
+                }
+                _ if !base => {
+                    let token = self.next()?;
+                    end = token.span;
+                    base = true;
+                    if self.is_punct("`")? {
+                        self.next()?;
+                        let (name, span) = self.expect_ident()?;
+                        words.push(format!("{}`{}", ident, name));
+                        end = span;
+                    } else {
+                        words.push(ident);
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            end = self.next()?.span;
+            words.push(ident);
+        }
+        if !base {
+            let token = self.next()?;
+            return Err(unexpected(&token, "a type"));
+        }
+
+        let mut pointers = 0;
+        while self.is_punct("*")? && !matches!(self.peek_nth(1)?.kind, TokenKind::Ident(_)) {
+            pointers += 1;
+            end = self.next()?.span;
+        }
+        Ok(TypeName {
+            name: words.join(" "),
+            pointers,
+            span: start.to(end),
+        })
+    }
+
+    /// Parses a type name in a cast or `sizeof`, where all `*` belong to the type.
+    fn abstract_type(&mut self) -> Result<TypeName> {
+        let mut ty = self.type_name()?;
+        while self.is_punct("*")? {
+            ty.pointers += 1;
+            ty.span = ty.span.to(self.next()?.span);
+        }
+        Ok(ty)
+    }
+
+    /// Parses an expression, including the comma operator at the lowest precedence.
+    fn expr(&mut self) -> Result<Expr> {
+        let mut lhs = self.assignment()?;
+        while self.eat_punct(",")? {
+            let rhs = self.assignment()?;
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Comma {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// Parses an expression without a top level comma, as in arguments and subscripts.
+    fn assignment(&mut self) -> Result<Expr> {
+        let lhs = self.ternary()?;
+        let op = match self.peek()? {
+            TokenKind::Punct(punct) => match *punct {
+                "=" => Some(None),
+                "*=" => Some(Some(BinaryOp::Mul)),
+                "/=" => Some(Some(BinaryOp::Div)),
+                "%=" => Some(Some(BinaryOp::Mod)),
+                "+=" => Some(Some(BinaryOp::Add)),
+                "-=" => Some(Some(BinaryOp::Sub)),
+                "<<=" => Some(Some(BinaryOp::Shl)),
+                ">>=" => Some(Some(BinaryOp::Shr)),
+                "&=" => Some(Some(BinaryOp::BitAnd)),
+                "^=" => Some(Some(BinaryOp::BitXor)),
+                "|=" => Some(Some(BinaryOp::BitOr)),
+                _ => None,
+            },
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.next()?;
+                let rhs = self.assignment()?;
+                Ok(Expr {
+                    span: lhs.span.to(rhs.span),
+                    kind: ExprKind::Assign {
+                        op,
+                        lhs: Box::new(lhs),
+                        rhs: Box::new(rhs),
+                    },
+                })
+            }
+            None => Ok(lhs),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<Expr> {
+        let cond = self.binary(0)?;
+        if !self.eat_punct("?")? {
+            return Ok(cond);
+        }
+        let then = self.expr()?;
+        self.expect_punct(":")?;
+        let otherwise = self.ternary()?;
+        Ok(Expr {
+            span: cond.span.to(otherwise.span),
+            kind: ExprKind::Ternary {
+                cond: Box::new(cond),
+                then: Box::new(then),
+                otherwise: Box::new(otherwise),
+            },
+        })
+    }
+
+    fn binary_op(&mut self) -> Result<Option<BinaryOp>> {
+        let op = match self.peek()? {
+            TokenKind::Punct(punct) => match *punct {
+                "*" => BinaryOp::Mul,
+                "/" => BinaryOp::Div,
+                "%" => BinaryOp::Mod,
+                "+" => BinaryOp::Add,
+                "-" => BinaryOp::Sub,
+                "<<" => BinaryOp::Shl,
+                ">>" => BinaryOp::Shr,
+                "<" => BinaryOp::Lt,
+                "<=" => BinaryOp::Le,
+                ">" => BinaryOp::Gt,
+                ">=" => BinaryOp::Ge,
+                "==" => BinaryOp::Eq,
+                "!=" => BinaryOp::Ne,
+                "&" => BinaryOp::BitAnd,
+                "^" => BinaryOp::BitXor,
+                "|" => BinaryOp::BitOr,
+                "&&" => BinaryOp::And,
+                "^^" => BinaryOp::Xor,
+                "||" => BinaryOp::Or,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(op))
+    }
+
+    /// Parses binary operators binding at least as tight as `min`, by precedence climbing.
+    fn binary(&mut self, min: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op()? {
+            if op.precedence() < min {
+                break;
+            }
+            self.next()?;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                },
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let token = self.peek_nth(0)?.clone();
+        let op = match &token.kind {
+            TokenKind::Punct("-") => Some(UnaryOp::Neg),
+            TokenKind::Punct("+") => Some(UnaryOp::Plus),
+            TokenKind::Punct("!") => Some(UnaryOp::Not),
+            TokenKind::Punct("~") => Some(UnaryOp::BitNot),
+            TokenKind::Punct("*") => Some(UnaryOp::Deref),
+            TokenKind::Punct("&") => Some(UnaryOp::AddrOf),
+            TokenKind::Punct("++") => Some(UnaryOp::Inc),
+            TokenKind::Punct("--") => Some(UnaryOp::Dec),
+            _ => None,
+        };
+        if let Some(op) = op {
+            self.next()?;
+            let expr = self.unary()?;
+            return Ok(Expr {
+                span: token.span.to(expr.span),
+                kind: ExprKind::Unary {
+                    op,
+                    expr: Box::new(expr),
+                },
+            });
+        }
+
+        match &token.kind {
+            TokenKind::Punct("(") if self.is_type_at(1)? => {
+                self.next()?;
+                let ty = self.abstract_type()?;
+                self.expect_punct(")")?;
+                let expr = self.unary()?;
+                Ok(Expr {
+                    span: token.span.to(expr.span),
+                    kind: ExprKind::Cast {
+                        ty,
+                        expr: Box::new(expr),
+                    },
+                })
+            }
+            TokenKind::Ident(ident) if ident == "sizeof" => {
+                self.next()?;
+                if self.is_punct("(")? && self.is_type_at(1)? {
+                    self.next()?;
+                    let ty = self.abstract_type()?;
+                    let end = self.expect_punct(")")?;
+                    return Ok(Expr {
+                        kind: ExprKind::SizeofType(ty),
+                        span: token.span.to(end),
+                    });
+                }
+                let expr = self.unary()?;
+                Ok(Expr {
+                    span: token.span.to(expr.span),
+                    kind: ExprKind::SizeofExpr(Box::new(expr)),
+                })
+            }
+            TokenKind::Ident(ident) if ident == "stringof" => {
+                self.next()?;
+                let expr = self.unary()?;
+                Ok(Expr {
+                    span: token.span.to(expr.span),
+                    kind: ExprKind::Stringof(Box::new(expr)),
+                })
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            let token = self.peek_nth(0)?.clone();
+            expr = match token.kind {
+                TokenKind::Punct("[") => {
+                    self.next()?;
+                    let keys = self.args("]")?;
+                    let span = expr.span.to(self.expect_punct("]")?);
+                    match expr.kind {
+                        ExprKind::Aggregation { name, keys: none } if none.is_empty() => Expr {
+                            kind: ExprKind::Aggregation { name, keys },
+                            span,
+                        },
+                        _ => Expr {
+                            kind: ExprKind::Index {
+                                base: Box::new(expr),
+                                keys,
+                            },
+                            span,
+                        },
+                    }
+                }
+                TokenKind::Punct("(") => {
+                    let function = match expr.kind {
+                        ExprKind::Ident(function) => function,
+                        _ => return Err(ParseError::new("only actions and subroutines can be called", expr.span)),
+                    };
+                    self.next()?;
+                    let args = self.args(")")?;
+                    Expr {
+                        kind: ExprKind::Call { function, args },
+                        span: expr.span.to(self.expect_punct(")")?),
+                    }
+                }
+                TokenKind::Punct(punct @ ("." | "->")) => {
+                    self.next()?;
+                    let (member, span) = self.expect_ident()?;
+                    Expr {
+                        span: expr.span.to(span),
+                        kind: ExprKind::Member {
+                            base: Box::new(expr),
+                            member,
+                            arrow: punct == "->",
+                        },
+                    }
+                }
+                TokenKind::Punct(punct @ ("++" | "--")) => {
+                    self.next()?;
+                    Expr {
+                        span: expr.span.to(token.span),
+                        kind: ExprKind::Postfix {
+                            op: if punct == "++" { UnaryOp::Inc } else { UnaryOp::Dec },
+                            expr: Box::new(expr),
+                        },
+                    }
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    /// Parses comma separated expressions up to, but not including, the `close` punctuator.
+    fn args(&mut self, close: &str) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.is_punct(close)? {
+            return Ok(args);
+        }
+        loop {
+            args.push(self.assignment()?);
+            if !self.eat_punct(",")? {
+                return Ok(args);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self.next()?;
+        let span = token.span;
+        let kind = match token.kind {
+            TokenKind::Int { value, text } => ExprKind::Int { value, text },
+            TokenKind::Char(text) => ExprKind::Char(text),
+            TokenKind::Str(mut value) => {
+                // Adjacent string constants are concatenated
+                let mut span = span;
+                while let TokenKind::Str(next) = self.peek()? {
+                    value.extend_from_slice(next);
+                    span = span.to(self.next()?.span);
+                }
+                return Ok(Expr {
+                    kind: ExprKind::Str(value),
+                    span,
+                });
+            }
+            TokenKind::MacroVar { name, quoted } => ExprKind::MacroVar { name, quoted },
+            TokenKind::Aggregation(name) => ExprKind::Aggregation { name, keys: Vec::new() },
+            TokenKind::Punct("(") => {
+                let expr = self.expr()?;
+                let end = self.expect_punct(")")?;
+                return Ok(Expr {
+                    kind: expr.kind,
+                    span: span.to(end),
+                });
+            }
+            TokenKind::Punct("`") => {
+                let (name, end) = self.expect_ident()?;
+                return Ok(Expr {
+                    kind: ExprKind::Symbol { module: None, name },
+                    span: span.to(end),
+                });
+            }
+            TokenKind::Ident(ident) => match ident.as_str() {
+                "self" | "this" => {
+                    self.expect_punct("->")?;
+                    let (name, end) = self.expect_ident()?;
+                    let scope = if ident == "self" { VarScope::Thread } else { VarScope::Clause };
+                    return Ok(Expr {
+                        kind: ExprKind::Var { scope, name },
+                        span: span.to(end),
+                    });
+                }
+                "offsetof" => {
+                    self.expect_punct("(")?;
+                    let ty = self.abstract_type()?;
+                    self.expect_punct(",")?;
+                    let (member, _) = self.expect_ident()?;
+                    let end = self.expect_punct(")")?;
+                    return Ok(Expr {
+                        kind: ExprKind::Offsetof { ty, member },
+                        span: span.to(end),
+                    });
+                }
+                "xlate" => {
+                    self.expect_punct("<")?;
+                    let ty = self.abstract_type()?;
+                    self.expect_punct(">")?;
+                    self.expect_punct("(")?;
+                    let expr = self.expr()?;
+                    let end = self.expect_punct(")")?;
+                    return Ok(Expr {
+                        kind: ExprKind::Xlate {
+                            ty,
+                            expr: Box::new(expr),
+                        },
+                        span: span.to(end),
+                    });
+                }
+                _ if self.is_punct("`")? => {
+                    self.next()?;
+                    let (name, end) = self.expect_ident()?;
+                    return Ok(Expr {
+                        kind: ExprKind::Symbol {
+                            module: Some(ident),
+                            name,
+                        },
+                        span: span.to(end),
+                    });
+                }
+                _ => ExprKind::Ident(ident),
+            },
+            _ => return Err(unexpected(&token, "an expression")),
+        };
+        Ok(Expr { kind, span })
+    }
+}
+
+/// The error for an unexpected token.
+fn unexpected(token: &Token, expected: &str) -> ParseError {
+    let found = match &token.kind {
+        TokenKind::Ident(ident) => format!("`{}`", ident),
+        TokenKind::Int { text, .. } => format!("`{}`", text),
+        TokenKind::Char(text) => format!("`'{}'`", text),
+        TokenKind::Str(_) => "a string".to_string(),
+        TokenKind::MacroVar { name, quoted } => format!("`{}{}`", if *quoted { "$$" } else { "$" }, name),
+        TokenKind::Aggregation(name) => format!("`@{}`", name),
+        TokenKind::Punct(punct) => format!("`{}`", punct),
+        TokenKind::PredicateEnd => "`/`".to_string(),
+        TokenKind::Directive(_) => "a control line".to_string(),
+        TokenKind::ProbeSpec(text) => format!("`{}`", text),
+        TokenKind::Eof => "the end of the script".to_string(),
+    };
+    ParseError::new(format!("expected {}, found {}", expected, found), token.span)
+}
@@ -0,0 +1,252 @@
+use crate::data::ProbeData;
+use crate::probe::ProbeInfo;
+use crate::record::Record;
+use crate::types::dtrace_status;
+
+/// An enabled probe that fired, along with its decoded records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbeEvent {
+    /// The CPU the probe fired on
+    pub cpu: i32,
+    /// The enabled probe ID
+    pub epid: u32,
+    /// The probe ID
+    pub id: u32,
+    pub provider: String,
+    pub module: String,
+    pub function: String,
+    pub name: String,
+    /// The records of the probe clause, in the order they were traced
+    pub records: Vec<Record>,
+}
+
+impl ProbeEvent {
+    /// Copies the description of `probe` so that it outlives the consume call it was handed out in.
+    pub fn new(probe: &ProbeData, records: Vec<Record>) -> Self {
+        Self {
+            cpu: probe.cpu(),
+            epid: probe.epid(),
+            id: probe.id(),
+            provider: probe.provider().to_string(),
+            module: probe.module().to_string(),
+            function: probe.function().to_string(),
+            name: probe.name().to_string(),
+            records,
+        }
+    }
+}
+
+/// The buffer that dropped data, from `dtrace_dropkind_t`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropKind {
+    /// The principal buffer was full
+    Principal,
+    /// The aggregation buffer was full
+    Aggregation,
+    /// The dynamic variable space was full
+    Dynamic,
+    /// Dynamic variables were dropped with rinsing dirty variables
+    DynamicRinse,
+    /// Dynamic variables were dropped with dirty variables
+    DynamicDirty,
+    /// A speculative buffer was full
+    Speculation,
+    /// All speculative buffers were busy
+    SpeculationBusy,
+    /// No speculative buffer was available
+    SpeculationUnavailable,
+    /// A stack string table overflowed
+    StackStringOverflow,
+    /// An error was raised while processing an error
+    DoubleError,
+}
+
+impl From<crate::dtrace_dropkind_t> for DropKind {
+    fn from(value: crate::dtrace_dropkind_t) -> Self {
+        match value {
+            crate::dtrace_dropkind_t::DTRACEDROP_PRINCIPAL => DropKind::Principal,
+            crate::dtrace_dropkind_t::DTRACEDROP_AGGREGATION => DropKind::Aggregation,
+            crate::dtrace_dropkind_t::DTRACEDROP_DYNAMIC => DropKind::Dynamic,
+            crate::dtrace_dropkind_t::DTRACEDROP_DYNRINSE => DropKind::DynamicRinse,
+            crate::dtrace_dropkind_t::DTRACEDROP_DYNDIRTY => DropKind::DynamicDirty,
+            crate::dtrace_dropkind_t::DTRACEDROP_SPEC => DropKind::Speculation,
+            crate::dtrace_dropkind_t::DTRACEDROP_SPECBUSY => DropKind::SpeculationBusy,
+            crate::dtrace_dropkind_t::DTRACEDROP_SPECUNAVAIL => DropKind::SpeculationUnavailable,
+            crate::dtrace_dropkind_t::DTRACEDROP_STKSTROVERFLOW => DropKind::StackStringOverflow,
+            crate::dtrace_dropkind_t::DTRACEDROP_DBLERROR => DropKind::DoubleError,
+        }
+    }
+}
+
+/// Data the kernel dropped because a buffer was full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DropEvent {
+    /// The buffer that dropped data
+    pub kind: DropKind,
+    /// The CPU whose buffer dropped data, `-1` if the drop is not specific to a CPU
+    pub cpu: i32,
+    /// Number of drops since the last report
+    pub count: u64,
+    /// Number of drops since tracing started
+    pub total: u64,
+    /// The message libdtrace would print for the drop
+    pub message: String,
+}
+
+impl DropEvent {
+    /// Copies the drop data handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be a valid drop data handed out by libdtrace.
+    pub(crate) unsafe fn from_raw(data: &crate::dtrace_dropdata_t) -> Self {
+        Self {
+            kind: DropKind::from(data.dtdda_kind),
+            cpu: data.dtdda_cpu,
+            count: data.dtdda_drops,
+            total: data.dtdda_total,
+            message: message(data.dtdda_msg),
+        }
+    }
+}
+
+/// Why an action faulted, from the `DTRACEFLT_*` fault codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultKind {
+    /// Unknown fault
+    Unknown,
+    /// Invalid address
+    BadAddress,
+    /// Invalid alignment
+    BadAlignment,
+    /// Illegal operation
+    IllegalOperation,
+    /// Divide by zero
+    DivideByZero,
+    /// Out of scratch space
+    NoScratch,
+    /// Illegal kernel access
+    KernelPrivilege,
+    /// Illegal user access
+    UserPrivilege,
+    /// Tuple stack overflow
+    TupleOverflow,
+    /// Bad stack
+    BadStack,
+    /// A fault raised by libdtrace itself
+    Library,
+    /// A fault code unknown to this crate
+    Other(i32),
+}
+
+impl From<i32> for FaultKind {
+    fn from(value: i32) -> Self {
+        match value as u32 {
+            crate::DTRACEFLT_UNKNOWN => FaultKind::Unknown,
+            crate::DTRACEFLT_BADADDR => FaultKind::BadAddress,
+            crate::DTRACEFLT_BADALIGN => FaultKind::BadAlignment,
+            crate::DTRACEFLT_ILLOP => FaultKind::IllegalOperation,
+            crate::DTRACEFLT_DIVZERO => FaultKind::DivideByZero,
+            crate::DTRACEFLT_NOSCRATCH => FaultKind::NoScratch,
+            crate::DTRACEFLT_KPRIV => FaultKind::KernelPrivilege,
+            crate::DTRACEFLT_UPRIV => FaultKind::UserPrivilege,
+            crate::DTRACEFLT_TUPOFLOW => FaultKind::TupleOverflow,
+            crate::DTRACEFLT_BADSTACK => FaultKind::BadStack,
+            crate::DTRACEFLT_LIBRARY => FaultKind::Library,
+            _ => FaultKind::Other(value),
+        }
+    }
+}
+
+/// A fault raised while executing the actions of an enabled probe, e.g. a bad address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultEvent {
+    /// The probe whose actions faulted
+    pub probe: ProbeInfo,
+    /// Why the action faulted
+    pub kind: FaultKind,
+    /// The CPU the fault occurred on
+    pub cpu: i32,
+    /// The index of the faulting action in its probe clause, negative for the predicate
+    pub action: i32,
+    /// The offset of the faulting instruction in the DIF object of the action
+    pub offset: i32,
+    /// The faulting address, for address faults
+    pub address: u64,
+    /// The message libdtrace would print for the fault
+    pub message: String,
+}
+
+impl FaultEvent {
+    /// Copies the error data handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be a valid error data handed out by libdtrace.
+    pub(crate) unsafe fn from_raw(data: &crate::dtrace_errdata_t) -> Self {
+        Self {
+            probe: match data.dteda_pdesc.is_null() {
+                true => ProbeInfo::default(),
+                false => ProbeInfo::from_raw(&*data.dteda_pdesc),
+            },
+            kind: FaultKind::from(data.dteda_fault),
+            cpu: data.dteda_cpu,
+            action: data.dteda_action,
+            offset: data.dteda_offset,
+            address: data.dteda_addr,
+            message: message(data.dteda_msg),
+        }
+    }
+}
+
+/// An option set by a D program, e.g. with `setopt()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionChange {
+    /// The name of the option
+    pub name: String,
+    /// The raw value before the change, see [`crate::options::DtraceOption::from_optval`]
+    pub old: i64,
+    /// The raw value after the change
+    pub new: i64,
+}
+
+impl OptionChange {
+    /// Copies the option data handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `data` must be a valid option data handed out by libdtrace.
+    pub(crate) unsafe fn from_raw(data: &crate::dtrace_setoptdata_t) -> Self {
+        Self {
+            name: message(data.dtsda_option),
+            old: data.dtsda_oldval,
+            new: data.dtsda_newval,
+        }
+    }
+}
+
+/// Copies a message handed out by libdtrace, without its trailing newline.
+unsafe fn message(msg: *const ::core::ffi::c_char) -> String {
+    if msg.is_null() {
+        return String::new();
+    }
+    ::core::ffi::CStr::from_ptr(msg)
+        .to_string_lossy()
+        .trim_end()
+        .to_string()
+}
+
+/// Something that happened while consuming trace data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// An enabled probe fired
+    Probe(ProbeEvent),
+    /// Output formatted by libdtrace, e.g. by `printf()` or `printa()`
+    Buffered(String),
+    /// The kernel dropped data
+    Drop(DropEvent),
+    /// An action faulted
+    Fault(FaultEvent),
+    /// The status of tracing changed
+    Status(dtrace_status),
+}
@@ -2,10 +2,29 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+// `d!` expands to paths under `::libdtrace_rs`, which have to resolve inside this crate too
+extern crate self as libdtrace_rs;
+pub use libdtrace_macros::d;
 pub mod callbacks;
 pub mod wrapper;
 pub mod utils;
 pub mod types;
+pub mod data;
+pub mod record;
+pub mod aggregation;
+pub mod program;
+pub mod dif;
+pub mod args;
+pub mod options;
+pub mod event;
+pub mod probe;
+pub mod dlang;
+mod stdio;
+pub mod backend;
+pub mod consumer;
+pub mod mock;
+#[cfg(feature = "async")]
+pub mod stream;
 
 #[cfg(test)]
 mod tests {
@@ -28,22 +47,141 @@ mod tests {
     #[test]
     fn dtrace_handle_buffered() -> Result<(), utils::Error> {
         dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?
-            .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Some(callbacks::buffered)), None)?;
+            .dtrace_register_handler(crate::types::dtrace_handler::Buffered(Box::new(|output| {
+                print!("{}", output);
+                crate::types::HandleAction::Ok
+            })))?;
         Ok(())
     }
 
     #[test]
     fn dtrace_compile_and_exec() -> Result<(), utils::Error> {
         let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
-        let prog = handle
+        let mut prog = handle
                     .dtrace_program_strcompile(
                         "dtrace:::BEGIN {trace(\"Hello World\");} syscall:::entry { @num[execname] = count(); }", 
                         dtrace_probespec::DTRACE_PROBESPEC_NAME, 
                         DTRACE_C_ZDEFS,
                         None)?;
         
-        handle.dtrace_program_exec(prog, None)?;
+        handle.dtrace_program_exec(&mut prog, None)?;
 
         Ok(())
     }
+
+    #[test]
+    fn dtrace_compile_multiple_programs() -> Result<(), utils::Error> {
+        let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let mut first = handle.dtrace_program_strcompile(
+            "dtrace:::BEGIN { trace(1); }",
+            dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            DTRACE_C_ZDEFS,
+            None,
+        )?;
+        let mut second = handle.dtrace_program_strcompile(
+            "dtrace:::BEGIN, dtrace:::END { @[probename] = count(); }",
+            dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            DTRACE_C_ZDEFS,
+            None,
+        )?;
+
+        assert!(first.info().is_none());
+        assert_eq!(first.exec()?.records, 1);
+        assert_eq!(second.exec()?.aggregates, 1);
+        let names: Vec<_> = second.statements()?.iter().map(|stmt| stmt.name().to_string()).collect();
+        assert_eq!(names, vec!["BEGIN", "END"]);
+        let statements = second.statements()?;
+        let actions = statements[0].actions();
+        assert!(!statements[0].has_predicate());
+        assert_eq!(actions.len(), 1);
+        assert_eq!((actions[0].kind as u32, actions[0].records), (DTRACEAGG_COUNT, 2));
+        let keys = cfg!(target_os = "windows").then_some(1);
+        assert_eq!(statements[0].aggregation().map(|agg| agg.keys), keys);
+        assert!(statements[0].predicate_dif().is_none());
+        let listed = statements[0].disassemble().contains("! DT_VAR(277) = \"probename\"");
+        assert_eq!(listed, cfg!(target_os = "windows"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn dtrace_exec_rejects_foreign_program() -> Result<(), utils::Error> {
+        let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let other = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let mut prog = other.dtrace_program_strcompile(
+            "dtrace:::BEGIN { trace(1); }",
+            dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            DTRACE_C_ZDEFS,
+            None,
+        )?;
+
+        let error = handle.dtrace_program_exec(&mut prog, None).unwrap_err();
+        assert_eq!(error.kind(), utils::ErrorKind::Os(libc::EINVAL));
+        assert!(prog.info().is_none());
+        other.dtrace_program_exec(&mut prog, None)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn dtrace_compile_with_macro_args() -> Result<(), utils::Error> {
+        let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let args = args::MacroArgs::new().int(1).string("Hello World");
+        let mut prog = handle.dtrace_program_strcompile(
+            "dtrace:::BEGIN /$1 == 1/ { trace($$2); }",
+            dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            DTRACE_C_ZDEFS,
+            Some(&args),
+        )?;
+        handle.dtrace_program_exec(&mut prog, None)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn dtrace_capture_output() -> Result<(), utils::Error> {
+        let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let mut prog = handle.dtrace_program_strcompile(
+            "dtrace:::BEGIN { printf(\"Hello %s\", \"World\"); @num[\"BEGIN\"] = count(); exit(0); }",
+            dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            DTRACE_C_ZDEFS,
+            None,
+        )?;
+        handle.dtrace_program_exec(&mut prog, None)?;
+        handle.dtrace_go()?;
+
+        let mut output = Vec::new();
+        loop {
+            handle.dtrace_sleep();
+            let status = handle.dtrace_work_with(
+                Some(&mut output),
+                |_| types::ConsumeAction::This,
+                |_, record| match record {
+                    Some(record::Record::Exit(_)) | None => types::ConsumeAction::Next,
+                    Some(_) => types::ConsumeAction::This,
+                },
+            )?;
+            if status == dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE {
+                break;
+            }
+        }
+        handle.dtrace_stop()?;
+        handle.dtrace_aggregate_print(Some(&mut output), None)?;
+
+        let output = String::from_utf8_lossy(&output);
+        assert!(output.contains("Hello World"));
+        assert!(output.contains("BEGIN"));
+        Ok(())
+    }
+
+    #[test]
+    fn dtrace_list_probes() -> Result<(), utils::Error> {
+        let handle = dtrace_hdl::dtrace_open(DTRACE_VERSION as i32, 0)?;
+        let filter = probe::ProbeDescription::new("dtrace", "", "", "BEGIN");
+        let probes: Vec<_> = handle.probes(Some(&filter))?.collect();
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].to_string(), "dtrace:::BEGIN");
+        assert_eq!(handle.probe_argc(&probes[0])?, 0);
+        Ok(())
+    }
 }
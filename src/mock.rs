@@ -0,0 +1,869 @@
+use crate::args::MacroArgs;
+use crate::backend::DtraceBackend;
+use crate::data::{AggregateData, ProbeData, RecordDesc};
+use crate::callbacks::Handlers;
+use crate::event::{DropEvent, Event, FaultEvent};
+use crate::options::{DtraceOption, Size, DTRACEOPT_UNSET};
+use crate::probe::{ProbeDescription, ProbeInfo, Probes};
+use crate::record::Record;
+use crate::types::{dtrace_aggwalk_order, dtrace_status, AggWalkAction, ConsumeAction};
+use crate::utils::{Error, ErrorKind};
+use ::core::ffi::c_int;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+
+/// Appends `bytes` to `data` at the next offset aligned to `alignment` and describes it with `desc`.
+fn push_record(data: &mut Vec<u8>, records: &mut Vec<RecordDesc>, mut desc: RecordDesc, bytes: &[u8]) {
+    let alignment = desc.alignment.max(1) as usize;
+    let padding = (alignment - data.len() % alignment) % alignment;
+    data.resize(data.len() + padding, 0);
+
+    desc.offset = data.len() as u32;
+    desc.size = bytes.len() as u32;
+    desc.alignment = alignment as u16;
+    data.extend_from_slice(bytes);
+    records.push(desc);
+}
+
+/// A scripted firing of an enabled probe, delivered to the handlers of [`MockBackend::work`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockProbe {
+    pub cpu: i32,
+    pub epid: u32,
+    pub id: u32,
+    pub provider: String,
+    pub module: String,
+    pub function: String,
+    pub name: String,
+    pub records: Vec<RecordDesc>,
+    pub data: Vec<u8>,
+    pub formats: Vec<String>,
+}
+
+impl MockProbe {
+    /// Creates a probe firing without any records.
+    pub fn new(provider: &str, module: &str, function: &str, name: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            module: module.to_string(),
+            function: function.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the CPU the probe fired on.
+    pub fn cpu(mut self, cpu: i32) -> Self {
+        self.cpu = cpu;
+        self
+    }
+
+    /// Sets the enabled probe ID and the probe ID.
+    pub fn ids(mut self, epid: u32, id: u32) -> Self {
+        self.epid = epid;
+        self.id = id;
+        self
+    }
+
+    /// Appends a record generated by `action`, placed at the next offset aligned to `alignment`.
+    pub fn record(self, action: u32, alignment: u16, bytes: &[u8]) -> Self {
+        let desc = RecordDesc {
+            action: action as u16,
+            alignment,
+            ..Default::default()
+        };
+        self.record_with(desc, bytes)
+    }
+
+    /// Appends a record described by `desc`. The offset and size of `desc` are filled in.
+    pub fn record_with(mut self, desc: RecordDesc, bytes: &[u8]) -> Self {
+        push_record(&mut self.data, &mut self.records, desc, bytes);
+        self
+    }
+
+    /// Appends the records of `printf(format, args...)`: a record referencing `format`, followed by one record per
+    /// argument. Arguments of 1, 2, 4 or 8 bytes are aligned to their size.
+    pub fn printf(mut self, format: &str, args: &[&[u8]]) -> Self {
+        self.formats.push(format.to_string());
+        let desc = RecordDesc {
+            action: crate::DTRACEACT_PRINTF as u16,
+            alignment: 1,
+            format: self.formats.len() as u32,
+            ..Default::default()
+        };
+        self = self.record_with(desc, &[]);
+        for arg in args {
+            let alignment = match arg.len() {
+                1 | 2 | 4 | 8 => arg.len() as u16,
+                _ => 1,
+            };
+            self = self.record(crate::DTRACEACT_PRINTF, alignment, arg);
+        }
+        self
+    }
+
+    /// Borrows the probe firing as the [`ProbeData`] handed to handlers.
+    pub fn as_probe_data(&self) -> ProbeData<'_> {
+        ProbeData::new(
+            self.cpu,
+            self.epid,
+            self.id,
+            &self.provider,
+            &self.module,
+            &self.function,
+            &self.name,
+            &self.data,
+            &self.records,
+        )
+        .with_formats(&self.formats)
+    }
+}
+
+/// A scripted aggregation record, delivered to the handler of [`MockBackend::aggregate_walk`].
+///
+/// The first record is expected to be the aggregation variable ID, followed by the keys and the value, just like
+/// the records libdtrace hands out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockAggregate {
+    pub id: u32,
+    pub variable_id: i64,
+    pub name: String,
+    pub records: Vec<RecordDesc>,
+    pub data: Vec<u8>,
+}
+
+impl MockAggregate {
+    /// Creates an aggregation record without any records.
+    pub fn new(id: u32, variable_id: i64, name: &str) -> Self {
+        Self {
+            id,
+            variable_id,
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Appends a record generated by `action`, placed at the next offset aligned to `alignment`.
+    pub fn record(self, action: u32, alignment: u16, bytes: &[u8]) -> Self {
+        let desc = RecordDesc {
+            action: action as u16,
+            alignment,
+            ..Default::default()
+        };
+        self.record_with(desc, bytes)
+    }
+
+    /// Appends a record described by `desc`. The offset and size of `desc` are filled in.
+    pub fn record_with(mut self, desc: RecordDesc, bytes: &[u8]) -> Self {
+        push_record(&mut self.data, &mut self.records, desc, bytes);
+        self
+    }
+
+    /// Borrows the aggregation record as the [`AggregateData`] handed to handlers.
+    pub fn as_aggregate_data(&self) -> AggregateData<'_> {
+        AggregateData::new(self.id, self.variable_id, &self.name, &self.data, &self.records)
+    }
+}
+
+/// A program compiled by [`MockBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockProgram {
+    source: String,
+    expanded: String,
+    spec: crate::dtrace_probespec,
+    flags: u32,
+    args: Vec<String>,
+}
+
+impl MockProgram {
+    /// The D source the program was compiled from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The D source after the macro arguments `$n` and `$$n` were substituted.
+    pub fn expanded(&self) -> &str {
+        &self.expanded
+    }
+
+    /// The probe specifier the program was compiled with.
+    pub fn spec(&self) -> crate::dtrace_probespec {
+        self.spec
+    }
+
+    /// The compiler flags the program was compiled with.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// The argument vector the program was compiled with, starting with `$0`. Empty if no arguments were passed.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    options: HashMap<String, String>,
+    compiled: Vec<MockProgram>,
+    executed: Vec<MockProgram>,
+    running: bool,
+    stopped: bool,
+    passes: VecDeque<Vec<MockProbe>>,
+    aggregates: Vec<MockAggregate>,
+    probes: Vec<ProbeInfo>,
+    snapshot: Vec<MockAggregate>,
+    handlers: Option<Handlers>,
+    pending: Vec<Event>,
+    printed: usize,
+}
+
+/// An in-memory [`DtraceBackend`] that replays scripted probe firings and aggregation data.
+///
+/// Every call to [`DtraceBackend::work`] after [`DtraceBackend::go`] delivers the next pass queued with
+/// [`MockBackend::push_work`]. Once the last pass has been delivered the mock behaves as if the D program called
+/// `exit()`: `work` returns `DTRACE_WORKSTATUS_DONE` and `status` returns [`dtrace_status::Exited`].
+///
+/// ```
+/// use libdtrace_rs::backend::DtraceBackend;
+/// use libdtrace_rs::mock::{MockBackend, MockProbe};
+///
+/// let backend = MockBackend::new();
+/// backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")]);
+/// backend.setopt("bufsize", "4m")?;
+/// assert_eq!(backend.option("bufsize").as_deref(), Some("4m"));
+/// // Run the consumer logic under test against `backend`
+/// # Ok::<(), libdtrace_rs::utils::Error>(())
+/// ```
+#[derive(Default)]
+pub struct MockBackend {
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    /// Creates a mock without any scripted data.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Queues the probe firings delivered by one call to `work`.
+    pub fn push_work(&self, probes: Vec<MockProbe>) {
+        self.state().passes.push_back(probes);
+    }
+
+    /// Queues buffered output, drops or faults, delivered to the handlers registered with `handle_events`,
+    /// `handle_drop` and `handle_fault` at the start of the next call to `work`.
+    pub fn push_events(&self, events: Vec<Event>) {
+        self.state().pending.extend(events);
+    }
+
+    /// Adds a probe listed by `probes`.
+    pub fn push_probe(&self, probe: ProbeInfo) {
+        self.state().probes.push(probe);
+    }
+
+    /// Adds an aggregation record, visible to `aggregate_walk` after the next `aggregate_snap`.
+    pub fn push_aggregate(&self, aggregate: MockAggregate) {
+        self.state().aggregates.push(aggregate);
+    }
+
+    /// The value an option was last set to.
+    pub fn option(&self, option: &str) -> Option<String> {
+        self.state().options.get(option).cloned()
+    }
+
+    /// All programs compiled so far.
+    pub fn compiled(&self) -> Vec<MockProgram> {
+        self.state().compiled.clone()
+    }
+
+    /// All programs executed so far.
+    pub fn executed(&self) -> Vec<MockProgram> {
+        self.state().executed.clone()
+    }
+
+    /// How often `aggregate_print` was called.
+    pub fn aggregations_printed(&self) -> usize {
+        self.state().printed
+    }
+
+    /// Whether `go` was called and `stop` was not.
+    pub fn is_running(&self) -> bool {
+        self.state().running
+    }
+}
+
+/// Substitutes the macro arguments in `source` the way the D compiler does.
+///
+/// `$n` is replaced by the n-th element of `argv` as is, `$$n` by the n-th element as a string literal. References
+/// inside string literals and comments are left alone, as are named macro variables like `$pid`. Referencing an
+/// argument that was not passed is an error unless `DTRACE_C_DEFARG` is set, in which case `$n` is `0` and `$$n` is
+/// `""`.
+fn expand_macro_args(source: &str, argv: &[String], flags: u32) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '"' => {
+                expanded.push(c);
+                while let Some((_, c)) = chars.next() {
+                    expanded.push(c);
+                    match c {
+                        '\\' => expanded.extend(chars.next().map(|(_, c)| c)),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if source[start..].starts_with("/*") => {
+                let end = source[start..].find("*/").map_or(source.len(), |end| start + end + 2);
+                expanded.push_str(&source[start..end]);
+                while chars.next_if(|&(index, _)| index < end).is_some() {}
+            }
+            '$' => {
+                let quoted = chars.next_if(|&(_, c)| c == '$').is_some();
+                let mut digits = String::new();
+                while let Some((_, digit)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                    digits.push(digit);
+                }
+                if digits.is_empty() {
+                    expanded.push_str(if quoted { "$$" } else { "$" });
+                    continue;
+                }
+
+                let value = match digits.parse::<usize>().ok().and_then(|index| argv.get(index)) {
+                    Some(value) => value.as_str(),
+                    None if flags & crate::DTRACE_C_DEFARG != 0 => match quoted {
+                        true => "",
+                        false => "0",
+                    },
+                    None => {
+                        let line = source[..start].matches('\n').count() + 1;
+                        let diagnostic = format!(
+                            "line {}: macro argument {}{} is not defined",
+                            line,
+                            if quoted { "$$" } else { "$" },
+                            digits
+                        );
+                        return Err(Error::compiler(&diagnostic, Some(source)).with_api("dtrace_program_strcompile"));
+                    }
+                };
+                match quoted {
+                    true => {
+                        expanded.push('"');
+                        for c in value.chars() {
+                            if c == '"' || c == '\\' {
+                                expanded.push('\\');
+                            }
+                            expanded.push(c);
+                        }
+                        expanded.push('"');
+                    }
+                    false => expanded.push_str(value),
+                }
+            }
+            c => expanded.push(c),
+        }
+    }
+
+    Ok(expanded)
+}
+
+impl DtraceBackend for MockBackend {
+    type Program<'a> = MockProgram;
+
+    fn open(version: c_int, _flags: c_int) -> Result<Self, Error> {
+        if version != crate::DTRACE_VERSION as c_int {
+            return Err(Error::from(ErrorKind::Version));
+        }
+        Ok(Self::new())
+    }
+
+    fn setopt(&self, option: &str, value: &str) -> Result<(), Error> {
+        if option.is_empty() {
+            return Err(Error::from(ErrorKind::BadOptName));
+        }
+        if DtraceOption::is_known(option) {
+            DtraceOption::parse(option, Some(value))?;
+        }
+        self.state().options.insert(option.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn getopt(&self, option: &str) -> Result<crate::dtrace_optval_t, Error> {
+        let state = self.state();
+        let value = state.options.get(option);
+        if DtraceOption::is_known(option) {
+            if option == "zdefs" {
+                return Err(Error::from(ErrorKind::BadOptName));
+            }
+            return match value {
+                Some(value) => DtraceOption::parse(option, Some(value)).map(|option| option.to_optval()),
+                None => Ok(DTRACEOPT_UNSET),
+            };
+        }
+
+        // Options not covered by `DtraceOption` are reported as sizes, or as set flags
+        match value.map(String::as_str) {
+            None => Err(Error::from(ErrorKind::BadOptName)),
+            Some("") => Ok(0),
+            Some(value) => value
+                .parse::<Size>()
+                .map(|size| size.bytes() as crate::dtrace_optval_t),
+        }
+    }
+
+    fn compile<'a>(
+        &'a self,
+        program: &str,
+        spec: crate::dtrace_probespec,
+        flags: u32,
+        args: Option<&MacroArgs>,
+    ) -> Result<Self::Program<'a>, Error> {
+        let args = args.map(MacroArgs::to_strings).unwrap_or_default();
+        let program = MockProgram {
+            source: program.to_string(),
+            expanded: expand_macro_args(program, &args, flags)?,
+            spec,
+            flags,
+            args,
+        };
+        self.state().compiled.push(program.clone());
+        Ok(program)
+    }
+
+    fn exec(&self, program: &mut Self::Program<'_>) -> Result<(), Error> {
+        self.state().executed.push(program.clone());
+        Ok(())
+    }
+
+    fn go(&self) -> Result<(), Error> {
+        let mut state = self.state();
+        state.running = true;
+        state.stopped = false;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), Error> {
+        let mut state = self.state();
+        state.running = false;
+        state.stopped = true;
+        Ok(())
+    }
+
+    fn status(&self) -> Result<dtrace_status, Error> {
+        let state = self.state();
+        Ok(match (state.running, state.stopped) {
+            (_, true) => dtrace_status::Stopped,
+            (false, false) => dtrace_status::None,
+            (true, false) if state.passes.is_empty() => dtrace_status::Exited,
+            (true, false) => dtrace_status::Ok,
+        })
+    }
+
+    fn sleep(&self) {}
+
+    fn work(
+        &self,
+        probe: &mut dyn FnMut(&ProbeData) -> ConsumeAction,
+        record: &mut dyn FnMut(&ProbeData, Option<&Record>) -> ConsumeAction,
+    ) -> Result<crate::dtrace_workstatus_t, Error> {
+        // Take the pass out of the lock so that the handlers may call back into the mock
+        let (pass, done, events, mut handlers) = {
+            let mut state = self.state();
+            if !state.running {
+                return Ok(crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_OKAY);
+            }
+            let pass = state.passes.pop_front().unwrap_or_default();
+            let events = match state.handlers.is_some() {
+                true => std::mem::take(&mut state.pending),
+                false => Vec::new(),
+            };
+            (pass, state.passes.is_empty(), events, state.handlers.take())
+        };
+
+        if let Some(handlers) = handlers.as_mut() {
+            events.into_iter().for_each(|event| {
+                handlers.dispatch(event);
+            });
+        }
+        let mut state = self.state();
+        if state.handlers.is_none() {
+            state.handlers = handlers;
+        }
+        drop(state);
+
+        for mock in &pass {
+            let data = mock.as_probe_data();
+            match probe(&data) {
+                ConsumeAction::This => {}
+                ConsumeAction::Next => continue,
+                ConsumeAction::Abort => return Err(Error::from(ErrorKind::DirAbort)),
+                ConsumeAction::Error => return Err(Error::from(ErrorKind::BadRval)),
+            }
+
+            let mut index = 0;
+            while let Some((decoded, span)) = Record::decode_next(&data, index) {
+                index += span;
+                match record(&data, Some(&decoded)) {
+                    ConsumeAction::This | ConsumeAction::Next => {}
+                    ConsumeAction::Abort => return Err(Error::from(ErrorKind::DirAbort)),
+                    ConsumeAction::Error => return Err(Error::from(ErrorKind::BadRval)),
+                }
+            }
+
+            match record(&data, None) {
+                ConsumeAction::This | ConsumeAction::Next => {}
+                ConsumeAction::Abort => return Err(Error::from(ErrorKind::DirAbort)),
+                ConsumeAction::Error => return Err(Error::from(ErrorKind::BadRval)),
+            }
+        }
+
+        match done {
+            true => Ok(crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE),
+            false => Ok(crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_OKAY),
+        }
+    }
+
+    fn handle_events(&mut self, handler: Box<dyn FnMut(Event) + Send>) -> Result<(), Error> {
+        self.state().handlers.get_or_insert_with(Handlers::default).events = Some(handler);
+        Ok(())
+    }
+
+    fn handle_drop(&mut self, handler: Box<dyn FnMut(&DropEvent) + Send>) -> Result<(), Error> {
+        self.state().handlers.get_or_insert_with(Handlers::default).set_drop(handler);
+        Ok(())
+    }
+
+    fn handle_fault(&mut self, handler: Box<dyn FnMut(&FaultEvent) + Send>) -> Result<(), Error> {
+        self.state().handlers.get_or_insert_with(Handlers::default).set_fault(handler);
+        Ok(())
+    }
+
+    fn probes(&self, filter: Option<&ProbeDescription>) -> Result<Probes, Error> {
+        let probes = self.state().probes.clone();
+        let probes = match filter {
+            Some(filter) => {
+                crate::dtrace_probedesc_t::try_from(filter)?;
+                probes.into_iter().filter(|probe| filter.matches(probe)).collect()
+            }
+            None => probes,
+        };
+        Ok(Probes::new(probes))
+    }
+
+    fn aggregate_snap(&self) -> Result<(), Error> {
+        let mut state = self.state();
+        state.snapshot = state.aggregates.clone();
+        Ok(())
+    }
+
+    /// Only counts the calls, see [`MockBackend::aggregations_printed`].
+    fn aggregate_print(&self) -> Result<(), Error> {
+        self.state().printed += 1;
+        Ok(())
+    }
+
+    /// Walks the aggregation records in the order they were pushed, `order` is ignored.
+    fn aggregate_walk(
+        &self,
+        _order: dtrace_aggwalk_order,
+        handler: &mut dyn FnMut(&AggregateData) -> AggWalkAction,
+    ) -> Result<(), Error> {
+        let snapshot = self.state().snapshot.clone();
+
+        for aggregate in snapshot {
+            match handler(&aggregate.as_aggregate_data()) {
+                AggWalkAction::Next => {}
+                AggWalkAction::Abort => break,
+                AggWalkAction::Clear => {
+                    let mut state = self.state();
+                    let state = &mut *state;
+                    let cleared = state.aggregates.iter_mut().chain(state.snapshot.iter_mut());
+                    for agg in cleared.filter(|agg| **agg == aggregate) {
+                        if let Some(value) = agg.records.last() {
+                            let range = value.offset as usize..(value.offset + value.size) as usize;
+                            agg.data[range].fill(0);
+                        }
+                    }
+                }
+                AggWalkAction::Remove => {
+                    let mut state = self.state();
+                    state.aggregates.retain(|agg| *agg != aggregate);
+                    state.snapshot.retain(|agg| *agg != aggregate);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::{BufferPolicy, Options, Rate};
+    use crate::event::{DropKind, FaultKind};
+    use std::sync::Arc;
+    use crate::record::Value;
+
+    /// Consumer logic written against the trait, as downstream code would.
+    fn count_functions<B: DtraceBackend>(backend: &B) -> Result<HashMap<String, usize>, Error> {
+        let mut program = backend.compile(
+            "syscall:::entry { trace(arg0); }",
+            crate::dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            crate::DTRACE_C_ZDEFS,
+            None,
+        )?;
+        backend.exec(&mut program)?;
+        backend.go()?;
+
+        let mut counts = HashMap::new();
+        loop {
+            backend.sleep();
+            let status = backend.work(
+                &mut |data| {
+                    *counts.entry(data.function().to_string()).or_insert(0) += 1;
+                    ConsumeAction::This
+                },
+                &mut |_, _| ConsumeAction::Next,
+            )?;
+            if status == crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_DONE {
+                break;
+            }
+        }
+        backend.stop()?;
+
+        Ok(counts)
+    }
+
+    #[test]
+    fn mock_work_delivers_scripted_probes() -> Result<(), Error> {
+        let backend = MockBackend::open(crate::DTRACE_VERSION as c_int, 0)?;
+        backend.push_work(vec![
+            MockProbe::new("syscall", "", "read", "entry"),
+            MockProbe::new("syscall", "", "write", "entry"),
+        ]);
+        backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")]);
+
+        let counts = count_functions(&backend)?;
+
+        assert_eq!(counts["read"], 2);
+        assert_eq!(counts["write"], 1);
+        assert_eq!(backend.executed().len(), 1);
+        assert_eq!(backend.status()?, dtrace_status::Stopped);
+        Ok(())
+    }
+
+    #[test]
+    fn mock_work_delivers_records() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")
+            .record(crate::DTRACEACT_DIFEXPR, 4, &7u32.to_ne_bytes())
+            .printf("%d\n", &[&42u64.to_ne_bytes()])
+            .record(crate::DTRACEACT_EXIT, 4, &0u32.to_ne_bytes())]);
+        backend.go()?;
+
+        let mut values = Vec::new();
+        let mut ends = 0;
+        backend.work(&mut |_| ConsumeAction::This, &mut |_, record| {
+            match record {
+                Some(record) => values.push(record.clone()),
+                None => ends += 1,
+            }
+            ConsumeAction::Next
+        })?;
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], Record::Trace(Value::Integer { value: 7, size: 4 }));
+        assert_eq!(
+            values[1],
+            Record::Printf {
+                action: crate::DTRACEACT_PRINTF as u16,
+                format: Some("%d\n".to_string()),
+                args: vec![Value::Integer { value: 42, size: 8 }],
+            }
+        );
+        assert_eq!(values[2], Record::Exit(0));
+        assert_eq!(ends, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn mock_work_aborts() {
+        let backend = MockBackend::new();
+        backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")]);
+        backend.go().unwrap();
+
+        let result = backend.work(&mut |_| ConsumeAction::Abort, &mut |_, _| ConsumeAction::This);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn mock_aggregate_walk() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        backend.push_aggregate(
+            MockAggregate::new(1, 1, "num")
+                .record(crate::DTRACEACT_DIFEXPR, 4, &1u32.to_ne_bytes())
+                .record(crate::DTRACEAGG_COUNT, 8, &3u64.to_ne_bytes()),
+        );
+        backend.aggregate_snap()?;
+
+        let mut seen = Vec::new();
+        backend.aggregate_walk(dtrace_aggwalk_order::None, &mut |data| {
+            let value = data.records().last().unwrap();
+            let bytes = &data.data()[value.offset as usize..][..8];
+            seen.push((data.name().to_string(), u64::from_ne_bytes(bytes.try_into().unwrap())));
+            AggWalkAction::Remove
+        })?;
+        assert_eq!(seen, vec![("num".to_string(), 3)]);
+
+        backend.aggregate_snap()?;
+        backend.aggregate_walk(dtrace_aggwalk_order::None, &mut |_| panic!("aggregate was removed"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn mock_options() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        backend.setopt("bufsize", "4m")?;
+        assert_eq!(backend.getopt("bufsize")?, 4194304);
+        assert_eq!(backend.getopt("aggsize")?, DTRACEOPT_UNSET);
+        assert!(backend.getopt("nosuchoption").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mock_typed_options() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        Options::new()
+            .bufsize(Size::mib(4))
+            .bufpolicy(BufferPolicy::Ring)
+            .switchrate(Rate::Hz(10))
+            .quiet()
+            .apply(&backend)?;
+
+        assert_eq!(backend.option("bufsize").as_deref(), Some("4m"));
+        assert_eq!(backend.get_option("bufsize")?, Some(DtraceOption::BufSize(Size::mib(4))));
+        assert_eq!(backend.get_option("bufpolicy")?, Some(DtraceOption::BufPolicy(BufferPolicy::Ring)));
+        assert_eq!(backend.get_option("switchrate")?, Some(DtraceOption::SwitchRate(Rate::Hz(10))));
+        assert_eq!(backend.get_option("quiet")?, Some(DtraceOption::Quiet));
+        assert_eq!(backend.get_option("aggsize")?, None);
+
+        assert!(backend.setopt("bufsize", "lots").is_err());
+        assert!(Options::new().strsize(Size(0)).apply(&backend).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mock_typed_drop_and_fault_handlers() -> Result<(), Error> {
+        let mut backend = MockBackend::new();
+        let drops = Arc::new(Mutex::new(Vec::new()));
+        let faults = Arc::new(Mutex::new(Vec::new()));
+        let seen = drops.clone();
+        backend.handle_drop(Box::new(move |drop| seen.lock().unwrap().push(drop.kind)))?;
+        let seen = faults.clone();
+        backend.handle_fault(Box::new(move |fault| seen.lock().unwrap().push(fault.kind)))?;
+
+        backend.push_events(vec![
+            Event::Buffered("ignored\n".to_string()),
+            Event::Drop(DropEvent {
+                kind: DropKind::Speculation,
+                cpu: -1,
+                count: 1,
+                total: 1,
+                message: "1 speculative drop".to_string(),
+            }),
+            Event::Fault(FaultEvent {
+                probe: Default::default(),
+                kind: FaultKind::DivideByZero,
+                cpu: 0,
+                action: 1,
+                offset: 4,
+                address: 0,
+                message: "divide-by-zero in action #1".to_string(),
+            }),
+        ]);
+        backend.push_work(vec![]);
+        backend.go()?;
+        backend.work(&mut |_| ConsumeAction::This, &mut |_, _| ConsumeAction::This)?;
+
+        assert_eq!(*drops.lock().unwrap(), [DropKind::Speculation]);
+        assert_eq!(*faults.lock().unwrap(), [FaultKind::DivideByZero]);
+        Ok(())
+    }
+
+    #[test]
+    fn mock_lists_probes() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        for (id, (function, name)) in [("read", "entry"), ("read", "return"), ("readv", "entry"), ("write", "entry")]
+            .into_iter()
+            .enumerate()
+        {
+            backend.push_probe(ProbeInfo {
+                id: id as u32 + 1,
+                provider: "syscall".to_string(),
+                module: String::new(),
+                function: function.to_string(),
+                name: name.to_string(),
+            });
+        }
+
+        assert_eq!(backend.probes(None)?.len(), 4);
+        let filter = ProbeDescription::new("syscall", "", "read*", "entry");
+        let probes: Vec<_> = backend.probes(Some(&filter))?.map(|probe| probe.to_string()).collect();
+        assert_eq!(probes, ["syscall::read:entry", "syscall::readv:entry"]);
+        let filter = ProbeDescription::new("sys[!c]all", "", "", "");
+        assert_eq!(backend.probes(Some(&filter))?.count(), 0);
+        let filter = ProbeDescription::new("syscall", "", &"f".repeat(200), "");
+        assert_eq!(backend.probes(Some(&filter)).unwrap_err().kind(), ErrorKind::BadSpec);
+        Ok(())
+    }
+
+    #[test]
+    fn mock_substitutes_macro_args() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        let args = MacroArgs::new().pid(1234).string("a \"quoted\" name");
+        let program = backend.compile(
+            r#"syscall:::entry /pid == $1 && execname == $$2/ { printf("$1 %d", $pid); /* $$2 */ }"#,
+            crate::dtrace_probespec::DTRACE_PROBESPEC_NAME,
+            0,
+            Some(&args),
+        )?;
+
+        assert_eq!(
+            program.expanded(),
+            r#"syscall:::entry /pid == 1234 && execname == "a \"quoted\" name"/ { printf("$1 %d", $pid); /* $$2 */ }"#
+        );
+        assert_eq!(program.args(), ["dtrace", "1234", "a \"quoted\" name"]);
+        Ok(())
+    }
+
+    #[test]
+    fn mock_rejects_undefined_macro_args() -> Result<(), Error> {
+        let backend = MockBackend::new();
+        let spec = crate::dtrace_probespec::DTRACE_PROBESPEC_NAME;
+        let args = MacroArgs::new().int(-1);
+
+        let error = backend
+            .compile("BEGIN\n{\n    trace($2);\n}", spec, 0, Some(&args))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::Compiler);
+        assert_eq!(error.api(), Some("dtrace_program_strcompile"));
+        assert_eq!(error.compile_error().and_then(|error| error.line()), Some(3));
+        let program = backend.compile(
+            "BEGIN { trace($2); trace($$3); trace($1); }",
+            spec,
+            crate::DTRACE_C_DEFARG,
+            Some(&args),
+        )?;
+        assert_eq!(program.expanded(), r#"BEGIN { trace(0); trace(""); trace(-1); }"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,615 @@
+use crate::utils::{Error, ErrorKind};
+use std::time::Duration;
+
+/// The value `dtrace_getopt` reports for an option that was never set, `DTRACEOPT_UNSET`.
+pub const DTRACEOPT_UNSET: crate::dtrace_optval_t = -2;
+
+const NANOSEC: u64 = 1_000_000_000;
+
+/// A buffer size in bytes, written with an optional `k`, `m`, `g` or `t` suffix, e.g. `4m`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Size(pub u64);
+
+impl Size {
+    /// A size of `n` kibibytes.
+    pub const fn kib(n: u64) -> Self {
+        Self(n << 10)
+    }
+
+    /// A size of `n` mebibytes.
+    pub const fn mib(n: u64) -> Self {
+        Self(n << 20)
+    }
+
+    /// A size of `n` gibibytes.
+    pub const fn gib(n: u64) -> Self {
+        Self(n << 30)
+    }
+
+    /// The size in bytes.
+    pub fn bytes(&self) -> u64 {
+        self.0
+    }
+}
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (suffix, shift) in [("t", 40), ("g", 30), ("m", 20), ("k", 10)] {
+            if self.0 != 0 && self.0.is_multiple_of(1 << shift) {
+                return write!(f, "{}{}", self.0 >> shift, suffix);
+            }
+        }
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for Size {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (digits, shift) = match value.char_indices().last() {
+            Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+                let shift = match suffix.to_ascii_lowercase() {
+                    'k' => 10,
+                    'm' => 20,
+                    'g' => 30,
+                    't' => 40,
+                    _ => return Err(Error::from(ErrorKind::BadOptVal)),
+                };
+                (&value[..index], shift)
+            }
+            _ => (value, 0),
+        };
+        digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(1 << shift))
+            .filter(|&bytes| bytes <= i64::MAX as u64)
+            .map(Size)
+            .ok_or_else(|| Error::from(ErrorKind::BadOptVal))
+    }
+}
+
+/// The rate of a periodic consumer activity, written either as a frequency (`10hz`, or a plain number) or as an
+/// interval with a time suffix (`500ms`).
+#[derive(Debug, Clone, Copy)]
+pub enum Rate {
+    /// A frequency per second.
+    Hz(u64),
+    /// The interval between two occurrences.
+    Interval(Duration),
+}
+
+impl Rate {
+    /// The interval between two occurrences.
+    pub fn interval(&self) -> Duration {
+        match *self {
+            Rate::Hz(0) => Duration::ZERO,
+            Rate::Hz(hz) => Duration::from_nanos(NANOSEC / hz),
+            Rate::Interval(interval) => interval,
+        }
+    }
+}
+
+impl PartialEq for Rate {
+    fn eq(&self, other: &Self) -> bool {
+        self.interval() == other.interval()
+    }
+}
+
+impl Eq for Rate {}
+
+impl std::fmt::Display for Rate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Rate::Hz(hz) => write!(f, "{}hz", hz),
+            Rate::Interval(interval) => {
+                let nanos = interval.as_nanos();
+                for (suffix, unit) in [("s", NANOSEC), ("ms", 1_000_000), ("us", 1_000)] {
+                    if nanos != 0 && nanos.is_multiple_of(unit as u128) {
+                        return write!(f, "{}{}", nanos / unit as u128, suffix);
+                    }
+                }
+                write!(f, "{}ns", nanos)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Rate {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let (digits, suffix) = value.split_at(split);
+        let value = digits.parse::<u64>().map_err(|_| Error::from(ErrorKind::BadOptVal))?;
+
+        let unit = match suffix.to_ascii_lowercase().as_str() {
+            "" | "hz" => return Ok(Rate::Hz(value)),
+            "ns" | "nsec" => 1,
+            "us" | "usec" => 1_000,
+            "ms" | "msec" => 1_000_000,
+            "s" | "sec" => NANOSEC,
+            "m" | "min" => 60 * NANOSEC,
+            "h" | "hour" => 60 * 60 * NANOSEC,
+            "d" | "day" => 24 * 60 * 60 * NANOSEC,
+            _ => return Err(Error::from(ErrorKind::BadOptVal)),
+        };
+        value
+            .checked_mul(unit)
+            .map(|nanos| Rate::Interval(Duration::from_nanos(nanos)))
+            .ok_or_else(|| Error::from(ErrorKind::BadOptVal))
+    }
+}
+
+/// What happens when a principal buffer fills up, the `bufpolicy` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferPolicy {
+    /// Switch between two buffers per CPU, dropping data while the consumer lags behind (the default)
+    Switch,
+    /// Stop tracing once any buffer is full
+    Fill,
+    /// Overwrite the oldest data, for tracing until a point of interest
+    Ring,
+}
+
+impl BufferPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            BufferPolicy::Switch => "switch",
+            BufferPolicy::Fill => "fill",
+            BufferPolicy::Ring => "ring",
+        }
+    }
+
+    fn to_raw(self) -> u32 {
+        match self {
+            BufferPolicy::Switch => crate::DTRACEOPT_BUFPOLICY_SWITCH,
+            BufferPolicy::Fill => crate::DTRACEOPT_BUFPOLICY_FILL,
+            BufferPolicy::Ring => crate::DTRACEOPT_BUFPOLICY_RING,
+        }
+    }
+
+    fn from_raw(value: u32) -> Self {
+        match value {
+            crate::DTRACEOPT_BUFPOLICY_FILL => BufferPolicy::Fill,
+            crate::DTRACEOPT_BUFPOLICY_RING => BufferPolicy::Ring,
+            _ => BufferPolicy::Switch,
+        }
+    }
+}
+
+/// A DTrace option along with its value.
+///
+/// Options that are flags have no value, setting them enables them. Options are validated when they are parsed or
+/// set, before they reach libdtrace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DtraceOption {
+    /// Principal buffer size per CPU
+    BufSize(Size),
+    /// Aggregation buffer size per CPU
+    AggSize(Size),
+    /// Principal buffer policy
+    BufPolicy(BufferPolicy),
+    /// Rate at which the principal buffers are consumed
+    SwitchRate(Rate),
+    /// Rate at which the aggregation buffers are consumed
+    AggRate(Rate),
+    /// Rate at which the status of tracing is checked
+    StatusRate(Rate),
+    /// Rate at which dynamic variables are cleaned
+    CleanRate(Rate),
+    /// Size of strings
+    StrSize(Size),
+    /// Dynamic variable space size
+    DynVarSize(Size),
+    /// Number of speculations
+    NSpec(u64),
+    /// Speculation buffer size
+    SpecSize(Size),
+    /// Number of kernel stack frames recorded by `stack()`
+    StackFrames(u64),
+    /// Number of user stack frames recorded by `ustack()`
+    UStackFrames(u64),
+    /// Only trace on the given CPU
+    Cpu(u32),
+    /// Only output explicitly traced data
+    Quiet,
+    /// Indent function entry and prefix it with `->`, prefix function return with `<-`
+    FlowIndent,
+    /// Allow destructive actions
+    Destructive,
+    /// Permit probe descriptions that do not match any probes
+    ZDefs,
+}
+
+impl DtraceOption {
+    const NAMES: [&'static str; 18] = [
+        "bufsize",
+        "aggsize",
+        "bufpolicy",
+        "switchrate",
+        "aggrate",
+        "statusrate",
+        "cleanrate",
+        "strsize",
+        "dynvarsize",
+        "nspec",
+        "specsize",
+        "stackframes",
+        "ustackframes",
+        "cpu",
+        "quiet",
+        "flowindent",
+        "destructive",
+        "zdefs",
+    ];
+
+    /// Whether `name` is one of the options covered by `DtraceOption`.
+    pub fn is_known(name: &str) -> bool {
+        Self::NAMES.contains(&name)
+    }
+
+    /// The name of the option as known to libdtrace.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DtraceOption::BufSize(_) => "bufsize",
+            DtraceOption::AggSize(_) => "aggsize",
+            DtraceOption::BufPolicy(_) => "bufpolicy",
+            DtraceOption::SwitchRate(_) => "switchrate",
+            DtraceOption::AggRate(_) => "aggrate",
+            DtraceOption::StatusRate(_) => "statusrate",
+            DtraceOption::CleanRate(_) => "cleanrate",
+            DtraceOption::StrSize(_) => "strsize",
+            DtraceOption::DynVarSize(_) => "dynvarsize",
+            DtraceOption::NSpec(_) => "nspec",
+            DtraceOption::SpecSize(_) => "specsize",
+            DtraceOption::StackFrames(_) => "stackframes",
+            DtraceOption::UStackFrames(_) => "ustackframes",
+            DtraceOption::Cpu(_) => "cpu",
+            DtraceOption::Quiet => "quiet",
+            DtraceOption::FlowIndent => "flowindent",
+            DtraceOption::Destructive => "destructive",
+            DtraceOption::ZDefs => "zdefs",
+        }
+    }
+
+    /// The value of the option as passed to `dtrace_setopt`, `None` for flags.
+    pub fn value(&self) -> Option<String> {
+        match self {
+            DtraceOption::BufSize(size)
+            | DtraceOption::AggSize(size)
+            | DtraceOption::StrSize(size)
+            | DtraceOption::DynVarSize(size)
+            | DtraceOption::SpecSize(size) => Some(size.to_string()),
+            DtraceOption::BufPolicy(policy) => Some(policy.as_str().to_string()),
+            DtraceOption::SwitchRate(rate)
+            | DtraceOption::AggRate(rate)
+            | DtraceOption::StatusRate(rate)
+            | DtraceOption::CleanRate(rate) => Some(rate.to_string()),
+            DtraceOption::NSpec(count) | DtraceOption::StackFrames(count) | DtraceOption::UStackFrames(count) => {
+                Some(count.to_string())
+            }
+            DtraceOption::Cpu(cpu) => Some(cpu.to_string()),
+            DtraceOption::Quiet | DtraceOption::FlowIndent | DtraceOption::Destructive | DtraceOption::ZDefs => None,
+        }
+    }
+
+    /// Checks that libdtrace would accept the value of the option.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the value is valid.
+    /// * `Err(EDT_BADOPTVAL)` - If a size, rate or count is zero or out of range.
+    pub fn validate(&self) -> Result<(), Error> {
+        let valid = match self {
+            DtraceOption::BufSize(size)
+            | DtraceOption::AggSize(size)
+            | DtraceOption::StrSize(size)
+            | DtraceOption::DynVarSize(size)
+            | DtraceOption::SpecSize(size) => size.0 > 0 && size.0 <= i64::MAX as u64,
+            DtraceOption::SwitchRate(rate)
+            | DtraceOption::AggRate(rate)
+            | DtraceOption::StatusRate(rate)
+            | DtraceOption::CleanRate(rate) => {
+                !rate.interval().is_zero() && rate.interval().as_nanos() <= i64::MAX as u128
+            }
+            DtraceOption::NSpec(count) | DtraceOption::StackFrames(count) | DtraceOption::UStackFrames(count) => {
+                *count > 0 && *count <= i64::MAX as u64
+            }
+            _ => true,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(Error::from(ErrorKind::BadOptVal)),
+        }
+    }
+
+    /// Parses and validates an option as it would be passed to `dtrace_setopt`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(DtraceOption)` - If the option is known and the value is valid.
+    /// * `Err(EDT_BADOPTNAME)` - If the option is not known.
+    /// * `Err(EDT_BADOPTVAL)` - If the value is not valid for the option, or a flag was given a value.
+    pub fn parse(name: &str, value: Option<&str>) -> Result<Self, Error> {
+        let bad_value = || Error::from(ErrorKind::BadOptVal);
+        let required = || value.ok_or_else(bad_value);
+        let count = || required()?.parse::<u64>().map_err(|_| bad_value());
+        let flag = |option| match value {
+            None | Some("") => Ok(option),
+            Some(_) => Err(bad_value()),
+        };
+
+        let option = match name {
+            "bufsize" => DtraceOption::BufSize(required()?.parse()?),
+            "aggsize" => DtraceOption::AggSize(required()?.parse()?),
+            "bufpolicy" => DtraceOption::BufPolicy(match required()? {
+                "switch" => BufferPolicy::Switch,
+                "fill" => BufferPolicy::Fill,
+                "ring" => BufferPolicy::Ring,
+                _ => return Err(bad_value()),
+            }),
+            "switchrate" => DtraceOption::SwitchRate(required()?.parse()?),
+            "aggrate" => DtraceOption::AggRate(required()?.parse()?),
+            "statusrate" => DtraceOption::StatusRate(required()?.parse()?),
+            "cleanrate" => DtraceOption::CleanRate(required()?.parse()?),
+            "strsize" => DtraceOption::StrSize(required()?.parse()?),
+            "dynvarsize" => DtraceOption::DynVarSize(required()?.parse()?),
+            "nspec" => DtraceOption::NSpec(count()?),
+            "specsize" => DtraceOption::SpecSize(required()?.parse()?),
+            "stackframes" => DtraceOption::StackFrames(count()?),
+            "ustackframes" => DtraceOption::UStackFrames(count()?),
+            "cpu" => DtraceOption::Cpu(required()?.parse().map_err(|_| bad_value())?),
+            "quiet" => flag(DtraceOption::Quiet)?,
+            "flowindent" => flag(DtraceOption::FlowIndent)?,
+            "destructive" => flag(DtraceOption::Destructive)?,
+            "zdefs" => flag(DtraceOption::ZDefs)?,
+            _ => return Err(Error::from(ErrorKind::BadOptName)),
+        };
+        option.validate()?;
+        Ok(option)
+    }
+
+    /// Converts the value reported by `dtrace_getopt` for the option `name`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(DtraceOption))` - If the option is set.
+    /// * `Ok(None)` - If the option is not set, i.e. `value` is [`DTRACEOPT_UNSET`].
+    /// * `Err(EDT_BADOPTNAME)` - If the option is not known, or cannot be read like compile time options such as
+    ///   `zdefs`.
+    pub fn from_optval(name: &str, value: crate::dtrace_optval_t) -> Result<Option<Self>, Error> {
+        if name == "zdefs" || !Self::is_known(name) {
+            return Err(Error::from(ErrorKind::BadOptName));
+        }
+        if value == DTRACEOPT_UNSET {
+            return Ok(None);
+        }
+
+        let size = || Size(value as u64);
+        let rate = || Rate::Interval(Duration::from_nanos(value as u64));
+        let option = match name {
+            "bufsize" => DtraceOption::BufSize(size()),
+            "aggsize" => DtraceOption::AggSize(size()),
+            "bufpolicy" => DtraceOption::BufPolicy(BufferPolicy::from_raw(value as u32)),
+            "switchrate" => DtraceOption::SwitchRate(rate()),
+            "aggrate" => DtraceOption::AggRate(rate()),
+            "statusrate" => DtraceOption::StatusRate(rate()),
+            "cleanrate" => DtraceOption::CleanRate(rate()),
+            "strsize" => DtraceOption::StrSize(size()),
+            "dynvarsize" => DtraceOption::DynVarSize(size()),
+            "nspec" => DtraceOption::NSpec(value as u64),
+            "specsize" => DtraceOption::SpecSize(size()),
+            "stackframes" => DtraceOption::StackFrames(value as u64),
+            "ustackframes" => DtraceOption::UStackFrames(value as u64),
+            "cpu" => DtraceOption::Cpu(value as u32),
+            "quiet" => DtraceOption::Quiet,
+            "flowindent" => DtraceOption::FlowIndent,
+            "destructive" => DtraceOption::Destructive,
+            _ => return Err(Error::from(ErrorKind::BadOptName)),
+        };
+        Ok(Some(option))
+    }
+
+    /// The value `dtrace_getopt` reports once the option is set.
+    pub fn to_optval(&self) -> crate::dtrace_optval_t {
+        match self {
+            DtraceOption::BufSize(size)
+            | DtraceOption::AggSize(size)
+            | DtraceOption::StrSize(size)
+            | DtraceOption::DynVarSize(size)
+            | DtraceOption::SpecSize(size) => size.0 as crate::dtrace_optval_t,
+            DtraceOption::BufPolicy(policy) => policy.to_raw() as crate::dtrace_optval_t,
+            DtraceOption::SwitchRate(rate)
+            | DtraceOption::AggRate(rate)
+            | DtraceOption::StatusRate(rate)
+            | DtraceOption::CleanRate(rate) => rate.interval().as_nanos() as crate::dtrace_optval_t,
+            DtraceOption::NSpec(count) | DtraceOption::StackFrames(count) | DtraceOption::UStackFrames(count) => {
+                *count as crate::dtrace_optval_t
+            }
+            DtraceOption::Cpu(cpu) => *cpu as crate::dtrace_optval_t,
+            DtraceOption::Quiet | DtraceOption::FlowIndent | DtraceOption::Destructive | DtraceOption::ZDefs => 0,
+        }
+    }
+}
+
+/// A set of options applied together, e.g. right after opening a handle.
+///
+/// ```
+/// use libdtrace_rs::mock::MockBackend;
+/// use libdtrace_rs::options::{Options, Rate, Size};
+///
+/// let handle = MockBackend::new();
+/// let options = Options::new()
+///     .bufsize(Size::mib(4))
+///     .switchrate(Rate::Hz(10))
+///     .quiet();
+/// options.apply(&handle)?;
+/// assert_eq!(handle.option("bufsize").as_deref(), Some("4m"));
+/// # Ok::<(), libdtrace_rs::utils::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    options: Vec<DtraceOption>,
+}
+
+impl Options {
+    /// Creates an empty set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an option, replacing an earlier value of the same option.
+    pub fn option(mut self, option: DtraceOption) -> Self {
+        self.options.retain(|existing| existing.name() != option.name());
+        self.options.push(option);
+        self
+    }
+
+    /// Sets the principal buffer size per CPU.
+    pub fn bufsize(self, size: Size) -> Self {
+        self.option(DtraceOption::BufSize(size))
+    }
+
+    /// Sets the aggregation buffer size per CPU.
+    pub fn aggsize(self, size: Size) -> Self {
+        self.option(DtraceOption::AggSize(size))
+    }
+
+    /// Sets the principal buffer policy.
+    pub fn bufpolicy(self, policy: BufferPolicy) -> Self {
+        self.option(DtraceOption::BufPolicy(policy))
+    }
+
+    /// Sets the rate at which the principal buffers are consumed.
+    pub fn switchrate(self, rate: Rate) -> Self {
+        self.option(DtraceOption::SwitchRate(rate))
+    }
+
+    /// Sets the rate at which the aggregation buffers are consumed.
+    pub fn aggrate(self, rate: Rate) -> Self {
+        self.option(DtraceOption::AggRate(rate))
+    }
+
+    /// Sets the rate at which the status of tracing is checked.
+    pub fn statusrate(self, rate: Rate) -> Self {
+        self.option(DtraceOption::StatusRate(rate))
+    }
+
+    /// Sets the size of strings.
+    pub fn strsize(self, size: Size) -> Self {
+        self.option(DtraceOption::StrSize(size))
+    }
+
+    /// Sets the number of speculations.
+    pub fn nspec(self, count: u64) -> Self {
+        self.option(DtraceOption::NSpec(count))
+    }
+
+    /// Sets the speculation buffer size.
+    pub fn specsize(self, size: Size) -> Self {
+        self.option(DtraceOption::SpecSize(size))
+    }
+
+    /// Only outputs explicitly traced data.
+    pub fn quiet(self) -> Self {
+        self.option(DtraceOption::Quiet)
+    }
+
+    /// Indents function entry and return.
+    pub fn flowindent(self) -> Self {
+        self.option(DtraceOption::FlowIndent)
+    }
+
+    /// Allows destructive actions.
+    pub fn destructive(self) -> Self {
+        self.option(DtraceOption::Destructive)
+    }
+
+    /// Permits probe descriptions that do not match any probes.
+    pub fn zdefs(self) -> Self {
+        self.option(DtraceOption::ZDefs)
+    }
+
+    /// The options in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &DtraceOption> {
+        self.options.iter()
+    }
+
+    /// Validates all options, then sets them one by one.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If all options are set.
+    /// * `Err(errno)` - If an option is invalid, in which case no option is set, or libdtrace rejects an option.
+    pub fn apply<B: crate::backend::DtraceBackend + ?Sized>(&self, backend: &B) -> Result<(), Error> {
+        self.options.iter().try_for_each(DtraceOption::validate)?;
+        self.options.iter().try_for_each(|option| backend.set_option(option))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_round_trip() -> Result<(), Error> {
+        assert_eq!("4m".parse::<Size>()?, Size::mib(4));
+        assert_eq!("512K".parse::<Size>()?, Size::kib(512));
+        assert_eq!("1000".parse::<Size>()?, Size(1000));
+        assert_eq!(Size::mib(4).to_string(), "4m");
+        assert_eq!(Size(1536).to_string(), "1536");
+        assert_eq!(Size(3 << 10).to_string(), "3k");
+        assert!("4x".parse::<Size>().is_err());
+        assert!("m".parse::<Size>().is_err());
+        assert!("99999999t".parse::<Size>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rates_round_trip() -> Result<(), Error> {
+        assert_eq!("10hz".parse::<Rate>()?, Rate::Hz(10));
+        assert_eq!("10".parse::<Rate>()?, Rate::Hz(10));
+        assert_eq!("500ms".parse::<Rate>()?, Rate::Interval(Duration::from_millis(500)));
+        assert_eq!("2hz".parse::<Rate>()?, "500ms".parse::<Rate>()?);
+        assert_eq!(Rate::Hz(10).to_string(), "10hz");
+        assert_eq!(Rate::Interval(Duration::from_millis(500)).to_string(), "500ms");
+        assert_eq!(Rate::Interval(Duration::from_secs(1)).to_string(), "1s");
+        assert!("10parsecs".parse::<Rate>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn options_are_validated() {
+        assert_eq!(
+            DtraceOption::parse("bufpolicy", Some("ring")).unwrap(),
+            DtraceOption::BufPolicy(BufferPolicy::Ring)
+        );
+        assert_eq!(DtraceOption::parse("quiet", None).unwrap(), DtraceOption::Quiet);
+        assert_eq!(DtraceOption::parse("nosuchoption", Some("1")).unwrap_err().kind(), ErrorKind::BadOptName);
+        assert_eq!(DtraceOption::parse("bufsize", Some("0")).unwrap_err().kind(), ErrorKind::BadOptVal);
+        assert_eq!(DtraceOption::parse("bufsize", None).unwrap_err().kind(), ErrorKind::BadOptVal);
+        assert_eq!(DtraceOption::parse("quiet", Some("yes")).unwrap_err().kind(), ErrorKind::BadOptVal);
+        assert_eq!(DtraceOption::parse("switchrate", Some("0hz")).unwrap_err().kind(), ErrorKind::BadOptVal);
+    }
+
+    #[test]
+    fn options_round_trip_through_optval() -> Result<(), Error> {
+        let options = [
+            DtraceOption::BufSize(Size::mib(4)),
+            DtraceOption::BufPolicy(BufferPolicy::Fill),
+            DtraceOption::SwitchRate(Rate::Hz(10)),
+            DtraceOption::NSpec(4),
+            DtraceOption::Quiet,
+        ];
+        for option in options {
+            let value = option.value();
+            let parsed = DtraceOption::parse(option.name(), value.as_deref())?;
+            assert_eq!(DtraceOption::from_optval(option.name(), parsed.to_optval())?, Some(option));
+        }
+        assert_eq!(DtraceOption::from_optval("quiet", DTRACEOPT_UNSET)?, None);
+        assert!(DtraceOption::from_optval("zdefs", 0).is_err());
+        Ok(())
+    }
+}
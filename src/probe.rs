@@ -0,0 +1,451 @@
+use crate::dtrace_probespec;
+use crate::utils::{Error, ErrorKind};
+
+/// A probe known to DTrace, identified by its ID and its `provider:module:function:name` description.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ProbeInfo {
+    pub id: u32,
+    pub provider: String,
+    pub module: String,
+    pub function: String,
+    pub name: String,
+}
+
+impl ProbeInfo {
+    /// Copies a probe description handed out by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// The fields of `desc` must be NUL terminated.
+    pub(crate) unsafe fn from_raw(desc: &crate::dtrace_probedesc_t) -> Self {
+        let field = |field: &[::core::ffi::c_char]| {
+            ::core::ffi::CStr::from_ptr(field.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        Self {
+            id: desc.dtpd_id,
+            provider: field(&desc.dtpd_provider),
+            module: field(&desc.dtpd_mod),
+            function: field(&desc.dtpd_func),
+            name: field(&desc.dtpd_name),
+        }
+    }
+}
+
+impl std::fmt::Display for ProbeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.provider, self.module, self.function, self.name)
+    }
+}
+
+/// A probe description used to select probes, e.g. `syscall::read*:entry`.
+///
+/// Each field is a glob pattern, an empty field matches any value. A description is parsed from the
+/// `provider:module:function:name` form D programs and `dtrace -n` use, where fewer than four fields are the rightmost
+/// ones, see [`ProbeDescription::parse`], and formatted as all four fields.
+///
+/// ```
+/// use libdtrace_rs::probe::ProbeDescription;
+///
+/// let desc: ProbeDescription = "read*:entry".parse().unwrap();
+/// assert_eq!(desc, ProbeDescription::default().with_function("read*").with_name("entry"));
+/// assert_eq!(desc.to_string(), "::read*:entry");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ProbeDescription {
+    pub provider: String,
+    pub module: String,
+    pub function: String,
+    pub name: String,
+}
+
+impl ProbeDescription {
+    /// Creates a probe description from its `provider:module:function:name` fields.
+    pub fn new(provider: &str, module: &str, function: &str, name: &str) -> Self {
+        Self {
+            provider: provider.to_string(),
+            module: module.to_string(),
+            function: function.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Parses a probe specification of one to four `:` separated fields, like `dtrace_str2desc` does.
+    ///
+    /// The last field is assigned to the field named by `spec` and the preceding ones to the fields before it, so
+    /// `read:entry` is `::read:entry` for [`dtrace_probespec::DTRACE_PROBESPEC_NAME`] and `BEGIN` is `:::BEGIN`.
+    /// Macro variables such as `$target` are not expanded.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The probe specification.
+    /// * `kind` - The field the last field of `spec` describes.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ProbeDescription)` - The parsed description.
+    /// * `Err(errno)` - `EDT_BADSPEC` if `spec` has more fields than `kind` allows, or a field does not fit into a
+    ///   `dtrace_probedesc_t`.
+    pub fn parse(spec: &str, kind: dtrace_probespec) -> Result<Self, Error> {
+        let mut desc = Self::default();
+        let mut fields = spec.rsplit(':');
+        let slots = [&mut desc.name, &mut desc.function, &mut desc.module, &mut desc.provider];
+        let skip = match kind {
+            dtrace_probespec::DTRACE_PROBESPEC_NAME => 0,
+            dtrace_probespec::DTRACE_PROBESPEC_FUNC => 1,
+            dtrace_probespec::DTRACE_PROBESPEC_MOD => 2,
+            dtrace_probespec::DTRACE_PROBESPEC_PROVIDER => 3,
+            dtrace_probespec::DTRACE_PROBESPEC_NONE => return Err(Error::from(ErrorKind::BadSpec)),
+        };
+
+        for (slot, field) in slots.into_iter().skip(skip).zip(&mut fields) {
+            *slot = field.to_string();
+        }
+        if fields.next().is_some() {
+            return Err(Error::from(ErrorKind::BadSpec));
+        }
+
+        crate::dtrace_probedesc_t::try_from(&desc)?;
+        Ok(desc)
+    }
+
+    /// Replaces the provider field.
+    pub fn with_provider(mut self, provider: &str) -> Self {
+        self.provider = provider.to_string();
+        self
+    }
+
+    /// Replaces the module field.
+    pub fn with_module(mut self, module: &str) -> Self {
+        self.module = module.to_string();
+        self
+    }
+
+    /// Replaces the function field.
+    pub fn with_function(mut self, function: &str) -> Self {
+        self.function = function.to_string();
+        self
+    }
+
+    /// Replaces the name field.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Whether `probe` is selected by this description.
+    pub fn matches(&self, probe: &ProbeInfo) -> bool {
+        glob(&self.provider, &probe.provider)
+            && glob(&self.module, &probe.module)
+            && glob(&self.function, &probe.function)
+            && glob(&self.name, &probe.name)
+    }
+}
+
+impl From<&ProbeInfo> for ProbeDescription {
+    fn from(probe: &ProbeInfo) -> Self {
+        Self::new(&probe.provider, &probe.module, &probe.function, &probe.name)
+    }
+}
+
+impl From<&crate::dtrace_probedesc_t> for ProbeDescription {
+    fn from(desc: &crate::dtrace_probedesc_t) -> Self {
+        Self {
+            provider: read_field(&desc.dtpd_provider),
+            module: read_field(&desc.dtpd_mod),
+            function: read_field(&desc.dtpd_func),
+            name: read_field(&desc.dtpd_name),
+        }
+    }
+}
+
+impl TryFrom<&ProbeDescription> for crate::dtrace_probedesc_t {
+    type Error = Error;
+
+    /// Copies the description into the fixed size fields of a `dtrace_probedesc_t`, with the probe ID unset.
+    ///
+    /// Fails with `EDT_BADSPEC` if a field does not fit or contains a NUL character.
+    fn try_from(desc: &ProbeDescription) -> Result<Self, Error> {
+        let mut raw: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        copy_field(&mut raw.dtpd_provider, &desc.provider)?;
+        copy_field(&mut raw.dtpd_mod, &desc.module)?;
+        copy_field(&mut raw.dtpd_func, &desc.function)?;
+        copy_field(&mut raw.dtpd_name, &desc.name)?;
+        Ok(raw)
+    }
+}
+
+impl std::str::FromStr for ProbeDescription {
+    type Err = Error;
+
+    /// Parses a probe specification whose last field is the probe name, see [`ProbeDescription::parse`].
+    fn from_str(spec: &str) -> Result<Self, Error> {
+        Self::parse(spec, dtrace_probespec::DTRACE_PROBESPEC_NAME)
+    }
+}
+
+impl std::fmt::Display for ProbeDescription {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}:{}:{}", self.provider, self.module, self.function, self.name)
+    }
+}
+
+/// Reads a field of a `dtrace_probedesc_t` up to its NUL terminator, or all of it if there is none.
+fn read_field(field: &[::core::ffi::c_char]) -> String {
+    let bytes: Vec<u8> = field.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Copies `value` into the NUL terminated `field`.
+fn copy_field(field: &mut [::core::ffi::c_char], value: &str) -> Result<(), Error> {
+    if value.len() >= field.len() || value.contains('\0') {
+        return Err(Error::from(ErrorKind::BadSpec));
+    }
+    for (c, byte) in field.iter_mut().zip(value.bytes()) {
+        *c = byte as ::core::ffi::c_char;
+    }
+    Ok(())
+}
+
+/// Matches `text` against the glob `pattern` the way DTrace matches probe descriptions.
+///
+/// `*` matches any sequence, `?` any single character and `[...]` any character of the set, which may contain ranges
+/// such as `a-z` and is negated by a leading `!`. A backslash matches the character following it literally. An empty
+/// pattern matches anything.
+pub(crate) fn glob(pattern: &str, text: &str) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*` if the rest of the pattern does not match
+    let mut backtrack = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match_class(&pattern[p..], text[t]),
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            (None, Some((star, start))) => {
+                p = star + 1;
+                t = start + 1;
+                backtrack = Some((star, start + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the character class at the start of `pattern`.
+///
+/// # Returns
+///
+/// The length of the class if `c` is a member of it, `None` otherwise. An unterminated `[` only matches itself.
+fn match_class(pattern: &[char], c: char) -> Option<usize> {
+    let (negated, first) = match pattern.get(1) {
+        Some('!') => (true, 2),
+        _ => (false, 1),
+    };
+    // A `]` right after the opening bracket is a member of the class
+    let end = match pattern.iter().skip(first + 1).position(|&c| c == ']') {
+        Some(end) => end + first + 1,
+        None => return (c == '[').then_some(1),
+    };
+
+    let mut found = false;
+    let mut i = first;
+    while i < end {
+        if i + 2 < end && pattern[i + 1] == '-' {
+            found |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            found |= pattern[i] == c;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(end + 1)
+}
+
+/// An iterator over the probes known to DTrace, see [`crate::wrapper::dtrace_hdl::probes`].
+#[derive(Debug, Clone)]
+pub struct Probes {
+    probes: std::vec::IntoIter<ProbeInfo>,
+}
+
+impl Probes {
+    pub(crate) fn new(probes: Vec<ProbeInfo>) -> Self {
+        Self {
+            probes: probes.into_iter(),
+        }
+    }
+}
+
+impl Iterator for Probes {
+    type Item = ProbeInfo;
+
+    fn next(&mut self) -> Option<ProbeInfo> {
+        self.probes.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.probes.size_hint()
+    }
+}
+
+impl ExactSizeIterator for Probes {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("", "anything"));
+        assert!(glob("read", "read"));
+        assert!(!glob("read", "readv"));
+        assert!(glob("read*", "readv"));
+        assert!(glob("*read*", "pread64"));
+        assert!(glob("r?ad", "read"));
+        assert!(glob("read[vl]", "readv"));
+        assert!(!glob("read[!vl]", "readv"));
+        assert!(glob("tick-[0-9]*", "tick-10s"));
+        assert!(glob("a\\*", "a*"));
+        assert!(!glob("a\\*", "ab"));
+        assert!(glob("[", "["));
+        assert!(glob("[]]", "]"));
+        assert!(glob("*-*-*", "a-b-c-d"));
+        assert!(!glob("*-*-*", "a-b"));
+    }
+
+    fn probe(provider: &str, module: &str, function: &str, name: &str) -> ProbeInfo {
+        ProbeInfo {
+            id: 1,
+            provider: provider.to_string(),
+            module: module.to_string(),
+            function: function.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_fills_fields_from_the_right() -> Result<(), Error> {
+        assert_eq!("BEGIN".parse::<ProbeDescription>()?, ProbeDescription::new("", "", "", "BEGIN"));
+        assert_eq!("read:entry".parse::<ProbeDescription>()?, ProbeDescription::new("", "", "read", "entry"));
+        assert_eq!(
+            "genunix:read:entry".parse::<ProbeDescription>()?,
+            ProbeDescription::new("", "genunix", "read", "entry")
+        );
+        assert_eq!(
+            "syscall::read:entry".parse::<ProbeDescription>()?,
+            ProbeDescription::new("syscall", "", "read", "entry")
+        );
+        assert_eq!("".parse::<ProbeDescription>()?, ProbeDescription::default());
+        assert_eq!(":::".parse::<ProbeDescription>()?, ProbeDescription::default());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_honors_the_spec_kind() -> Result<(), Error> {
+        let parse = ProbeDescription::parse;
+        assert_eq!(parse("syscall", dtrace_probespec::DTRACE_PROBESPEC_PROVIDER)?.provider, "syscall");
+        assert_eq!(parse("genunix", dtrace_probespec::DTRACE_PROBESPEC_MOD)?.module, "genunix");
+        assert_eq!(
+            parse("fbt:genunix", dtrace_probespec::DTRACE_PROBESPEC_MOD)?,
+            ProbeDescription::new("fbt", "genunix", "", "")
+        );
+        assert_eq!(
+            parse("syscall::read", dtrace_probespec::DTRACE_PROBESPEC_FUNC)?,
+            ProbeDescription::new("syscall", "", "read", "")
+        );
+
+        let error = parse("syscall:read", dtrace_probespec::DTRACE_PROBESPEC_PROVIDER).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BadSpec);
+        assert!(parse("a:b:c:d:e", dtrace_probespec::DTRACE_PROBESPEC_NAME).is_err());
+        assert!(parse("BEGIN", dtrace_probespec::DTRACE_PROBESPEC_NONE).is_err());
+        assert!("syscall::read:entry:".parse::<ProbeDescription>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_rejects_fields_too_long_for_libdtrace() {
+        let function = "f".repeat(crate::DTRACE_FUNCNAMELEN as usize);
+        assert!(format!("syscall::{}:entry", function).parse::<ProbeDescription>().is_err());
+        let function = "f".repeat(crate::DTRACE_FUNCNAMELEN as usize - 1);
+        assert!(format!("syscall::{}:entry", function).parse::<ProbeDescription>().is_ok());
+        assert!("BEG\0IN".parse::<ProbeDescription>().is_err());
+    }
+
+    #[test]
+    fn format_round_trips() -> Result<(), Error> {
+        for spec in ["syscall::read:entry", ":::BEGIN", "fbt:genunix:*:return", "profile:::tick-[0-9]*s", ":::"] {
+            let desc: ProbeDescription = spec.parse()?;
+            assert_eq!(desc.to_string(), spec);
+            assert_eq!(desc.to_string().parse::<ProbeDescription>()?, desc);
+        }
+        assert_eq!("read:entry".parse::<ProbeDescription>()?.to_string(), "::read:entry");
+        Ok(())
+    }
+
+    #[test]
+    fn builder_sets_fields() {
+        let desc = ProbeDescription::default()
+            .with_provider("syscall")
+            .with_module("vmlinux")
+            .with_function("read")
+            .with_name("entry");
+        assert_eq!(desc, ProbeDescription::new("syscall", "vmlinux", "read", "entry"));
+        assert_eq!(ProbeDescription::from(&probe("syscall", "vmlinux", "read", "entry")), desc);
+    }
+
+    #[test]
+    fn raw_descriptions_round_trip() -> Result<(), Error> {
+        let desc = ProbeDescription::new("syscall", "", "read*", "entry");
+        let raw = crate::dtrace_probedesc_t::try_from(&desc)?;
+        assert_eq!(raw.dtpd_id, crate::DTRACE_IDNONE);
+        assert_eq!(ProbeDescription::from(&raw), desc);
+
+        // A field that fills its array without a terminator is read up to the end of the array
+        let mut raw: crate::dtrace_probedesc_t = unsafe { ::core::mem::zeroed() };
+        raw.dtpd_name.fill(b'x' as ::core::ffi::c_char);
+        assert_eq!(ProbeDescription::from(&raw).name.len(), raw.dtpd_name.len());
+        Ok(())
+    }
+
+    #[test]
+    fn descriptions_match_probes() {
+        let read = probe("syscall", "vmlinux", "read", "entry");
+        let pread = probe("syscall", "vmlinux", "pread64", "return");
+
+        let all = ProbeDescription::default();
+        assert!(all.matches(&read) && all.matches(&pread));
+        let entries: ProbeDescription = "syscall:::entry".parse().unwrap();
+        assert!(entries.matches(&read) && !entries.matches(&pread));
+        let reads: ProbeDescription = "*read*:".parse().unwrap();
+        assert_eq!(reads, ProbeDescription::default().with_function("*read*"));
+        assert!(reads.matches(&read) && reads.matches(&pread));
+        let exact = ProbeDescription::from(&read);
+        assert!(exact.matches(&read) && !exact.matches(&pread));
+        let class: ProbeDescription = "sys[a-z]all:vmlinux:?read*:r*".parse().unwrap();
+        assert!(class.matches(&pread) && !class.matches(&read));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::dif::DifObject;
+use crate::probe::ProbeDescription;
+use crate::utils::Error;
+use crate::wrapper::dtrace_hdl;
+
+/// Information about a D program, filled in by `dtrace_program_exec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProgramInfo {
+    /// Number of aggregations in the program
+    pub aggregates: u32,
+    /// Number of record generating actions in the program
+    pub records: u32,
+    /// Number of probes matched by the program
+    pub matches: u32,
+    /// Number of speculations in the program
+    pub speculations: u32,
+}
+
+impl From<&crate::dtrace_proginfo> for ProgramInfo {
+    fn from(value: &crate::dtrace_proginfo) -> Self {
+        Self {
+            aggregates: value.dpi_aggregates,
+            records: value.dpi_recgens,
+            matches: value.dpi_matches,
+            speculations: value.dpi_speculations,
+        }
+    }
+}
+
+/// An action of a [`Statement`], in the order the statement records them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Action {
+    /// The kind of action, one of the `DTRACEACT_*` or `DTRACEAGG_*` values
+    pub kind: u16,
+    /// Number of records the action generates, the keys and the value for an aggregating action
+    pub records: u32,
+    /// The variable ID of the aggregation an aggregating action updates, only known on Windows
+    pub aggregation: Option<u32>,
+    /// The argument of the action, e.g. the parameters of `lquantize()`
+    pub arg: u64,
+}
+
+impl Action {
+    /// Whether the action is an aggregating function such as `count()` or `quantize()`.
+    pub fn is_aggregation(&self) -> bool {
+        self.kind as u32 & !0xff == crate::DTRACEACT_AGGREGATION
+    }
+}
+
+/// The aggregation a [`Statement`] updates, e.g. `@counts[execname] = count()`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AggregationDesc {
+    /// The name of the aggregation, as the compiler names it
+    pub name: String,
+    /// The variable ID of the aggregation, as in [`Action::aggregation`]
+    pub id: u32,
+    /// The aggregating function, one of the `DTRACEAGG_*` values
+    pub function: u16,
+    /// Number of keys of the aggregation
+    pub keys: u32,
+    /// The argument of the aggregating function, e.g. the parameters of `lquantize()`
+    pub arg: u64,
+}
+
+/// The DIF object of an action description of a [`Statement`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionDif {
+    /// The kind of the action description, e.g. `DTRACEACT_DIFEXPR` for a key of an aggregation
+    pub kind: u16,
+    pub dif: DifObject,
+}
+
+/// The name and variable ID of the aggregation an aggregating statement updates.
+///
+/// `dtsd_aggdata` points to the compiler's `dt_ident_t`, which is only part of the bindings on Windows.
+///
+/// # Safety
+///
+/// `stmt` must point to a valid `dtrace_stmtdesc_t`.
+#[cfg(target_os = "windows")]
+unsafe fn aggregation_ident(stmt: &crate::dtrace_stmtdesc_t) -> Option<(String, u32)> {
+    let ident = (stmt.dtsd_aggdata as *const crate::dt_ident_t).as_ref()?;
+    let name = match ident.di_name.is_null() {
+        true => String::new(),
+        false => ::core::ffi::CStr::from_ptr(ident.di_name).to_string_lossy().into_owned(),
+    };
+    Some((name, ident.di_id as u32))
+}
+
+/// The other systems do not install the header declaring `dt_ident_t`, so its layout is unknown and the aggregation
+/// of a statement is not available.
+#[cfg(not(target_os = "windows"))]
+unsafe fn aggregation_ident(_stmt: &crate::dtrace_stmtdesc_t) -> Option<(String, u32)> {
+    None
+}
+
+/// Copies the DIF object of a predicate or an action, `None` if `difo` is null.
+///
+/// # Safety
+///
+/// `difo` must be null or point to a valid `dtrace_difo_t`.
+#[cfg(target_os = "windows")]
+unsafe fn dif_object(difo: *const crate::dtrace_difo_t) -> Option<DifObject> {
+    difo.as_ref().map(|difo| DifObject::from_raw(difo))
+}
+
+/// [`DifObject`] decodes the instruction format of DTrace for Windows, so the DIF objects of the other systems are not
+/// copied.
+#[cfg(not(target_os = "windows"))]
+unsafe fn dif_object(_difo: *const crate::dtrace_difo_t) -> Option<DifObject> {
+    None
+}
+
+/// A statement of a compiled D program, i.e. a probe clause for each probe description it enables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    probe: ProbeDescription,
+    has_predicate: bool,
+    predicate: Option<DifObject>,
+    actions: Vec<Action>,
+    action_dif: Vec<ActionDif>,
+    aggregation: Option<AggregationDesc>,
+}
+
+impl Statement {
+    /// Reads the statement description handed out by `dtrace_stmt_iter`.
+    ///
+    /// The key expressions of an aggregating action are folded into it, as libdtrace records them with the
+    /// aggregation rather than on their own.
+    ///
+    /// # Safety
+    ///
+    /// `stmt` must point to a valid `dtrace_stmtdesc_t`.
+    pub(crate) unsafe fn from_raw(stmt: &crate::dtrace_stmtdesc_t) -> Self {
+        let ecb = &*stmt.dtsd_ecbdesc;
+        let ident = aggregation_ident(stmt);
+
+        let mut actions: Vec<Action> = Vec::new();
+        let mut action_dif = Vec::new();
+        let mut aggregation = None;
+        let mut next = stmt.dtsd_action;
+        while let Some(raw) = next.as_ref() {
+            let mut action = Action {
+                kind: raw.dtad_kind,
+                records: 1,
+                aggregation: None,
+                arg: raw.dtad_arg,
+            };
+            if let Some(dif) = dif_object(raw.dtad_difo) {
+                action_dif.push(ActionDif {
+                    kind: raw.dtad_kind,
+                    dif,
+                });
+            }
+            if action.is_aggregation() {
+                let keys = (raw.dtad_ntuple as usize).min(actions.len());
+                actions.truncate(actions.len() - keys);
+                action.records += keys as u32;
+                action.aggregation = ident.as_ref().map(|(_, id)| *id);
+                aggregation = ident.as_ref().map(|(name, id)| AggregationDesc {
+                    name: name.clone(),
+                    id: *id,
+                    function: raw.dtad_kind,
+                    keys: keys as u32,
+                    arg: raw.dtad_arg,
+                });
+            }
+            actions.push(action);
+
+            next = match next == stmt.dtsd_action_last {
+                true => std::ptr::null_mut(),
+                false => raw.dtad_next,
+            };
+        }
+
+        Self {
+            probe: ProbeDescription::from(&ecb.dted_probe),
+            has_predicate: !ecb.dted_pred.dtpdd_difo.is_null(),
+            predicate: dif_object(ecb.dted_pred.dtpdd_difo),
+            actions,
+            action_dif,
+            aggregation,
+        }
+    }
+
+    /// The probe description the statement enables.
+    pub fn probe(&self) -> &ProbeDescription {
+        &self.probe
+    }
+
+    /// The provider of the probe description the statement enables, empty if it matches any provider.
+    pub fn provider(&self) -> &str {
+        &self.probe.provider
+    }
+
+    /// The module of the probe description the statement enables, empty if it matches any module.
+    pub fn module(&self) -> &str {
+        &self.probe.module
+    }
+
+    /// The function of the probe description the statement enables, empty if it matches any function.
+    pub fn function(&self) -> &str {
+        &self.probe.function
+    }
+
+    /// The name of the probe description the statement enables, empty if it matches any name.
+    pub fn name(&self) -> &str {
+        &self.probe.name
+    }
+
+    /// Whether the statement has a predicate, i.e. a `/.../` guard.
+    pub fn has_predicate(&self) -> bool {
+        self.has_predicate
+    }
+
+    /// The DIF object of the predicate, if the statement has one.
+    ///
+    /// The DIF objects are only copied on Windows, whose instruction format [`DifObject`] decodes. Elsewhere this is
+    /// always `None`, like [`Statement::action_dif`] is always empty.
+    pub fn predicate_dif(&self) -> Option<&DifObject> {
+        self.predicate.as_ref()
+    }
+
+    /// The actions of the statement, in the order they are recorded.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    /// The aggregation the statement updates, if it has an aggregating action.
+    ///
+    /// libdtrace only describes the aggregation through the compiler's private `dt_ident_t`, which is part of the
+    /// bindings on Windows alone. Elsewhere this is always `None`, while [`Statement::actions`] still folds the keys.
+    pub fn aggregation(&self) -> Option<&AggregationDesc> {
+        self.aggregation.as_ref()
+    }
+
+    /// The DIF objects of the action descriptions, including those of the keys folded into an aggregating action.
+    pub fn action_dif(&self) -> &[ActionDif] {
+        &self.action_dif
+    }
+
+    /// Disassembles the DIF of the predicate and of the actions, see [`DifObject`].
+    ///
+    /// Elsewhere than on Windows only the probe description is listed, see [`Statement::predicate_dif`].
+    pub fn disassemble(&self) -> String {
+        let mut listing = format!("{}\n", self.probe);
+        if let Some(predicate) = &self.predicate {
+            listing += &format!("\npredicate\n{}", predicate);
+        }
+        for (index, action) in self.action_dif.iter().enumerate() {
+            listing += &format!("\naction {} (kind 0x{:x})\n{}", index, action.kind, action.dif);
+        }
+        listing
+    }
+}
+
+/// A D program compiled by [`dtrace_hdl::dtrace_program_strcompile`] or [`dtrace_hdl::dtrace_program_fcompile`].
+///
+/// The program is owned by the handle that compiled it and cannot outlive it. Any number of programs may be compiled
+/// into, and executed on, the same handle.
+pub struct Program<'h> {
+    handle: &'h dtrace_hdl,
+    program: *mut crate::dtrace_prog,
+    raw_info: Option<crate::dtrace_proginfo>,
+    info: Option<ProgramInfo>,
+}
+
+impl<'h> Program<'h> {
+    /// Wraps a program compiled by libdtrace.
+    ///
+    /// # Safety
+    ///
+    /// `program` must be a non-null program compiled into `handle`.
+    pub(crate) unsafe fn from_raw(handle: &'h dtrace_hdl, program: *mut crate::dtrace_prog) -> Self {
+        Self {
+            handle,
+            program,
+            raw_info: None,
+            info: None,
+        }
+    }
+
+    /// The raw program, for use with the libdtrace bindings.
+    pub fn as_ptr(&self) -> *mut crate::dtrace_prog {
+        self.program
+    }
+
+    /// Whether the program was compiled into `handle`.
+    pub fn is_compiled_by(&self, handle: &dtrace_hdl) -> bool {
+        std::ptr::eq(self.handle, handle)
+    }
+
+    /// Creates the object file for the program and downloads it to the kernel, see
+    /// [`dtrace_hdl::dtrace_program_exec`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(&ProgramInfo)` - If the program execution is successful.
+    /// * `Err(errno)` - If the program execution fails. The error number (`errno`) is returned.
+    pub fn exec(&mut self) -> Result<&ProgramInfo, Error> {
+        let mut info: crate::dtrace_proginfo = unsafe { ::core::mem::zeroed() };
+        match unsafe { crate::dtrace_program_exec(self.handle.handle, self.program, &mut info) } {
+            0 => {
+                self.raw_info = Some(info);
+                Ok(self.info.insert(ProgramInfo::from(&info)))
+            }
+            _ => Err(Error::from((self.handle, "dtrace_program_exec"))),
+        }
+    }
+
+    /// Information about the program, available once it has been executed.
+    pub fn info(&self) -> Option<&ProgramInfo> {
+        self.info.as_ref()
+    }
+
+    /// The raw information about the program, available once it has been executed.
+    pub(crate) fn raw_info(&self) -> Option<&crate::dtrace_proginfo> {
+        self.raw_info.as_ref()
+    }
+
+    /// The statements of the program, one per probe description of each probe clause.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Statement>)` - The statements of the program.
+    /// * `Err(errno)` - If the statements could not be iterated. The error number (`errno`) is returned.
+    pub fn statements(&self) -> Result<Vec<Statement>, Error> {
+        let mut statements = Vec::new();
+        let mut push = |stmt: &crate::dtrace_stmtdesc_t| statements.push(unsafe { Statement::from_raw(stmt) });
+        let mut handler = crate::callbacks::IterHandler::new(&mut push);
+
+        let status = unsafe {
+            crate::dtrace_stmt_iter(
+                self.handle.handle,
+                self.program,
+                Some(crate::callbacks::statement),
+                &mut handler as *mut _ as *mut ::core::ffi::c_void,
+            )
+        };
+        handler.resume_panic();
+        match status {
+            0 => Ok(statements),
+            _ => Err(Error::from((self.handle, "dtrace_stmt_iter"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(kind: u32, ntuple: u32, next: *mut crate::dtrace_actdesc_t) -> crate::dtrace_actdesc_t {
+        crate::dtrace_actdesc_t {
+            dtad_kind: kind as u16,
+            dtad_ntuple: ntuple,
+            dtad_next: next,
+            ..unsafe { ::core::mem::zeroed() }
+        }
+    }
+
+    #[test]
+    fn statement_folds_aggregation_keys() {
+        let mut exit = action(crate::DTRACEACT_EXIT, 0, std::ptr::null_mut());
+        let mut agg = action(crate::DTRACEAGG_LQUANTIZE, 2, &mut exit);
+        agg.dtad_arg = 10;
+        let mut key2 = action(crate::DTRACEACT_DIFEXPR, 0, &mut agg);
+        let mut key1 = action(crate::DTRACEACT_DIFEXPR, 0, &mut key2);
+        let mut trace = action(crate::DTRACEACT_DIFEXPR, 0, &mut key1);
+        // trace("hi")
+        let mut trace_code = [0x26000001u32, 0x23000001];
+        let mut trace_strings = *b"hi\0";
+        let mut trace_dif: crate::dtrace_difo_t = unsafe { ::core::mem::zeroed() };
+        trace_dif.dtdo_buf = trace_code.as_mut_ptr();
+        trace_dif.dtdo_len = trace_code.len() as u32;
+        trace_dif.dtdo_strtab = trace_strings.as_mut_ptr() as *mut ::core::ffi::c_char;
+        trace_dif.dtdo_strlen = trace_strings.len() as u32;
+        trace.dtad_difo = &mut trace_dif;
+        // pid
+        let mut predicate_code = [0x29011601u32, 0x23000001];
+        let mut predicate: crate::dtrace_difo_t = unsafe { ::core::mem::zeroed() };
+        predicate.dtdo_buf = predicate_code.as_mut_ptr();
+        predicate.dtdo_len = predicate_code.len() as u32;
+
+        let mut ecb: crate::dtrace_ecbdesc_t = unsafe { ::core::mem::zeroed() };
+        ecb.dted_probe = (&ProbeDescription::new("syscall", "", "read", "entry")).try_into().unwrap();
+        ecb.dted_pred.dtpdd_difo = &mut predicate;
+        let mut stmt: crate::dtrace_stmtdesc_t = unsafe { ::core::mem::zeroed() };
+        stmt.dtsd_ecbdesc = &mut ecb;
+        stmt.dtsd_action = &mut trace;
+        stmt.dtsd_action_last = &mut agg;
+        #[cfg(target_os = "windows")]
+        let mut ident: crate::dt_ident_t = unsafe { ::core::mem::zeroed() };
+        #[cfg(target_os = "windows")]
+        {
+            ident.di_name = c"lat".as_ptr() as *mut _;
+            ident.di_id = 3;
+            stmt.dtsd_aggdata = &mut ident as *mut crate::dt_ident_t as *mut ::core::ffi::c_void;
+        }
+        let aggregation_id = cfg!(target_os = "windows").then_some(3);
+
+        let statement = unsafe { Statement::from_raw(&stmt) };
+        assert_eq!(statement.probe().to_string(), "syscall::read:entry");
+        assert!(statement.has_predicate());
+        let listing = statement.disassemble();
+        #[cfg(target_os = "windows")]
+        {
+            assert_eq!(statement.predicate_dif().map(|dif| &dif.instructions[..]), Some(&predicate_code[..]));
+            assert_eq!(statement.action_dif().len(), 1);
+            assert_eq!(statement.action_dif()[0].kind, crate::DTRACEACT_DIFEXPR as u16);
+            assert_eq!(statement.action_dif()[0].dif.string(0), Some("hi"));
+            assert!(listing.starts_with("syscall::read:entry\n\npredicate\nDIFO returns D type (unknown) (size 0)\n"));
+            assert!(listing.contains("00: 29011601    ldgs DT_VAR(278), %r1         ! DT_VAR(278) = \"pid\"\n"));
+            assert!(listing.contains("\naction 0 (kind 0x1)\n"));
+            assert!(listing.contains("00: 26000001    sets DT_STRING[0], %r1        ! \"hi\"\n"));
+        }
+        #[cfg(not(target_os = "windows"))]
+        {
+            assert!(statement.predicate_dif().is_none());
+            assert!(statement.action_dif().is_empty());
+            assert_eq!(listing, "syscall::read:entry\n");
+        }
+        assert_eq!(
+            statement.actions(),
+            &[
+                Action {
+                    kind: crate::DTRACEACT_DIFEXPR as u16,
+                    records: 1,
+                    aggregation: None,
+                    arg: 0,
+                },
+                Action {
+                    kind: crate::DTRACEAGG_LQUANTIZE as u16,
+                    records: 3,
+                    aggregation: aggregation_id,
+                    arg: 10,
+                },
+            ]
+        );
+        assert_eq!(
+            statement.aggregation(),
+            aggregation_id
+                .map(|id| AggregationDesc {
+                    name: "lat".to_string(),
+                    id,
+                    function: crate::DTRACEAGG_LQUANTIZE as u16,
+                    keys: 2,
+                    arg: 10,
+                })
+                .as_ref()
+        );
+    }
+}
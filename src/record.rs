@@ -0,0 +1,444 @@
+use crate::data::{ProbeData, RecordDesc};
+
+/// A value traced by a D expression, e.g. by `trace()` or as a `printf()` argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// An integer of `size` bytes, stored zero-extended. Use [`Value::as_i64`] for signed values.
+    Integer { value: u64, size: u8 },
+    /// A NUL-terminated string.
+    String(String),
+    /// Any other data, e.g. a traced struct.
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Decodes the data of a single record.
+    ///
+    /// Records of 1, 2, 4 or 8 bytes are integers. Larger records are strings if they contain printable characters up
+    /// to the first NUL byte and only NUL bytes after it, the same heuristic libdtrace uses to print them.
+    pub fn decode(bytes: &[u8]) -> Self {
+        match *bytes {
+            [b0] => Value::Integer {
+                value: b0 as u64,
+                size: 1,
+            },
+            [b0, b1] => Value::Integer {
+                value: u16::from_ne_bytes([b0, b1]) as u64,
+                size: 2,
+            },
+            [b0, b1, b2, b3] => Value::Integer {
+                value: u32::from_ne_bytes([b0, b1, b2, b3]) as u64,
+                size: 4,
+            },
+            [b0, b1, b2, b3, b4, b5, b6, b7] => Value::Integer {
+                value: u64::from_ne_bytes([b0, b1, b2, b3, b4, b5, b6, b7]),
+                size: 8,
+            },
+            _ => match as_string(bytes) {
+                Some(string) => Value::String(string),
+                None => Value::Bytes(bytes.to_vec()),
+            },
+        }
+    }
+
+    /// The integer as an unsigned value.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Integer { value, .. } => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The integer as a signed value, sign-extended from its size.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Integer { value, size } => {
+                let shift = 64 - 8 * (*size as u32).clamp(1, 8);
+                Some(((*value << shift) as i64) >> shift)
+            }
+            _ => None,
+        }
+    }
+
+    /// The string, if the value is one.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+/// Interprets `bytes` as a NUL-terminated string padded with NUL bytes.
+fn as_string(bytes: &[u8]) -> Option<String> {
+    let len = bytes.iter().position(|&b| b == 0)?;
+    if bytes[len..].iter().any(|&b| b != 0) {
+        return None;
+    }
+    let string = &bytes[..len];
+    if !string
+        .iter()
+        .all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace() || b >= 0x80)
+    {
+        return None;
+    }
+    String::from_utf8(string.to_vec()).ok()
+}
+
+/// A record of an enabled probe, decoded according to the action that generated it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// `trace()` of a non-string expression.
+    Trace(Value),
+    /// `trace()` of a string expression.
+    String(String),
+    /// A `printf()`-like action: `printf()`, `printa()`, `system()` or `freopen()`.
+    ///
+    /// `format` is `None` if the format string could not be resolved. This is always the case for the probe data of
+    /// libdtrace on other systems than Windows, see [`ProbeData::format`]; the arguments are decoded regardless.
+    Printf {
+        action: u16,
+        format: Option<String>,
+        args: Vec<Value>,
+    },
+    /// `stack()`, the kernel program counters from the innermost frame outwards.
+    Stack(Vec<u64>),
+    /// `ustack()` or `jstack()`, the user program counters of process `pid` from the innermost frame outwards.
+    UStack { pid: u64, frames: Vec<u64> },
+    /// `sym()`, a kernel address to be printed as a symbol.
+    Sym(u64),
+    /// `mod()`, a kernel address to be printed as a module.
+    Mod(u64),
+    /// `usym()`, a user address of process `pid` to be printed as a symbol.
+    USym { pid: u64, address: u64 },
+    /// `umod()`, a user address of process `pid` to be printed as a module.
+    UMod { pid: u64, address: u64 },
+    /// `uaddr()`, a user address of process `pid` to be printed as a symbol and offset.
+    UAddr { pid: u64, address: u64 },
+    /// `exit()` with the exit code.
+    Exit(i32),
+    /// `tracemem()`, truncated to the dynamic size if one was given.
+    Tracemem(Vec<u8>),
+    /// Any other action, with its raw data.
+    Other { action: u16, data: Vec<u8> },
+    /// A record whose data lies outside of the probe data or is not aligned as its description requires.
+    Invalid(RecordDesc),
+}
+
+impl Record {
+    /// Decodes the record at `index` of `probe`.
+    ///
+    /// A `printf()`-like action spans several records, see [`Record::decode_next`].
+    pub fn decode(probe: &ProbeData, index: usize) -> Option<Self> {
+        Self::decode_next(probe, index).map(|(record, _)| record)
+    }
+
+    /// Decodes the record at `index` of `probe`, and returns it along with the number of records it spans.
+    ///
+    /// A `printf()`-like action is made up of a record carrying the format string, followed by one record per
+    /// argument. Like libdtrace, the whole group is decoded into a single [`Record::Printf`].
+    ///
+    /// Returns `None` only if there is no record at `index`, a record that does not fit the probe data is decoded
+    /// as [`Record::Invalid`]. Only the records of the group are visited, so walking all the records of a probe with
+    /// the returned spans takes linear time.
+    pub fn decode_next(probe: &ProbeData, index: usize) -> Option<(Self, usize)> {
+        let mut records = probe.records();
+        let desc = records.nth(index)?;
+
+        let record = match desc.action as u32 {
+            crate::DTRACEACT_PRINTF | crate::DTRACEACT_PRINTA | crate::DTRACEACT_SYSTEM | crate::DTRACEACT_FREOPEN => {
+                let group: Vec<RecordDesc> = std::iter::once(desc)
+                    .chain(records.take_while(|next| next.action == desc.action && next.format == 0))
+                    .collect();
+                let args = group
+                    .iter()
+                    .map(|arg| checked_bytes(probe.data(), arg).ok_or(*arg))
+                    .collect::<Result<Vec<_>, _>>();
+                let group = group.len();
+                let args = match args {
+                    Ok(args) => args
+                        .into_iter()
+                        .filter(|bytes| !bytes.is_empty())
+                        .map(Value::decode)
+                        .collect(),
+                    Err(invalid) => return Some((Record::Invalid(invalid), group)),
+                };
+                let format = match desc.format {
+                    0 => None,
+                    format => probe.format(format),
+                };
+                return Some((
+                    Record::Printf {
+                        action: desc.action,
+                        format,
+                        args,
+                    },
+                    group,
+                ));
+            }
+            crate::DTRACEACT_TRACEMEM => {
+                let dynsize = index
+                    .checked_sub(1)
+                    .and_then(|previous| probe.records().nth(previous))
+                    .filter(|previous| previous.action as u32 == crate::DTRACEACT_TRACEMEM_DYNSIZE)
+                    .and_then(|previous| checked_bytes(probe.data(), &previous))
+                    .and_then(|bytes| Value::decode(bytes).as_u64());
+                match checked_bytes(probe.data(), &desc) {
+                    Some(bytes) => {
+                        let len = dynsize.map_or(bytes.len(), |size| bytes.len().min(size as usize));
+                        Record::Tracemem(bytes[..len].to_vec())
+                    }
+                    None => Record::Invalid(desc),
+                }
+            }
+            _ => match checked_bytes(probe.data(), &desc) {
+                Some(bytes) => Record::from_desc(&desc, bytes),
+                None => Record::Invalid(desc),
+            },
+        };
+
+        Some((record, 1))
+    }
+
+    /// Decodes a record on its own, given its description and its data.
+    ///
+    /// Records that depend on their neighbours, like the arguments of `printf()`, are decoded without them; use
+    /// [`Record::decode_next`] for the records of enabled probes.
+    pub fn from_desc(desc: &RecordDesc, bytes: &[u8]) -> Self {
+        match desc.action as u32 {
+            crate::DTRACEACT_DIFEXPR => match Value::decode(bytes) {
+                Value::String(string) => Record::String(string),
+                value => Record::Trace(value),
+            },
+            crate::DTRACEACT_STACK => Record::Stack(frames(words(bytes))),
+            crate::DTRACEACT_USTACK | crate::DTRACEACT_JSTACK => {
+                let mut words = words(bytes);
+                let pid = words.next().unwrap_or(0);
+                let nframes = (desc.arg & 0xffff_ffff) as usize;
+                Record::UStack {
+                    pid,
+                    frames: frames(words.take(nframes)),
+                }
+            }
+            crate::DTRACEACT_SYM => Record::Sym(words(bytes).next().unwrap_or(0)),
+            crate::DTRACEACT_MOD => Record::Mod(words(bytes).next().unwrap_or(0)),
+            crate::DTRACEACT_USYM | crate::DTRACEACT_UMOD | crate::DTRACEACT_UADDR => {
+                let mut words = words(bytes);
+                let pid = words.next().unwrap_or(0);
+                let address = words.next().unwrap_or(0);
+                match desc.action as u32 {
+                    crate::DTRACEACT_USYM => Record::USym { pid, address },
+                    crate::DTRACEACT_UMOD => Record::UMod { pid, address },
+                    _ => Record::UAddr { pid, address },
+                }
+            }
+            crate::DTRACEACT_EXIT => Record::Exit(Value::decode(bytes).as_i64().map(|code| code as i32).unwrap_or(0)),
+            crate::DTRACEACT_TRACEMEM => Record::Tracemem(bytes.to_vec()),
+            _ => Record::Other {
+                action: desc.action,
+                data: bytes.to_vec(),
+            },
+        }
+    }
+}
+
+/// The data of the record described by `desc`, empty if the record lies outside of `data`.
+pub(crate) fn record_bytes<'a>(data: &'a [u8], desc: &RecordDesc) -> &'a [u8] {
+    let start = desc.offset as usize;
+    let end = start.saturating_add(desc.size as usize);
+    data.get(start..end).unwrap_or_default()
+}
+
+/// The data of the record described by `desc`, `None` if the record lies outside of `data` or its offset is not a
+/// multiple of its alignment.
+pub(crate) fn checked_bytes<'a>(data: &'a [u8], desc: &RecordDesc) -> Option<&'a [u8]> {
+    if !desc.offset.is_multiple_of(desc.alignment.max(1) as u32) {
+        return None;
+    }
+    let start = desc.offset as usize;
+    data.get(start..start.checked_add(desc.size as usize)?)
+}
+
+/// The native endian 64-bit words of `bytes`.
+pub(crate) fn words(bytes: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    bytes
+        .chunks_exact(8)
+        .map(|word| u64::from_ne_bytes(word.try_into().unwrap()))
+}
+
+/// Stack frames up to the first empty one.
+fn frames(words: impl Iterator<Item = u64>) -> Vec<u64> {
+    words.take_while(|&pc| pc != 0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProbe;
+
+    fn desc(action: u32, size: u32, offset: u32) -> RecordDesc {
+        RecordDesc {
+            action: action as u16,
+            size,
+            offset,
+            alignment: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Hand-built data of `syscall::read:entry { trace(pid); trace(execname); exit(3); }`, without the EPID header.
+    const TRACE_EXIT: &[u8] = &[
+        0x39, 0x30, 0x00, 0x00, // pid = 12345
+        b'b', b'a', b's', b'h', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // execname, strsize = 16
+        0x03, 0x00, 0x00, 0x00, // exit(3)
+    ];
+
+    /// Hand-built data of `profile-97 { stack(4); ustack(4); }`, without the EPID header.
+    const STACKS: &[u8] = &[
+        0x10, 0x32, 0x54, 0x76, 0x98, 0xff, 0xff, 0xff, // kernel frame 0
+        0x20, 0x32, 0x54, 0x76, 0x98, 0xff, 0xff, 0xff, // kernel frame 1
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // unused kernel frames
+        0xd2, 0x04, 0, 0, 0, 0, 0, 0, // pid = 1234
+        0x00, 0x10, 0x40, 0, 0, 0, 0, 0, // user frame 0
+        0x00, 0x20, 0x40, 0, 0, 0, 0, 0, // user frame 1
+        0x00, 0x30, 0x40, 0, 0, 0, 0, 0, // user frame 2
+        0, 0, 0, 0, 0, 0, 0, 0, // unused user frame
+    ];
+
+    #[test]
+    fn decode_trace_and_exit() {
+        let records = [
+            desc(crate::DTRACEACT_DIFEXPR, 4, 0),
+            desc(crate::DTRACEACT_DIFEXPR, 16, 4),
+            desc(crate::DTRACEACT_EXIT, 4, 20),
+        ];
+        let probe = ProbeData::new(0, 1, 1, "syscall", "", "read", "entry", TRACE_EXIT, &records);
+
+        assert_eq!(
+            Record::decode(&probe, 0),
+            Some(Record::Trace(Value::Integer { value: 12345, size: 4 }))
+        );
+        assert_eq!(Record::decode(&probe, 1), Some(Record::String("bash".to_string())));
+        assert_eq!(Record::decode(&probe, 2), Some(Record::Exit(3)));
+        assert_eq!(Record::decode(&probe, 3), None);
+        assert_eq!(probe.records().nth(2).map(|desc| desc.offset), Some(20));
+        assert_eq!(probe.records().nth(usize::MAX), None);
+    }
+
+    #[test]
+    fn decode_stacks() {
+        let records = [
+            RecordDesc {
+                arg: 4,
+                ..desc(crate::DTRACEACT_STACK, 32, 0)
+            },
+            RecordDesc {
+                arg: 4,
+                ..desc(crate::DTRACEACT_USTACK, 40, 32)
+            },
+        ];
+        let probe = ProbeData::new(0, 1, 1, "profile", "", "", "profile-97", STACKS, &records);
+
+        assert_eq!(
+            Record::decode(&probe, 0),
+            Some(Record::Stack(vec![0xffff_ff98_7654_3210, 0xffff_ff98_7654_3220]))
+        );
+        assert_eq!(
+            Record::decode(&probe, 1),
+            Some(Record::UStack {
+                pid: 1234,
+                frames: vec![0x401000, 0x402000, 0x403000],
+            })
+        );
+    }
+
+    #[test]
+    fn decode_printf_group() {
+        let mock = MockProbe::new("syscall", "", "write", "entry")
+            .record(crate::DTRACEACT_DIFEXPR, 1, &[0xff])
+            .printf(
+                "%s wrote %d bytes\n",
+                &[b"cat\0\0\0\0\0\0\0\0\0", &(-1i32).to_ne_bytes()],
+            )
+            .record(crate::DTRACEACT_EXIT, 4, &0i32.to_ne_bytes());
+        let probe = mock.as_probe_data();
+
+        assert_eq!(
+            Record::decode(&probe, 0).and_then(|r| match r {
+                Record::Trace(value) => value.as_i64(),
+                _ => None,
+            }),
+            Some(-1)
+        );
+        let (printf, span) = Record::decode_next(&probe, 1).unwrap();
+        assert_eq!(span, 3);
+        match printf {
+            Record::Printf { action, format, args } => {
+                assert_eq!(action as u32, crate::DTRACEACT_PRINTF);
+                assert_eq!(format.as_deref(), Some("%s wrote %d bytes\n"));
+                assert_eq!(args[0].as_str(), Some("cat"));
+                assert_eq!(args[1].as_i64(), Some(-1));
+            }
+            record => panic!("unexpected record {record:?}"),
+        }
+        assert_eq!(Record::decode_next(&probe, 4), Some((Record::Exit(0), 1)));
+    }
+
+    #[test]
+    fn decode_tracemem_and_addresses() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&3u64.to_ne_bytes());
+        data.extend_from_slice(b"abcdef\0\x01");
+        data.extend_from_slice(&42u64.to_ne_bytes());
+        data.extend_from_slice(&0x401000u64.to_ne_bytes());
+        let records = [
+            desc(crate::DTRACEACT_TRACEMEM_DYNSIZE, 8, 0),
+            desc(crate::DTRACEACT_TRACEMEM, 8, 8),
+            desc(crate::DTRACEACT_UADDR, 16, 16),
+            desc(crate::DTRACEACT_DIFEXPR, 8, 8),
+        ];
+        let probe = ProbeData::new(0, 1, 1, "pid42", "a.out", "main", "entry", &data, &records);
+
+        assert_eq!(Record::decode(&probe, 1), Some(Record::Tracemem(b"abc".to_vec())));
+        assert_eq!(
+            Record::decode(&probe, 2),
+            Some(Record::UAddr {
+                pid: 42,
+                address: 0x401000
+            })
+        );
+        assert!(matches!(
+            Record::decode(&probe, 3),
+            Some(Record::Trace(Value::Integer { size: 8, .. }))
+        ));
+    }
+
+    #[test]
+    fn decode_invalid_records() {
+        let misaligned = RecordDesc {
+            alignment: 8,
+            ..desc(crate::DTRACEACT_DIFEXPR, 8, 4)
+        };
+        let outside = desc(crate::DTRACEACT_STACK, 32, 8);
+        let aligned = RecordDesc {
+            alignment: 4,
+            ..desc(crate::DTRACEACT_DIFEXPR, 4, 20)
+        };
+        let records = [misaligned, outside, aligned];
+        let probe = ProbeData::new(0, 1, 1, "syscall", "", "read", "entry", TRACE_EXIT, &records);
+
+        assert_eq!(Record::decode(&probe, 0), Some(Record::Invalid(misaligned)));
+        assert_eq!(Record::decode(&probe, 1), Some(Record::Invalid(outside)));
+        assert_eq!(Record::decode(&probe, 2), Some(Record::Trace(Value::Integer { value: 3, size: 4 })));
+
+        let printf = RecordDesc {
+            format: 1,
+            ..desc(crate::DTRACEACT_PRINTF, 0, 0)
+        };
+        let arg = desc(crate::DTRACEACT_PRINTF, 8, 24);
+        let records = [printf, arg, desc(crate::DTRACEACT_EXIT, 4, 20)];
+        let probe = ProbeData::new(0, 1, 1, "syscall", "", "read", "entry", TRACE_EXIT, &records);
+        assert_eq!(Record::decode_next(&probe, 0), Some((Record::Invalid(arg), 2)));
+        assert_eq!(Record::decode_next(&probe, 2), Some((Record::Exit(3), 1)));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::utils::Error;
+use std::io::{Read, Write};
+
+/// The Rust stream behind a [`CFile`].
+enum Stream<'a> {
+    Read(&'a mut dyn Read),
+    Write(&'a mut dyn Write),
+}
+
+/// The state passed as cookie to the stdio callbacks.
+struct Cookie<'a> {
+    stream: Stream<'a>,
+    /// The first I/O error of the stream, reported once the `FILE` is closed
+    error: Option<std::io::Error>,
+}
+
+/// A C `FILE` stream backed by a Rust reader or writer, for the libdtrace APIs that read or write through stdio.
+///
+/// On Linux the stream is bridged with `fopencookie`, every read or write of libdtrace goes straight to the Rust
+/// stream. Elsewhere the data goes through a temporary file: the input is copied into it up front and the output is
+/// copied out of it by [`CFile::finish`].
+pub(crate) struct CFile<'a> {
+    file: *mut crate::FILE,
+    cookie: Box<Cookie<'a>>,
+}
+
+impl<'a> CFile<'a> {
+    /// Opens a `FILE` that libdtrace writes `writer` through.
+    pub fn writer(writer: &'a mut dyn Write) -> Result<Self, Error> {
+        Self::open(Stream::Write(writer))
+    }
+
+    /// Opens a `FILE` that libdtrace reads `reader` through.
+    pub fn reader(reader: &'a mut dyn Read) -> Result<Self, Error> {
+        Self::open(Stream::Read(reader))
+    }
+
+    /// The `FILE` to pass to libdtrace.
+    pub fn as_ptr(&self) -> *mut crate::FILE {
+        self.file
+    }
+
+    /// Flushes and closes the `FILE`.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If all data reached the Rust stream.
+    /// * `Err(errno)` - If reading or writing the Rust stream failed.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let result = self.close(self.file);
+        match self.cookie.error.take() {
+            Some(error) => Err(Error::from(error)),
+            None => result,
+        }
+    }
+
+    /// Runs `call` with the `FILE` of `stream`, or with a null `FILE` if there is none, and closes it afterwards.
+    ///
+    /// The error of `call` takes precedence over the error of the stream, as libdtrace fails on write errors itself.
+    pub fn with<T>(
+        stream: Option<&'a mut dyn Write>,
+        call: impl FnOnce(*mut crate::FILE) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let file = stream.map(CFile::writer).transpose()?;
+        let result = call(file.as_ref().map_or(std::ptr::null_mut(), CFile::as_ptr));
+        let closed = file.map_or(Ok(()), CFile::finish);
+        let value = result?;
+        closed.map(|()| value)
+    }
+}
+
+impl Drop for CFile<'_> {
+    fn drop(&mut self) {
+        if !self.file.is_null() {
+            let _ = self.close(self.file);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use super::{CFile, Cookie, Stream};
+    use crate::utils::Error;
+    use ::core::ffi::{c_char, c_int, c_void};
+    use std::io::Write;
+
+    #[repr(C)]
+    struct cookie_io_functions_t {
+        read: Option<unsafe extern "C" fn(*mut c_void, *mut c_char, usize) -> isize>,
+        write: Option<unsafe extern "C" fn(*mut c_void, *const c_char, usize) -> isize>,
+        seek: Option<unsafe extern "C" fn(*mut c_void, *mut i64, c_int) -> c_int>,
+        close: Option<unsafe extern "C" fn(*mut c_void) -> c_int>,
+    }
+
+    extern "C" {
+        fn fopencookie(cookie: *mut c_void, mode: *const c_char, funcs: cookie_io_functions_t) -> *mut crate::FILE;
+        fn fclose(stream: *mut crate::FILE) -> c_int;
+    }
+
+    /// Read callback, `-1` tells stdio that the read failed.
+    unsafe extern "C" fn read(cookie: *mut c_void, buf: *mut c_char, size: usize) -> isize {
+        let cookie = &mut *(cookie as *mut Cookie);
+        let buf = std::slice::from_raw_parts_mut(buf as *mut u8, size);
+        let result = match &mut cookie.stream {
+            Stream::Read(reader) => ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| reader.read(buf))),
+            Stream::Write(_) => return -1,
+        };
+
+        match result {
+            Ok(Ok(read)) => read as isize,
+            Ok(Err(error)) => {
+                cookie.error.get_or_insert(error);
+                -1
+            }
+            Err(_) => {
+                cookie.error.get_or_insert(std::io::Error::other("reader panicked"));
+                -1
+            }
+        }
+    }
+
+    /// Write callback, `0` tells stdio that the write failed.
+    unsafe extern "C" fn write(cookie: *mut c_void, buf: *const c_char, size: usize) -> isize {
+        let cookie = &mut *(cookie as *mut Cookie);
+        let buf = std::slice::from_raw_parts(buf as *const u8, size);
+        let result = match &mut cookie.stream {
+            Stream::Write(writer) => {
+                ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| writer.write_all(buf)))
+            }
+            Stream::Read(_) => return 0,
+        };
+
+        match result {
+            Ok(Ok(())) => size as isize,
+            Ok(Err(error)) => {
+                cookie.error.get_or_insert(error);
+                0
+            }
+            Err(_) => {
+                cookie.error.get_or_insert(std::io::Error::other("writer panicked"));
+                0
+            }
+        }
+    }
+
+    impl<'a> CFile<'a> {
+        pub(super) fn open(stream: Stream<'a>) -> Result<Self, Error> {
+            let mode = match stream {
+                Stream::Read(_) => c"r",
+                Stream::Write(_) => c"w",
+            };
+            let mut cookie = Box::new(Cookie { stream, error: None });
+            let funcs = cookie_io_functions_t {
+                read: Some(read),
+                write: Some(write),
+                seek: None,
+                close: None,
+            };
+
+            let file = unsafe { fopencookie(&mut *cookie as *mut Cookie as *mut c_void, mode.as_ptr(), funcs) };
+            match file.is_null() {
+                true => Err(Error::from(std::io::Error::last_os_error()).with_api("fopencookie")),
+                false => Ok(Self { file, cookie }),
+            }
+        }
+
+        pub(super) fn close(&mut self, file: *mut crate::FILE) -> Result<(), Error> {
+            let status = unsafe { fclose(file) };
+            self.file = std::ptr::null_mut();
+            if let Stream::Write(writer) = &mut self.cookie.stream {
+                if let Err(error) = writer.flush() {
+                    self.cookie.error.get_or_insert(error);
+                }
+            }
+            match status {
+                0 => Ok(()),
+                _ => Err(Error::from(std::io::Error::last_os_error()).with_api("fclose")),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use super::{CFile, Cookie, Stream};
+    use crate::utils::Error;
+    use ::core::ffi::{c_int, c_void};
+    use std::io::Write;
+
+    extern "C" {
+        fn tmpfile() -> *mut crate::FILE;
+        fn fread(ptr: *mut c_void, size: usize, nmemb: usize, stream: *mut crate::FILE) -> usize;
+        fn fwrite(ptr: *const c_void, size: usize, nmemb: usize, stream: *mut crate::FILE) -> usize;
+        fn fflush(stream: *mut crate::FILE) -> c_int;
+        fn rewind(stream: *mut crate::FILE);
+        fn fclose(stream: *mut crate::FILE) -> c_int;
+    }
+
+    impl<'a> CFile<'a> {
+        pub(super) fn open(mut stream: Stream<'a>) -> Result<Self, Error> {
+            let mut input = Vec::new();
+            if let Stream::Read(reader) = &mut stream {
+                reader.read_to_end(&mut input)?;
+            }
+
+            let file = unsafe { tmpfile() };
+            if file.is_null() {
+                return Err(Error::from(std::io::Error::last_os_error()).with_api("tmpfile"));
+            }
+            let file = Self {
+                file,
+                cookie: Box::new(Cookie { stream, error: None }),
+            };
+            unsafe {
+                if fwrite(input.as_ptr() as *const c_void, 1, input.len(), file.file) != input.len() {
+                    return Err(Error::from(std::io::Error::last_os_error()).with_api("fwrite"));
+                }
+                rewind(file.file);
+            }
+            Ok(file)
+        }
+
+        pub(super) fn close(&mut self, file: *mut crate::FILE) -> Result<(), Error> {
+            self.file = std::ptr::null_mut();
+            if let Stream::Write(writer) = &mut self.cookie.stream {
+                let mut output = Vec::new();
+                let mut buf = [0u8; 8192];
+                unsafe {
+                    fflush(file);
+                    rewind(file);
+                    loop {
+                        match fread(buf.as_mut_ptr() as *mut c_void, 1, buf.len(), file) {
+                            0 => break,
+                            read => output.extend_from_slice(&buf[..read]),
+                        }
+                    }
+                }
+                if let Err(error) = writer.write_all(&output).and_then(|()| writer.flush()) {
+                    self.cookie.error.get_or_insert(error);
+                }
+            }
+
+            match unsafe { fclose(file) } {
+                0 => Ok(()),
+                _ => Err(Error::from(std::io::Error::last_os_error()).with_api("fclose")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::core::ffi::{c_int, c_void};
+
+    extern "C" {
+        fn fputs(s: *const ::core::ffi::c_char, stream: *mut crate::FILE) -> c_int;
+        fn fread(ptr: *mut c_void, size: usize, nmemb: usize, stream: *mut crate::FILE) -> usize;
+    }
+
+    #[test]
+    fn writer_receives_stdio_output() -> Result<(), Error> {
+        let mut output = Vec::new();
+        let file = CFile::writer(&mut output)?;
+        unsafe {
+            fputs(c"count = ".as_ptr(), file.as_ptr());
+            fputs(c"42\n".as_ptr(), file.as_ptr());
+        }
+        file.finish()?;
+        assert_eq!(output, b"count = 42\n");
+        Ok(())
+    }
+
+    #[test]
+    fn reader_feeds_stdio_input() -> Result<(), Error> {
+        let mut input: &[u8] = b"BEGIN { exit(0); }";
+        let file = CFile::reader(&mut input)?;
+        let mut buf = [0u8; 64];
+        let read = unsafe { fread(buf.as_mut_ptr() as *mut c_void, 1, buf.len(), file.as_ptr()) };
+        file.finish()?;
+        assert_eq!(&buf[..read], b"BEGIN { exit(0); }");
+        Ok(())
+    }
+
+    #[test]
+    fn writer_errors_are_reported() {
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::from_raw_os_error(28))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut broken = Broken;
+        let file = CFile::writer(&mut broken).unwrap();
+        unsafe { fputs(c"lost".as_ptr(), file.as_ptr()) };
+        assert_eq!(file.finish().unwrap_err().errno(), 28);
+    }
+}
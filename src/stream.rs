@@ -0,0 +1,200 @@
+use crate::backend::DtraceBackend;
+use crate::consumer::{Consumer, StopToken};
+use crate::event::Event;
+use crate::utils::Error;
+use futures::channel::{mpsc, oneshot};
+use futures::{SinkExt, Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Number of events the worker buffers before it waits for the stream to be polled.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// A [`Stream`] of the events of a running DTrace consumer.
+///
+/// A worker thread owns the backend and runs its [`Consumer`] loop, sending the probes that fired, buffered output,
+/// drops, faults and status changes down a bounded channel. Once the channel is full the worker waits, so a slow
+/// stream applies backpressure instead of buffering without bound (the kernel reports the data it had to drop as
+/// [`Event::Drop`]).
+///
+/// The stream ends once tracing stopped, e.g. because the program called `exit()`, after the printed aggregations. A
+/// failing consumer is reported as an `Err` item and ends the stream as well. Dropping the stream triggers the
+/// [`StopToken`] of the consumer and detaches the worker, which stops tracing and drops the backend in the
+/// background. Use [`EventStream::close`] to wait for that without blocking the executor.
+///
+/// ```ignore
+/// let mut prog = handle.dtrace_program_strcompile(PROGRAM, spec, 0, None)?;
+/// handle.dtrace_program_exec(&mut prog, None)?;
+/// let mut events = handle.into_stream()?;
+/// while let Some(event) = events.next().await {
+///     println!("{:?}", event?);
+/// }
+/// ```
+pub struct EventStream {
+    receiver: mpsc::Receiver<Result<Event, Error>>,
+    stop: StopToken,
+    /// Resolves once the worker has dropped the consumer and its backend.
+    done: Option<oneshot::Receiver<()>>,
+}
+
+impl EventStream {
+    /// Starts tracing on `backend` and streams its events, see [`EventStream::with_capacity`].
+    pub fn new<B>(backend: B) -> Result<Self, Error>
+    where
+        B: DtraceBackend + Send + 'static,
+    {
+        Self::with_capacity(backend, DEFAULT_CAPACITY)
+    }
+
+    /// Starts tracing on `backend` and streams its events.
+    ///
+    /// The programs have to be executed already, the stream registers its event handler and calls `go` itself.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend, owned by the worker thread from now on.
+    /// * `capacity` - Number of events buffered before the worker waits for the stream to be polled.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EventStream)` - If tracing started.
+    /// * `Err(errno)` - If the handler could not be registered or tracing could not be started.
+    pub fn with_capacity<B>(backend: B, capacity: usize) -> Result<Self, Error>
+    where
+        B: DtraceBackend + Send + 'static,
+    {
+        let mut consumer = Consumer::new(backend)?;
+        consumer.start()?;
+
+        let stop = consumer.stop_token();
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (finished, done) = oneshot::channel();
+        std::thread::spawn(move || {
+            run(consumer, sender);
+            let _ = finished.send(());
+        });
+
+        Ok(Self {
+            receiver,
+            stop,
+            done: Some(done),
+        })
+    }
+
+    /// Stops tracing and waits until the worker has stopped the consumer and dropped the backend.
+    ///
+    /// Unlike dropping the stream, this makes sure the backend is released, e.g. before opening a new handle.
+    pub async fn close(mut self) {
+        self.cancel();
+        if let Some(done) = self.done.take() {
+            let _ = done.await;
+        }
+    }
+
+    /// Triggers the [`StopToken`], closing the channel wakes up a worker waiting for capacity.
+    fn cancel(&mut self) {
+        self.stop.stop();
+        self.receiver.close();
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for EventStream {
+    /// Detaches the worker, joining it would block the executor until the consumer loop notices the stop.
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Runs the consumer loop on the worker thread, until tracing stopped or the stream is dropped.
+fn run<B: DtraceBackend>(mut consumer: Consumer<B>, mut sender: mpsc::Sender<Result<Event, Error>>) {
+    let stop = consumer.stop_token();
+    let result = consumer.run(|event| {
+        if !stop.is_stopped() && futures::executor::block_on(sender.send(Ok(event))).is_err() {
+            stop.stop();
+        }
+    });
+    if let Err(err) = result {
+        let _ = futures::executor::block_on(sender.send(Err(err)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{DropEvent, DropKind};
+    use crate::mock::{MockBackend, MockProbe};
+    use crate::types::dtrace_status;
+
+    #[test]
+    fn stream_delivers_events_in_order() {
+        let backend = MockBackend::new();
+        backend.setopt("switchrate", "1ms").unwrap();
+        backend.push_events(vec![Event::Buffered("hello\n".to_string())]);
+        backend.push_work(vec![MockProbe::new("syscall", "", "read", "entry")
+            .record(crate::DTRACEACT_DIFEXPR, 8, &7u64.to_ne_bytes())]);
+        backend.push_work(vec![MockProbe::new("syscall", "", "write", "entry")]);
+
+        let events: Vec<_> = futures::executor::block_on(EventStream::new(backend).unwrap().collect());
+        let events: Vec<_> = events.into_iter().map(Result::unwrap).collect();
+
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0], Event::Buffered("hello\n".to_string()));
+        match &events[1] {
+            Event::Probe(probe) => {
+                assert_eq!(probe.function, "read");
+                assert_eq!(probe.records.len(), 1);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert!(matches!(&events[2], Event::Probe(probe) if probe.function == "write"));
+        assert_eq!(events[3], Event::Status(dtrace_status::Exited));
+    }
+
+    #[test]
+    fn stream_close_waits_for_worker() {
+        let backend = MockBackend::new();
+        backend.setopt("switchrate", "1ms").unwrap();
+        for _ in 0..100 {
+            backend.push_work(vec![MockProbe::new("profile", "", "", "tick-1ms")]);
+        }
+        backend.push_events(vec![Event::Drop(DropEvent {
+            kind: DropKind::Principal,
+            cpu: 0,
+            count: 1,
+            total: 1,
+            message: "1 drop on CPU 0".to_string(),
+        })]);
+
+        let mut stream = EventStream::with_capacity(backend, 1).unwrap();
+        let first = futures::executor::block_on(stream.next()).unwrap().unwrap();
+        assert!(matches!(first, Event::Drop(drop) if drop.count == 1));
+
+        // Returns once the worker blocked on the full channel has finished
+        futures::executor::block_on(stream.close());
+    }
+
+    #[test]
+    fn stream_detaches_worker_on_drop() {
+        let backend = MockBackend::new();
+        backend.setopt("switchrate", "1ms").unwrap();
+        for _ in 0..100 {
+            backend.push_work(vec![MockProbe::new("profile", "", "", "tick-1ms")]);
+        }
+
+        let mut stream = EventStream::with_capacity(backend, 1).unwrap();
+        assert!(futures::executor::block_on(stream.next()).is_some());
+        let done = stream.done.take().unwrap();
+
+        // Returns right away, the detached worker still stops tracing and drops the backend
+        drop(stream);
+        assert_eq!(futures::executor::block_on(done), Ok(()));
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum dtrace_aggwalk_order {
     /// No sorting, use the default order
     None,
@@ -21,6 +22,7 @@ pub enum dtrace_aggwalk_order {
     ValVarRevSorted,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum dtrace_status {
     /// No Status
@@ -48,10 +50,85 @@ impl From<u32> for dtrace_status {
     }
 }
 
+/// A handler registered with [`crate::wrapper::dtrace_hdl::dtrace_register_handler`].
+///
+/// The handle owns the handler until it is closed. A panic raised by the handler is caught before it reaches
+/// libdtrace and aborts the libdtrace call in progress, as if the handler returned [`HandleAction::Abort`].
 pub enum dtrace_handler {
-    Buffered(crate::dtrace_handle_buffered_f),
-    Drop(crate::dtrace_handle_drop_f),
-    Err(crate::dtrace_handle_err_f),
-    Proc(crate::dtrace_handle_proc_f),
-    SetOpt(crate::dtrace_handle_setopt_f),
+    /// Called with the output formatted by libdtrace, e.g. by `printf()` or `printa()`
+    Buffered(Box<dyn FnMut(&str) -> HandleAction + Send>),
+    /// Called with the data the kernel dropped
+    Drop(Box<dyn FnMut(&crate::event::DropEvent) -> HandleAction + Send>),
+    /// Called with the faults raised by enabled probes
+    Err(Box<dyn FnMut(&crate::event::FaultEvent) -> HandleAction + Send>),
+    /// Called with the messages about grabbed processes
+    Proc(Box<dyn FnMut(&str) + Send>),
+    /// Called whenever a D program sets an option, e.g. with `setopt()`
+    SetOpt(Box<dyn FnMut(&crate::event::OptionChange) -> HandleAction + Send>),
+}
+
+/// Value returned from the handlers registered with `dtrace_register_handler`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleAction {
+    /// Continue processing
+    Ok,
+    /// Stop processing, the libdtrace call in progress fails
+    Abort,
+}
+
+impl From<HandleAction> for ::core::ffi::c_int {
+    fn from(value: HandleAction) -> Self {
+        match value {
+            HandleAction::Ok => crate::DTRACE_HANDLE_OK as ::core::ffi::c_int,
+            HandleAction::Abort => crate::DTRACE_HANDLE_ABORT as ::core::ffi::c_int,
+        }
+    }
+}
+
+/// Value returned from the probe and record handlers passed to `dtrace_consume` and `dtrace_work`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeAction {
+    /// Process the current probe or record (for example, print it)
+    This,
+    /// Skip the current probe or record and continue with the next one
+    Next,
+    /// Stop consuming data, the consume call fails with `EDT_DIRABORT`
+    Abort,
+    /// Stop consuming data and report an error
+    Error,
+}
+
+impl From<ConsumeAction> for ::core::ffi::c_int {
+    fn from(value: ConsumeAction) -> Self {
+        match value {
+            ConsumeAction::This => crate::DTRACE_CONSUME_THIS as ::core::ffi::c_int,
+            ConsumeAction::Next => crate::DTRACE_CONSUME_NEXT as ::core::ffi::c_int,
+            ConsumeAction::Abort => crate::DTRACE_CONSUME_ABORT as ::core::ffi::c_int,
+            ConsumeAction::Error => crate::DTRACE_CONSUME_ERROR as ::core::ffi::c_int,
+        }
+    }
+}
+
+/// Value returned from the handler passed to `dtrace_aggregate_walk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggWalkAction {
+    /// Continue with the next aggregation record
+    Next,
+    /// Stop walking the aggregation
+    Abort,
+    /// Clear the data of the current record and continue
+    Clear,
+    /// Remove the current record from the aggregation and continue
+    Remove,
+}
+
+impl From<AggWalkAction> for ::core::ffi::c_int {
+    fn from(value: AggWalkAction) -> Self {
+        match value {
+            AggWalkAction::Next => crate::DTRACE_AGGWALK_NEXT as ::core::ffi::c_int,
+            AggWalkAction::Abort => crate::DTRACE_AGGWALK_ABORT as ::core::ffi::c_int,
+            AggWalkAction::Clear => crate::DTRACE_AGGWALK_CLEAR as ::core::ffi::c_int,
+            AggWalkAction::Remove => crate::DTRACE_AGGWALK_REMOVE as ::core::ffi::c_int,
+        }
+    }
 }
@@ -1,13 +1,505 @@
+use ::core::ffi::c_int;
+
+/// The first libdtrace error number, smaller numbers are system `errno` values.
+const EDT_BASE: c_int = crate::EDT_BASE as c_int;
+
+/// Why a DTrace operation failed, mapped from the libdtrace `EDT_*` error numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Client is requesting an unsupported version
+    Version,
+    /// Version string is invalid or overflows
+    VersInval,
+    /// Requested API version is not defined
+    VersUndef,
+    /// Requested API version has been reduced
+    VersReduced,
+    /// A libctf call failed
+    Ctf,
+    /// Error in D program compilation, see [`Error::compile_error`]
+    Compiler,
+    /// Tuple register allocation failure
+    NoTupReg,
+    /// Memory allocation failure
+    NoMem,
+    /// Integer limit exceeded
+    Int2Big,
+    /// String limit exceeded
+    Str2Big,
+    /// Unknown module name
+    NoMod,
+    /// Unknown provider name
+    NoProv,
+    /// Unknown probe name
+    NoProbe,
+    /// Unknown symbol name
+    NoSym,
+    /// No symbol corresponds to address
+    NoSymAddr,
+    /// Unknown type name
+    NoType,
+    /// Unknown variable name
+    NoVar,
+    /// Unknown aggregation name
+    NoAgg,
+    /// Improper use of type name scoping operator
+    BadScope,
+    /// Overspecified probe description
+    BadSpec,
+    /// Bad macro variable in probe description
+    BadSpcv,
+    /// Invalid probe identifier
+    BadId,
+    /// Module is not currently loaded
+    NotLoaded,
+    /// Module does not contain any CTF data
+    NoCtf,
+    /// Module and program data models don't match
+    DataModel,
+    /// Library has newer DIF version than driver
+    DifVers,
+    /// Unrecognized aggregating action
+    BadAgg,
+    /// File I/O error
+    Fio,
+    /// Invalid DIF program
+    DifInval,
+    /// Invalid DIF size
+    DifSize,
+    /// Failed to copyin DIF program
+    DifFault,
+    /// Bad probe description
+    BadProbe,
+    /// Invalid probe description glob
+    BadPglob,
+    /// Declaration scope stack underflow
+    NoScope,
+    /// Declaration stack underflow
+    NoDecl,
+    /// Record list does not match statement
+    DMismatch,
+    /// Record data offset error
+    DOffset,
+    /// Record data alignment error
+    DAlign,
+    /// Invalid `dtrace_setopt` option name
+    BadOptName,
+    /// Invalid `dtrace_setopt` option value
+    BadOptVal,
+    /// Invalid `dtrace_setopt` option context
+    BadOptCtx,
+    /// Failed to fork preprocessor
+    CppFork,
+    /// Failed to exec preprocessor
+    CppExec,
+    /// Preprocessor not found
+    CppEnt,
+    /// Unknown preprocessor error
+    CppErr,
+    /// External symbol table overflow
+    SymOflow,
+    /// Operation illegal when tracing is active
+    Active,
+    /// Destructive actions not allowed
+    Destructive,
+    /// No anonymous tracing state
+    NoAnon,
+    /// Can't claim anonymous state and enable probes
+    IsAnon,
+    /// END enablings exceed size of principal buffer
+    EndTooBig,
+    /// Failed to load type for printf conversion
+    NoConv,
+    /// Incomplete printf conversion
+    BadConv,
+    /// Invalid library ERROR action
+    BadError,
+    /// Abort due to error
+    ErrAbort,
+    /// Abort due to drop
+    DropAbort,
+    /// Abort explicitly directed by a handler
+    DirAbort,
+    /// Invalid return value from a handler
+    BadRval,
+    /// Invalid normalization
+    BadNormal,
+    /// Enabling exceeds size of buffer
+    BufTooSmall,
+    /// Invalid truncation
+    BadTrunc,
+    /// Device busy (active kernel debugger)
+    Busy,
+    /// Insufficient privileges to use DTrace
+    Access,
+    /// DTrace device not available
+    NoEnt,
+    /// Abort due to systemic unresponsiveness
+    Bricked,
+    /// Failed to load hard-wired definitions
+    Hardwire,
+    /// libelf is out-of-date with respect to libdtrace
+    ElfVersion,
+    /// Attempt to buffer output without handler
+    NoBuffered,
+    /// Description matched an unstable set of probes
+    Unstable,
+    /// Invalid setopt library action
+    BadSetopt,
+    /// Invalid stack program counter size
+    BadStackPc,
+    /// Invalid aggregation variable identifier
+    BadAggVar,
+    /// Client is requesting a deprecated version
+    OVersion,
+    /// Failed to enable probe
+    EnablingErr,
+    /// No probe sites for declared provider
+    NoProbes,
+    /// Failed to load a module
+    CantLoad,
+    /// A system `errno` value
+    Os(c_int),
+    /// An error number unknown to this crate
+    Unknown(c_int),
+}
+
+/// Pairs each `EDT_*` error number with its kind and the message libdtrace reports for it.
+///
+/// The error numbers are the constants bindgen generates from `dt_impl.h`, which `build.rs` copies the `EDT_*` enum
+/// out of on Linux, where libdtrace does not install it.
+macro_rules! edt_kinds {
+    ($($edt:ident => $kind:ident, $message:literal;)*) => {
+        const EDT_KINDS: [(c_int, ErrorKind, &str); [$(stringify!($edt)),*].len()] =
+            [$((crate::$edt as c_int, ErrorKind::$kind, $message)),*];
+    };
+}
+
+edt_kinds! {
+    EDT_VERSION => Version, "Client is requesting an unsupported version";
+    EDT_VERSINVAL => VersInval, "Version string is invalid or overflows";
+    EDT_VERSUNDEF => VersUndef, "Requested API version is not defined";
+    EDT_VERSREDUCED => VersReduced, "Requested API version has been reduced";
+    EDT_CTF => Ctf, "A libctf call failed";
+    EDT_COMPILER => Compiler, "Error in D program compilation";
+    EDT_NOTUPREG => NoTupReg, "Tuple register allocation failure";
+    EDT_NOMEM => NoMem, "Memory allocation failure";
+    EDT_INT2BIG => Int2Big, "Integer limit exceeded";
+    EDT_STR2BIG => Str2Big, "String limit exceeded";
+    EDT_NOMOD => NoMod, "Unknown module name";
+    EDT_NOPROV => NoProv, "Unknown provider name";
+    EDT_NOPROBE => NoProbe, "Unknown probe name";
+    EDT_NOSYM => NoSym, "Unknown symbol name";
+    EDT_NOSYMADDR => NoSymAddr, "No symbol corresponds to address";
+    EDT_NOTYPE => NoType, "Unknown type name";
+    EDT_NOVAR => NoVar, "Unknown variable name";
+    EDT_NOAGG => NoAgg, "Unknown aggregation name";
+    EDT_BADSCOPE => BadScope, "Improper use of type name scoping operator";
+    EDT_BADSPEC => BadSpec, "Overspecified probe description";
+    EDT_BADSPCV => BadSpcv, "Bad macro variable in probe description";
+    EDT_BADID => BadId, "Invalid probe identifier";
+    EDT_NOTLOADED => NotLoaded, "Module is not currently loaded";
+    EDT_NOCTF => NoCtf, "Module does not contain any CTF data";
+    EDT_DATAMODEL => DataModel, "Module and program data models don't match";
+    EDT_DIFVERS => DifVers, "Library has newer DIF version than driver";
+    EDT_BADAGG => BadAgg, "Unrecognized aggregating action";
+    EDT_FIO => Fio, "File I/O error";
+    EDT_DIFINVAL => DifInval, "Invalid DIF program";
+    EDT_DIFSIZE => DifSize, "Invalid DIF size";
+    EDT_DIFFAULT => DifFault, "Failed to copyin DIF program";
+    EDT_BADPROBE => BadProbe, "Bad probe description";
+    EDT_BADPGLOB => BadPglob, "Invalid probe description glob";
+    EDT_NOSCOPE => NoScope, "Declaration scope stack underflow";
+    EDT_NODECL => NoDecl, "Declaration stack underflow";
+    EDT_DMISMATCH => DMismatch, "Record list does not match statement";
+    EDT_DOFFSET => DOffset, "Record data offset error";
+    EDT_DALIGN => DAlign, "Record data alignment error";
+    EDT_BADOPTNAME => BadOptName, "Invalid dtrace_setopt option name";
+    EDT_BADOPTVAL => BadOptVal, "Invalid dtrace_setopt option value";
+    EDT_BADOPTCTX => BadOptCtx, "Invalid dtrace_setopt option context";
+    EDT_CPPFORK => CppFork, "Failed to fork preprocessor";
+    EDT_CPPEXEC => CppExec, "Failed to exec preprocessor";
+    EDT_CPPENT => CppEnt, "Preprocessor not found";
+    EDT_CPPERR => CppErr, "Unknown preprocessor error";
+    EDT_SYMOFLOW => SymOflow, "External symbol table overflow";
+    EDT_ACTIVE => Active, "Operation illegal when tracing is active";
+    EDT_DESTRUCTIVE => Destructive, "Destructive actions not allowed";
+    EDT_NOANON => NoAnon, "No anonymous tracing state";
+    EDT_ISANON => IsAnon, "Can't claim anonymous state and enable probes";
+    EDT_ENDTOOBIG => EndTooBig, "END enablings exceed size of principal buffer";
+    EDT_NOCONV => NoConv, "Failed to load type for printf conversion";
+    EDT_BADCONV => BadConv, "Incomplete printf conversion";
+    EDT_BADERROR => BadError, "Invalid library ERROR action";
+    EDT_ERRABORT => ErrAbort, "Abort due to error";
+    EDT_DROPABORT => DropAbort, "Abort due to drop";
+    EDT_DIRABORT => DirAbort, "Abort explicitly directed by a handler";
+    EDT_BADRVAL => BadRval, "Invalid return value from a handler";
+    EDT_BADNORMAL => BadNormal, "Invalid normalization";
+    EDT_BUFTOOSMALL => BufTooSmall, "Enabling exceeds size of buffer";
+    EDT_BADTRUNC => BadTrunc, "Invalid truncation";
+    EDT_BUSY => Busy, "Device busy (active kernel debugger)";
+    EDT_ACCESS => Access, "Insufficient privileges to use DTrace";
+    EDT_NOENT => NoEnt, "DTrace device not available";
+    EDT_BRICKED => Bricked, "Abort due to systemic unresponsiveness";
+    EDT_HARDWIRE => Hardwire, "Failed to load hard-wired definitions";
+    EDT_ELFVERSION => ElfVersion, "libelf is out-of-date with respect to libdtrace";
+    EDT_NOBUFFERED => NoBuffered, "Attempt to buffer output without handler";
+    EDT_UNSTABLE => Unstable, "Description matched an unstable set of probes";
+    EDT_BADSETOPT => BadSetopt, "Invalid setopt library action";
+    EDT_BADSTACKPC => BadStackPc, "Invalid stack program counter size";
+    EDT_BADAGGVAR => BadAggVar, "Invalid aggregation variable identifier";
+    EDT_OVERSION => OVersion, "Client is requesting a deprecated version";
+    EDT_ENABLING_ERR => EnablingErr, "Failed to enable probe";
+    EDT_NOPROBES => NoProbes, "No probe sites for declared provider";
+    EDT_CANTLOAD => CantLoad, "Failed to load a module";
+}
+
+impl ErrorKind {
+    /// Maps an error number reported by libdtrace to its kind.
+    pub fn from_errno(errno: c_int) -> Self {
+        match errno {
+            errno if errno < EDT_BASE => ErrorKind::Os(errno),
+            errno => EDT_KINDS
+                .iter()
+                .find(|(edt, _, _)| *edt == errno)
+                .map_or(ErrorKind::Unknown(errno), |(_, kind, _)| *kind),
+        }
+    }
+
+    /// The error number of the kind.
+    pub fn errno(&self) -> c_int {
+        match *self {
+            ErrorKind::Os(errno) | ErrorKind::Unknown(errno) => errno,
+            kind => EDT_KINDS
+                .iter()
+                .find(|(_, edt, _)| *edt == kind)
+                .map_or(EDT_BASE, |(errno, _, _)| *errno),
+        }
+    }
+
+    /// The message libdtrace reports for the error, without calling into libdtrace.
+    fn message(&self) -> String {
+        match *self {
+            ErrorKind::Os(errno) => std::io::Error::from_raw_os_error(errno).to_string(),
+            ErrorKind::Unknown(errno) => format!("Unknown error {}", errno),
+            kind => EDT_KINDS
+                .iter()
+                .find(|(_, edt, _)| *edt == kind)
+                .map_or_else(String::new, |(_, _, message)| message.to_string()),
+        }
+    }
+}
+
+/// A compiler diagnostic, parsed from the message libdtrace reports when compiling a D program fails.
+///
+/// libdtrace formats diagnostics as `[D_TAG] file, line N: message`, where the tag and file are optional. Syntax
+/// errors name the offending token as `near "token"`. libdtrace does not report columns, the column is the position
+/// of the offending token on its line when the source of the program is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    tag: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    column: Option<u32>,
+    token: Option<String>,
+    message: String,
+}
+
+impl CompileError {
+    /// Parses a compiler diagnostic.
+    ///
+    /// # Arguments
+    ///
+    /// * `diagnostic` - The message reported by libdtrace.
+    /// * `source` - The source of the program, used to locate the offending token.
+    pub fn parse(diagnostic: &str, source: Option<&str>) -> Self {
+        let mut rest = diagnostic.trim();
+
+        let mut tag = None;
+        if let Some(tagged) = rest.strip_prefix('[') {
+            if let Some((name, message)) = tagged.split_once(']') {
+                tag = Some(name.to_string());
+                rest = message.trim_start();
+            }
+        }
+
+        let mut file = None;
+        let mut line = None;
+        if let Some((location, message)) = rest.split_once(": ") {
+            let (name, number) = match location.rsplit_once(", line ") {
+                Some((name, number)) => (Some(name), number),
+                None => (None, location.strip_prefix("line ").unwrap_or_default()),
+            };
+            if let Ok(number) = number.parse::<u32>() {
+                file = name.map(str::to_string);
+                line = Some(number);
+                rest = message;
+            }
+        }
+
+        let token = rest
+            .split_once("near \"")
+            .and_then(|(_, token)| token.rsplit_once('"'))
+            .map(|(token, _)| token.to_string());
+        let column = match (source, line, &token) {
+            (Some(source), Some(line), Some(token)) => source
+                .lines()
+                .nth(line.saturating_sub(1) as usize)
+                .and_then(|text| text.find(token.as_str()))
+                .map(|column| column as u32 + 1),
+            _ => None,
+        };
+
+        Self {
+            tag,
+            file,
+            line,
+            column,
+            token,
+            message: rest.trim_end().to_string(),
+        }
+    }
+
+    /// The diagnostic tag, e.g. `D_SYNTAX`, if libdtrace was asked to report tags.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// The file the program was compiled from, if any.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The line of the error, starting at 1.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The column of the offending token, starting at 1.
+    pub fn column(&self) -> Option<u32> {
+        self.column
+    }
+
+    /// The offending token.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// The diagnostic without its location.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            (Some(line), None) => write!(f, "line {}: {}", line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Error {
-    _errno: i32,
+    errno: c_int,
     message: String,
+    api: Option<&'static str>,
+    compile: Option<Box<CompileError>>,
+}
+
+impl Error {
+    /// The error number, either a system `errno` value or one of the libdtrace `EDT_*` codes.
+    pub fn errno(&self) -> c_int {
+        self.errno
+    }
+
+    /// Why the operation failed.
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::from_errno(self.errno)
+    }
+
+    /// The error message reported by libdtrace.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The libdtrace function that failed, e.g. `dtrace_go`, if known.
+    pub fn api(&self) -> Option<&'static str> {
+        self.api
+    }
+
+    /// The compiler diagnostic if compiling a D program failed.
+    pub fn compile_error(&self) -> Option<&CompileError> {
+        self.compile.as_deref()
+    }
+
+    /// Records the libdtrace function that failed.
+    pub fn with_api(mut self, api: &'static str) -> Self {
+        self.api = Some(api);
+        self
+    }
+
+    /// Parses the message of a compiler error into a [`CompileError`], `source` is the source of the program.
+    pub(crate) fn with_source(mut self, source: Option<&str>) -> Self {
+        if self.kind() == ErrorKind::Compiler {
+            self.compile = Some(Box::new(CompileError::parse(&self.message, source)));
+        }
+        self
+    }
+
+    /// Creates a compiler error with a libdtrace formatted diagnostic.
+    pub(crate) fn compiler(diagnostic: &str, source: Option<&str>) -> Self {
+        Self {
+            errno: ErrorKind::Compiler.errno(),
+            message: diagnostic.to_string(),
+            api: None,
+            compile: None,
+        }
+        .with_source(source)
+    }
 }
 
-impl From<::core::ffi::c_int> for Error {
-    fn from(value: ::core::ffi::c_int) -> Self {
-        let message = crate::wrapper::dtrace_hdl::dtrace_errmsg(None, value).to_string();
-        Self { _errno: value, message }
+impl From<c_int> for Error {
+    fn from(value: c_int) -> Self {
+        Self {
+            errno: value,
+            message: ErrorKind::from_errno(value).message(),
+            api: None,
+            compile: None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Self {
+            // Errors without an errno, e.g. from an in-memory writer, are reported as `EIO`
+            errno: error.raw_os_error().unwrap_or(libc::EIO),
+            message: error.to_string(),
+            api: None,
+            compile: None,
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::from(kind.errno())
+    }
+}
+
+impl From<crate::dlang::builder::BuildError> for Error {
+    /// A program that cannot be rendered is reported as `EINVAL`.
+    fn from(error: crate::dlang::builder::BuildError) -> Self {
+        Self {
+            errno: libc::EINVAL,
+            message: error.message,
+            api: None,
+            compile: None,
+        }
     }
 }
 
@@ -15,48 +507,86 @@ impl From<&crate::wrapper::dtrace_hdl> for Error {
     fn from(handle: &crate::wrapper::dtrace_hdl) -> Self {
         let errno = handle.dtrace_errno();
         let message = crate::wrapper::dtrace_hdl::dtrace_errmsg(Some(handle), errno).to_string();
-        Self { _errno: errno, message }
+        Self {
+            errno,
+            message,
+            api: None,
+            compile: None,
+        }
+    }
+}
+
+impl From<(&crate::wrapper::dtrace_hdl, &'static str)> for Error {
+    /// The last error of the handle, raised by the libdtrace function `api`.
+    fn from((handle, api): (&crate::wrapper::dtrace_hdl, &'static str)) -> Self {
+        Error::from(handle).with_api(api)
     }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Error: {}", self.message)
+        match (self.api, &self.compile) {
+            (Some(api), Some(compile)) => write!(f, "Error: {}: {}", api, compile),
+            (Some(api), None) => write!(f, "Error: {}: {}", api, self.message),
+            (None, Some(compile)) => write!(f, "Error: {}", compile),
+            (None, None) => write!(f, "Error: {}", self.message),
+        }
     }
 }
 
 impl std::error::Error for Error {}
 
-extern "C" {
-    fn fopen(
-        __filename: *const ::core::ffi::c_char,
-        __modes: *const ::core::ffi::c_char,
-    ) -> *mut crate::FILE;
-
-    fn fclose(__stream: *mut crate::FILE) -> ::core::ffi::c_int;
-}
-
-pub struct File {
-    pub file: *mut crate::FILE,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl Drop for File {
-    fn drop(&mut self) {
-        unsafe {
-            fclose(self.file);
+    #[test]
+    fn error_kinds_map_to_errno() {
+        assert_eq!(EDT_BASE, crate::EDT_VERSION as c_int);
+        assert_eq!(ErrorKind::from_errno(crate::EDT_VERSION as c_int), ErrorKind::Version);
+        assert_eq!(ErrorKind::from_errno(crate::EDT_COMPILER as c_int), ErrorKind::Compiler);
+        assert_eq!(ErrorKind::from_errno(crate::EDT_NOPROBE as c_int), ErrorKind::NoProbe);
+        assert_eq!(ErrorKind::from_errno(crate::EDT_BADOPTNAME as c_int), ErrorKind::BadOptName);
+        assert_eq!(ErrorKind::from_errno(crate::EDT_DIRABORT as c_int), ErrorKind::DirAbort);
+        assert_eq!(ErrorKind::DropAbort.errno(), crate::EDT_DROPABORT as c_int);
+        assert_eq!(ErrorKind::CantLoad.errno(), crate::EDT_CANTLOAD as c_int);
+        assert_eq!(ErrorKind::from_errno(22), ErrorKind::Os(22));
+        assert_eq!(ErrorKind::from_errno(5000), ErrorKind::Unknown(5000));
+        for (errno, kind, _) in EDT_KINDS.iter() {
+            assert_eq!(EDT_KINDS.iter().filter(|(other, _, _)| other == errno).count(), 1);
+            assert_eq!(kind.errno(), *errno);
+            assert_eq!(ErrorKind::from_errno(*errno), *kind);
         }
     }
-}
 
-impl File {
-    pub fn new(filename: &str, modes: &str) -> Result<Self, String> {
-        let filename = std::ffi::CString::new(filename).unwrap();
-        let modes = std::ffi::CString::new(modes).unwrap();
-        let file = unsafe { fopen(filename.as_ptr(), modes.as_ptr()) };
-        if file.is_null() {
-            Err("Failed to open file".to_string())
-        } else {
-            Ok(Self { file})
-        }
+    #[test]
+    fn errors_from_errno_do_not_need_libdtrace() {
+        let error = Error::from(ErrorKind::BadOptName);
+        assert_eq!(error.errno(), crate::EDT_BADOPTNAME as c_int);
+        assert_eq!(error.message(), "Invalid dtrace_setopt option name");
+        let error = Error::from(libc::EINVAL);
+        assert_eq!(error.kind(), ErrorKind::Os(libc::EINVAL));
+        assert_eq!(error.message(), std::io::Error::from_raw_os_error(libc::EINVAL).to_string());
+        assert_eq!(Error::from(5000).message(), "Unknown error 5000");
+    }
+
+    #[test]
+    fn compile_errors_are_parsed() {
+        let source = "BEGIN\n{\n    trace(1) }\n";
+        let error = CompileError::parse("[D_SYNTAX] script.d, line 3: syntax error near \"}\"", Some(source));
+        assert_eq!(error.tag(), Some("D_SYNTAX"));
+        assert_eq!(error.file(), Some("script.d"));
+        assert_eq!(error.line(), Some(3));
+        assert_eq!(error.column(), Some(14));
+        assert_eq!(error.token(), Some("}"));
+        assert_eq!(error.message(), "syntax error near \"}\"");
+
+        let error = CompileError::parse("line 1: macro argument $2 is not defined", None);
+        assert_eq!((error.tag(), error.file(), error.line()), (None, None, Some(1)));
+        assert_eq!((error.column(), error.token()), (None, None));
+
+        let error = CompileError::parse("probe description syscall::nosuch:entry does not match any probes", None);
+        assert_eq!(error.line(), None);
+        assert_eq!(error.message(), "probe description syscall::nosuch:entry does not match any probes");
     }
 }
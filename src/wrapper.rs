@@ -1,15 +1,33 @@
 #![allow(dead_code)]
-use crate::types::{dtrace_aggwalk_order, dtrace_status};
-use crate::utils::{Error, self};
+use crate::aggregation::Aggregation;
+use crate::backend::DtraceBackend;
+use crate::args::MacroArgs;
+use crate::callbacks;
+use crate::consumer::Events;
+use crate::options::DtraceOption;
+use crate::probe::{ProbeDescription, ProbeInfo, Probes};
+use crate::program::Program;
+use crate::data::ProbeData;
+use crate::event::{DropEvent, Event, FaultEvent};
+use crate::record::Record;
+use crate::stdio::CFile;
+use crate::types::{dtrace_aggwalk_order, dtrace_status, ConsumeAction};
+use crate::utils::{Error, ErrorKind};
 use ::core::ffi::c_int;
+use std::io::{Read, Write};
 /// Represents a handle to a DTrace instance.
 pub struct dtrace_hdl {
-    handle: *mut crate::dtrace_hdl_t,
+    pub(crate) handle: *mut crate::dtrace_hdl_t,
+    /// The handlers registered with the handle, freed only after the handle is closed
+    handlers: Option<Box<callbacks::Handlers>>,
 }
 
 impl From<*mut crate::dtrace_hdl_t> for dtrace_hdl {
     fn from(value: *mut crate::dtrace_hdl_t) -> Self {
-        Self { handle: value }
+        Self {
+            handle: value,
+            handlers: None,
+        }
     }
 }
 
@@ -46,7 +64,7 @@ impl dtrace_hdl {
         let handle = unsafe { crate::dtrace_open(version, flags, &mut errp) };
 
         if handle.is_null() {
-            return Err(Error::from(errp));
+            return Err(Error::from(errp).with_api("dtrace_open"));
         }
 
         Ok(handle.into())
@@ -62,7 +80,7 @@ impl dtrace_hdl {
     pub fn dtrace_go(&self) -> Result<(), Error> {
         match unsafe { crate::dtrace_go(self.handle) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
+            _ => Err(Error::from((self, "dtrace_go"))),
         }
     }
 
@@ -79,7 +97,7 @@ impl dtrace_hdl {
     pub fn dtrace_stop(&self) -> Result<(), Error> {
         match unsafe { crate::dtrace_stop(self.handle) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
+            _ => Err(Error::from((self, "dtrace_stop"))),
         }
     }
 
@@ -114,13 +132,13 @@ impl dtrace_hdl {
     /// # Arguments
     ///
     /// * `handle` - An optional handle to a DTrace instance. If `None`, the error message will be
-    ///              retrieved for the global DTrace instance.
+    ///   retrieved for the global DTrace instance.
     /// * `errno` - The error number.
     ///
     /// # Returns
     ///
     /// Returns the error message as a [`String`].
-    pub fn dtrace_errmsg<'a>(handle: Option<&'a Self>, errno: c_int) -> &'a str {
+    pub fn dtrace_errmsg(handle: Option<&Self>, errno: c_int) -> &str {
         unsafe {
             let handle = match handle {
                 Some(handle) => handle.handle,
@@ -150,7 +168,7 @@ impl dtrace_hdl {
         let value = std::ffi::CString::new(value).unwrap();
         match unsafe { crate::dtrace_setopt(self.handle, option.as_ptr(), value.as_ptr()) } {
             0 => Ok(self),
-            _ => Err(Error::from(&self)),
+            _ => Err(Error::from((&self, "dtrace_setopt"))),
         }
     }
 
@@ -168,7 +186,51 @@ impl dtrace_hdl {
         let mut optval: crate::dtrace_optval_t = 0;
         match unsafe { crate::dtrace_getopt(self.handle, option.as_ptr(), &mut optval) } {
             0 => Ok(optval),
-            _ => Err(Error::from(self)),
+            _ => Err(Error::from((self, "dtrace_getopt"))),
+        }
+    }
+
+    /// Validates a typed DTrace option and sets it. Flag options are set without a value.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The option and its value.
+    ///
+    /// # Returns
+    ///
+    /// Returns the handle if the option was set successfully, or an error code if the option is invalid or could
+    /// not be set.
+    pub fn dtrace_set_option(self, option: &DtraceOption) -> Result<Self, Error> {
+        option.validate()?;
+        self.setopt_raw(option.name(), option.value().as_deref())?;
+        Ok(self)
+    }
+
+    /// Retrieves the value of a DTrace option as a typed option.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - The name of the option to retrieve.
+    ///
+    /// # Returns
+    ///
+    /// Returns the option if it is set, `None` if it is not set, or an error code if the option could not be
+    /// retrieved.
+    pub fn dtrace_get_option(&self, option: &str) -> Result<Option<DtraceOption>, Error> {
+        DtraceOption::from_optval(option, self.dtrace_getopt(option)?)
+    }
+
+    /// Sets an option, passing a null value for `None`.
+    pub(crate) fn setopt_raw(&self, option: &str, value: Option<&str>) -> Result<(), Error> {
+        let option = std::ffi::CString::new(option).map_err(|_| Error::from(ErrorKind::BadOptName))?;
+        let value = value
+            .map(std::ffi::CString::new)
+            .transpose()
+            .map_err(|_| Error::from(ErrorKind::BadOptVal))?;
+        let value = value.as_ref().map_or(std::ptr::null(), |value| value.as_ptr());
+        match unsafe { crate::dtrace_setopt(self.handle, option.as_ptr(), value) } {
+            0 => Ok(()),
+            _ => Err(Error::from((self, "dtrace_setopt"))),
         }
     }
 
@@ -185,37 +247,32 @@ impl dtrace_hdl {
     ///
     /// * `flags` - Flags to control the compilation behavior. Common flags:
     ///     * `DTRACE_C_ZDEFS` - Instructs the compiler to permit probes, whose definitions do not match the existing probes.
-    ///                          By default, the compiler does not permit this.
-    ///    *  `DTRACE_C_DIFV` - Shows the target language instructions that results from the compilation and additional information to execute the target language instructions.
-    ///    *  `DTRACE_C_CPP` - Instructs the compiler to preprocess the input program with the C preprocessor.
+    ///       By default, the compiler does not permit this.
+    ///     * `DTRACE_C_DIFV` - Shows the target language instructions that results from the compilation and additional information to execute the target language instructions.
+    ///     * `DTRACE_C_CPP` - Instructs the compiler to preprocess the input program with the C preprocessor.
     ///
     /// The full list of flags can be found [here](https://github.com/microsoft/DTrace-on-Windows/blob/0adebf25928264dffdc8240e850503865409f334/lib/libdtrace/common/dtrace.h#L115).
-    /// * `args` - Optional arguments passed to the program.
+    /// * `args` - Optional macro arguments passed to the program, referenced as `$1`, `$$1`, ... See [`MacroArgs`].
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing a reference to the compiled `dtrace_prog` if successful, or
+    /// Returns a `Result` containing the compiled [`Program`] if successful, or
     /// an error code if the program could not be compiled.
     pub fn dtrace_program_strcompile<'a>(
         &'a self,
         program: &str,
         spec: crate::dtrace_probespec,
         flags: u32,
-        args: Option<Vec<String>>,
-    ) -> Result<&'a mut crate::dtrace_prog, Error> {
+        args: Option<&MacroArgs>,
+    ) -> Result<Program<'a>, Error> {
+        let source = program;
         let program = std::ffi::CString::new(program).unwrap();
 
-        // Break the arguments into argc and argv
-        let (argc, argv) = match args {
+        // Break the arguments into argc and argv, `argv` owns the strings until the compiler is done with them
+        let argv = args.map(MacroArgs::to_argv).transpose()?;
+        let (argc, argv) = match &argv {
             None => (0, std::ptr::null()),
-            Some(args) => {
-                let mut argv: Vec<*mut ::core::ffi::c_char> = Vec::new();
-                for arg in args {
-                    let arg = std::ffi::CString::new(arg).unwrap();
-                    argv.push(arg.as_ptr() as *mut ::core::ffi::c_char);
-                }
-                (argv.len() as c_int, argv.as_ptr())
-            }
+            Some(argv) => (argv.argc(), argv.as_ptr()),
         };
 
         let prog;
@@ -231,46 +288,49 @@ impl dtrace_hdl {
         }
 
         if prog.is_null() {
-            return Err(Error::from(self));
+            return Err(Error::from((self, "dtrace_program_strcompile")).with_source(Some(source)));
         }
 
-        unsafe { Ok(&mut *prog) }
+        unsafe { Ok(Program::from_raw(self, prog)) }
     }
 
+    /// Compiles a DTrace program from a file, see [`dtrace_hdl::dtrace_program_strcompile`].
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The source of the DTrace program, e.g. a [`std::fs::File`].
+    /// * `flags` - Flags to control the compilation behavior, see [`dtrace_hdl::dtrace_program_strcompile`].
+    /// * `args` - Optional macro arguments passed to the program, referenced as `$1`, `$$1`, ... See [`MacroArgs`].
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the compiled [`Program`] if successful, or
+    /// an error code if the program could not be compiled.
     pub fn dtrace_program_fcompile<'a>(
         &'a self,
-        file: Option<&utils::File>,
+        file: &mut dyn Read,
         flags: u32,
-        args: Option<Vec<String>>,
-    ) -> Result<&'a mut crate::dtrace_prog, Error> {
-        // Break the arguments into argc and argv
-        let (argc, argv) = match args {
+        args: Option<&MacroArgs>,
+    ) -> Result<Program<'a>, Error> {
+        // Break the arguments into argc and argv, `argv` owns the strings until the compiler is done with them
+        let argv = args.map(MacroArgs::to_argv).transpose()?;
+        let (argc, argv) = match &argv {
             None => (0, std::ptr::null()),
-            Some(args) => {
-                let mut argv: Vec<*mut ::core::ffi::c_char> = Vec::new();
-                for arg in args {
-                    let arg = std::ffi::CString::new(arg).unwrap();
-                    argv.push(arg.as_ptr() as *mut ::core::ffi::c_char);
-                }
-                (argv.len() as c_int, argv.as_ptr())
-            }
-        };
-
-        let file = match file {
-            Some(file) => file.file,
-            None => std::ptr::null_mut(),
+            Some(argv) => (argv.argc(), argv.as_ptr()),
         };
 
+        let file = CFile::reader(file)?;
         let prog;
         unsafe {
-            prog = crate::dtrace_program_fcompile(self.handle, file, flags, argc, argv);
+            prog = crate::dtrace_program_fcompile(self.handle, file.as_ptr(), flags, argc, argv);
         }
+        file.finish()?;
 
         if prog.is_null() {
-            return Err(Error::from(self));
+            return Err(Error::from((self, "dtrace_program_fcompile")).with_source(None));
         }
 
-        unsafe { Ok(&mut *prog) }
+        unsafe { Ok(Program::from_raw(self, prog)) }
     }
 
     /// After the D program is compiled, this function is used to create the object file for the program and download the object file to the kernel.
@@ -278,33 +338,34 @@ impl dtrace_hdl {
     ///
     /// # Arguments
     ///
-    /// * `program` - A mutable reference to the data structure representing the compiled program. This is returned by the `dtrace_strcompile()` function.
+    /// * `program` - The compiled program. This is returned by the `dtrace_program_strcompile()` function.
     /// * `info` - An optional mutable reference to a variable, which contains information about the D program. The definition of the `dtrace_proginfo_t` can be found [`here`](https://github.com/microsoft/DTrace-on-Windows/blob/0adebf25928264dffdc8240e850503865409f334/lib/libdtrace/common/dtrace.h#L106).
     ///
     /// # Returns
     ///
     /// * `Ok(())` - If the program execution is successful.
-    /// * `Err(errno)` - If the program execution fails. The error number (`errno`) is returned.
+    /// * `Err(errno)` - If the program execution fails. The error number (`errno`) is returned, `EINVAL` if the
+    ///   program was compiled into another handle.
     pub fn dtrace_program_exec(
         &self,
-        program: &mut crate::dtrace_prog,
+        program: &mut Program<'_>,
         info: Option<&mut crate::dtrace_proginfo>,
     ) -> Result<(), Error> {
-        let info = match info {
-            Some(info) => info,
-            None => std::ptr::null_mut(),
-        };
-        match unsafe { crate::dtrace_program_exec(self.handle, program, info) } {
-            0 => Ok(()),
-            _ => Err(Error::from(self)),
+        if !program.is_compiled_by(self) {
+            return Err(Error::from(libc::EINVAL).with_api("dtrace_program_exec"));
+        }
+        program.exec()?;
+        if let (Some(info), Some(raw_info)) = (info, program.raw_info()) {
+            *info = *raw_info;
         }
+        Ok(())
     }
 
     /// Iterates over the statements associated with a D program, calling the specified function on each statement.
     ///
     /// # Arguments
     ///
-    /// * `program` -  A mutable reference to the data structure representing the compiled program. This is returned by the `dtrace_strcompile()` function.
+    /// * `program` - The compiled program. This is returned by the `dtrace_program_strcompile()` function.
     /// * `handler` - The function to call on each statement.
     ///
     ///     The handler function must have the following signature:
@@ -316,21 +377,81 @@ impl dtrace_hdl {
     /// # Returns
     ///
     /// * `Ok(())` - If the iteration is successful.
-    /// * `Err(errno)` - If the iteration fails. The error number (`errno`) is returned.
+    /// * `Err(errno)` - If the iteration fails. The error number (`errno`) is returned, `EINVAL` if the program was
+    ///   compiled into another handle.
     pub fn dtrace_stmt_iter(
         &self,
-        program: &mut crate::dtrace_prog,
+        program: &mut Program<'_>,
         handler: crate::dtrace_stmt_f,
         arg: Option<*mut ::core::ffi::c_void>,
     ) -> Result<(), Error> {
+        if !program.is_compiled_by(self) {
+            return Err(Error::from(libc::EINVAL).with_api("dtrace_stmt_iter"));
+        }
         let arg = match arg {
             Some(arg) => arg,
             None => std::ptr::null_mut(),
         };
 
-        match unsafe { crate::dtrace_stmt_iter(self.handle, program, handler, arg) } {
+        match unsafe { crate::dtrace_stmt_iter(self.handle, program.as_ptr(), handler, arg) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
+            _ => Err(Error::from((self, "dtrace_stmt_iter"))),
+        }
+    }
+
+    /// Lists the probes known to DTrace, like `dtrace -l`.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - An optional description selecting the probes, whose fields may be glob patterns such as `read*`.
+    ///   If [`None`] is passed, all probes are listed.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Probes)` - The matching probes, in the order libdtrace reports them. No matching probe is not an error.
+    /// * `Err(errno)` - If the filter is invalid or the probes could not be listed.
+    pub fn probes(&self, filter: Option<&ProbeDescription>) -> Result<Probes, Error> {
+        let filter = filter.map(crate::dtrace_probedesc_t::try_from).transpose()?;
+        let mut probes = Vec::new();
+        let mut push = |desc: &crate::dtrace_probedesc_t| probes.push(unsafe { ProbeInfo::from_raw(desc) });
+        let mut handler = callbacks::IterHandler::new(&mut push);
+
+        let status = unsafe {
+            crate::dtrace_probe_iter(
+                self.handle,
+                filter.as_ref().map_or(std::ptr::null(), |filter| filter as *const _),
+                Some(callbacks::probe),
+                &mut handler as *mut _ as *mut ::core::ffi::c_void,
+            )
+        };
+        handler.resume_panic();
+        match status {
+            0 => Ok(Probes::new(probes)),
+            _ if self.dtrace_errno() == ErrorKind::NoProbe.errno() => Ok(Probes::new(Vec::new())),
+            _ => Err(Error::from((self, "dtrace_probe_iter"))),
+        }
+    }
+
+    /// Determines the number of arguments of a probe, `args[0]` through `args[n - 1]` in D.
+    ///
+    /// libdtrace describes the argument types by their CTF type, which it does not offer a way to name.
+    ///
+    /// # Arguments
+    ///
+    /// * `probe` - The probe, as listed by [`dtrace_hdl::probes`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(argc)` - The number of arguments.
+    /// * `Err(errno)` - If the probe does not exist.
+    pub fn probe_argc(&self, probe: &ProbeInfo) -> Result<usize, Error> {
+        let mut desc = crate::dtrace_probedesc_t::try_from(&ProbeDescription::from(probe))?;
+        desc.dtpd_id = probe.id;
+        let mut info: crate::dtrace_probeinfo_t = unsafe { ::core::mem::zeroed() };
+
+        match unsafe { crate::dtrace_probe_info(self.handle, &desc, &mut info) } {
+            0 => Ok(info.dtp_argc.max(0) as usize),
+            _ => Err(Error::from((self, "dtrace_probe_info"))),
         }
     }
 
@@ -345,7 +466,7 @@ impl dtrace_hdl {
     /// * `Err(errno)` - If the status could not be determined.
     pub fn dtrace_status(&self) -> Result<dtrace_status, Error> {
         match unsafe { crate::dtrace_status(self.handle) } {
-            -1 => Err(Error::from(self)),
+            -1 => Err(Error::from((self, "dtrace_status"))),
             status => Ok(dtrace_status::from(status as u32)),
         }
     }
@@ -354,7 +475,8 @@ impl dtrace_hdl {
     ///
     /// # Arguments
     ///
-    /// * `file` - An optional file handle for output.
+    /// * `file` - An optional writer for the output formatted by libdtrace, e.g. by `printf()`. If [`None`] is passed,
+    ///   the output goes to the buffered output handler.
     /// * `p_hldr` - A pointer to a function that processes an `enabling control block (ECB)`. An `ECB` is a clause from a D program associated with the enabled probe.
     /// * `r_hldr` - A pointer to a function that processes a records from the `ECB`.
     /// * `arg` - An optional argument to be passed to the `p_hldr` and `r_hldr` functions. This argument can maintain any state between successive invocations of the functions.
@@ -365,24 +487,20 @@ impl dtrace_hdl {
     /// * `Err(errno)` - If the consumption fails. The error number (`errno`) is returned.
     pub fn dtrace_consume(
         &self,
-        file: Option<&utils::File>,
+        file: Option<&mut dyn Write>,
         p_hldr: crate::dtrace_consume_probe_f,
         r_hldr: crate::dtrace_consume_rec_f,
         arg: Option<*mut ::core::ffi::c_void>,
     ) -> Result<(), Error> {
-        let file = match file {
-            Some(file) => file.file,
-            None => std::ptr::null_mut(),
-        };
         let arg = match arg {
             Some(arg) => arg,
             None => std::ptr::null_mut(),
         };
 
-        match unsafe { crate::dtrace_consume(self.handle, file, p_hldr, r_hldr, arg) } {
+        CFile::with(file, |file| match unsafe { crate::dtrace_consume(self.handle, file, p_hldr, r_hldr, arg) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
-        }
+            _ => Err(Error::from((self, "dtrace_consume"))),
+        })
     }
 
     /// Performs all of the work that must to be done periodically by a DTrace consumer.
@@ -391,7 +509,8 @@ impl dtrace_hdl {
     ///
     /// # Arguments
     ///
-    /// * `file` - An optional file handle for output.
+    /// * `file` - An optional writer for the output formatted by libdtrace, e.g. by `printf()`. If [`None`] is passed,
+    ///   the output goes to the buffered output handler.
     /// * `chew` - A function pointer that is called for each enabled probe ID (EPID) that is processed from the buffer.
     /// * `chewrec` - A function pointer that is called for each record that is processed for an EPID.
     /// * `arg` - An optional argument to be passed to the `chew` and `chewrec` functions. This argument can maintain any state between successive invocations of the functions.
@@ -403,24 +522,112 @@ impl dtrace_hdl {
     /// * `DTRACE_WORKSTATUS_ERROR` - If an error occurs while performing the work.
     pub fn dtrace_work(
         &self,
-        file: Option<&utils::File>,
+        file: Option<&mut dyn Write>,
         p_hldr: crate::dtrace_consume_probe_f,
         r_hldr: crate::dtrace_consume_rec_f,
         arg: Option<&mut ::core::ffi::c_void>,
     ) -> Result<crate::dtrace_workstatus_t, Error> {
-        let file = match file {
-            Some(file) => file.file,
-            None => std::ptr::null_mut(),
-        };
         let arg = match arg {
             Some(arg) => arg,
             None => std::ptr::null_mut(),
         };
-        match unsafe { crate::dtrace_work(self.handle, file, p_hldr, r_hldr, arg) } {
+        CFile::with(file, |file| match unsafe { crate::dtrace_work(self.handle, file, p_hldr, r_hldr, arg) } {
             crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_ERROR => {
-                Err(Error::from(self))
+                Err(Error::from((self, "dtrace_work")))
             }
             status => Ok(status),
+        })
+    }
+
+    /// Consumes data from the principal buffers, like [`dtrace_hdl::dtrace_consume`], with Rust closures as handlers.
+    ///
+    /// The handlers are only borrowed for the duration of the call. A panic in either handler aborts the
+    /// consumption and is resumed once libdtrace has returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - An optional writer for the output formatted by libdtrace, e.g. by `printf()`. If [`None`] is passed,
+    ///   the output goes to the buffered output handler.
+    /// * `probe` - Called for each enabled probe that fired.
+    /// * `record` - Called with each record of the enabled probe decoded into a [`Record`], and with `None` after the
+    ///   last record. The records of a `printf()`-like action are passed as a single [`Record::Printf`].
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the consumption is successful.
+    /// * `Err(errno)` - If the consumption fails, including when a handler returns [`ConsumeAction::Abort`] or
+    ///   [`ConsumeAction::Error`].
+    pub fn dtrace_consume_with<P, R>(
+        &self,
+        file: Option<&mut dyn Write>,
+        mut probe: P,
+        mut record: R,
+    ) -> Result<(), Error>
+    where
+        P: FnMut(&ProbeData) -> ConsumeAction,
+        R: FnMut(&ProbeData, Option<&Record>) -> ConsumeAction,
+    {
+        let mut handlers = callbacks::ConsumeHandlers::new(&mut probe, &mut record);
+        let status = CFile::with(file, |file| unsafe {
+            Ok(crate::dtrace_consume(
+                self.handle,
+                file,
+                Some(callbacks::consume_probe),
+                Some(callbacks::consume_rec),
+                &mut handlers as *mut _ as *mut ::core::ffi::c_void,
+            ))
+        });
+        handlers.resume_panic();
+
+        match status? {
+            0 => Ok(()),
+            _ => Err(Error::from((self, "dtrace_consume"))),
+        }
+    }
+
+    /// Performs the periodic consumer work, like [`dtrace_hdl::dtrace_work`], with Rust closures as handlers.
+    ///
+    /// The handlers are only borrowed for the duration of the call. A panic in either handler aborts the
+    /// consumption and is resumed once libdtrace has returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - An optional writer for the output formatted by libdtrace, e.g. by `printf()`. If [`None`] is passed,
+    ///   the output goes to the buffered output handler.
+    /// * `probe` - Called for each enabled probe that fired.
+    /// * `record` - Called with each record of the enabled probe decoded into a [`Record`], and with `None` after the
+    ///   last record. The records of a `printf()`-like action are passed as a single [`Record::Printf`].
+    ///
+    /// # Returns
+    ///
+    /// * `DTRACE_WORKSTATUS_OKAY` - If the work is successfully performed.
+    /// * `DTRACE_WORKSTATUS_DONE` - If the work is done and no more work is expected.
+    /// * `Err(errno)` - If an error occurs while performing the work.
+    pub fn dtrace_work_with<P, R>(
+        &self,
+        file: Option<&mut dyn Write>,
+        mut probe: P,
+        mut record: R,
+    ) -> Result<crate::dtrace_workstatus_t, Error>
+    where
+        P: FnMut(&ProbeData) -> ConsumeAction,
+        R: FnMut(&ProbeData, Option<&Record>) -> ConsumeAction,
+    {
+        let mut handlers = callbacks::ConsumeHandlers::new(&mut probe, &mut record);
+        let status = CFile::with(file, |file| unsafe {
+            Ok(crate::dtrace_work(
+                self.handle,
+                file,
+                Some(callbacks::consume_probe),
+                Some(callbacks::consume_rec),
+                &mut handlers as *mut _ as *mut ::core::ffi::c_void,
+            ))
+        });
+        handlers.resume_panic();
+
+        match status? {
+            crate::dtrace_workstatus_t::DTRACE_WORKSTATUS_ERROR => Err(Error::from((self, "dtrace_work"))),
+            status => Ok(status),
         }
     }
 
@@ -428,73 +635,192 @@ impl dtrace_hdl {
 
     /* Handler APIs START */
     /// Sets a handler functions for processing trace data.
-    /// 
+    ///
+    /// The handle owns the handler and frees it only after it is closed, so the handler may capture whatever state it
+    /// needs, e.g. the sending half of a channel. A panic raised by the handler is caught before it reaches libdtrace
+    /// and aborts the libdtrace call in progress. Registering a handler of the same kind again replaces it.
+    ///
     /// # Arguments
-    /// 
-    /// * `handler` - An enum variant from [`dtrace_handler`] representing the handler function to be called for each trace record. Possible values:
-    ///     * `Buffered(handler)` - The handler function to be called for each buffered trace record.
+    ///
+    /// * `handler` - An enum variant from [`crate::types::dtrace_handler`] holding the handler to be called. Possible values:
+    ///     * `Buffered(handler)` - The handler to be called with each buffered output.
     ///         * If [`None`] is passed to `dtrace_work`, `dtrace_consume` or `dtrace_aggregate_print` function, then libdtrace makes use of the buffered I/O handler to process buffered trace data.
-    ///         * The handler function must have the following signature:
-    ///             ```rs
-    ///                 unsafe extern "C" fn(*const dtrace_bufdata_t, *mut c_void) -> c_int
-    ///             ```
-    ///     * `Drop(handler)` - The handler function to be called for each dropped trace record.
-    ///         * The handler function must have the following signature:
-    ///             ```rs
-    ///                 unsafe extern "C" fn(*const dtrace_dropdata_t, *mut c_void) -> c_int
-    ///             ```
-    ///     * `Err(handler)` - To register a handler function for processing errors such as accessing an invalid address or dividing by zero.
-    ///         * The handler function must have the following signature:
-    ///             ```rs
-    ///                 unsafe extern "C" fn(*const dtrace_errdata_t, *mut c_void) -> c_int
-    ///             ```
+    ///     * `Drop(handler)` - The handler to be called for each drop, see [`dtrace_hdl::dtrace_handle_drop`].
+    ///     * `Err(handler)` - To register a handler for processing errors such as accessing an invalid address or dividing by zero, see [`dtrace_hdl::dtrace_handle_err`]. libdtrace enables a `dtrace:::ERROR` clause of its own for it.
     ///     * `SetOpt(handler)` - This handler is called whenever a DTrace option is set from inside a D program.
-    ///         * The handler function must have the following signature:
-    ///             ```rs
-    ///                 unsafe extern "C" fn(*const dtrace_setoptdata_t, *mut c_void) -> c_int
-    ///             ```
-    ///     * `Proc(handler)` - Unsupported.
-    /// * `arg` - An optional argument to be passed to the handler function. This argument can maintain any state between successive invocations of the handler function.
-    /// 
+    ///     * `Proc(handler)` - This handler is called with the messages about processes grabbed by libdtrace.
+    ///
     /// # Returns
-    /// 
+    ///
     /// Returns `Ok(())` if the handler was set successfully, or an error code if the handler could
     /// not be set.
-    pub fn dtrace_register_handler(
-        self,
-        handler: crate::types::dtrace_handler,
-        arg: Option<*mut ::core::ffi::c_void>,
-    ) -> Result<Self, Error> {
-        let status;
-        let arg = match arg {
-            Some(arg) => arg,
-            None => std::ptr::null_mut(),
-        };
-
-        unsafe {
-            status = match handler {
-                crate::types::dtrace_handler::Buffered(handler) => {
-                    crate::dtrace_handle_buffered(self.handle, handler, arg)
+    pub fn dtrace_register_handler(mut self, handler: crate::types::dtrace_handler) -> Result<Self, Error> {
+        let handlers = self.handlers();
+        match handler {
+            crate::types::dtrace_handler::Buffered(handler) => {
+                let install = handlers.buffered.is_none() && handlers.events.is_none();
+                handlers.buffered = Some(handler);
+                if install {
+                    self.install("dtrace_handle_buffered", |handle, arg| unsafe {
+                        crate::dtrace_handle_buffered(handle, Some(callbacks::event_buffered), arg)
+                    })?;
                 }
-                crate::types::dtrace_handler::Drop(handler) => {
-                    crate::dtrace_handle_drop(self.handle, handler, arg)
+            }
+            crate::types::dtrace_handler::Drop(handler) => {
+                let install = handlers.drop.is_none() && handlers.events.is_none();
+                handlers.drop = Some(handler);
+                if install {
+                    self.install_drop()?;
                 }
-                crate::types::dtrace_handler::Err(handler) => {
-                    crate::dtrace_handle_err(self.handle, handler, arg)
+            }
+            crate::types::dtrace_handler::Err(handler) => {
+                let install = handlers.fault.is_none() && handlers.events.is_none();
+                handlers.fault = Some(handler);
+                if install {
+                    self.install_err()?;
                 }
-                crate::types::dtrace_handler::SetOpt(handler) => {
-                    crate::dtrace_handle_setopt(self.handle, handler, arg)
+            }
+            crate::types::dtrace_handler::SetOpt(handler) => {
+                let install = handlers.setopt.is_none();
+                handlers.setopt = Some(handler);
+                if install {
+                    self.install("dtrace_handle_setopt", |handle, arg| unsafe {
+                        crate::dtrace_handle_setopt(handle, Some(callbacks::event_setopt), arg)
+                    })?;
                 }
-                crate::types::dtrace_handler::Proc(handler) => {
-                    crate::dtrace_handle_proc(self.handle, handler, arg)
+            }
+            crate::types::dtrace_handler::Proc(handler) => {
+                let install = handlers.proc.is_none();
+                handlers.proc = Some(handler);
+                if install {
+                    self.install("dtrace_handle_proc", |handle, arg| unsafe {
+                        crate::dtrace_handle_proc(handle, Some(callbacks::event_proc), arg)
+                    })?;
                 }
-            };
+            }
         }
+        Ok(self)
+    }
 
-        if status == 0 {
-            Ok(self)
-        } else {
-            Err(Error::from(&self))
+    /// Registers a handler for buffered output, drops and faults, delivered as [`Event`]s while consuming.
+    ///
+    /// The handler is called in addition to the handlers registered with [`dtrace_hdl::dtrace_register_handler`].
+    /// libdtrace only accepts drop and error handlers before tracing starts, so this has to be called before
+    /// [`dtrace_hdl::dtrace_go`]. Calling it again replaces the handler. Faults are reported by the error handler, which
+    /// enables a `dtrace:::ERROR` clause, see [`dtrace_hdl::dtrace_handle_err`].
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler, owned by the handle until it is closed.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the handler was registered successfully, or an error code if it could not be registered.
+    pub fn dtrace_handle_events(&mut self, handler: Box<dyn FnMut(Event) + Send>) -> Result<(), Error> {
+        let handlers = self.handlers();
+        let first = handlers.events.is_none();
+        let (buffered, drop, fault) = (handlers.buffered.is_none(), handlers.drop.is_none(), handlers.fault.is_none());
+        handlers.events = Some(handler);
+        if first && buffered {
+            self.install("dtrace_handle_buffered", |handle, arg| unsafe {
+                crate::dtrace_handle_buffered(handle, Some(callbacks::event_buffered), arg)
+            })?;
+        }
+        if first && drop {
+            self.install_drop()?;
+        }
+        if first && fault {
+            self.install_err()?;
+        }
+        Ok(())
+    }
+
+    /// Registers a handler for the data the kernel dropped, e.g. because a buffer was full.
+    ///
+    /// Drops are reported while consuming, once per CPU and kind of buffer. libdtrace only accepts drop handlers before
+    /// tracing starts, so this has to be called before [`dtrace_hdl::dtrace_go`]. Calling it again replaces the
+    /// handler. The handler is called in addition to the one registered with [`dtrace_hdl::dtrace_handle_events`].
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler, owned by the handle until it is closed.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the handler was registered successfully, or an error code if it could not be registered.
+    pub fn dtrace_handle_drop(&mut self, handler: Box<dyn FnMut(&DropEvent) + Send>) -> Result<(), Error> {
+        let handlers = self.handlers();
+        let install = handlers.drop.is_none() && handlers.events.is_none();
+        handlers.set_drop(handler);
+        if install {
+            self.install_drop()?;
+        }
+        Ok(())
+    }
+
+    /// Registers a handler for the faults raised while executing the actions of enabled probes, such as accessing an
+    /// invalid address or dividing by zero.
+    ///
+    /// libdtrace only accepts error handlers before tracing starts, so this has to be called before
+    /// [`dtrace_hdl::dtrace_go`]. Calling it again replaces the handler. The handler is called in addition to the one
+    /// registered with [`dtrace_hdl::dtrace_handle_events`].
+    ///
+    /// libdtrace reports faults through a `dtrace:::ERROR` clause that it compiles and enables on the handle when the
+    /// first error handler is registered. From then on the ERROR probe is enabled whenever tracing runs, and its
+    /// records are consumed as faults rather than handed to the probe and record handlers.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - The handler, owned by the handle until it is closed.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the handler was registered successfully, or an error code if it could not be registered.
+    pub fn dtrace_handle_err(&mut self, handler: Box<dyn FnMut(&FaultEvent) + Send>) -> Result<(), Error> {
+        let handlers = self.handlers();
+        let install = handlers.fault.is_none() && handlers.events.is_none();
+        handlers.set_fault(handler);
+        if install {
+            self.install_err()?;
+        }
+        Ok(())
+    }
+
+    /// The handlers owned by the handle, allocated on first use.
+    ///
+    /// Each trampoline is only installed once a handler it delivers to is registered, libdtrace keeps failing on the
+    /// drops and faults nobody handles until then.
+    fn handlers(&mut self) -> &mut callbacks::Handlers {
+        // Keep the handlers alive from here on, libdtrace holds on to `arg` even if a later registration fails
+        self.handlers.get_or_insert_with(Box::default)
+    }
+
+    /// Installs the drop trampoline, for the first drop or events handler.
+    fn install_drop(&mut self) -> Result<(), Error> {
+        self.install("dtrace_handle_drop", |handle, arg| unsafe {
+            crate::dtrace_handle_drop(handle, Some(callbacks::event_drop), arg)
+        })
+    }
+
+    /// Installs the error trampoline, for the first fault or events handler. This makes libdtrace enable its
+    /// `dtrace:::ERROR` clause, see [`dtrace_hdl::dtrace_handle_err`].
+    fn install_err(&mut self) -> Result<(), Error> {
+        self.install("dtrace_handle_err", |handle, arg| unsafe {
+            crate::dtrace_handle_err(handle, Some(callbacks::event_fault), arg)
+        })
+    }
+
+    /// Registers a trampoline with the handlers owned by the handle as its `arg`.
+    fn install(
+        &mut self,
+        api: &'static str,
+        register: impl FnOnce(*mut crate::dtrace_hdl_t, *mut ::core::ffi::c_void) -> c_int,
+    ) -> Result<(), Error> {
+        let handlers = self.handlers.as_deref_mut().unwrap();
+        let arg = handlers as *mut callbacks::Handlers as *mut ::core::ffi::c_void;
+        match register(self.handle, arg) {
+            0 => Ok(()),
+            _ => Err(Error::from((&*self, api))),
         }
     }
 
@@ -512,7 +838,7 @@ impl dtrace_hdl {
     pub fn dtrace_aggregate_snap(&self) -> Result<(), Error> {
         match unsafe { crate::dtrace_aggregate_snap(self.handle) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
+            _ => Err(Error::from((self, "dtrace_aggregate_snap"))),
         }
     }
 
@@ -523,7 +849,8 @@ impl dtrace_hdl {
     ///
     /// # Arguments
     ///
-    /// * `file` - An optional file handle for output.
+    /// * `file` - An optional writer for the output formatted by libdtrace, e.g. by `printf()`. If [`None`] is passed,
+    ///   the output goes to the buffered output handler.
     /// * `handler` - A function pointer that is called for each aggregate buffer that is processed.
     /// * `arg` - An optional argument to be passed to the `handler` function. This argument can maintain any state between successive invocations of the function.
     ///
@@ -533,19 +860,13 @@ impl dtrace_hdl {
     /// * `Err(i32)` - If the processing fails. The error number is returned.
     pub fn dtrace_aggregate_print(
         &self,
-        file: Option<&utils::File>,
+        file: Option<&mut dyn Write>,
         handler: crate::dtrace_aggregate_walk_f,
     ) -> Result<(), Error> {
-        let file = match file {
-            Some(file) => file.file,
-            None => std::ptr::null_mut(),
-        };
-
-        match unsafe { crate::dtrace_aggregate_print(self.handle, file, handler) }
-        {
+        CFile::with(file, |file| match unsafe { crate::dtrace_aggregate_print(self.handle, file, handler) } {
             0 => Ok(()),
-            _ => Err(Error::from(self)),
-        }
+            _ => Err(Error::from((self, "dtrace_aggregate_print"))),
+        })
     }
 
     /// Processes DTrace aggregate data.
@@ -606,9 +927,54 @@ impl dtrace_hdl {
         if status == 0 {
             Ok(())
         } else {
-            Err(Error::from(self))
+            Err(Error::from((self, "dtrace_aggregate_walk")))
         }
     }
 
+    /// Decodes the aggregation data of the last snapshot.
+    ///
+    /// Call [`dtrace_hdl::dtrace_aggregate_snap`] (or [`dtrace_hdl::dtrace_work`], which calls it) first to retrieve
+    /// the aggregation data from the kernel.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order in which the aggregations are returned. One of the members of the [`dtrace_aggwalk_order`] enum.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Vec<Aggregation>)` - One entry per aggregation variable and key.
+    /// * `Err(i32)` - If the aggregation data could not be walked. The error number is returned.
+    pub fn aggregations(&self, order: dtrace_aggwalk_order) -> Result<Vec<Aggregation>, Error> {
+        DtraceBackend::aggregations(self, order)
+    }
+
     /* Aggregation APIs END */
+
+    /// Starts tracing and iterates over the trace events, see [`crate::consumer::Events`].
+    ///
+    /// Compile and execute the programs first, the iterator calls [`dtrace_hdl::dtrace_go`] itself after registering
+    /// its handler with [`dtrace_hdl::dtrace_handle_events`]. The iteration ends once tracing stopped.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Events)` - If tracing started.
+    /// * `Err(errno)` - If the handler could not be registered or tracing could not be started.
+    pub fn events(&mut self) -> Result<Events<'_, Self>, Error> {
+        Events::new(self)
+    }
+
+    /// Starts tracing and streams the trace events from a dedicated worker thread, see
+    /// [`crate::stream::EventStream`].
+    ///
+    /// Compile and execute the programs first, the stream calls [`dtrace_hdl::dtrace_go`] itself after registering
+    /// its handler with [`dtrace_hdl::dtrace_handle_events`]. Dropping the stream stops tracing and closes the handle.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(EventStream)` - If tracing started.
+    /// * `Err(errno)` - If the handler could not be registered or tracing could not be started.
+    #[cfg(feature = "async")]
+    pub fn into_stream(self) -> Result<crate::stream::EventStream, Error> {
+        crate::stream::EventStream::new(self)
+    }
 }
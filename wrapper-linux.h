@@ -0,0 +1 @@
+#include <dtrace.h>